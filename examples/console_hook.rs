@@ -0,0 +1,131 @@
+use hudhook::ImguiRenderLoop;
+use imgui::{
+    Condition, FocusedWidget, HistoryDirection, InputTextCallback, InputTextCallbackHandler,
+    TextCallbackData,
+};
+
+const COMMANDS: &[&str] = &["clear", "echo", "help", "history", "quit"];
+
+// Input text callbacks for the command line: only accept printable ASCII,
+// complete command names with Tab and browse the history with Up and Down.
+struct CommandLineCallbacks<'a> {
+    history: &'a [String],
+    history_pos: &'a mut Option<usize>,
+}
+
+impl InputTextCallbackHandler for CommandLineCallbacks<'_> {
+    fn char_filter(&mut self, c: char) -> Option<char> {
+        (c.is_ascii_graphic() || c == ' ').then_some(c)
+    }
+
+    fn on_completion(&mut self, mut data: TextCallbackData) {
+        let mut candidates = COMMANDS.iter().filter(|cmd| cmd.starts_with(data.str()));
+
+        if let (Some(cmd), None) = (candidates.next(), candidates.next()) {
+            data.clear();
+            data.push_str(cmd);
+            data.push_str(" ");
+        }
+    }
+
+    fn on_history(&mut self, dir: HistoryDirection, mut data: TextCallbackData) {
+        let last = match self.history.len() {
+            0 => return,
+            len => len - 1,
+        };
+
+        *self.history_pos = match (dir, *self.history_pos) {
+            (HistoryDirection::Up, None) => Some(last),
+            (HistoryDirection::Up, Some(pos)) => Some(pos.saturating_sub(1)),
+            (HistoryDirection::Down, Some(pos)) if pos < last => Some(pos + 1),
+            (HistoryDirection::Down, _) => None,
+        };
+
+        data.clear();
+        if let Some(pos) = *self.history_pos {
+            data.push_str(&self.history[pos]);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ConsoleExample {
+    input: String,
+    filter: String,
+    log: Vec<String>,
+    history: Vec<String>,
+    history_pos: Option<usize>,
+}
+
+impl ConsoleExample {
+    fn execute(&mut self) {
+        let line = self.input.trim().to_string();
+        self.input.clear();
+        self.history_pos = None;
+
+        if line.is_empty() {
+            return;
+        }
+
+        self.log.push(format!("> {line}"));
+        self.history.push(line.clone());
+
+        let (cmd, args) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        match cmd {
+            "clear" => self.log.clear(),
+            "echo" => self.log.push(args.into()),
+            "help" => self.log.push(format!("Commands: {}", COMMANDS.join(", "))),
+            "history" => self.log.extend(self.history.iter().cloned()),
+            "quit" => hudhook::eject(),
+            _ => self.log.push(format!("Unknown command: {cmd}")),
+        }
+    }
+}
+
+impl ImguiRenderLoop for ConsoleExample {
+    fn render(&mut self, ui: &mut imgui::Ui) {
+        ui.window("Console")
+            .size([480.0, 320.0], Condition::FirstUseEver)
+            .position([16.0, 16.0], Condition::FirstUseEver)
+            .build(|| {
+                // Pick a command from a combo box, filtered by the text typed in it.
+                if let Some(_combo) = ui.begin_combo("Commands", "Pick a command") {
+                    ui.input_text("##filter", &mut self.filter).hint("Filter").build();
+
+                    for cmd in COMMANDS.iter().filter(|cmd| cmd.contains(self.filter.as_str())) {
+                        if ui.selectable(cmd) {
+                            self.input = format!("{cmd} ");
+                        }
+                    }
+                }
+
+                ui.child_window("Log").size([0.0, -32.0]).build(|| {
+                    for line in &self.log {
+                        ui.text(line);
+                    }
+                });
+
+                let entered = ui
+                    .input_text("##command", &mut self.input)
+                    .hint("Type a command, Tab to complete")
+                    .enter_returns_true(true)
+                    .callback(
+                        InputTextCallback::CHAR_FILTER
+                            | InputTextCallback::COMPLETION
+                            | InputTextCallback::HISTORY,
+                        CommandLineCallbacks {
+                            history: &self.history,
+                            history_pos: &mut self.history_pos,
+                        },
+                    )
+                    .build();
+
+                if entered {
+                    self.execute();
+                    ui.set_keyboard_focus_here_with_offset(FocusedWidget::Previous);
+                }
+            });
+    }
+}
+
+hudhook::hudhook!(hudhook::hooks::dx11::ImguiDx11Hooks, ConsoleExample::default());
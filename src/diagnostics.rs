@@ -0,0 +1,93 @@
+//! This module contains the events reported through
+//! [`HudhookBuilder::with_diagnostics`](crate::HudhookBuilder::with_diagnostics).
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use tracing::error;
+use windows::core::Error;
+
+type Callback = Arc<dyn Fn(DiagnosticEvent) + Send + Sync>;
+
+static CALLBACK: Lazy<RwLock<Option<Callback>>> = Lazy::new(|| RwLock::new(None));
+
+// Whether a present has been seen since the hooks were last enabled.
+static PRESENTED: AtomicBool = AtomicBool::new(false);
+
+/// A step of setting up and drawing the overlay, reported to the callback
+/// set via
+/// [`HudhookBuilder::with_diagnostics`](crate::HudhookBuilder::with_diagnostics).
+///
+/// The events are reported in the order the steps happen: the hooks are
+/// installed, the application presents, the renderer is set up, and then the
+/// frames are rendered, or fail to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiagnosticEvent {
+    /// The hooks of a [`Hooks`](crate::Hooks) implementation, named by its
+    /// type, were enabled by [`Hudhook::apply`](crate::Hudhook::apply) or
+    /// [`Hudhook::reapply`](crate::Hudhook::reapply).
+    HookInstalled(&'static str),
+    /// The application presented for the first time since the hooks were
+    /// enabled, i.e. the hooks are called.
+    FirstPresent,
+    /// The application presents through the named hooked function, e.g.
+    /// `"gdi32!SwapBuffers"`. Reported right before
+    /// [`DiagnosticEvent::RendererInitialized`] by the hooks that intercept
+    /// more than one function to present, i.e. the OpenGL 3 ones.
+    PresentFunction(&'static str),
+    /// The overlay was set up on the named renderer, e.g. `"DirectX 11"`,
+    /// and is about to draw its first frame. Reported again whenever the
+    /// overlay is set up anew, e.g. after a device reset.
+    RendererInitialized(&'static str),
+    /// Drawing a frame failed, with the error, as logged.
+    RenderError(String),
+    /// A frame was drawn. This is reported on every frame, so it should be
+    /// cheap to handle.
+    FrameRendered,
+}
+
+pub(crate) fn set_callback(callback: Callback) {
+    *CALLBACK.write() = Some(callback);
+}
+
+pub(crate) fn emit(event: DiagnosticEvent) {
+    let Some(callback) = CALLBACK.read().clone() else {
+        return;
+    };
+
+    // The callback runs in the hooks, which must not unwind into the
+    // application.
+    if panic::catch_unwind(AssertUnwindSafe(|| callback(event))).is_err() {
+        error!("Diagnostics callback panicked");
+    }
+}
+
+// Report the first present after the hooks were enabled.
+pub(crate) fn present() {
+    if !PRESENTED.swap(true, Ordering::SeqCst) {
+        emit(DiagnosticEvent::FirstPresent);
+    }
+}
+
+// Wait for the next present, once the hooks have been enabled.
+pub(crate) fn hooks_enabling() {
+    PRESENTED.store(false, Ordering::SeqCst);
+}
+
+pub(crate) fn hooks_enabled(names: impl IntoIterator<Item = &'static str>) {
+    for name in names {
+        emit(DiagnosticEvent::HookInstalled(name));
+    }
+}
+
+// Log an error of the hooks' render function, and report it.
+pub(crate) fn render_error(e: &Error) {
+    error!("Render error: {e:?}");
+    if CALLBACK.read().is_some() {
+        emit(DiagnosticEvent::RenderError(format!("{e:?}")));
+    }
+}
@@ -0,0 +1,66 @@
+//! Error type for setting up and tearing down the hooks.
+
+use std::fmt;
+
+use windows::core::Error;
+
+use crate::mh::MH_STATUS;
+
+/// The things that can go wrong while applying or removing the hooks, i.e.
+/// in [`Hudhook::apply`](crate::Hudhook::apply) and
+/// [`Hudhook::unapply`](crate::Hudhook::unapply).
+#[derive(Debug)]
+pub enum HudhookError {
+    /// A minhook call failed, e.g. while initializing it or creating,
+    /// enabling or disabling a hook.
+    MinHook(MH_STATUS),
+    /// The dummy device or swap chain used to find the addresses of the
+    /// functions to hook couldn't be created.
+    DeviceCreation(Error),
+    /// A shader of the renderer didn't compile.
+    ShaderCompile(String),
+    /// The address of the function that presents frames couldn't be found,
+    /// e.g. because the graphics library isn't loaded in the process.
+    PresentAddressNotFound,
+    /// No window was available to create the dummy device with.
+    WindowNotFound,
+    /// The application didn't load its graphics library within the attempts
+    /// configured with
+    /// [`HudhookBuilder::with_hook_retry`](crate::HudhookBuilder::with_hook_retry).
+    RendererNotLoaded,
+}
+
+impl fmt::Display for HudhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HudhookError::MinHook(status) => write!(f, "minhook error: {status:?}"),
+            HudhookError::DeviceCreation(e) => write!(f, "couldn't create dummy device: {e}"),
+            HudhookError::ShaderCompile(msg) => write!(f, "couldn't compile shader: {msg}"),
+            HudhookError::PresentAddressNotFound => {
+                write!(f, "couldn't find the address of the present function")
+            },
+            HudhookError::WindowNotFound => write!(f, "couldn't create dummy window"),
+            HudhookError::RendererNotLoaded => {
+                write!(f, "the application didn't load its graphics library in time")
+            },
+        }
+    }
+}
+
+impl std::error::Error for HudhookError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HudhookError::DeviceCreation(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MH_STATUS> for HudhookError {
+    fn from(status: MH_STATUS) -> Self {
+        HudhookError::MinHook(status)
+    }
+}
+
+/// Result type of the fallible [`hudhook`](crate) setup functions.
+pub type Result<T, E = HudhookError> = std::result::Result<T, E>;
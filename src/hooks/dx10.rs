@@ -0,0 +1,235 @@
+//! Hooks for DirectX 10.
+
+use std::ffi::c_void;
+use std::mem;
+use std::sync::OnceLock;
+use std::thread;
+
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tracing::{error, trace};
+use windows::core::{w, Error, Interface, Result, HRESULT};
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::Graphics::Direct3D10::{
+    D3D10CreateDeviceAndSwapChain, ID3D10Device, ID3D10Texture2D, D3D10_DRIVER_TYPE_NULL,
+    D3D10_SDK_VERSION,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED,
+    DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_SAMPLE_DESC,
+};
+use windows::Win32::Graphics::Dxgi::{
+    IDXGISwapChain, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+};
+
+use super::{is_module_loaded, select_swap_chain, swap_chain_hwnd, DummyHwnd};
+use crate::diagnostics::{self, DiagnosticEvent};
+use crate::mh::MhHook;
+use crate::renderer::config::create_context;
+use crate::renderer::{D3D10RenderEngine, Pipeline};
+use crate::{util, Hooks, HudhookError, ImguiRenderLoop};
+
+type DXGISwapChainPresentType =
+    unsafe extern "system" fn(This: IDXGISwapChain, SyncInterval: u32, Flags: u32) -> HRESULT;
+
+struct Trampolines {
+    dxgi_swap_chain_present: DXGISwapChainPresentType,
+}
+
+static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
+static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D10RenderEngine>>> = OnceCell::new();
+static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
+
+unsafe fn init_pipeline(swap_chain: &IDXGISwapChain) -> Result<Mutex<Pipeline<D3D10RenderEngine>>> {
+    let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
+
+    let mut ctx = create_context()?;
+    let engine = D3D10RenderEngine::new(swap_chain, &mut ctx)?;
+
+    let Some(render_loop) = RENDER_LOOP.take() else {
+        error!("Render loop not yet initialized");
+        return Err(Error::from_hresult(HRESULT(-1)));
+    };
+
+    let pipeline = Pipeline::new(hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
+        RENDER_LOOP.get_or_init(move || render_loop);
+        e
+    })?;
+    diagnostics::emit(DiagnosticEvent::RendererInitialized("DirectX 10"));
+
+    Ok(Mutex::new(pipeline))
+}
+
+fn render(swap_chain: &IDXGISwapChain) -> Result<()> {
+    diagnostics::present();
+
+    unsafe {
+        let pipeline = match PIPELINE.get() {
+            Some(pipeline) => pipeline,
+            None if select_swap_chain(swap_chain) => {
+                PIPELINE.get_or_try_init(|| init_pipeline(swap_chain))?
+            },
+            None => return Ok(()),
+        };
+
+        let Some(mut pipeline) = pipeline.try_lock() else {
+            error!("Could not lock pipeline");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        // Leave the application's other swap chains alone.
+        if swap_chain_hwnd(swap_chain) != Some(pipeline.hwnd()) {
+            return Ok(());
+        }
+
+        pipeline.prepare_render()?;
+
+        let target: ID3D10Texture2D = swap_chain.GetBuffer(0)?;
+
+        pipeline.render(target)?;
+    }
+    Ok(())
+}
+
+unsafe extern "system" fn dxgi_swap_chain_present_impl(
+    swap_chain: IDXGISwapChain,
+    sync_interval: u32,
+    flags: u32,
+) -> HRESULT {
+    let Trampolines { dxgi_swap_chain_present } =
+        TRAMPOLINES.get().expect("DirectX 10 trampolines uninitialized");
+
+    if let Err(e) = render(&swap_chain) {
+        diagnostics::render_error(&e);
+    }
+
+    trace!("Call IDXGISwapChain::Present trampoline");
+    dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+}
+
+fn get_target_addrs() -> crate::Result<DXGISwapChainPresentType> {
+    let mut p_device: Option<ID3D10Device> = None;
+    let mut p_swap_chain: Option<IDXGISwapChain> = None;
+
+    let dummy_hwnd = DummyHwnd::try_new()?;
+    unsafe {
+        D3D10CreateDeviceAndSwapChain(
+            None,
+            D3D10_DRIVER_TYPE_NULL,
+            None,
+            0,
+            D3D10_SDK_VERSION,
+            Some(&DXGI_SWAP_CHAIN_DESC {
+                BufferDesc: DXGI_MODE_DESC {
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    ScanlineOrdering: DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED,
+                    Scaling: DXGI_MODE_SCALING_UNSPECIFIED,
+                    ..Default::default()
+                },
+                BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+                BufferCount: 1,
+                OutputWindow: dummy_hwnd.hwnd(),
+                Windowed: BOOL(1),
+                SwapEffect: DXGI_SWAP_EFFECT_DISCARD,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, ..Default::default() },
+                ..Default::default()
+            }),
+            Some(&mut p_swap_chain),
+            Some(&mut p_device),
+        )
+        .map_err(HudhookError::DeviceCreation)?;
+    }
+
+    let Some(swap_chain) = p_swap_chain else {
+        error!("D3D10CreateDeviceAndSwapChain returned no swap chain");
+        return Err(HudhookError::DeviceCreation(Error::from_hresult(HRESULT(-1))));
+    };
+
+    let present_ptr: DXGISwapChainPresentType = unsafe {
+        mem::transmute::<
+            unsafe extern "system" fn(*mut c_void, u32, u32) -> HRESULT,
+            DXGISwapChainPresentType,
+        >(swap_chain.vtable().Present)
+    };
+
+    Ok(present_ptr)
+}
+
+/// Hooks for DirectX 10.
+///
+/// DirectX 10 and DirectX 11 present through the same `IDXGISwapChain`, so
+/// only one of [`ImguiDx10Hooks`] and
+/// [`ImguiDx11Hooks`](crate::hooks::dx11::ImguiDx11Hooks) should be used at a
+/// time.
+pub struct ImguiDx10Hooks([MhHook; 1]);
+
+impl ImguiDx10Hooks {
+    /// Construct a set of [`MhHook`]s that will render UI via the
+    /// provided [`ImguiRenderLoop`].
+    ///
+    /// The following functions are hooked:
+    /// - `IDXGISwapChain::Present`
+    ///
+    /// The shaders start compiling on a background thread right away, so that
+    /// the first frame doesn't stall on it. If the first frame comes before
+    /// the compilation is done, it waits for it instead of compiling again.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the functions to hook can't be found or the hooks can't be
+    /// created.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn new<T>(t: T) -> crate::Result<Self>
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        let dxgi_swap_chain_present_addr = get_target_addrs()?;
+
+        thread::spawn(D3D10RenderEngine::prewarm);
+
+        trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
+        let hook_present = MhHook::new(
+            dxgi_swap_chain_present_addr as *mut _,
+            dxgi_swap_chain_present_impl as *mut _,
+        )?;
+
+        RENDER_LOOP.get_or_init(|| Box::new(t));
+        TRAMPOLINES.get_or_init(|| Trampolines {
+            dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
+                hook_present.trampoline(),
+            ),
+        });
+
+        Ok(Self([hook_present]))
+    }
+}
+
+impl Hooks for ImguiDx10Hooks {
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        unsafe { Self::new(t) }.map(Box::new)
+    }
+
+    fn is_renderer_loaded() -> bool
+    where
+        Self: Sized,
+    {
+        is_module_loaded(w!("d3d10.dll")) || is_module_loaded(w!("d3d10_1.dll"))
+    }
+
+    fn hooks(&self) -> &[MhHook] {
+        &self.0
+    }
+
+    unsafe fn unhook(&mut self) {
+        TRAMPOLINES.take();
+        PIPELINE.take().map(|p| p.into_inner().take());
+        RENDER_LOOP.take(); // should already be null
+    }
+}
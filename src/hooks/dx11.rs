@@ -3,13 +3,13 @@
 use std::ffi::c_void;
 use std::mem;
 use std::sync::OnceLock;
+use std::thread;
 
-use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{error, trace};
-use windows::core::{Error, Interface, Result, HRESULT};
-use windows::Win32::Foundation::BOOL;
+use windows::core::{w, Error, Interface, Result, HRESULT};
+use windows::Win32::Foundation::{BOOL, HWND, S_OK};
 use windows::Win32::Graphics::Direct3D::{
     D3D_DRIVER_TYPE_NULL, D3D_FEATURE_LEVEL_10_0, D3D_FEATURE_LEVEL_11_0,
 };
@@ -18,23 +18,44 @@ use windows::Win32::Graphics::Direct3D11::{
     D3D11_CREATE_DEVICE_FLAG, D3D11_SDK_VERSION,
 };
 use windows::Win32::Graphics::Dxgi::Common::{
-    DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED,
+    DXGI_FORMAT, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED,
     DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    IDXGISwapChain, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+    IDXGISwapChain, IDXGISwapChain1, DXGI_PRESENT_PARAMETERS, DXGI_SWAP_CHAIN_DESC,
+    DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
 
-use super::DummyHwnd;
+use super::{is_module_loaded, select_swap_chain, swap_chain_hwnd, DummyHwnd};
+use crate::diagnostics::{self, DiagnosticEvent};
 use crate::mh::MhHook;
+use crate::renderer::config::create_context;
 use crate::renderer::{D3D11RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::{util, Hooks, HudhookError, ImguiRenderLoop, PresentAction};
 
 type DXGISwapChainPresentType =
     unsafe extern "system" fn(This: IDXGISwapChain, SyncInterval: u32, Flags: u32) -> HRESULT;
 
+type DXGISwapChain1Present1Type = unsafe extern "system" fn(
+    This: IDXGISwapChain1,
+    SyncInterval: u32,
+    PresentFlags: u32,
+    pPresentParameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT;
+
+type DXGISwapChainResizeBuffersType = unsafe extern "system" fn(
+    This: IDXGISwapChain,
+    buffer_count: u32,
+    width: u32,
+    height: u32,
+    new_format: DXGI_FORMAT,
+    flags: u32,
+) -> HRESULT;
+
 struct Trampolines {
     dxgi_swap_chain_present: DXGISwapChainPresentType,
+    dxgi_swap_chain1_present1: DXGISwapChain1Present1Type,
+    dxgi_swap_chain_resize_buffers: DXGISwapChainResizeBuffersType,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
@@ -44,8 +65,8 @@ static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceC
 unsafe fn init_pipeline(swap_chain: &IDXGISwapChain) -> Result<Mutex<Pipeline<D3D11RenderEngine>>> {
     let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
 
-    let mut ctx = Context::create();
-    let engine = D3D11RenderEngine::new(&swap_chain.GetDevice()?, &mut ctx)?;
+    let mut ctx = create_context()?;
+    let engine = D3D11RenderEngine::new(swap_chain, &mut ctx)?;
 
     let Some(render_loop) = RENDER_LOOP.take() else {
         error!("Render loop not yet initialized");
@@ -56,19 +77,33 @@ unsafe fn init_pipeline(swap_chain: &IDXGISwapChain) -> Result<Mutex<Pipeline<D3
         RENDER_LOOP.get_or_init(move || render_loop);
         e
     })?;
+    diagnostics::emit(DiagnosticEvent::RendererInitialized("DirectX 11"));
 
     Ok(Mutex::new(pipeline))
 }
 
 fn render(swap_chain: &IDXGISwapChain) -> Result<()> {
+    diagnostics::present();
+
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(swap_chain))?;
+        let pipeline = match PIPELINE.get() {
+            Some(pipeline) => pipeline,
+            None if select_swap_chain(swap_chain) => {
+                PIPELINE.get_or_try_init(|| init_pipeline(swap_chain))?
+            },
+            None => return Ok(()),
+        };
 
         let Some(mut pipeline) = pipeline.try_lock() else {
             error!("Could not lock pipeline");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
+        // Leave the application's other swap chains alone.
+        if swap_chain_hwnd(swap_chain) != Some(pipeline.hwnd()) {
+            return Ok(());
+        }
+
         pipeline.prepare_render()?;
 
         let target: ID3D11Texture2D = swap_chain.GetBuffer(0)?;
@@ -78,28 +113,112 @@ fn render(swap_chain: &IDXGISwapChain) -> Result<()> {
     Ok(())
 }
 
+// Call the original present function, or not, as the render loop decides if
+// the swap chain presents to its window.
+fn present(
+    hwnd: Option<HWND>,
+    sync_interval: u32,
+    flags: u32,
+    call_original: impl FnOnce() -> HRESULT,
+) -> HRESULT {
+    let action = unsafe { PIPELINE.get() }
+        .and_then(|pipeline| pipeline.try_lock())
+        .filter(|pipeline| hwnd == Some(pipeline.hwnd()))
+        .map_or(PresentAction::CallOriginal, |mut pipeline| {
+            pipeline.on_present(sync_interval, flags)
+        });
+
+    match action {
+        PresentAction::CallOriginal => call_original(),
+        PresentAction::Skip => S_OK,
+        PresentAction::Override(result) => {
+            let original = call_original();
+            trace!("Override present result {original:?} with {result:?}");
+            result
+        },
+    }
+}
+
 unsafe extern "system" fn dxgi_swap_chain_present_impl(
     swap_chain: IDXGISwapChain,
     sync_interval: u32,
     flags: u32,
 ) -> HRESULT {
-    let Trampolines { dxgi_swap_chain_present } =
+    let Trampolines { dxgi_swap_chain_present, .. } =
+        TRAMPOLINES.get().expect("DirectX 11 trampolines uninitialized");
+
+    if let Err(e) = render(&swap_chain) {
+        diagnostics::render_error(&e);
+    }
+
+    present(swap_chain_hwnd(&swap_chain), sync_interval, flags, || {
+        trace!("Call IDXGISwapChain::Present trampoline");
+        dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+    })
+}
+
+unsafe extern "system" fn dxgi_swap_chain1_present1_impl(
+    swap_chain: IDXGISwapChain1,
+    sync_interval: u32,
+    flags: u32,
+    present_parameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT {
+    let Trampolines { dxgi_swap_chain1_present1, .. } =
         TRAMPOLINES.get().expect("DirectX 11 trampolines uninitialized");
 
     if let Err(e) = render(&swap_chain) {
-        error!("Render error: {e:?}");
+        diagnostics::render_error(&e);
     }
 
-    trace!("Call IDXGISwapChain::Present trampoline");
-    dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+    present(swap_chain_hwnd(&swap_chain), sync_interval, flags, || {
+        trace!("Call IDXGISwapChain1::Present1 trampoline");
+        dxgi_swap_chain1_present1(swap_chain, sync_interval, flags, present_parameters)
+    })
 }
 
-fn get_target_addrs() -> DXGISwapChainPresentType {
+unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
+    p_this: IDXGISwapChain,
+    buffer_count: u32,
+    width: u32,
+    height: u32,
+    new_format: DXGI_FORMAT,
+    flags: u32,
+) -> HRESULT {
+    let Trampolines { dxgi_swap_chain_resize_buffers, .. } =
+        TRAMPOLINES.get().expect("DirectX 11 trampolines uninitialized");
+
+    // The back buffers can only be resized once all the references to them
+    // are released, so wait for the frame being rendered, if any, to finish.
+    let pipeline = PIPELINE.get().map(|pipeline| pipeline.lock());
+    let swap_chain = p_this.clone();
+
+    trace!("Call IDXGISwapChain::ResizeBuffers trampoline");
+    let result =
+        dxgi_swap_chain_resize_buffers(p_this, buffer_count, width, height, new_format, flags);
+
+    if let (true, Some(mut pipeline)) = (result.is_ok(), pipeline) {
+        match util::try_out_param(|v| swap_chain.GetDesc(v)) {
+            Ok(desc) if desc.OutputWindow == pipeline.hwnd() => {
+                pipeline.resize(desc.BufferDesc.Width, desc.BufferDesc.Height)
+            },
+            Ok(_) => {},
+            Err(e) => error!("Could not get the resized swap chain's description: {e:?}"),
+        }
+    }
+
+    result
+}
+
+fn get_target_addrs() -> crate::Result<(
+    DXGISwapChainPresentType,
+    DXGISwapChain1Present1Type,
+    DXGISwapChainResizeBuffersType,
+)> {
     let mut p_device: Option<ID3D11Device> = None;
     let mut p_context: Option<ID3D11DeviceContext> = None;
     let mut p_swap_chain: Option<IDXGISwapChain> = None;
 
-    let dummy_hwnd = DummyHwnd::new();
+    let dummy_hwnd = DummyHwnd::try_new()?;
     unsafe {
         D3D11CreateDeviceAndSwapChain(
             None,
@@ -128,10 +247,13 @@ fn get_target_addrs() -> DXGISwapChainPresentType {
             None,
             Some(&mut p_context),
         )
-        .expect("D3D11CreateDeviceAndSwapChain failed");
+        .map_err(HudhookError::DeviceCreation)?;
     }
 
-    let swap_chain = p_swap_chain.unwrap();
+    let Some(swap_chain) = p_swap_chain else {
+        error!("D3D11CreateDeviceAndSwapChain returned no swap chain");
+        return Err(HudhookError::DeviceCreation(Error::from_hresult(HRESULT(-1))));
+    };
 
     let present_ptr: DXGISwapChainPresentType = unsafe {
         mem::transmute::<
@@ -139,12 +261,17 @@ fn get_target_addrs() -> DXGISwapChainPresentType {
             DXGISwapChainPresentType,
         >(swap_chain.vtable().Present)
     };
+    let swap_chain1: IDXGISwapChain1 = swap_chain.cast().map_err(HudhookError::DeviceCreation)?;
+    let present1_ptr: DXGISwapChain1Present1Type =
+        unsafe { mem::transmute(swap_chain1.vtable().Present1) };
+    let resize_buffers_ptr: DXGISwapChainResizeBuffersType =
+        unsafe { mem::transmute(swap_chain.vtable().ResizeBuffers) };
 
-    present_ptr
+    Ok((present_ptr, present1_ptr, resize_buffers_ptr))
 }
 
 /// Hooks for DirectX 11.
-pub struct ImguiDx11Hooks([MhHook; 1]);
+pub struct ImguiDx11Hooks([MhHook; 3]);
 
 impl ImguiDx11Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -152,41 +279,86 @@ impl ImguiDx11Hooks {
     ///
     /// The following functions are hooked:
     /// - `IDXGISwapChain::Present`
+    /// - `IDXGISwapChain1::Present1`
+    /// - `IDXGISwapChain::ResizeBuffers`
+    ///
+    /// The overlay is rendered the same way whichever of `Present` and
+    /// `Present1` the application presents with.
+    ///
+    /// The display size follows the size of the back buffers whenever the
+    /// application resizes them, e.g. when switching to fullscreen.
+    ///
+    /// The shaders start compiling on a background thread right away, so that
+    /// the first frame doesn't stall on it. If the first frame comes before
+    /// the compilation is done, it waits for it instead of compiling again.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the functions to hook can't be found or the hooks can't be
+    /// created.
     ///
     /// # Safety
     ///
     /// yolo
-    pub unsafe fn new<T>(t: T) -> Self
+    pub unsafe fn new<T>(t: T) -> crate::Result<Self>
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let dxgi_swap_chain_present_addr = get_target_addrs();
+        let (
+            dxgi_swap_chain_present_addr,
+            dxgi_swap_chain1_present1_addr,
+            dxgi_swap_chain_resize_buffers_addr,
+        ) = get_target_addrs()?;
+
+        thread::spawn(D3D11RenderEngine::prewarm);
 
         trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
         let hook_present = MhHook::new(
             dxgi_swap_chain_present_addr as *mut _,
             dxgi_swap_chain_present_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::Present hook");
+        )?;
+        trace!("IDXGISwapChain1::Present1 = {:p}", dxgi_swap_chain1_present1_addr as *const c_void);
+        let hook_present1 = MhHook::new(
+            dxgi_swap_chain1_present1_addr as *mut _,
+            dxgi_swap_chain1_present1_impl as *mut _,
+        )?;
+        let hook_resize_buffers = MhHook::new(
+            dxgi_swap_chain_resize_buffers_addr as *mut _,
+            dxgi_swap_chain_resize_buffers_impl as *mut _,
+        )?;
 
         RENDER_LOOP.get_or_init(|| Box::new(t));
         TRAMPOLINES.get_or_init(|| Trampolines {
             dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
                 hook_present.trampoline(),
             ),
+            dxgi_swap_chain1_present1: mem::transmute::<*mut c_void, DXGISwapChain1Present1Type>(
+                hook_present1.trampoline(),
+            ),
+            dxgi_swap_chain_resize_buffers: mem::transmute::<
+                *mut c_void,
+                DXGISwapChainResizeBuffersType,
+            >(hook_resize_buffers.trampoline()),
         });
 
-        Self([hook_present])
+        Ok(Self([hook_present, hook_present1, hook_resize_buffers]))
     }
 }
 
 impl Hooks for ImguiDx11Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { Self::new(t) })
+        unsafe { Self::new(t) }.map(Box::new)
+    }
+
+    fn is_renderer_loaded() -> bool
+    where
+        Self: Sized,
+    {
+        is_module_loaded(w!("d3d11.dll"))
     }
 
     fn hooks(&self) -> &[MhHook] {
@@ -2,14 +2,15 @@
 
 use std::ffi::c_void;
 use std::mem;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::OnceLock;
+use std::thread;
 
-use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
-use tracing::{debug, error, trace, warn};
-use windows::core::{Error, Interface, Result, HRESULT};
-use windows::Win32::Foundation::BOOL;
+use tracing::{debug, error, info, trace, warn};
+use windows::core::{w, Error, Interface, Result, HRESULT};
+use windows::Win32::Foundation::{BOOL, HWND, S_OK};
 use windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0;
 use windows::Win32::Graphics::Direct3D12::{
     D3D12CreateDevice, ID3D12CommandList, ID3D12CommandQueue, ID3D12Device, ID3D12Resource,
@@ -20,19 +21,28 @@ use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL, DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    CreateDXGIFactory2, IDXGIFactory2, IDXGISwapChain, IDXGISwapChain3, DXGI_SWAP_CHAIN_DESC,
-    DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH, DXGI_SWAP_EFFECT_FLIP_DISCARD,
-    DXGI_USAGE_RENDER_TARGET_OUTPUT,
+    CreateDXGIFactory2, IDXGIFactory2, IDXGISwapChain, IDXGISwapChain1, IDXGISwapChain3,
+    DXGI_PRESENT_PARAMETERS, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH,
+    DXGI_SWAP_EFFECT_FLIP_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
 
-use super::DummyHwnd;
+use super::{is_module_loaded, select_swap_chain, swap_chain_hwnd, DummyHwnd};
+use crate::diagnostics::{self, DiagnosticEvent};
 use crate::mh::MhHook;
+use crate::renderer::config::{create_context, PIPELINE_CONFIG};
 use crate::renderer::{D3D12RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::{util, Dx12CommandQueue, Hooks, HudhookError, ImguiRenderLoop, PresentAction};
 
 type DXGISwapChainPresentType =
     unsafe extern "system" fn(This: IDXGISwapChain3, SyncInterval: u32, Flags: u32) -> HRESULT;
 
+type DXGISwapChain1Present1Type = unsafe extern "system" fn(
+    This: IDXGISwapChain1,
+    SyncInterval: u32,
+    PresentFlags: u32,
+    pPresentParameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT;
+
 type DXGISwapChainResizeBuffersType = unsafe extern "system" fn(
     This: IDXGISwapChain3,
     buffer_count: u32,
@@ -50,6 +60,7 @@ type D3D12CommandQueueExecuteCommandListsType = unsafe extern "system" fn(
 
 struct Trampolines {
     dxgi_swap_chain_present: DXGISwapChainPresentType,
+    dxgi_swap_chain1_present1: DXGISwapChain1Present1Type,
     dxgi_swap_chain_resize_buffers: DXGISwapChainResizeBuffersType,
     d3d12_command_queue_execute_command_lists: D3D12CommandQueueExecuteCommandListsType,
 }
@@ -58,7 +69,8 @@ static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
 
 enum InitializationContext {
     Empty,
-    WithSwapChain(IDXGISwapChain3),
+    // The swap chain, and the queues known not to be the one it presents on.
+    WithSwapChain(IDXGISwapChain3, Vec<usize>),
     Complete(IDXGISwapChain3, ID3D12CommandQueue),
     Done,
 }
@@ -68,25 +80,36 @@ impl InitializationContext {
     fn insert_swap_chain(&mut self, swap_chain: &IDXGISwapChain3) {
         *self = match mem::replace(self, InitializationContext::Empty) {
             InitializationContext::Empty => {
-                InitializationContext::WithSwapChain(swap_chain.clone())
+                InitializationContext::WithSwapChain(swap_chain.clone(), Vec::new())
             },
             s => s,
         }
     }
 
     // Transition to a complete state if the swap chain is set and the command queue
-    // is associated with it.
+    // is a direct queue associated with it.
     fn insert_command_queue(&mut self, command_queue: &ID3D12CommandQueue) {
         *self = match mem::replace(self, InitializationContext::Empty) {
-            InitializationContext::WithSwapChain(swap_chain) => {
-                if unsafe { Self::check_command_queue(&swap_chain, command_queue) } {
-                    trace!(
+            // Each queue only needs to be scanned once.
+            InitializationContext::WithSwapChain(swap_chain, rejected)
+                if rejected.contains(&(command_queue.as_raw() as usize)) =>
+            {
+                InitializationContext::WithSwapChain(swap_chain, rejected)
+            },
+            InitializationContext::WithSwapChain(swap_chain, mut rejected) => {
+                // Only direct queues can present, so skip the scan for compute and copy ones.
+                let is_direct =
+                    unsafe { command_queue.GetDesc() }.Type == D3D12_COMMAND_LIST_TYPE_DIRECT;
+
+                if is_direct && unsafe { Self::check_command_queue(&swap_chain, command_queue) } {
+                    info!(
                         "Found command queue matching swap chain {swap_chain:?} at \
                          {command_queue:?}"
                     );
                     InitializationContext::Complete(swap_chain, command_queue.clone())
                 } else {
-                    InitializationContext::WithSwapChain(swap_chain)
+                    rejected.push(command_queue.as_raw() as usize);
+                    InitializationContext::WithSwapChain(swap_chain, rejected)
                 }
             },
             s => s,
@@ -104,9 +127,7 @@ impl InitializationContext {
 
     // Mark the context as done so no further operations are executed on it.
     fn done(&mut self) {
-        if let InitializationContext::Complete(..) = self {
-            *self = InitializationContext::Done;
-        }
+        *self = InitializationContext::Done;
     }
 
     unsafe fn check_command_queue(
@@ -138,19 +159,38 @@ impl InitializationContext {
 
 static INITIALIZATION_CONTEXT: Mutex<InitializationContext> =
     Mutex::new(InitializationContext::Empty);
+static PRESENTS_WITHOUT_QUEUE: AtomicU32 = AtomicU32::new(0);
 static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D12RenderEngine>>> = OnceCell::new();
 static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
 
-unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
-    let Some((swap_chain, command_queue)) = ({ INITIALIZATION_CONTEXT.lock().get() }) else {
-        error!("Initialization context incomplete");
-        return Err(Error::from_hresult(HRESULT(-1)));
+unsafe fn init_pipeline(
+    presenting_swap_chain: &IDXGISwapChain3,
+) -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
+    let command_queue_mode = PIPELINE_CONFIG.lock().dx12_command_queue;
+
+    let captured = INITIALIZATION_CONTEXT.lock().get();
+    let (swap_chain, command_queue) = match captured {
+        Some((swap_chain, command_queue)) => {
+            info!("Rendering on the application's command queue {command_queue:?}");
+            (swap_chain, command_queue)
+        },
+        None if command_queue_mode == Dx12CommandQueue::CapturedOrOwn => {
+            warn!(
+                "The application's command queue wasn't captured before the first present, \
+                 rendering on a new command queue"
+            );
+            (presenting_swap_chain.clone(), create_command_queue(presenting_swap_chain)?)
+        },
+        None => {
+            error!("The application's command queue hasn't been captured yet");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        },
     };
 
     let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
 
-    let mut ctx = Context::create();
-    let engine = D3D12RenderEngine::new(&command_queue, &mut ctx)?;
+    let mut ctx = create_context()?;
+    let engine = D3D12RenderEngine::new(&swap_chain.cast()?, &command_queue, &mut ctx)?;
 
     let Some(render_loop) = RENDER_LOOP.take() else {
         error!("Render loop not yet initialized");
@@ -161,6 +201,7 @@ unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
         RENDER_LOOP.get_or_init(move || render_loop);
         e
     })?;
+    diagnostics::emit(DiagnosticEvent::RendererInitialized("DirectX 12"));
 
     {
         INITIALIZATION_CONTEXT.lock().done();
@@ -169,15 +210,83 @@ unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
     Ok(Mutex::new(pipeline))
 }
 
+// How many presents to wait for the application's command queue to be
+// captured, about five seconds at 60 FPS, before warning that it may never be.
+const QUEUE_CAPTURE_PRESENTS: u32 = 300;
+
+// Whether the pipeline can be set up, because the application's command queue
+// has been captured or the overlay may render on a queue of its own. Logs why
+// nothing is drawn otherwise, once rather than on every present.
+fn is_command_queue_ready() -> bool {
+    if PIPELINE_CONFIG.lock().dx12_command_queue == Dx12CommandQueue::CapturedOrOwn {
+        return true;
+    }
+
+    let rejected = match &*INITIALIZATION_CONTEXT.lock() {
+        InitializationContext::Complete(..) => return true,
+        InitializationContext::WithSwapChain(_, rejected) => rejected.len(),
+        _ => 0,
+    };
+
+    let presents = PRESENTS_WITHOUT_QUEUE.fetch_add(1, Ordering::Relaxed) + 1;
+    if presents == 1 {
+        info!(
+            "Waiting for the application to execute command lists on the queue it presents on \
+             before rendering"
+        );
+    } else if presents == QUEUE_CAPTURE_PRESENTS {
+        warn!(
+            "The application's command queue still hasn't been captured after {presents} \
+             presents, and nothing is drawn until it is. {rejected} command queues were found \
+             not to belong to the swap chain. If the overlay never shows up, try \
+             `HudhookBuilder::with_dx12_command_queue(Dx12CommandQueue::CapturedOrOwn)`"
+        );
+    }
+
+    false
+}
+
+// Create a direct command queue on the device of the swap chain, for when the
+// application's one couldn't be captured.
+unsafe fn create_command_queue(swap_chain: &IDXGISwapChain3) -> Result<ID3D12CommandQueue> {
+    let device: ID3D12Device = swap_chain.GetDevice()?;
+
+    device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
+        Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
+        Priority: 0,
+        Flags: D3D12_COMMAND_QUEUE_FLAG_NONE,
+        NodeMask: 0,
+    })
+}
+
 fn render(swap_chain: &IDXGISwapChain3) -> Result<()> {
+    diagnostics::present();
+
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline())?;
+        let pipeline = match PIPELINE.get() {
+            Some(pipeline) => pipeline,
+            None if select_swap_chain(swap_chain) => {
+                {
+                    INITIALIZATION_CONTEXT.lock().insert_swap_chain(swap_chain);
+                }
+                if !is_command_queue_ready() {
+                    return Ok(());
+                }
+                PIPELINE.get_or_try_init(|| init_pipeline(swap_chain))?
+            },
+            None => return Ok(()),
+        };
 
         let Some(mut pipeline) = pipeline.try_lock() else {
             error!("Could not lock pipeline");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
+        // Leave the application's other swap chains alone.
+        if swap_chain_hwnd(swap_chain) != Some(pipeline.hwnd()) {
+            return Ok(());
+        }
+
         pipeline.prepare_render()?;
 
         let target: ID3D12Resource =
@@ -189,25 +298,75 @@ fn render(swap_chain: &IDXGISwapChain3) -> Result<()> {
     Ok(())
 }
 
-unsafe extern "system" fn dxgi_swap_chain_present_impl(
-    swap_chain: IDXGISwapChain3,
+// Call the original present function, or not, as the render loop decides if
+// the swap chain presents to its window.
+fn present(
+    hwnd: Option<HWND>,
     sync_interval: u32,
     flags: u32,
+    call_original: impl FnOnce() -> HRESULT,
 ) -> HRESULT {
-    {
-        INITIALIZATION_CONTEXT.lock().insert_swap_chain(&swap_chain);
+    let action = unsafe { PIPELINE.get() }
+        .and_then(|pipeline| pipeline.try_lock())
+        .filter(|pipeline| hwnd == Some(pipeline.hwnd()))
+        .map_or(PresentAction::CallOriginal, |mut pipeline| {
+            pipeline.on_present(sync_interval, flags)
+        });
+
+    match action {
+        PresentAction::CallOriginal => call_original(),
+        PresentAction::Skip => S_OK,
+        PresentAction::Override(result) => {
+            let original = call_original();
+            trace!("Override present result {original:?} with {result:?}");
+            result
+        },
     }
+}
 
+unsafe extern "system" fn dxgi_swap_chain_present_impl(
+    swap_chain: IDXGISwapChain3,
+    sync_interval: u32,
+    flags: u32,
+) -> HRESULT {
     let Trampolines { dxgi_swap_chain_present, .. } =
         TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
 
     if let Err(e) = render(&swap_chain) {
         util::print_dxgi_debug_messages();
-        error!("Render error: {e:?}");
+        diagnostics::render_error(&e);
+    }
+
+    present(swap_chain_hwnd(&swap_chain), sync_interval, flags, || {
+        trace!("Call IDXGISwapChain::Present trampoline");
+        dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+    })
+}
+
+unsafe extern "system" fn dxgi_swap_chain1_present1_impl(
+    swap_chain: IDXGISwapChain1,
+    sync_interval: u32,
+    flags: u32,
+    present_parameters: *const DXGI_PRESENT_PARAMETERS,
+) -> HRESULT {
+    let Trampolines { dxgi_swap_chain1_present1, .. } =
+        TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
+
+    // Swap chains created on a DirectX 12 queue are always `IDXGISwapChain3`.
+    match swap_chain.cast::<IDXGISwapChain3>() {
+        Ok(swap_chain3) => {
+            if let Err(e) = render(&swap_chain3) {
+                util::print_dxgi_debug_messages();
+                diagnostics::render_error(&e);
+            }
+        },
+        Err(e) => error!("Could not get IDXGISwapChain3: {e:?}"),
     }
 
-    trace!("Call IDXGISwapChain::Present trampoline");
-    dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+    present(swap_chain_hwnd(&swap_chain), sync_interval, flags, || {
+        trace!("Call IDXGISwapChain1::Present1 trampoline");
+        dxgi_swap_chain1_present1(swap_chain, sync_interval, flags, present_parameters)
+    })
 }
 
 unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
@@ -221,8 +380,26 @@ unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
     let Trampolines { dxgi_swap_chain_resize_buffers, .. } =
         TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
 
+    // The back buffers can only be resized once all the references to them
+    // are released, so wait for the frame being rendered, if any, to finish.
+    let pipeline = PIPELINE.get().map(|pipeline| pipeline.lock());
+    let swap_chain = p_this.clone();
+
     trace!("Call IDXGISwapChain::ResizeBuffers trampoline");
-    dxgi_swap_chain_resize_buffers(p_this, buffer_count, width, height, new_format, flags)
+    let result =
+        dxgi_swap_chain_resize_buffers(p_this, buffer_count, width, height, new_format, flags);
+
+    if let (true, Some(mut pipeline)) = (result.is_ok(), pipeline) {
+        match util::try_out_param(|v| swap_chain.GetDesc(v)) {
+            Ok(desc) if desc.OutputWindow == pipeline.hwnd() => {
+                pipeline.resize(desc.BufferDesc.Width, desc.BufferDesc.Height)
+            },
+            Ok(_) => {},
+            Err(e) => error!("Could not get the resized swap chain's description: {e:?}"),
+        }
+    }
+
+    result
 }
 
 unsafe extern "system" fn d3d12_command_queue_execute_command_lists_impl(
@@ -245,19 +422,21 @@ unsafe extern "system" fn d3d12_command_queue_execute_command_lists_impl(
     d3d12_command_queue_execute_command_lists(command_queue, num_command_lists, command_lists);
 }
 
-fn get_target_addrs() -> (
+fn get_target_addrs() -> crate::Result<(
     DXGISwapChainPresentType,
+    DXGISwapChain1Present1Type,
     DXGISwapChainResizeBuffersType,
     D3D12CommandQueueExecuteCommandListsType,
-) {
-    let dummy_hwnd = DummyHwnd::new();
+)> {
+    let dummy_hwnd = DummyHwnd::try_new()?;
 
-    let factory: IDXGIFactory2 = unsafe { CreateDXGIFactory2(0) }.unwrap();
-    let adapter = unsafe { factory.EnumAdapters(0) }.unwrap();
+    let factory: IDXGIFactory2 =
+        unsafe { CreateDXGIFactory2(0) }.map_err(HudhookError::DeviceCreation)?;
+    let adapter = unsafe { factory.EnumAdapters(0) }.map_err(HudhookError::DeviceCreation)?;
 
     let device: ID3D12Device =
         util::try_out_ptr(|v| unsafe { D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_11_0, v) })
-            .expect("D3D12CreateDevice failed");
+            .map_err(HudhookError::DeviceCreation)?;
 
     let command_queue: ID3D12CommandQueue = unsafe {
         device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
@@ -267,7 +446,7 @@ fn get_target_addrs() -> (
             NodeMask: 0,
         })
     }
-    .unwrap();
+    .map_err(HudhookError::DeviceCreation)?;
 
     let swap_chain: IDXGISwapChain = match util::try_out_ptr(|v| unsafe {
         factory
@@ -297,22 +476,25 @@ fn get_target_addrs() -> (
         Ok(swap_chain) => swap_chain,
         Err(e) => {
             util::print_dxgi_debug_messages();
-            panic!("{e:?}");
+            return Err(HudhookError::DeviceCreation(e));
         },
     };
 
     let present_ptr: DXGISwapChainPresentType =
         unsafe { mem::transmute(swap_chain.vtable().Present) };
+    let swap_chain1: IDXGISwapChain1 = swap_chain.cast().map_err(HudhookError::DeviceCreation)?;
+    let present1_ptr: DXGISwapChain1Present1Type =
+        unsafe { mem::transmute(swap_chain1.vtable().Present1) };
     let resize_buffers_ptr: DXGISwapChainResizeBuffersType =
         unsafe { mem::transmute(swap_chain.vtable().ResizeBuffers) };
     let cqecl_ptr: D3D12CommandQueueExecuteCommandListsType =
         unsafe { mem::transmute(command_queue.vtable().ExecuteCommandLists) };
 
-    (present_ptr, resize_buffers_ptr, cqecl_ptr)
+    Ok((present_ptr, present1_ptr, resize_buffers_ptr, cqecl_ptr))
 }
 
 /// Hooks for DirectX 12.
-pub struct ImguiDx12Hooks([MhHook; 3]);
+pub struct ImguiDx12Hooks([MhHook; 4]);
 
 impl ImguiDx12Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -320,38 +502,67 @@ impl ImguiDx12Hooks {
     ///
     /// The following functions are hooked:
     /// - `IDXGISwapChain3::Present`
+    /// - `IDXGISwapChain3::Present1`
     /// - `IDXGISwapChain3::ResizeBuffers`
     /// - `ID3D12CommandQueue::ExecuteCommandLists`
     ///
+    /// The overlay is rendered the same way whichever of `Present` and
+    /// `Present1` the application presents with.
+    ///
+    /// The display size follows the size of the back buffers whenever the
+    /// application resizes them, e.g. when switching to fullscreen.
+    ///
+    /// The shaders start compiling on a background thread right away, so that
+    /// the first frame doesn't stall on it. If the first frame comes before
+    /// the compilation is done, it waits for it instead of compiling again.
+    ///
+    /// The overlay renders on the application's command queue, captured in
+    /// `ExecuteCommandLists`, or on its own queue as configured via
+    /// [`HudhookBuilder::with_dx12_command_queue`](crate::HudhookBuilder::with_dx12_command_queue).
+    /// The captured queue is kept alive by the overlay, but it's only valid
+    /// for as long as the application renders with it: if the application
+    /// recreates its device or swap chain, the overlay keeps submitting to
+    /// the stale queue.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the functions to hook can't be found or the hooks can't be
+    /// created.
+    ///
     /// # Safety
     ///
     /// yolo
-    pub unsafe fn new<T>(t: T) -> Self
+    pub unsafe fn new<T>(t: T) -> crate::Result<Self>
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
         let (
             dxgi_swap_chain_present_addr,
+            dxgi_swap_chain1_present1_addr,
             dxgi_swap_chain_resize_buffers_addr,
             d3d12_command_queue_execute_command_lists_addr,
-        ) = get_target_addrs();
+        ) = get_target_addrs()?;
+
+        thread::spawn(D3D12RenderEngine::prewarm);
 
         trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
         let hook_present = MhHook::new(
             dxgi_swap_chain_present_addr as *mut _,
             dxgi_swap_chain_present_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::Present hook");
+        )?;
+        trace!("IDXGISwapChain1::Present1 = {:p}", dxgi_swap_chain1_present1_addr as *const c_void);
+        let hook_present1 = MhHook::new(
+            dxgi_swap_chain1_present1_addr as *mut _,
+            dxgi_swap_chain1_present1_impl as *mut _,
+        )?;
         let hook_resize_buffers = MhHook::new(
             dxgi_swap_chain_resize_buffers_addr as *mut _,
             dxgi_swap_chain_resize_buffers_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::ResizeBuffers hook");
+        )?;
         let hook_cqecl = MhHook::new(
             d3d12_command_queue_execute_command_lists_addr as *mut _,
             d3d12_command_queue_execute_command_lists_impl as *mut _,
-        )
-        .expect("couldn't create ID3D12CommandQueue::ExecuteCommandLists hook");
+        )?;
 
         RENDER_LOOP.get_or_init(|| Box::new(t));
 
@@ -359,6 +570,9 @@ impl ImguiDx12Hooks {
             dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
                 hook_present.trampoline(),
             ),
+            dxgi_swap_chain1_present1: mem::transmute::<*mut c_void, DXGISwapChain1Present1Type>(
+                hook_present1.trampoline(),
+            ),
             dxgi_swap_chain_resize_buffers: mem::transmute::<
                 *mut c_void,
                 DXGISwapChainResizeBuffersType,
@@ -369,17 +583,24 @@ impl ImguiDx12Hooks {
             >(hook_cqecl.trampoline()),
         });
 
-        Self([hook_present, hook_resize_buffers, hook_cqecl])
+        Ok(Self([hook_present, hook_present1, hook_resize_buffers, hook_cqecl]))
     }
 }
 
 impl Hooks for ImguiDx12Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { Self::new(t) })
+        unsafe { Self::new(t) }.map(Box::new)
+    }
+
+    fn is_renderer_loaded() -> bool
+    where
+        Self: Sized,
+    {
+        is_module_loaded(w!("d3d12.dll"))
     }
 
     fn hooks(&self) -> &[MhHook] {
@@ -391,5 +612,6 @@ impl Hooks for ImguiDx12Hooks {
         PIPELINE.take().map(|p| p.into_inner().take());
         RENDER_LOOP.take(); // should already be null
         *INITIALIZATION_CONTEXT.lock() = InitializationContext::Empty;
+        PRESENTS_WITHOUT_QUEUE.store(0, Ordering::Relaxed);
     }
 }
@@ -4,11 +4,10 @@ use std::ffi::c_void;
 use std::mem;
 use std::sync::OnceLock;
 
-use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{error, trace};
-use windows::core::{Error, Interface, Result, HRESULT};
+use windows::core::{w, Error, Interface, Result, HRESULT};
 use windows::Win32::Foundation::{BOOL, HWND, RECT};
 use windows::Win32::Graphics::Direct3D9::{
     Direct3DCreate9, IDirect3DDevice9, D3DADAPTER_DEFAULT, D3DBACKBUFFER_TYPE_MONO,
@@ -17,10 +16,12 @@ use windows::Win32::Graphics::Direct3D9::{
 };
 use windows::Win32::Graphics::Gdi::RGNDATA;
 
-use super::DummyHwnd;
+use super::{is_module_loaded, DummyHwnd};
+use crate::diagnostics::{self, DiagnosticEvent};
 use crate::mh::MhHook;
+use crate::renderer::config::create_context;
 use crate::renderer::{D3D9RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::{util, Hooks, HudhookError, ImguiRenderLoop};
 
 type Dx9PresentType = unsafe extern "system" fn(
     this: IDirect3DDevice9,
@@ -49,7 +50,7 @@ unsafe fn init_pipeline(device: &IDirect3DDevice9) -> Result<Mutex<Pipeline<D3D9
 
     let hwnd = creation_parameters.hFocusWindow;
 
-    let mut ctx = Context::create();
+    let mut ctx = create_context()?;
     trace!("creating engine");
     let engine = D3D9RenderEngine::new(device, &mut ctx)?;
 
@@ -63,10 +64,13 @@ unsafe fn init_pipeline(device: &IDirect3DDevice9) -> Result<Mutex<Pipeline<D3D9
         RENDER_LOOP.get_or_init(move || render_loop);
         e
     })?;
+    diagnostics::emit(DiagnosticEvent::RendererInitialized("DirectX 9"));
     Ok(Mutex::new(pipeline))
 }
 
 fn render(device: &IDirect3DDevice9) -> Result<()> {
+    diagnostics::present();
+
     let pipeline = unsafe { PIPELINE.get_or_try_init(|| init_pipeline(device)) }?;
 
     let Some(mut pipeline) = pipeline.try_lock() else {
@@ -96,7 +100,7 @@ unsafe extern "system" fn dx9_present_impl(
         TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
 
     if let Err(e) = render(&device) {
-        error!("Render error: {e:?}");
+        diagnostics::render_error(&e);
     }
 
     trace!("Call IDirect3DDevice9::Present trampoline");
@@ -109,22 +113,42 @@ unsafe extern "system" fn dx9_reset_impl(
     let Trampolines { dx9_reset, .. } =
         TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
 
-    trace!("Resetting pipeline");
-    if let Some(pipeline) = PIPELINE.take() {
-        let render_loop = pipeline.into_inner().take();
+    trace!("Call IDirect3DDevice9::Reset trampoline");
+    let Some(pipeline) = PIPELINE.get() else {
+        return dx9_reset(this, present_params);
+    };
 
-        RENDER_LOOP.set(render_loop).map_err(|_| ()).expect("Render loop cell should be empty");
+    let mut pipeline = pipeline.lock();
+    let result = pipeline
+        .reset_device(|engine| engine.release_device_objects(), || dx9_reset(this, present_params));
+
+    // The back buffers may have been resized. A size of zero stands for the
+    // client area of the window.
+    if let (true, Some(present_params)) = (result.is_ok(), present_params.as_ref()) {
+        let (width, height) =
+            match (present_params.BackBufferWidth, present_params.BackBufferHeight) {
+                (0, _) | (_, 0) => {
+                    let (width, height) = util::win_size(pipeline.hwnd());
+                    (width as u32, height as u32)
+                },
+                size => size,
+            };
+        pipeline.resize(width, height);
     }
 
-    dx9_reset(this, present_params)
+    result
 }
 
-fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
-    let d9 = unsafe { Direct3DCreate9(D3D_SDK_VERSION).unwrap() };
+fn get_target_addrs() -> crate::Result<(Dx9PresentType, Dx9ResetType)> {
+    let Some(d9) = (unsafe { Direct3DCreate9(D3D_SDK_VERSION) }) else {
+        error!("Direct3DCreate9 failed");
+        return Err(HudhookError::DeviceCreation(Error::from_hresult(HRESULT(-1))));
+    };
 
     let mut d3d_display_mode =
         D3DDISPLAYMODE { Width: 0, Height: 0, RefreshRate: 0, Format: D3DFORMAT(0) };
-    unsafe { d9.GetAdapterDisplayMode(D3DADAPTER_DEFAULT, &mut d3d_display_mode).unwrap() };
+    unsafe { d9.GetAdapterDisplayMode(D3DADAPTER_DEFAULT, &mut d3d_display_mode) }
+        .map_err(HudhookError::DeviceCreation)?;
 
     let mut present_params = D3DPRESENT_PARAMETERS {
         Windowed: BOOL(1),
@@ -133,7 +157,7 @@ fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
         ..Default::default()
     };
 
-    let dummy_hwnd = DummyHwnd::new();
+    let dummy_hwnd = DummyHwnd::try_new()?;
     let device: IDirect3DDevice9 = util::try_out_ptr(|v| {
         unsafe {
             d9.CreateDevice(
@@ -146,13 +170,13 @@ fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
             )
         }
     })
-    .expect("IDirect3DDevice9::CreateDevice: failed to create device");
+    .map_err(HudhookError::DeviceCreation)?;
 
     let present_ptr = device.vtable().Present;
     let reset_ptr = device.vtable().Reset;
 
     unsafe {
-        (
+        Ok((
             mem::transmute::<
                 unsafe extern "system" fn(
                     *mut c_void,
@@ -167,7 +191,7 @@ fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
                 unsafe extern "system" fn(*mut c_void, *mut D3DPRESENT_PARAMETERS) -> HRESULT,
                 Dx9ResetType,
             >(reset_ptr),
-        )
+        ))
     }
 }
 
@@ -181,21 +205,24 @@ impl ImguiDx9Hooks {
     /// The following functions are hooked:
     /// - `IDirect3DDevice9::Present`
     ///
+    /// # Errors
+    ///
+    /// Fails if the functions to hook can't be found or the hooks can't be
+    /// created.
+    ///
     /// # Safety
     ///
     /// yolo
-    pub unsafe fn new<T>(t: T) -> Self
+    pub unsafe fn new<T>(t: T) -> crate::Result<Self>
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let (dx9_present_addr, dx9_reset_addr) = get_target_addrs();
+        let (dx9_present_addr, dx9_reset_addr) = get_target_addrs()?;
 
         trace!("IDirect3DDevice9::Present = {:p}", dx9_present_addr as *const c_void);
         let hook_present =
-            MhHook::new(dx9_present_addr as *mut c_void, dx9_present_impl as *mut c_void)
-                .expect("couldn't create IDirect3DDevice9::Present hook");
-        let hook_reset = MhHook::new(dx9_reset_addr as *mut c_void, dx9_reset_impl as *mut c_void)
-            .expect("couldn't create IDirect3DDevice9::Reset hook");
+            MhHook::new(dx9_present_addr as *mut c_void, dx9_present_impl as *mut c_void)?;
+        let hook_reset = MhHook::new(dx9_reset_addr as *mut c_void, dx9_reset_impl as *mut c_void)?;
 
         RENDER_LOOP.get_or_init(|| Box::new(t));
         TRAMPOLINES.get_or_init(|| Trampolines {
@@ -203,17 +230,24 @@ impl ImguiDx9Hooks {
             dx9_reset: mem::transmute::<*mut c_void, Dx9ResetType>(hook_reset.trampoline()),
         });
 
-        Self([hook_present, hook_reset])
+        Ok(Self([hook_present, hook_reset]))
     }
 }
 
 impl Hooks for ImguiDx9Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { Self::new(t) })
+        unsafe { Self::new(t) }.map(Box::new)
+    }
+
+    fn is_renderer_loaded() -> bool
+    where
+        Self: Sized,
+    {
+        is_module_loaded(w!("d3d9.dll"))
     }
 
     fn hooks(&self) -> &[MhHook] {
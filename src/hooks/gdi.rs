@@ -0,0 +1,252 @@
+//! Hooks for GDI, as a last resort.
+
+use std::ffi::c_void;
+use std::mem;
+use std::sync::OnceLock;
+
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tracing::{error, trace};
+use windows::core::{s, Error, Result, HRESULT};
+use windows::Win32::Foundation::{BOOL, HWND, RECT};
+use windows::Win32::Graphics::Gdi::{
+    IntersectClipRect, RestoreDC, SaveDC, WindowFromDC, HDC, ROP_CODE,
+};
+use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+
+use crate::diagnostics::{self, DiagnosticEvent};
+use crate::mh::MhHook;
+use crate::renderer::config::create_context;
+use crate::renderer::{GdiRenderEngine, Pipeline};
+use crate::{Hooks, HudhookError, ImguiRenderLoop};
+
+type Gdi32BitBltType = unsafe extern "system" fn(
+    hdc: HDC,
+    x: i32,
+    y: i32,
+    cx: i32,
+    cy: i32,
+    hdc_src: HDC,
+    x1: i32,
+    y1: i32,
+    rop: ROP_CODE,
+) -> BOOL;
+
+type Gdi32SwapBuffersType = unsafe extern "system" fn(hdc: HDC) -> BOOL;
+
+struct Trampolines {
+    gdi32_bit_blt: Gdi32BitBltType,
+    gdi32_swap_buffers: Gdi32SwapBuffersType,
+}
+
+static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
+static mut PIPELINE: OnceCell<Mutex<Pipeline<GdiRenderEngine>>> = OnceCell::new();
+static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
+static mut TARGET_HWND: OnceCell<HWND> = OnceCell::new();
+
+unsafe fn init_pipeline(hwnd: HWND) -> Result<Mutex<Pipeline<GdiRenderEngine>>> {
+    let mut ctx = create_context()?;
+    let engine = GdiRenderEngine::new(hwnd, &mut ctx)?;
+
+    let Some(render_loop) = RENDER_LOOP.take() else {
+        error!("Render loop not yet initialized");
+        return Err(Error::from_hresult(HRESULT(-1)));
+    };
+
+    let pipeline = Pipeline::new(hwnd, ctx, engine, render_loop).map_err(|(e, render_loop)| {
+        RENDER_LOOP.get_or_init(move || render_loop);
+        e
+    })?;
+    diagnostics::emit(DiagnosticEvent::RendererInitialized("GDI"));
+
+    TARGET_HWND.get_or_init(|| hwnd);
+
+    Ok(Mutex::new(pipeline))
+}
+
+// Draw the overlay onto `dc`, within `clip` if given.
+fn render(dc: HDC, clip: Option<RECT>) -> Result<()> {
+    unsafe {
+        // Blits to memory DCs are the application drawing its own back
+        // buffer, and only those to the window present a frame. Once the
+        // overlay is up, it stays on the window it started on.
+        let hwnd = WindowFromDC(dc);
+        if hwnd.0 == 0 || TARGET_HWND.get().is_some_and(|&target| target != hwnd) {
+            return Ok(());
+        }
+
+        diagnostics::present();
+
+        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(hwnd))?;
+
+        let Some(mut pipeline) = pipeline.try_lock() else {
+            error!("Could not lock pipeline");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        pipeline.prepare_render()?;
+
+        let Some(RECT { left, top, right, bottom }) = clip else {
+            return pipeline.render(dc);
+        };
+
+        let saved_dc = SaveDC(dc);
+        if saved_dc == 0 {
+            error!("Could not save the state of the window DC");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        IntersectClipRect(dc, left, top, right, bottom);
+        let result = pipeline.render(dc);
+        if !RestoreDC(dc, saved_dc).as_bool() {
+            error!("Could not restore the state of the window DC");
+        }
+
+        result
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn gdi32_bit_blt_impl(
+    hdc: HDC,
+    x: i32,
+    y: i32,
+    cx: i32,
+    cy: i32,
+    hdc_src: HDC,
+    x1: i32,
+    y1: i32,
+    rop: ROP_CODE,
+) -> BOOL {
+    let Trampolines { gdi32_bit_blt, .. } =
+        TRAMPOLINES.get().expect("GDI trampolines uninitialized");
+
+    trace!("Call BitBlt trampoline");
+    let result = gdi32_bit_blt(hdc, x, y, cx, cy, hdc_src, x1, y1, rop);
+
+    // Draw over what the application has just blitted, and only there: the
+    // rest of the window still has the overlay blended onto it, and blending
+    // it again would make its translucent parts ever more opaque.
+    if result.as_bool() {
+        let clip =
+            RECT { left: x, top: y, right: x.saturating_add(cx), bottom: y.saturating_add(cy) };
+        if let Err(e) = render(hdc, Some(clip)) {
+            diagnostics::render_error(&e);
+        }
+    }
+
+    result
+}
+
+unsafe extern "system" fn gdi32_swap_buffers_impl(hdc: HDC) -> BOOL {
+    let Trampolines { gdi32_swap_buffers, .. } =
+        TRAMPOLINES.get().expect("GDI trampolines uninitialized");
+
+    trace!("Call SwapBuffers trampoline");
+    let result = gdi32_swap_buffers(hdc);
+
+    if result.as_bool() {
+        if let Err(e) = render(hdc, None) {
+            diagnostics::render_error(&e);
+        }
+    }
+
+    result
+}
+
+// Get the addresses of BitBlt and SwapBuffers in gdi32.dll
+unsafe fn get_target_addrs() -> crate::Result<(Gdi32BitBltType, Gdi32SwapBuffersType)> {
+    let gdi32module = GetModuleHandleA(s!("gdi32.dll")).map_err(|e| {
+        error!("Couldn't find gdi32.dll: {e}");
+        HudhookError::PresentAddressNotFound
+    })?;
+
+    let Some(bit_blt) = GetProcAddress(gdi32module, s!("BitBlt")) else {
+        error!("Couldn't find BitBlt");
+        return Err(HudhookError::PresentAddressNotFound);
+    };
+
+    let Some(swap_buffers) = GetProcAddress(gdi32module, s!("SwapBuffers")) else {
+        error!("Couldn't find SwapBuffers");
+        return Err(HudhookError::PresentAddressNotFound);
+    };
+
+    Ok((
+        mem::transmute::<unsafe extern "system" fn() -> isize, Gdi32BitBltType>(bit_blt),
+        mem::transmute::<unsafe extern "system" fn() -> isize, Gdi32SwapBuffersType>(swap_buffers),
+    ))
+}
+
+/// Hooks for GDI.
+///
+/// This is a last resort, for applications whose renderer isn't supported or
+/// can't be told at injection time, and for 2D titles that draw with GDI. The
+/// UI is rasterized on the CPU and blended onto the window after the
+/// application swaps its buffers, or onto the part of it the application
+/// blits to, so it's slow, only redrawn about 30 times per second, and may
+/// flicker on windows that are also drawn to some other way. Input goes through
+/// [`ImguiRenderLoop::message_filter`] and [`ImguiRenderLoop::on_wnd_proc`] as
+/// with the other backends.
+pub struct ImguiGdiHooks([MhHook; 2]);
+
+impl ImguiGdiHooks {
+    /// Construct a set of [`MhHook`]s that will render UI via the
+    /// provided [`ImguiRenderLoop`].
+    ///
+    /// The following functions are hooked:
+    /// - `gdi32::BitBlt`
+    /// - `gdi32::SwapBuffers`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the functions to hook can't be found or the hooks can't be
+    /// created.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn new<T>(t: T) -> crate::Result<Self>
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        let (gdi32_bit_blt_addr, gdi32_swap_buffers_addr) = get_target_addrs()?;
+
+        trace!("BitBlt = {:p}", gdi32_bit_blt_addr as *const c_void);
+        let hook_bit_blt = MhHook::new(gdi32_bit_blt_addr as *mut _, gdi32_bit_blt_impl as *mut _)?;
+        trace!("SwapBuffers = {:p}", gdi32_swap_buffers_addr as *const c_void);
+        let hook_swap_buffers =
+            MhHook::new(gdi32_swap_buffers_addr as *mut _, gdi32_swap_buffers_impl as *mut _)?;
+
+        RENDER_LOOP.get_or_init(move || Box::new(t));
+        TRAMPOLINES.get_or_init(|| Trampolines {
+            gdi32_bit_blt: mem::transmute::<*mut c_void, Gdi32BitBltType>(
+                hook_bit_blt.trampoline(),
+            ),
+            gdi32_swap_buffers: mem::transmute::<*mut c_void, Gdi32SwapBuffersType>(
+                hook_swap_buffers.trampoline(),
+            ),
+        });
+
+        Ok(Self([hook_bit_blt, hook_swap_buffers]))
+    }
+}
+
+impl Hooks for ImguiGdiHooks {
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        unsafe { Self::new(t) }.map(Box::new)
+    }
+
+    fn hooks(&self) -> &[MhHook] {
+        &self.0
+    }
+
+    unsafe fn unhook(&mut self) {
+        TRAMPOLINES.take();
+        PIPELINE.take().map(|p| p.into_inner().take());
+        RENDER_LOOP.take(); // should already be null
+        TARGET_HWND.take();
+    }
+}
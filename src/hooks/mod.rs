@@ -1,48 +1,188 @@
 //! Implementations of render engine hooks.
 
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use std::collections::HashMap;
 use std::mem;
 use std::sync::OnceLock;
 
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use once_cell::sync::Lazy;
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use parking_lot::Mutex;
 use tracing::{debug, error};
 use windows::core::w;
-use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, WPARAM};
+#[cfg(any(
+    feature = "dx9",
+    feature = "dx10",
+    feature = "dx11",
+    feature = "dx12",
+    feature = "opengl3"
+))]
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, RECT, WPARAM};
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::{IDXGISwapChain, DXGI_SWAP_CHAIN_DESC};
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
 use windows::Win32::System::Threading::GetCurrentProcessId;
 use windows::Win32::UI::WindowsAndMessaging::{
-    CreateWindowExW, DefWindowProcW, DestroyWindow, EnumWindows, GetWindowThreadProcessId,
-    RegisterClassExW, UnregisterClassW, CS_HREDRAW, CS_VREDRAW, WNDCLASSEXW,
-    WS_EX_OVERLAPPEDWINDOW, WS_OVERLAPPEDWINDOW,
+    CreateWindowExW, DefWindowProcW, DestroyWindow, EnumWindows, GetClientRect,
+    GetWindowThreadProcessId, IsWindowVisible, RegisterClassExW, UnregisterClassW, CS_HREDRAW,
+    CS_VREDRAW, WNDCLASSEXW, WS_EX_OVERLAPPEDWINDOW, WS_OVERLAPPEDWINDOW,
 };
 
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use windows::Win32::UI::WindowsAndMessaging::{GetAncestor, GetForegroundWindow, GA_ROOT};
+
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use crate::renderer::config::PIPELINE_CONFIG;
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use crate::util;
+#[cfg(any(feature = "dx9", feature = "dx10", feature = "dx11", feature = "dx12"))]
+use crate::HudhookError;
+
+#[cfg(feature = "dx10")]
+pub mod dx10;
 #[cfg(feature = "dx11")]
 pub mod dx11;
 #[cfg(feature = "dx12")]
 pub mod dx12;
 #[cfg(feature = "dx9")]
 pub mod dx9;
+#[cfg(feature = "gdi")]
+pub mod gdi;
 #[cfg(feature = "opengl3")]
 pub mod opengl3;
+#[cfg(feature = "gdi")]
+pub mod overlay;
+
+// Whether the application has loaded the module, e.g. its graphics library.
+#[cfg(any(
+    feature = "dx9",
+    feature = "dx10",
+    feature = "dx11",
+    feature = "dx12",
+    feature = "opengl3"
+))]
+pub(crate) fn is_module_loaded(name: PCWSTR) -> bool {
+    unsafe { GetModuleHandleW(name) }.is_ok()
+}
+
+// The window a swap chain presents to.
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+pub(crate) fn swap_chain_hwnd(swap_chain: &IDXGISwapChain) -> Option<HWND> {
+    match util::try_out_param(|v| unsafe { swap_chain.GetDesc(v) }) {
+        Ok(desc) => Some(desc.OutputWindow),
+        Err(e) => {
+            error!("Could not get the swap chain's description: {e:?}");
+            None
+        },
+    }
+}
+
+// Whether the overlay should be set up on the presenting swap chain, when it
+// hasn't been yet. Decided by the selector set via
+// `HudhookBuilder::with_swap_chain_selector`, if any, and by
+// `SwapChainCandidates` otherwise.
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+pub(crate) fn select_swap_chain(swap_chain: &IDXGISwapChain) -> bool {
+    static CANDIDATES: Lazy<Mutex<SwapChainCandidates>> =
+        Lazy::new(|| Mutex::new(SwapChainCandidates::default()));
+
+    let desc = match util::try_out_param(|v| unsafe { swap_chain.GetDesc(v) }) {
+        Ok(desc) => desc,
+        Err(e) => {
+            error!("Could not get the swap chain's description: {e:?}");
+            return false;
+        },
+    };
+
+    let selector = PIPELINE_CONFIG.lock().swap_chain_selector.clone();
+    match selector {
+        Some(selector) => selector(&desc),
+        None => CANDIDATES.lock().select(&desc),
+    }
+}
+
+// Picks the largest of the swap chains presenting to the foreground window,
+// or to any window if the application isn't in the foreground.
+//
+// A swap chain is only picked on its second present: applications present
+// each of their swap chains once per frame, so by then all of them have been
+// seen at least once.
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+#[derive(Default)]
+struct SwapChainCandidates(HashMap<isize, (u32, SwapChainRank)>);
+
+// Swap chains presenting to the foreground window come first, then the largest.
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+type SwapChainRank = (bool, u64);
+
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+impl SwapChainCandidates {
+    fn select(&mut self, desc: &DXGI_SWAP_CHAIN_DESC) -> bool {
+        let foreground =
+            unsafe { GetForegroundWindow() == GetAncestor(desc.OutputWindow, GA_ROOT) };
+        let area = desc.BufferDesc.Width as u64 * desc.BufferDesc.Height as u64;
+        self.rank(desc.OutputWindow, (foreground, area))
+    }
+
+    fn rank(&mut self, hwnd: HWND, rank: SwapChainRank) -> bool {
+        let (presents, current_rank) = self.0.entry(hwnd.0).or_insert((0, rank));
+        *presents += 1;
+        *current_rank = rank;
+
+        let presents = *presents;
+        presents >= 2 && self.0.values().all(|&(_, other_rank)| other_rank <= rank)
+    }
+}
 
 /// A utility function to retrieve the top level [`HWND`] belonging to this
 /// process.
+///
+/// If the process has more than one top level window, e.g. a hidden splash
+/// screen besides the game window, the visible one with the largest client
+/// area is preferred. If none is visible, the first one found is returned.
 pub fn find_process_hwnd() -> Option<HWND> {
     static mut FOUND_HWND: OnceLock<HWND> = OnceLock::new();
 
-    unsafe extern "system" fn enum_callback(hwnd: HWND, _: LPARAM) -> BOOL {
+    unsafe extern "system" fn enum_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
         let mut pid = 0;
         GetWindowThreadProcessId(hwnd, Some(&mut pid));
         tracing::debug!("hwnd {hwnd:?} has pid {pid} vs {}", GetCurrentProcessId());
+        #[cfg(feature = "gdi")]
+        if overlay::is_overlay_window(hwnd) {
+            return BOOL::from(true);
+        }
         if pid == GetCurrentProcessId() {
-            FOUND_HWND.get_or_init(|| hwnd);
-            BOOL::from(false)
-        } else {
-            BOOL::from(true)
+            let candidates = &mut *(lparam.0 as *mut Vec<HWND>);
+            candidates.push(hwnd);
         }
+        BOOL::from(true)
     }
 
+    let mut candidates: Vec<HWND> = Vec::new();
     unsafe {
         FOUND_HWND.take();
-        EnumWindows(Some(enum_callback), LPARAM(0)).ok();
+        EnumWindows(Some(enum_callback), LPARAM(&mut candidates as *mut _ as isize)).ok();
+    }
+
+    // Iterate in reverse, as `max_by_key` returns the last of equal elements
+    // and the first window found should win ties.
+    let largest_visible = candidates
+        .iter()
+        .rev()
+        .filter(|&&hwnd| unsafe { IsWindowVisible(hwnd) }.as_bool())
+        .filter_map(|&hwnd| {
+            let mut rect = RECT::default();
+            unsafe { GetClientRect(hwnd, &mut rect) }.ok()?;
+            let area = (rect.right - rect.left) as i64 * (rect.bottom - rect.top) as i64;
+            Some((hwnd, area))
+        })
+        .max_by_key(|&(_, area)| area)
+        .map(|(hwnd, _)| hwnd);
+
+    if let Some(hwnd) = largest_visible.or_else(|| candidates.first().copied()) {
+        unsafe { FOUND_HWND.get_or_init(|| hwnd) };
     }
 
     unsafe { FOUND_HWND.get().copied() }
@@ -80,7 +220,7 @@ impl DummyHwnd {
             lpfnWndProc: Some(wnd_proc),
             cbClsExtra: 0,
             cbWndExtra: 0,
-            hInstance: unsafe { GetModuleHandleW(None).unwrap().into() },
+            hInstance: unsafe { GetModuleHandleW(None) }.map(Into::into).unwrap_or_default(),
             lpszClassName: w!("HUDHOOK"),
             ..Default::default()
         };
@@ -109,6 +249,17 @@ impl DummyHwnd {
         Self(hwnd, wndclass)
     }
 
+    // Construct the dummy window, failing if it couldn't be created.
+    #[cfg(any(feature = "dx9", feature = "dx10", feature = "dx11", feature = "dx12"))]
+    pub(crate) fn try_new() -> crate::Result<Self> {
+        let dummy_hwnd = Self::new();
+        if dummy_hwnd.0 .0 == 0 {
+            return Err(HudhookError::WindowNotFound);
+        }
+
+        Ok(dummy_hwnd)
+    }
+
     /// Retrieve the window handle.
     pub fn hwnd(&self) -> HWND {
         self.0
@@ -128,3 +279,29 @@ impl Drop for DummyHwnd {
         }
     }
 }
+
+#[cfg(all(test, any(feature = "dx10", feature = "dx11", feature = "dx12")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_chain_candidates() {
+        let editor = HWND(1);
+        let game = HWND(2);
+        let mut candidates = SwapChainCandidates::default();
+
+        // The smaller view presents first, but isn't picked until the larger
+        // one has been seen.
+        assert!(!candidates.rank(editor, (true, 640 * 360)));
+        assert!(!candidates.rank(game, (true, 1920 * 1080)));
+        assert!(!candidates.rank(editor, (true, 640 * 360)));
+        assert!(candidates.rank(game, (true, 1920 * 1080)));
+
+        // Windows in the foreground win over larger ones in the background.
+        let mut candidates = SwapChainCandidates::default();
+        assert!(!candidates.rank(editor, (true, 640 * 360)));
+        assert!(!candidates.rank(game, (false, 1920 * 1080)));
+        assert!(candidates.rank(editor, (true, 640 * 360)));
+        assert!(!candidates.rank(game, (false, 1920 * 1080)));
+    }
+}
@@ -1,35 +1,51 @@
 //! Hooks for OpenGL 3.
 
+use std::cell::Cell;
 use std::ffi::{c_void, CString};
 use std::mem;
 use std::sync::OnceLock;
 
-use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{error, trace};
-use windows::core::{Error, Result, HRESULT, PCSTR};
+use windows::core::{s, w, Error, Result, HRESULT, PCSTR};
+use windows::Win32::Foundation::BOOL;
 use windows::Win32::Graphics::Gdi::{WindowFromDC, HDC};
-use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetModuleHandleW, GetProcAddress};
 
+use super::is_module_loaded;
+use crate::diagnostics::{self, DiagnosticEvent};
 use crate::mh::MhHook;
+use crate::renderer::config::create_context;
 use crate::renderer::{OpenGl3RenderEngine, Pipeline};
-use crate::{Hooks, ImguiRenderLoop};
+use crate::{Hooks, HudhookError, ImguiRenderLoop};
 
-type OpenGl32wglSwapBuffersType = unsafe extern "system" fn(HDC) -> ();
+type OpenGl32wglSwapBuffersType = unsafe extern "system" fn(HDC) -> BOOL;
+type Gdi32SwapBuffersType = unsafe extern "system" fn(HDC) -> BOOL;
 
 struct Trampolines {
     opengl32_wgl_swap_buffers: OpenGl32wglSwapBuffersType,
+    gdi32_swap_buffers: Option<Gdi32SwapBuffersType>,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
 static mut PIPELINE: OnceCell<Mutex<Pipeline<OpenGl3RenderEngine>>> = OnceCell::new();
 static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
 
-unsafe fn init_pipeline(dc: HDC) -> Result<Mutex<Pipeline<OpenGl3RenderEngine>>> {
+thread_local! {
+    // Whether a swap is being intercepted on this thread. `SwapBuffers` and
+    // `wglSwapBuffers` call each other, and the overlay must only be rendered
+    // once, by whichever the application called.
+    static SWAPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe fn init_pipeline(
+    dc: HDC,
+    entry_point: &'static str,
+) -> Result<Mutex<Pipeline<OpenGl3RenderEngine>>> {
     let hwnd = WindowFromDC(dc);
 
-    let mut ctx = Context::create();
+    let mut ctx = create_context()?;
     let engine = OpenGl3RenderEngine::new(&mut ctx)?;
 
     let Some(render_loop) = RENDER_LOOP.take() else {
@@ -41,13 +57,17 @@ unsafe fn init_pipeline(dc: HDC) -> Result<Mutex<Pipeline<OpenGl3RenderEngine>>>
         RENDER_LOOP.get_or_init(move || render_loop);
         e
     })?;
+    diagnostics::emit(DiagnosticEvent::PresentFunction(entry_point));
+    diagnostics::emit(DiagnosticEvent::RendererInitialized("OpenGL 3"));
 
     Ok(Mutex::new(pipeline))
 }
 
-fn render(dc: HDC) -> Result<()> {
+fn render(dc: HDC, entry_point: &'static str) -> Result<()> {
+    diagnostics::present();
+
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(dc))?;
+        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(dc, entry_point))?;
 
         let Some(mut pipeline) = pipeline.try_lock() else {
             error!("Could not lock pipeline");
@@ -62,61 +82,117 @@ fn render(dc: HDC) -> Result<()> {
     Ok(())
 }
 
-unsafe extern "system" fn opengl32_wgl_swap_buffers_impl(dc: HDC) {
-    let Trampolines { opengl32_wgl_swap_buffers } =
-        TRAMPOLINES.get().expect("OpenGL3 trampolines uninitialized");
+// Render the overlay, unless the swap was already intercepted further up the
+// call stack, then call `swap`.
+fn intercept_swap<R>(dc: HDC, entry_point: &'static str, swap: impl FnOnce() -> R) -> R {
+    if SWAPPING.with(|swapping| swapping.replace(true)) {
+        return swap();
+    }
 
-    if let Err(e) = render(dc) {
-        error!("Render error: {e:?}");
+    if let Err(e) = render(dc, entry_point) {
+        diagnostics::render_error(&e);
     }
 
-    trace!("Call OpenGL3 wglSwapBuffers trampoline");
-    opengl32_wgl_swap_buffers(dc);
+    let result = swap();
+    SWAPPING.with(|swapping| swapping.set(false));
+    result
+}
+
+unsafe extern "system" fn opengl32_wgl_swap_buffers_impl(dc: HDC) -> BOOL {
+    let Trampolines { opengl32_wgl_swap_buffers, .. } =
+        TRAMPOLINES.get().expect("OpenGL3 trampolines uninitialized");
+
+    intercept_swap(dc, "opengl32!wglSwapBuffers", || {
+        trace!("Call OpenGL3 wglSwapBuffers trampoline");
+        opengl32_wgl_swap_buffers(dc)
+    })
+}
+
+unsafe extern "system" fn gdi32_swap_buffers_impl(dc: HDC) -> BOOL {
+    let Trampolines { gdi32_swap_buffers, .. } =
+        TRAMPOLINES.get().expect("OpenGL3 trampolines uninitialized");
+    let gdi32_swap_buffers = gdi32_swap_buffers.expect("SwapBuffers trampoline uninitialized");
+
+    intercept_swap(dc, "gdi32!SwapBuffers", || {
+        trace!("Call OpenGL3 SwapBuffers trampoline");
+        gdi32_swap_buffers(dc)
+    })
 }
 
 // Get the address of wglSwapBuffers in opengl32.dll
-unsafe fn get_opengl_wglswapbuffers_addr() -> OpenGl32wglSwapBuffersType {
+unsafe fn get_opengl_wglswapbuffers_addr() -> crate::Result<OpenGl32wglSwapBuffersType> {
     // Grab a handle to opengl32.dll
     let opengl32dll = CString::new("opengl32.dll").unwrap();
-    let opengl32module = GetModuleHandleA(PCSTR(opengl32dll.as_ptr() as *mut _))
-        .expect("failed finding opengl32.dll");
+    let opengl32module = GetModuleHandleA(PCSTR(opengl32dll.as_ptr() as *mut _)).map_err(|e| {
+        error!("Couldn't find opengl32.dll: {e}");
+        HudhookError::PresentAddressNotFound
+    })?;
 
     // Grab the address of wglSwapBuffers
     let wglswapbuffers = CString::new("wglSwapBuffers").unwrap();
-    let wglswapbuffers_func =
-        GetProcAddress(opengl32module, PCSTR(wglswapbuffers.as_ptr() as *mut _)).unwrap();
+    let Some(wglswapbuffers_func) =
+        GetProcAddress(opengl32module, PCSTR(wglswapbuffers.as_ptr() as *mut _))
+    else {
+        error!("Couldn't find wglSwapBuffers");
+        return Err(HudhookError::PresentAddressNotFound);
+    };
 
-    mem::transmute::<unsafe extern "system" fn() -> isize, OpenGl32wglSwapBuffersType>(
+    Ok(mem::transmute::<unsafe extern "system" fn() -> isize, OpenGl32wglSwapBuffersType>(
         wglswapbuffers_func,
-    )
+    ))
+}
+
+// Get the address of SwapBuffers in gdi32.dll, if it's loaded.
+unsafe fn get_gdi_swapbuffers_addr() -> Option<Gdi32SwapBuffersType> {
+    let gdi32module = GetModuleHandleW(w!("gdi32.dll")).ok()?;
+    let swapbuffers_func = GetProcAddress(gdi32module, s!("SwapBuffers"))?;
+
+    Some(mem::transmute::<unsafe extern "system" fn() -> isize, Gdi32SwapBuffersType>(
+        swapbuffers_func,
+    ))
 }
 
 /// Hooks for OpenGL 3.
-pub struct ImguiOpenGl3Hooks([MhHook; 1]);
+pub struct ImguiOpenGl3Hooks(Vec<MhHook>);
 
 impl ImguiOpenGl3Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
     /// provided [`ImguiRenderLoop`].
     ///
     /// The following functions are hooked:
+    /// - `gdi32::SwapBuffers`, if `gdi32.dll` is loaded
     /// - `opengl32::wglSwapBuffers`
     ///
+    /// Applications swap either through `SwapBuffers`, the usual way, or
+    /// through `wglSwapBuffers` directly, and each ends up calling the other.
+    /// Both are hooked so that the overlay is drawn either way, but only once
+    /// per swap, by the function the application called. It's reported via
+    /// [`DiagnosticEvent::PresentFunction`] once the overlay is set up.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the functions to hook can't be found or the hooks can't be
+    /// created.
+    ///
     /// # Safety
     ///
     /// yolo
-    pub unsafe fn new<T>(t: T) -> Self
+    pub unsafe fn new<T>(t: T) -> crate::Result<Self>
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
         // Grab the addresses
-        let hook_opengl_swap_buffers_address = get_opengl_wglswapbuffers_addr();
+        let hook_opengl_swap_buffers_address = get_opengl_wglswapbuffers_addr()?;
+        let hook_gdi_swap_buffers_address = get_gdi_swapbuffers_addr();
 
         // Create detours
         let hook_opengl_wgl_swap_buffers = MhHook::new(
             hook_opengl_swap_buffers_address as *mut _,
             opengl32_wgl_swap_buffers_impl as *mut _,
-        )
-        .expect("couldn't create opengl32.wglSwapBuffers hook");
+        )?;
+        let hook_gdi_swap_buffers = hook_gdi_swap_buffers_address
+            .map(|address| MhHook::new(address as *mut _, gdi32_swap_buffers_impl as *mut _))
+            .transpose()?;
 
         // Initialize the render loop and store detours
         RENDER_LOOP.get_or_init(move || Box::new(t));
@@ -124,19 +200,29 @@ impl ImguiOpenGl3Hooks {
             opengl32_wgl_swap_buffers: mem::transmute::<*mut c_void, OpenGl32wglSwapBuffersType>(
                 hook_opengl_wgl_swap_buffers.trampoline(),
             ),
+            gdi32_swap_buffers: hook_gdi_swap_buffers
+                .as_ref()
+                .map(|hook| mem::transmute::<*mut c_void, Gdi32SwapBuffersType>(hook.trampoline())),
         });
 
-        Self([hook_opengl_wgl_swap_buffers])
+        Ok(Self(hook_gdi_swap_buffers.into_iter().chain([hook_opengl_wgl_swap_buffers]).collect()))
     }
 }
 
 impl Hooks for ImguiOpenGl3Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { ImguiOpenGl3Hooks::new(t) })
+        unsafe { ImguiOpenGl3Hooks::new(t) }.map(Box::new)
+    }
+
+    fn is_renderer_loaded() -> bool
+    where
+        Self: Sized,
+    {
+        is_module_loaded(w!("opengl32.dll"))
     }
 
     fn hooks(&self) -> &[MhHook] {
@@ -0,0 +1,353 @@
+//! An overlay drawn in a window of its own, over the application's, instead
+//! of in its swap chain.
+
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tracing::{debug, error};
+use windows::core::{w, Error, Result, PCWSTR};
+use windows::Win32::Foundation::{COLORREF, HWND, LPARAM, LRESULT, POINT, RECT, SIZE, WPARAM};
+use windows::Win32::Graphics::Gdi::{ClientToScreen, AC_SRC_ALPHA, AC_SRC_OVER, BLENDFUNCTION};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetClientRect,
+    GetForegroundWindow, IsIconic, IsWindow, IsWindowVisible, PeekMessageW, RegisterClassExW,
+    SetWindowPos, ShowWindow, TranslateMessage, UnregisterClassW, UpdateLayeredWindow,
+    HWND_TOPMOST, MSG, PM_REMOVE, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SW_HIDE,
+    SW_SHOWNOACTIVATE, ULW_ALPHA, WNDCLASSEXW, WS_EX_LAYERED, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW,
+    WS_EX_TOPMOST, WS_EX_TRANSPARENT, WS_POPUP,
+};
+
+use crate::diagnostics::{self, DiagnosticEvent};
+use crate::hooks::find_process_hwnd;
+use crate::mh::MhHook;
+use crate::renderer::config::create_context;
+use crate::renderer::{Bitmap, GdiRenderEngine, Pipeline};
+use crate::{HookStatus, Hooks, Hudhook, ImguiRenderLoop};
+
+type RenderLoop = Box<dyn ImguiRenderLoop + Send + Sync>;
+
+// How often the overlay window is moved over the application window and
+// drawn again.
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+const CLASS_NAME: PCWSTR = w!("HUDHOOK_OVERLAY");
+
+// The overlay window, so that it's never mistaken for the application's.
+static OVERLAY_HWND: AtomicIsize = AtomicIsize::new(0);
+
+// Whether `hwnd` is the overlay window, which belongs to the process as well.
+pub(crate) fn is_overlay_window(hwnd: HWND) -> bool {
+    hwnd.0 != 0 && OVERLAY_HWND.load(Ordering::SeqCst) == hwnd.0
+}
+
+// The overlay window and the pipeline drawing into it. The pipeline is bound
+// to the application window, whose messages it receives as usual.
+struct Overlay {
+    hwnd: HWND,
+    target: HWND,
+    pipeline: Pipeline<GdiRenderEngine>,
+    bitmap: Option<Bitmap>,
+    shown: bool,
+}
+
+impl Overlay {
+    unsafe fn new(target: HWND, render_loop: RenderLoop) -> std::result::Result<Self, RenderLoop> {
+        let hwnd = match create_window() {
+            Ok(hwnd) => hwnd,
+            Err(e) => {
+                error!("Could not create the overlay window: {e:?}");
+                return Err(render_loop);
+            },
+        };
+
+        let pipeline = match create_context() {
+            Ok(mut ctx) => match GdiRenderEngine::new(target, &mut ctx) {
+                Ok(engine) => Pipeline::new(target, ctx, engine, render_loop),
+                Err(e) => Err((e, render_loop)),
+            },
+            Err(e) => Err((e, render_loop)),
+        };
+
+        match pipeline {
+            Ok(pipeline) => {
+                OVERLAY_HWND.store(hwnd.0, Ordering::SeqCst);
+                diagnostics::emit(DiagnosticEvent::RendererInitialized("overlay window"));
+                Ok(Self { hwnd, target, pipeline, bitmap: None, shown: false })
+            },
+            Err((e, render_loop)) => {
+                error!("Could not create the overlay pipeline: {e:?}");
+                DestroyWindow(hwnd).ok();
+                Err(render_loop)
+            },
+        }
+    }
+
+    // Follow the client area of the application window, and draw a frame
+    // over it. The overlay is hidden while the application isn't in the
+    // foreground, so that it doesn't cover other windows.
+    unsafe fn render(&mut self) -> Result<()> {
+        let foreground = GetForegroundWindow() == self.target;
+        if !foreground || IsIconic(self.target).as_bool() || !IsWindowVisible(self.target).as_bool()
+        {
+            self.hide();
+            return Ok(());
+        }
+
+        let mut rect = RECT::default();
+        GetClientRect(self.target, &mut rect)?;
+        let mut origin = POINT::default();
+        if !ClientToScreen(self.target, &mut origin).as_bool() {
+            return Err(Error::from_win32());
+        }
+
+        let (width, height) = ((rect.right - rect.left) as u32, (rect.bottom - rect.top) as u32);
+        if width == 0 || height == 0 {
+            self.hide();
+            return Ok(());
+        }
+
+        if self.bitmap.as_ref().map(|bitmap| (bitmap.width, bitmap.height)) != Some((width, height))
+        {
+            self.bitmap = None;
+            self.bitmap = Some(Bitmap::new(width, height)?);
+        }
+        let Some(bitmap) = &mut self.bitmap else {
+            return Ok(());
+        };
+
+        diagnostics::present();
+
+        // The frame is blended onto a transparent bitmap, where it's left as
+        // it is.
+        bitmap.pixels().fill(0);
+        self.pipeline.prepare_render()?;
+        self.pipeline.render(bitmap.dc)?;
+
+        UpdateLayeredWindow(
+            self.hwnd,
+            None,
+            Some(&origin),
+            Some(&SIZE { cx: width as i32, cy: height as i32 }),
+            bitmap.dc,
+            Some(&POINT::default()),
+            COLORREF(0),
+            Some(&BLENDFUNCTION {
+                BlendOp: AC_SRC_OVER as u8,
+                BlendFlags: 0,
+                SourceConstantAlpha: 255,
+                AlphaFormat: AC_SRC_ALPHA as u8,
+            }),
+            ULW_ALPHA,
+        )?;
+
+        // Other topmost windows may have been raised above the overlay.
+        SetWindowPos(
+            self.hwnd,
+            HWND_TOPMOST,
+            0,
+            0,
+            0,
+            0,
+            SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE,
+        )?;
+
+        if !self.shown {
+            ShowWindow(self.hwnd, SW_SHOWNOACTIVATE);
+            self.shown = true;
+        }
+
+        Ok(())
+    }
+
+    unsafe fn hide(&mut self) {
+        if self.shown {
+            ShowWindow(self.hwnd, SW_HIDE);
+            self.shown = false;
+        }
+    }
+
+    unsafe fn destroy(self) {
+        self.pipeline.take();
+        OVERLAY_HWND.store(0, Ordering::SeqCst);
+        if let Err(e) = DestroyWindow(self.hwnd) {
+            error!("Could not destroy the overlay window: {e:?}");
+        }
+
+        // The window procedure of the class is gone once the library is freed.
+        let hinstance = GetModuleHandleW(None).map(Into::into).unwrap_or_default();
+        if let Err(e) = UnregisterClassW(CLASS_NAME, hinstance) {
+            error!("Could not unregister the overlay window class: {e:?}");
+        }
+    }
+}
+
+// A transparent, click-through window, kept above the others and out of the
+// taskbar and of the focus.
+unsafe fn create_window() -> Result<HWND> {
+    unsafe extern "system" fn wnd_proc(
+        hwnd: HWND,
+        msg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    }
+
+    let hinstance = GetModuleHandleW(None)?.into();
+    let wndclass = WNDCLASSEXW {
+        cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+        lpfnWndProc: Some(wnd_proc),
+        hInstance: hinstance,
+        lpszClassName: CLASS_NAME,
+        ..Default::default()
+    };
+    RegisterClassExW(&wndclass);
+
+    let hwnd = CreateWindowExW(
+        WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+        wndclass.lpszClassName,
+        w!("hudhook overlay"),
+        WS_POPUP,
+        0,
+        0,
+        0,
+        0,
+        None,
+        None,
+        hinstance,
+        None,
+    );
+
+    if hwnd.0 == 0 {
+        return Err(Error::from_win32());
+    }
+
+    Ok(hwnd)
+}
+
+// Runs the overlay until `running` is cleared. The overlay window is created,
+// and torn down, on this thread, which pumps its messages.
+fn overlay_thread(render_loop: RenderLoop, running: Arc<AtomicBool>) {
+    let mut render_loop = Some(render_loop);
+    let mut overlay: Option<Overlay> = None;
+
+    while running.load(Ordering::SeqCst) {
+        unsafe {
+            let mut msg = MSG::default();
+            while PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE).as_bool() {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+
+            // The application window may have been closed and replaced.
+            if overlay.as_ref().is_some_and(|overlay| !IsWindow(overlay.target).as_bool()) {
+                if let Some(overlay) = overlay.take() {
+                    debug!("Application window {:?} is gone", overlay.target);
+                    overlay.destroy();
+                }
+            }
+
+            let applied = Hudhook::status() == HookStatus::Applied;
+            match &mut overlay {
+                Some(overlay) if applied => {
+                    if let Err(e) = overlay.render() {
+                        diagnostics::render_error(&e);
+                    }
+                },
+                Some(overlay) => overlay.hide(),
+                None if applied => {
+                    if let Some(target) = find_process_hwnd() {
+                        if let Some(rl) = render_loop.take() {
+                            debug!("Drawing the overlay over {target:?}");
+                            match Overlay::new(target, rl) {
+                                Ok(new_overlay) => overlay = Some(new_overlay),
+                                Err(rl) => render_loop = Some(rl),
+                            }
+                        }
+                    }
+                },
+                None => {},
+            }
+        }
+
+        thread::sleep(FRAME_INTERVAL);
+    }
+
+    if let Some(overlay) = overlay {
+        unsafe { overlay.destroy() };
+    }
+}
+
+/// An overlay drawn in a window of its own, laid over the application window,
+/// instead of in the application's swap chain.
+///
+/// Nothing of the application's rendering is hooked, so this works whatever
+/// API it renders with, and for applications that don't take kindly to their
+/// swap chain being touched. The UI is rasterized on the CPU, like with
+/// [`ImguiGdiHooks`](crate::hooks::gdi::ImguiGdiHooks), so it's slow and only
+/// redrawn about 30 times per second; it's meant for HUDs rather than
+/// elaborate UIs. Applications in exclusive fullscreen hide it altogether.
+///
+/// The overlay window is transparent to the mouse, always on top while the
+/// application is in the foreground, and follows the client area of the
+/// application window as it moves and is resized. The input is read from the
+/// application window, as with the other backends, and goes through
+/// [`ImguiRenderLoop::message_filter`] and [`ImguiRenderLoop::on_wnd_proc`].
+///
+/// See [`HudhookBuilder::with_external_overlay_window`](crate::HudhookBuilder::with_external_overlay_window).
+pub struct ImguiOverlayWindow {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ImguiOverlayWindow {
+    /// Start the thread that draws the overlay via the provided
+    /// [`ImguiRenderLoop`] once the hooks are applied, over the window found
+    /// by [`find_process_hwnd`].
+    ///
+    /// No functions are hooked.
+    pub fn new<T>(t: T) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let running = Arc::clone(&running);
+            thread::spawn(move || overlay_thread(Box::new(t), running))
+        };
+
+        Self { running, thread: Some(thread) }
+    }
+}
+
+impl Hooks for ImguiOverlayWindow {
+    fn from_render_loop<T>(t: T) -> crate::Result<Box<Self>>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Ok(Box::new(Self::new(t)))
+    }
+
+    fn hooks(&self) -> &[MhHook] {
+        &[]
+    }
+
+    unsafe fn unhook(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // The render loop may be the one ejecting, from the overlay thread.
+        if let Some(thread) = self.thread.take() {
+            if thread.thread().id() == thread::current().id() {
+                return;
+            }
+            if thread.join().is_err() {
+                error!("Overlay thread panicked");
+            }
+        }
+    }
+}
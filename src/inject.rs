@@ -1,49 +1,347 @@
 //! Facilities for injecting compiled DLLs into target processes.
 
 use std::ffi::c_void;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, Read};
 use std::mem::{self, size_of};
-use std::path::PathBuf;
-
-use tracing::debug;
-use windows::core::{s, w, Error, Result, HRESULT, HSTRING, PCSTR, PCWSTR};
-use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE, MAX_PATH};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error};
+use windows::core::{s, w, Error, HSTRING, PCSTR, PCWSTR};
+use windows::Win32::Foundation::{
+    CloseHandle, FreeLibrary, BOOL, E_ACCESSDENIED, HANDLE, HMODULE, MAX_PATH, WAIT_OBJECT_0,
+};
 use windows::Win32::System::Diagnostics::Debug::WriteProcessMemory;
 use windows::Win32::System::Diagnostics::ToolHelp::{
-    CreateToolhelp32Snapshot, Process32First, Process32FirstW, Process32Next, Process32NextW,
-    PROCESSENTRY32, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+    CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, Process32First, Process32FirstW,
+    Process32Next, Process32NextW, MODULEENTRY32W, PROCESSENTRY32, PROCESSENTRY32W,
+    TH32CS_SNAPMODULE, TH32CS_SNAPMODULE32, TH32CS_SNAPPROCESS,
+};
+use windows::Win32::System::LibraryLoader::{
+    GetModuleHandleW, GetProcAddress, LoadLibraryExW, DONT_RESOLVE_DLL_REFERENCES,
 };
-use windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress};
 use windows::Win32::System::Memory::{
     VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE,
 };
+use windows::Win32::System::SystemInformation::{
+    IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64,
+    IMAGE_FILE_MACHINE_IA64, IMAGE_FILE_MACHINE_UNKNOWN,
+};
 use windows::Win32::System::Threading::{
-    CreateRemoteThread, GetExitCodeThread, OpenProcess, WaitForSingleObject, INFINITE,
-    PROCESS_ALL_ACCESS,
+    CreateRemoteThread, GetExitCodeThread, GetProcessId, IsWow64Process2, OpenProcess,
+    WaitForSingleObject, INFINITE, PROCESS_ALL_ACCESS,
 };
 use windows::Win32::UI::WindowsAndMessaging::{FindWindowA, FindWindowW, GetWindowThreadProcessId};
 
+/// Errors that can occur while looking up a process or injecting a DLL.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum InjectError {
+    /// No process matching the search criteria was found.
+    ProcessNotFound,
+    /// The process was found, but the current user is not allowed to open it
+    /// with the permissions required for injection.
+    AccessDenied,
+    /// `OpenProcess` failed for a reason other than access denied.
+    OpenProcessFailed(Error),
+    /// Couldn't allocate memory for the DLL path in the target process.
+    AllocFailed(Error),
+    /// Couldn't write the DLL path in the target process.
+    WriteFailed(Error),
+    /// Couldn't spawn the thread loading the DLL in the target process.
+    CreateRemoteThreadFailed(Error),
+    /// `LoadLibraryW` failed in the target process.
+    RemoteLoadLibraryReturnedNull,
+    /// No module with the requested name is loaded in the target process.
+    ModuleNotFound,
+    /// The ejected module was still loaded in the target process after
+    /// waiting for it to be freed.
+    ModuleStillLoaded,
+    /// The awaited process didn't appear in time.
+    Timeout,
+    /// Some other Windows API call failed.
+    Windows(Error),
+    /// The DLL file could not be read.
+    Io(io::Error),
+    /// The DLL file is not a valid PE image.
+    InvalidDll,
+    /// The bitness of the DLL does not match the bitness of the target
+    /// process.
+    ArchitectureMismatch {
+        /// Whether the target process is 64-bit.
+        process_is_64: bool,
+        /// Whether the DLL is 64-bit.
+        dll_is_64: bool,
+    },
+}
+
+impl Display for InjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InjectError::ProcessNotFound => write!(f, "process not found"),
+            InjectError::AccessDenied => {
+                write!(f, "access denied while opening the process; try running as administrator")
+            },
+            InjectError::OpenProcessFailed(e) => write!(f, "couldn't open the process: {e}"),
+            InjectError::AllocFailed(e) => write!(f, "couldn't allocate remote memory: {e}"),
+            InjectError::WriteFailed(e) => write!(f, "couldn't write remote memory: {e}"),
+            InjectError::CreateRemoteThreadFailed(e) => {
+                write!(f, "couldn't create the remote thread: {e}")
+            },
+            InjectError::RemoteLoadLibraryReturnedNull => {
+                write!(f, "the DLL could not be loaded by the target process")
+            },
+            InjectError::ModuleNotFound => write!(f, "module not found in the target process"),
+            InjectError::ModuleStillLoaded => {
+                write!(f, "the module is still loaded in the target process")
+            },
+            InjectError::Timeout => write!(f, "timed out waiting for the process"),
+            InjectError::Windows(e) => write!(f, "Windows API error: {e}"),
+            InjectError::Io(e) => write!(f, "couldn't read DLL: {e}"),
+            InjectError::InvalidDll => write!(f, "the DLL is not a valid PE image"),
+            InjectError::ArchitectureMismatch { process_is_64, dll_is_64 } => write!(
+                f,
+                "can't inject a {}-bit DLL into a {}-bit process",
+                if *dll_is_64 { 64 } else { 32 },
+                if *process_is_64 { 64 } else { 32 },
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InjectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InjectError::OpenProcessFailed(e)
+            | InjectError::AllocFailed(e)
+            | InjectError::WriteFailed(e)
+            | InjectError::CreateRemoteThreadFailed(e)
+            | InjectError::Windows(e) => Some(e),
+            InjectError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for InjectError {
+    fn from(e: Error) -> Self {
+        InjectError::Windows(e)
+    }
+}
+
+impl From<io::Error> for InjectError {
+    fn from(e: io::Error) -> Self {
+        InjectError::Io(e)
+    }
+}
+
+/// A module loaded in a process, as listed by [`Process::modules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    /// The file name of the module, e.g. `game.exe` or `kernel32.dll`.
+    pub name: String,
+    /// The full path of the module.
+    pub path: PathBuf,
+    /// The address the module is loaded at, in the process.
+    pub base: usize,
+    /// The size of the loaded module, in bytes.
+    pub size: usize,
+}
+
 /// A process, open with the permissions appropriate for injection.
 pub struct Process(HANDLE);
 
 impl Process {
     /// Retrieve the process ID by window title, returning the first match, and
     /// open it with the appropriate permissions.
-    pub fn by_title(title: &str) -> Result<Self> {
+    pub fn by_title(title: &str) -> Result<Self, InjectError> {
         get_process_by_title(title).map(Self)
     }
 
     /// Retrieve the process ID by executable name, returning the first match,
     /// and open it with the appropriate permissions.
-    pub fn by_name(name: &str) -> Result<Self> {
+    pub fn by_name(name: &str) -> Result<Self, InjectError> {
         get_process_by_name(name).map(Self)
     }
 
+    /// Wait for a process with the given executable name to start, and open
+    /// it with the appropriate permissions.
+    ///
+    /// This is meant for launchers started before the game: the process list
+    /// is polled every 100 milliseconds, and the first match is returned as
+    /// soon as it appears. Fails with [`InjectError::Timeout`] if no such
+    /// process appears within `timeout`, or with any error other than
+    /// [`InjectError::ProcessNotFound`] that opening the process fails with.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// use hudhook::inject::Process;
+    ///
+    /// Command::new("Game.exe").spawn().unwrap();
+    /// Process::wait_for_by_name("Game.exe", Duration::from_secs(30))
+    ///     .unwrap()
+    ///     .inject("hello_hud.dll".into())
+    ///     .unwrap();
+    /// ```
+    pub fn wait_for_by_name(name: &str, timeout: Duration) -> Result<Self, InjectError> {
+        let start = Instant::now();
+
+        loop {
+            match get_process_by_name(name) {
+                Ok(handle) => return Ok(Self(handle)),
+                Err(InjectError::ProcessNotFound) => {},
+                Err(e) => return Err(e),
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(InjectError::Timeout);
+            }
+
+            thread::sleep(PROCESS_POLL_INTERVAL);
+        }
+    }
+
+    /// Wait for the process to spawn a child process with the given executable
+    /// name, and open the child with the appropriate permissions.
+    ///
+    /// This is meant for games started through a launcher: look up the
+    /// launcher with [`Process::by_name`], then wait for it to start the
+    /// actual game executable and inject into the latter. The process tree is
+    /// polled every 100 milliseconds, and only direct children of the process
+    /// are considered. Fails with [`InjectError::Timeout`] if no such child
+    /// appears within `timeout`.
+    pub fn wait_for_child(&self, child_name: &str, timeout: Duration) -> Result<Self, InjectError> {
+        let pid = unsafe { GetProcessId(self.0) };
+        let start = Instant::now();
+
+        loop {
+            if let Some(child_pid) = unsafe { find_child_process(pid, child_name) }? {
+                debug!("Found child process {child_name} with PID {child_pid}");
+                return open_process(child_pid).map(Self);
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(InjectError::Timeout);
+            }
+
+            thread::sleep(PROCESS_POLL_INTERVAL);
+        }
+    }
+
     /// Inject the DLL in the process.
-    pub fn inject(&self, dll_path: PathBuf) -> Result<()> {
+    ///
+    /// Fails with [`InjectError::ArchitectureMismatch`] before touching the
+    /// target process if the bitness of the DLL doesn't match the bitness of
+    /// the process.
+    pub fn inject(&self, dll_path: PathBuf) -> Result<(), InjectError> {
+        let dll_path = dll_path.canonicalize()?;
+        self.check_architecture(&dll_path)?;
+        self.load_library(&dll_path)
+    }
+
+    /// Inject the DLL in the process, and hand it a blob of data, e.g. a
+    /// configuration or a path to one.
+    ///
+    /// Once the DLL is loaded, the data is copied to the target process and
+    /// passed to the `hudhook_init` export of the DLL in a remote thread. DLLs
+    /// built with the [`hudhook!`](crate::hudhook) macro have one, which
+    /// stores the data for [`init_data`](crate::init_data) and
+    /// [`wait_for_init_data`](crate::wait_for_init_data) to return. If the DLL
+    /// doesn't export `hudhook_init`, the data is dropped and this behaves
+    /// like [`Process::inject`].
+    ///
+    /// `hudhook_init` receives a pointer to the length of the data, as a
+    /// little-endian `u64`, followed by the data itself. The block is freed
+    /// as soon as the export returns, so it has to copy what it needs.
+    ///
+    /// The injector and the DLL need to have the same architecture.
+    pub fn inject_with_data(&self, dll_path: PathBuf, data: &[u8]) -> Result<(), InjectError> {
+        let dll_path = dll_path.canonicalize()?;
+        self.check_architecture(&dll_path)?;
+        self.load_library(&dll_path)?;
+
+        let module_name = dll_path.file_name().ok_or(InjectError::InvalidDll)?.to_string_lossy();
+        let (remote_base, module_path) = self.find_module(&module_name)?;
+
+        let Some(offset) = find_export_offset(&module_path, s!("hudhook_init"))? else {
+            debug!("{module_name} doesn't export hudhook_init, dropping the data");
+            return Ok(());
+        };
+
+        let block = init_block(data);
+        let block_buf = unsafe {
+            VirtualAllocEx(self.0, None, block.len(), MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE)
+        };
+
+        if block_buf.is_null() {
+            return Err(InjectError::AllocFailed(Error::from_win32()));
+        }
+
+        let res = unsafe {
+            WriteProcessMemory(
+                self.0,
+                block_buf,
+                block.as_ptr() as *const c_void,
+                block.len(),
+                None,
+            )
+        };
+
+        if let Err(e) = res {
+            self.free_remote(block_buf);
+            return Err(InjectError::WriteFailed(e));
+        }
+
+        debug!("Passing {} bytes to {module_name} via hudhook_init", data.len());
+
+        let thread = unsafe {
+            CreateRemoteThread(
+                self.0,
+                None,
+                0,
+                Some(mem::transmute::<usize, unsafe extern "system" fn(*mut c_void) -> u32>(
+                    remote_base + offset,
+                )),
+                Some(block_buf),
+                0,
+                None,
+            )
+        };
+
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(e) => {
+                self.free_remote(block_buf);
+                return Err(InjectError::CreateRemoteThreadFailed(e));
+            },
+        };
+
+        // As in `load_library`, the block is leaked if the export may still be
+        // reading it.
+        if unsafe { WaitForSingleObject(thread, INFINITE) } != WAIT_OBJECT_0 {
+            let e = Error::from_win32();
+            close_handle(thread);
+            return Err(InjectError::Windows(e));
+        }
+
+        close_handle(thread);
+        self.free_remote(block_buf);
+
+        Ok(())
+    }
+
+    // Load the DLL in the process with a remote call to `LoadLibraryW`.
+    fn load_library(&self, dll_path: &Path) -> Result<(), InjectError> {
         let proc_addr =
             unsafe { GetProcAddress(GetModuleHandleW(w!("Kernel32"))?, s!("LoadLibraryW")) };
 
-        let dll_path = HSTRING::from(dll_path.canonicalize().unwrap().as_path());
+        let module_name = dll_path.file_name().ok_or(InjectError::InvalidDll)?.to_string_lossy();
+        let dll_path = HSTRING::from(dll_path);
         let dll_path_buf = unsafe {
             VirtualAllocEx(
                 self.0,
@@ -54,6 +352,10 @@ impl Process {
             )
         };
 
+        if dll_path_buf.is_null() {
+            return Err(InjectError::AllocFailed(Error::from_win32()));
+        }
+
         let mut bytes_written = 0usize;
         let res = unsafe {
             WriteProcessMemory(
@@ -67,6 +369,11 @@ impl Process {
 
         debug!("WriteProcessMemory: written {} bytes, returned {:?}", bytes_written, res);
 
+        if let Err(e) = res {
+            self.free_remote(dll_path_buf);
+            return Err(InjectError::WriteFailed(e));
+        }
+
         let thread = unsafe {
             CreateRemoteThread(
                 self.0,
@@ -82,23 +389,208 @@ impl Process {
                 0,
                 None,
             )
-        }?;
+        };
+
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(e) => {
+                self.free_remote(dll_path_buf);
+                return Err(InjectError::CreateRemoteThreadFailed(e));
+            },
+        };
 
-        unsafe {
-            WaitForSingleObject(thread, INFINITE);
-            let mut exit_code = 0u32;
-            GetExitCodeThread(thread, &mut exit_code as *mut u32)?;
-            CloseHandle(thread)?;
-            VirtualFreeEx(self.0, dll_path_buf, 0, MEM_RELEASE)?;
+        // The thread may still be reading the path, so leak it rather than
+        // free it from under the thread.
+        if unsafe { WaitForSingleObject(thread, INFINITE) } != WAIT_OBJECT_0 {
+            let e = Error::from_win32();
+            close_handle(thread);
+            return Err(InjectError::Windows(e));
+        }
 
-            Ok(())
+        let mut exit_code = 0u32;
+        let res = unsafe { GetExitCodeThread(thread, &mut exit_code as *mut u32) };
+        close_handle(thread);
+        self.free_remote(dll_path_buf);
+        res?;
+
+        // The exit code is the low 32 bits of the module handle returned by
+        // `LoadLibraryW`. It's zero if the library couldn't be loaded, but may
+        // also be for a 64-bit handle, so make sure the module isn't there.
+        if exit_code == 0 && self.find_module(&module_name).is_err() {
+            return Err(InjectError::RemoteLoadLibraryReturnedNull);
         }
+
+        Ok(())
+    }
+
+    // Free memory allocated in the process. This is cleanup on the way out, so
+    // a failure is logged rather than returned in place of the outcome.
+    fn free_remote(&self, buf: *mut c_void) {
+        if let Err(e) = unsafe { VirtualFreeEx(self.0, buf, 0, MEM_RELEASE) } {
+            error!("Couldn't free remote memory: {e}");
+        }
+    }
+
+    /// Eject a previously injected DLL from the process.
+    ///
+    /// `module_name` is the file name of the DLL, e.g. `hello_hud.dll`. If
+    /// the DLL was built with the [`hudhook!`](crate::hudhook) macro, its
+    /// `hudhook_eject` export is invoked in a remote thread, so that the
+    /// hooks are unapplied before the library is freed, same as calling
+    /// [`eject`](crate::eject) from the render loop. Otherwise, the library
+    /// is freed with a remote call to `FreeLibrary`.
+    ///
+    /// Either way, this returns once the module is gone from the process, so
+    /// that it can be injected again right away. Fails with
+    /// [`InjectError::ModuleStillLoaded`] if it's still there after 10
+    /// seconds, e.g. if the DLL was loaded more than once.
+    ///
+    /// The injector and the DLL need to have the same architecture.
+    pub fn eject(&self, module_name: &str) -> Result<(), InjectError> {
+        let (remote_base, module_path) = self.find_module(module_name)?;
+
+        let start_routine = match find_export_offset(&module_path, s!("hudhook_eject"))? {
+            Some(offset) => {
+                debug!("Ejecting {module_name} via hudhook_eject");
+                remote_base + offset
+            },
+            None => {
+                debug!("Ejecting {module_name} via FreeLibrary");
+                unsafe { GetProcAddress(GetModuleHandleW(w!("Kernel32"))?, s!("FreeLibrary")) }
+                    .ok_or_else(Error::from_win32)? as usize
+            },
+        };
+
+        let thread = unsafe {
+            CreateRemoteThread(
+                self.0,
+                None,
+                0,
+                Some(mem::transmute::<usize, unsafe extern "system" fn(*mut c_void) -> u32>(
+                    start_routine,
+                )),
+                Some(remote_base as *const c_void),
+                0,
+                None,
+            )
+        }
+        .map_err(InjectError::CreateRemoteThreadFailed)?;
+
+        if unsafe { WaitForSingleObject(thread, INFINITE) } != WAIT_OBJECT_0 {
+            let e = Error::from_win32();
+            close_handle(thread);
+            return Err(InjectError::Windows(e));
+        }
+        close_handle(thread);
+
+        // `hudhook_eject` returns as soon as it has started unloading the DLL,
+        // which happens on another thread.
+        let start = Instant::now();
+        loop {
+            match self.find_module(module_name) {
+                Err(InjectError::ModuleNotFound) => return Ok(()),
+                Err(e) => return Err(e),
+                Ok(_) => {},
+            }
+
+            if start.elapsed() >= EJECT_TIMEOUT {
+                return Err(InjectError::ModuleStillLoaded);
+            }
+
+            thread::sleep(PROCESS_POLL_INTERVAL);
+        }
+    }
+
+    /// List the modules loaded in the process, i.e. its executable and the
+    /// DLLs, with the address each is loaded at, e.g. to compute the
+    /// addresses of the game's data from offsets within its executable.
+    ///
+    /// Modules of both architectures are listed for 32-bit processes running
+    /// on a 64-bit OS. The list is a snapshot: modules may be loaded or freed
+    /// right after it's taken.
+    pub fn modules(&self) -> Result<Vec<ModuleInfo>, InjectError> {
+        let pid = unsafe { GetProcessId(self.0) };
+        let snapshot =
+            unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, pid) }?;
+        let mut me32 = MODULEENTRY32W {
+            dwSize: mem::size_of::<MODULEENTRY32W>() as u32,
+            ..Default::default()
+        };
+
+        let mut modules = Vec::new();
+        let mut found = unsafe { Module32FirstW(snapshot, &mut me32) }.is_ok();
+        while found {
+            modules.push(ModuleInfo {
+                name: from_wide(&me32.szModule),
+                path: PathBuf::from(from_wide(&me32.szExePath)),
+                base: me32.modBaseAddr as usize,
+                size: me32.modBaseSize as usize,
+            });
+
+            found = unsafe { Module32NextW(snapshot, &mut me32) }.is_ok();
+        }
+
+        unsafe { CloseHandle(snapshot) }?;
+
+        Ok(modules)
+    }
+
+    /// Find a module loaded in the process by its file name, e.g.
+    /// `game.exe`, ignoring the case. See [`Process::modules`].
+    ///
+    /// Returns `None` if no such module is loaded, or if the modules can't be
+    /// listed.
+    pub fn module(&self, name: &str) -> Option<ModuleInfo> {
+        self.modules().ok()?.into_iter().find(|module| module.name.eq_ignore_ascii_case(name))
+    }
+
+    // Find the base address and the path of a module loaded in the process.
+    fn find_module(&self, module_name: &str) -> Result<(usize, PathBuf), InjectError> {
+        self.modules()?
+            .into_iter()
+            .find(|module| module.name.eq_ignore_ascii_case(module_name))
+            .map(|module| (module.base, module.path))
+            .ok_or(InjectError::ModuleNotFound)
     }
 
     /// Retrieve the process handle.
     pub fn handle(&self) -> HANDLE {
         self.0
     }
+
+    /// Check whether the process is 64-bit.
+    pub fn is_64_bit(&self) -> windows::core::Result<bool> {
+        let mut process_machine = IMAGE_FILE_MACHINE::default();
+        let mut native_machine = IMAGE_FILE_MACHINE::default();
+        unsafe { IsWow64Process2(self.0, &mut process_machine, Some(&mut native_machine)) }?;
+
+        // The process machine is unknown if the process is not running under WOW64,
+        // in which case it has the same architecture as the OS.
+        let machine = if process_machine == IMAGE_FILE_MACHINE_UNKNOWN {
+            native_machine
+        } else {
+            process_machine
+        };
+
+        Ok(is_64_bit_machine(machine))
+    }
+
+    // Make sure the DLL can be loaded by the process.
+    fn check_architecture(&self, dll_path: &Path) -> Result<(), InjectError> {
+        let mut header = Vec::with_capacity(PE_HEADER_READ_SIZE);
+        File::open(dll_path)?.take(PE_HEADER_READ_SIZE as u64).read_to_end(&mut header)?;
+
+        let dll_is_64 = is_64_bit_machine(pe_machine(&header).ok_or(InjectError::InvalidDll)?);
+        let process_is_64 = self.is_64_bit()?;
+
+        debug!("Process is 64-bit: {process_is_64}, DLL is 64-bit: {dll_is_64}");
+
+        if process_is_64 != dll_is_64 {
+            return Err(InjectError::ArchitectureMismatch { process_is_64, dll_is_64 });
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for Process {
@@ -107,8 +599,83 @@ impl Drop for Process {
     }
 }
 
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long an ejected module may take to be freed.
+const EJECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The PE header is found within the first page of the image in practice.
+const PE_HEADER_READ_SIZE: usize = 0x1000;
+
+// Lay out the data passed to `hudhook_init`: its length, then the data.
+fn init_block(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(size_of::<u64>() + data.len());
+    block.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    block.extend_from_slice(data);
+    block
+}
+
+// Read the `Machine` field of the COFF header of a PE image.
+fn pe_machine(image: &[u8]) -> Option<IMAGE_FILE_MACHINE> {
+    if image.get(..2)? != b"MZ" {
+        return None;
+    }
+
+    // The offset comes from the file, and may be anything.
+    let e_lfanew = u32::from_le_bytes(image.get(0x3c..0x40)?.try_into().ok()?) as usize;
+    if image.get(e_lfanew..e_lfanew.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+
+    let machine = image.get(e_lfanew.checked_add(4)?..e_lfanew.checked_add(6)?)?;
+    Some(IMAGE_FILE_MACHINE(u16::from_le_bytes([machine[0], machine[1]])))
+}
+
+fn is_64_bit_machine(machine: IMAGE_FILE_MACHINE) -> bool {
+    matches!(machine, IMAGE_FILE_MACHINE_AMD64 | IMAGE_FILE_MACHINE_ARM64 | IMAGE_FILE_MACHINE_IA64)
+}
+
+// Find the offset of an exported function from the base of the DLL, without
+// running any of its code.
+fn find_export_offset(dll_path: &Path, name: PCSTR) -> Result<Option<usize>, InjectError> {
+    let dll_path = HSTRING::from(dll_path);
+    let module: HMODULE =
+        unsafe { LoadLibraryExW(&dll_path, HANDLE::default(), DONT_RESOLVE_DLL_REFERENCES) }?;
+
+    let offset = unsafe { GetProcAddress(module, name) }
+        .map(|proc_addr| proc_addr as usize - module.0 as usize);
+
+    unsafe { FreeLibrary(module) }?;
+
+    Ok(offset)
+}
+
+// Open the process with the permissions required for injection.
+fn open_process(pid: u32) -> Result<HANDLE, InjectError> {
+    unsafe { OpenProcess(PROCESS_ALL_ACCESS, BOOL(0), pid) }.map_err(|e| {
+        if e.code() == E_ACCESSDENIED {
+            InjectError::AccessDenied
+        } else {
+            InjectError::OpenProcessFailed(e)
+        }
+    })
+}
+
+// Close a handle, logging a failure, as with `Process::free_remote`.
+fn close_handle(handle: HANDLE) {
+    if let Err(e) = unsafe { CloseHandle(handle) } {
+        error!("Couldn't close handle: {e}");
+    }
+}
+
+// Convert a null-terminated UTF-16 buffer.
+fn from_wide(buf: &[u16]) -> String {
+    let zero_idx = buf.iter().position(|&x| x == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..zero_idx])
+}
+
 // Find process given the title of one of its windows.
-fn get_process_by_title(title: &str) -> Result<HANDLE> {
+fn get_process_by_title(title: &str) -> Result<HANDLE, InjectError> {
     if cfg!(target_arch = "x86") {
         unsafe { get_process_by_title32(title) }
     } else if cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
@@ -119,37 +686,37 @@ fn get_process_by_title(title: &str) -> Result<HANDLE> {
 }
 
 // 32-bit implementation. Uses [`std::ffi::CString`] and `FindWindowA`.
-unsafe fn get_process_by_title32(title: &str) -> Result<HANDLE> {
+unsafe fn get_process_by_title32(title: &str) -> Result<HANDLE, InjectError> {
     let title = HSTRING::from(title).to_os_string();
     let hwnd = FindWindowA(None, PCSTR(title.as_encoded_bytes().as_ptr()));
 
     if hwnd.0 == 0 {
-        return Err(Error::from_win32());
+        return Err(InjectError::ProcessNotFound);
     }
 
     let mut pid: u32 = 0;
     GetWindowThreadProcessId(hwnd, Some(&mut pid));
 
-    OpenProcess(PROCESS_ALL_ACCESS, BOOL(0), pid)
+    open_process(pid)
 }
 
 // 64-bit implementation. Uses [`widestring::U16CString`] and `FindWindowW`.
-unsafe fn get_process_by_title64(title: &str) -> Result<HANDLE> {
+unsafe fn get_process_by_title64(title: &str) -> Result<HANDLE, InjectError> {
     let title = HSTRING::from(title);
     let hwnd = FindWindowW(None, PCWSTR(title.as_ptr()));
 
     if hwnd.0 == 0 {
-        return Err(Error::from_win32());
+        return Err(InjectError::ProcessNotFound);
     }
 
     let mut pid: u32 = 0;
     GetWindowThreadProcessId(hwnd, Some(&mut pid));
 
-    OpenProcess(PROCESS_ALL_ACCESS, BOOL(0), pid)
+    open_process(pid)
 }
 
 // Find process given the process name.
-fn get_process_by_name(name: &str) -> Result<HANDLE> {
+fn get_process_by_name(name: &str) -> Result<HANDLE, InjectError> {
     if cfg!(target_arch = "x86") {
         unsafe { get_process_by_name32(name) }
     } else if cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
@@ -160,7 +727,7 @@ fn get_process_by_name(name: &str) -> Result<HANDLE> {
 }
 
 // 32-bit implementation. Uses [`PROCESSENTRY32`].
-unsafe fn get_process_by_name32(name_str: &str) -> Result<HANDLE> {
+unsafe fn get_process_by_name32(name_str: &str) -> Result<HANDLE, InjectError> {
     let name = HSTRING::from(name_str).to_os_string();
     let name = name.as_encoded_bytes();
 
@@ -170,7 +737,7 @@ unsafe fn get_process_by_name32(name_str: &str) -> Result<HANDLE> {
 
     if Process32First(snapshot, &mut pe32).is_err() {
         CloseHandle(snapshot)?;
-        return Err(Error::from_win32());
+        return Err(Error::from_win32().into());
     }
 
     let pid = loop {
@@ -183,17 +750,17 @@ unsafe fn get_process_by_name32(name_str: &str) -> Result<HANDLE> {
 
         if Process32Next(snapshot, &mut pe32).is_err() {
             CloseHandle(snapshot)?;
-            break Err(Error::from_hresult(HRESULT(-1)));
+            break Err(InjectError::ProcessNotFound);
         }
     }?;
 
     CloseHandle(snapshot)?;
 
-    OpenProcess(PROCESS_ALL_ACCESS, BOOL(0), pid)
+    open_process(pid)
 }
 
 // 64-bit implementation. Uses [`PROCESSENTRY32W`].
-unsafe fn get_process_by_name64(name_str: &str) -> Result<HANDLE> {
+unsafe fn get_process_by_name64(name_str: &str) -> Result<HANDLE, InjectError> {
     let name = HSTRING::from(name_str);
 
     let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
@@ -202,7 +769,7 @@ unsafe fn get_process_by_name64(name_str: &str) -> Result<HANDLE> {
 
     if Process32FirstW(snapshot, &mut pe32).is_err() {
         CloseHandle(snapshot)?;
-        return Err(Error::from_win32());
+        return Err(Error::from_win32().into());
     }
 
     let pid = loop {
@@ -215,11 +782,104 @@ unsafe fn get_process_by_name64(name_str: &str) -> Result<HANDLE> {
 
         if Process32NextW(snapshot, &mut pe32).is_err() {
             CloseHandle(snapshot)?;
-            break Err(Error::from_hresult(HRESULT(-1)));
+            break Err(InjectError::ProcessNotFound);
         }
     }?;
 
     CloseHandle(snapshot)?;
 
-    OpenProcess(PROCESS_ALL_ACCESS, BOOL(0), pid)
+    open_process(pid)
+}
+
+// Find a process with the given name, spawned by the process with the given
+// PID.
+unsafe fn find_child_process(parent_pid: u32, name: &str) -> Result<Option<u32>, InjectError> {
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+    let mut pe32 =
+        PROCESSENTRY32W { dwSize: mem::size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+
+    let mut found = Process32FirstW(snapshot, &mut pe32).is_ok();
+    let pid = loop {
+        if !found {
+            break None;
+        }
+
+        let zero_idx = pe32.szExeFile.iter().position(|&x| x == 0).unwrap_or(pe32.szExeFile.len());
+        let proc_name = String::from_utf16_lossy(&pe32.szExeFile[..zero_idx]);
+
+        if pe32.th32ParentProcessID == parent_pid && proc_name.eq_ignore_ascii_case(name) {
+            break Some(pe32.th32ProcessID);
+        }
+
+        found = Process32NextW(snapshot, &mut pe32).is_ok();
+    };
+
+    CloseHandle(snapshot)?;
+
+    Ok(pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_I386;
+
+    use super::*;
+
+    fn pe_image(machine: IMAGE_FILE_MACHINE) -> Vec<u8> {
+        let mut image = vec![0u8; 0x100];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        image[0x80..0x84].copy_from_slice(b"PE\0\0");
+        image[0x84..0x86].copy_from_slice(&machine.0.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn test_pe_machine() {
+        assert_eq!(pe_machine(&pe_image(IMAGE_FILE_MACHINE_AMD64)), Some(IMAGE_FILE_MACHINE_AMD64));
+        assert_eq!(pe_machine(&pe_image(IMAGE_FILE_MACHINE_I386)), Some(IMAGE_FILE_MACHINE_I386));
+        assert!(is_64_bit_machine(IMAGE_FILE_MACHINE_AMD64));
+        assert!(!is_64_bit_machine(IMAGE_FILE_MACHINE_I386));
+
+        assert_eq!(pe_machine(b"MZ"), None);
+        assert_eq!(pe_machine(&[0u8; 0x100]), None);
+
+        let mut truncated = pe_image(IMAGE_FILE_MACHINE_AMD64);
+        truncated.truncate(0x85);
+        assert_eq!(pe_machine(&truncated), None);
+
+        let mut out_of_bounds = pe_image(IMAGE_FILE_MACHINE_AMD64);
+        out_of_bounds[0x3c..0x40].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(pe_machine(&out_of_bounds), None);
+    }
+
+    #[test]
+    fn test_modules() -> Result<(), InjectError> {
+        let process = Process(open_process(std::process::id())?);
+
+        let modules = process.modules()?;
+        let exe = std::env::current_exe()?;
+        let exe_name = exe.file_name().unwrap().to_string_lossy();
+        assert!(modules
+            .iter()
+            .any(|module| module.name.eq_ignore_ascii_case(&exe_name) && module.size > 0));
+
+        let kernel32 = process.module("KERNEL32.DLL").expect("kernel32 is loaded");
+        assert!(kernel32.name.eq_ignore_ascii_case("kernel32.dll"));
+        assert_eq!(kernel32.base, unsafe { GetModuleHandleW(w!("kernel32.dll")) }?.0 as usize);
+        assert!(process.module("not-a-module.dll").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_block() {
+        let block = init_block(b"hello");
+        assert_eq!(block.len(), 13);
+        assert_eq!(unsafe { crate::read_init_block(block.as_ptr() as *const c_void) }, b"hello");
+
+        let block = init_block(&[]);
+        assert_eq!(block, [0u8; 8]);
+        assert!(unsafe { crate::read_init_block(block.as_ptr() as *const c_void) }.is_empty());
+    }
 }
@@ -4,7 +4,8 @@
 //! render loop of applications and drawing things on screen via
 //! [`dear imgui`](https://docs.rs/imgui/0.11.0/imgui/).
 //!
-//! Currently, DirectX9, DirectX 11, DirectX 12 and OpenGL 3 are supported.
+//! Currently, DirectX9, DirectX 10, DirectX 11, DirectX 12 and OpenGL 3 are
+//! supported.
 //!
 //! For complete, fully fledged examples of usage, check out the following
 //! projects:
@@ -31,7 +32,9 @@
 //! manipulation and UI rendering. It does, alas, contain a hefty amount of FFI
 //! and `unsafe` code which still has to be thoroughly tested, validated and
 //! audited for soundness. It should be OK for small projects such as videogame
-//! mods, but it may crash your application at this stage.
+//! mods, but it may crash your application at this stage. Panics in your
+//! render loop, at least, are caught instead of taking the application down:
+//! see [`HudhookBuilder::with_catch_panics`].
 //!
 //! ## Examples
 //!
@@ -73,6 +76,12 @@
 //! }
 //!
 //! {
+//!     // Use this if hooking into a DirectX 10 application.
+//!     use hudhook::hooks::dx10::ImguiDx10Hooks;
+//!     hudhook!(ImguiDx10Hooks, MyRenderLoop);
+//! }
+//!
+//! {
 //!     // Use this if hooking into a DirectX 11 application.
 //!     use hudhook::hooks::dx11::ImguiDx11Hooks;
 //!     hudhook!(ImguiDx11Hooks, MyRenderLoop);
@@ -114,54 +123,590 @@
 #![allow(static_mut_refs)]
 #![deny(missing_docs)]
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::any;
+use std::ffi::c_void;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use imgui::{Context, Io, TextureId, Ui};
+use imgui::{Context, FontId, Io, TextureId, Ui};
 use once_cell::sync::OnceCell;
-use tracing::error;
-use windows::core::Error;
-use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, WPARAM};
+use parking_lot::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, error};
+use windows::core::{Error, HRESULT};
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Direct3D9::IDirect3DDevice9;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT;
+use windows::Win32::Graphics::Dxgi::{IDXGISwapChain, DXGI_SWAP_CHAIN_DESC};
 use windows::Win32::System::Console::{
     AllocConsole, FreeConsole, GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE,
     ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_OUTPUT_HANDLE,
 };
 use windows::Win32::System::LibraryLoader::FreeLibraryAndExitThread;
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
 pub use {imgui, tracing, windows};
 
-use crate::mh::{MH_ApplyQueued, MH_Initialize, MH_Uninitialize, MhHook, MH_STATUS};
+use crate::mh::{MH_ApplyQueued, MH_Initialize, MH_Uninitialize, MhHook, RawHook, MH_STATUS};
+use crate::renderer::config::{Layer, PipelineConfig, PIPELINE_CONFIG};
 
+mod diagnostics;
+mod error;
 pub mod hooks;
 #[cfg(feature = "inject")]
 pub mod inject;
+mod logging;
+pub mod memory;
+mod message;
 pub mod mh;
+pub mod proxy;
 pub(crate) mod renderer;
 
+pub use diagnostics::DiagnosticEvent;
+pub use error::{HudhookError, Result};
+pub use message::{Message, MessageSender};
+pub use renderer::color_space::ColorSpace;
+pub use renderer::config::{
+    Dx12CommandQueue, InputMode, NavMouseWarp, StylePreset, WindowLimitAction,
+};
+pub use renderer::keys::{Keybind, Modifiers};
 pub use renderer::msg_filter::MessageFilter;
+pub use renderer::texture::{
+    AlphaMode, DeviceHandle, PixelFormat, RenderTargetHandle, TextureFilter, TextureHandle,
+    TextureOptions,
+};
 
 pub mod util;
 
 // Global state objects.
 static mut MODULE: OnceCell<HINSTANCE> = OnceCell::new();
 static mut HUDHOOK: OnceCell<Hudhook> = OnceCell::new();
+static HOOK_STATUS: AtomicU8 = AtomicU8::new(HookStatus::NotApplied as u8);
 static CONSOLE_ALLOCATED: AtomicBool = AtomicBool::new(false);
+static INIT_DATA: OnceCell<Vec<u8>> = OnceCell::new();
 
 /// Texture Loader for ImguiRenderLoop callbacks to load and replace textures
 pub trait RenderContext {
     /// Load texture and return TextureId to use. Invoke it in your
     /// [`crate::ImguiRenderLoop::initialize`] method for setting up textures.
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId, Error>;
+    ///
+    /// `data` holds `width * height` RGBA8 pixels with straight alpha, in
+    /// tightly packed rows from top to bottom. Use
+    /// [`RenderContext::load_texture_with_format`] for other layouts and
+    /// [`RenderContext::load_texture_with_alpha_mode`] for premultiplied data.
+    ///
+    /// The caller owns one reference to the texture, which can be released
+    /// with [`RenderContext::unload_texture`].
+    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId, Error> {
+        self.load_texture_with_format(data, width, height, PixelFormat::Rgba8)
+    }
+
+    /// Load texture data whose pixels are laid out as described by `format`.
+    /// See [`RenderContext::load_texture`].
+    ///
+    /// This saves converting e.g. BGRA captures or grayscale masks on the CPU
+    /// first: the renderers either sample them natively or convert them while
+    /// uploading.
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId, Error>;
+
+    /// Load texture data with the given filter and mipmaps, laid out as
+    /// described by the format in `options`. See
+    /// [`RenderContext::load_texture`].
+    ///
+    /// The mipmaps are generated on the CPU, and uploaded along with the
+    /// texture. [`RenderContext::replace_texture_with_format`] generates them
+    /// again. The GDI renderer draws textures without filtering, and ignores
+    /// the options besides the format.
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId, Error> {
+        if options.filter != TextureFilter::Linear || options.mipmaps {
+            error!("Texture options {options:?} aren't supported here");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        self.load_texture_with_format(data, width, height, options.format)
+    }
+
+    /// Load texture data whose alpha is described by `alpha_mode`, converting
+    /// it to straight alpha first if needed. See
+    /// [`RenderContext::load_texture`].
+    fn load_texture_with_alpha_mode(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        alpha_mode: AlphaMode,
+    ) -> Result<TextureId, Error> {
+        match alpha_mode {
+            AlphaMode::Straight => self.load_texture(data, width, height),
+            AlphaMode::Premultiplied => {
+                let mut data = data.to_vec();
+                util::unpremultiply_alpha(&mut data);
+                self.load_texture(&data, width, height)
+            },
+        }
+    }
+
+    /// Decode a PNG, JPEG or WebP image and load it as a texture via
+    /// [`RenderContext::load_texture`]. Return the texture id along with the
+    /// width and height of the image, e.g. to size an [`imgui::Image`].
+    ///
+    /// The pixels are converted to RGBA8 with straight alpha, which is what
+    /// the renderers expect.
+    #[cfg(feature = "image")]
+    fn load_texture_from_bytes(&mut self, bytes: &[u8]) -> Result<(TextureId, u32, u32), Error> {
+        let image = match image::load_from_memory(bytes) {
+            Ok(image) => image.into_rgba8(),
+            Err(e) => {
+                error!("Couldn't decode image: {e}");
+                return Err(Error::from_hresult(HRESULT(-1)));
+            },
+        };
+
+        let (width, height) = image.dimensions();
+        let texture_id = self.load_texture(image.as_raw(), width, height)?;
+        Ok((texture_id, width, height))
+    }
+
+    /// Read an image file and load it as a texture. See
+    /// [`RenderContext::load_texture_from_bytes`].
+    #[cfg(feature = "image")]
+    fn load_texture_from_path(&mut self, path: &Path) -> Result<(TextureId, u32, u32), Error> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Couldn't read {}: {e}", path.display());
+                return Err(Error::from_hresult(HRESULT(-1)));
+            },
+        };
+
+        self.load_texture_from_bytes(&bytes)
+    }
 
     /// Upload an image to an existing texture, replacing its content. Invoke it
     /// in your [`crate::ImguiRenderLoop::before_render`] method for
     /// updating textures.
+    ///
+    /// `data` has the same format as in [`RenderContext::load_texture`];
+    /// premultiplied data can be converted with
+    /// [`util::unpremultiply_alpha`].
+    ///
+    /// The content is uploaded into the existing GPU resource, which is never
+    /// reallocated, so this is cheap enough to stream e.g. a video into the
+    /// texture on every frame. `width` and `height` must be the size the
+    /// texture was loaded with; load a new texture for a different size.
     fn replace_texture(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+    ) -> Result<(), Error> {
+        self.replace_texture_with_format(texture_id, data, width, height, PixelFormat::Rgba8)
+    }
+
+    /// Upload an image laid out as described by `format` to an existing
+    /// texture. The format must be the one the texture was loaded with.
+    fn replace_texture_with_format(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
     ) -> Result<(), Error>;
+
+    /// Release a reference to a texture. The texture is freed when no
+    /// references to it are left, including the ones held by
+    /// [`TextureHandle`]s.
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<(), Error>;
+
+    /// Like [`RenderContext::load_texture`], but return a handle that owns the
+    /// only reference to the texture, instead of its id. The texture is freed
+    /// once the handle and its clones are dropped, e.g. along with the panel
+    /// displaying it, without having to call [`RenderContext::unload_texture`].
+    fn load_texture_handle(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<TextureHandle, Error> {
+        let texture_id = self.load_texture(data, width, height)?;
+        let handle = match self.texture_handle(texture_id) {
+            Ok(handle) => handle,
+            Err(e) => {
+                self.unload_texture(texture_id).ok();
+                return Err(e);
+            },
+        };
+        self.unload_texture(texture_id)?;
+
+        Ok(handle)
+    }
+
+    /// Create a texture to render to, e.g. a 3D model preview or an effect of
+    /// your own, and display it with [`imgui::Image`] like any loaded
+    /// texture. Unload it with [`RenderContext::unload_texture`].
+    ///
+    /// Draw to it in [`ImguiRenderLoop::before_render`] with the device
+    /// obtained via [`RenderContext::with_swap_chain`] or
+    /// [`RenderContext::with_d3d9_device`], or the current OpenGL context,
+    /// through the objects in the returned [`RenderTargetHandle`]. Restore any
+    /// state you change, as the application's frame is still being rendered.
+    ///
+    /// On DirectX 12, record the draws in a command list of your own, with
+    /// the barriers described in [`RenderTargetHandle::D3D12`], then execute
+    /// it and wait on a fence for it to complete before returning, so that
+    /// it's done before the overlay samples the texture.
+    ///
+    /// Not supported by the GDI renderer.
+    fn create_render_texture(
+        &mut self,
+        _width: u32,
+        _height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle), Error> {
+        error!("Render textures aren't supported here");
+        Err(Error::from_hresult(HRESULT(-1)))
+    }
+
+    /// Acquire a new reference to a loaded texture, released when the returned
+    /// handle is dropped. Use it to share a texture between multiple widgets
+    /// or render loops without any of them freeing it prematurely.
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle, Error>;
+
+    /// The width and height of a loaded texture, in pixels, e.g. to size an
+    /// [`imgui::Image`] widget. Returns `None` if the texture doesn't exist,
+    /// and while the frame is built on another thread via
+    /// [`HudhookBuilder::with_async_frames`].
+    fn texture_size(&self, _texture_id: TextureId) -> Option<[f32; 2]> {
+        None
+    }
+
+    /// Read back the current content of the back buffer the overlay renders
+    /// to, and return its width, height and tightly-packed RGBA8 pixels.
+    ///
+    /// Invoked in [`crate::ImguiRenderLoop::before_render`], it captures the
+    /// application's frame alone; invoked in
+    /// [`crate::ImguiRenderLoop::after_render`], it captures it with the
+    /// overlay on top.
+    ///
+    /// The readback waits for the GPU to finish rendering the frame, so it
+    /// stalls the frame it is invoked in. Back buffers in formats other than
+    /// 8-bit RGBA or BGRA, e.g. HDR ones, aren't supported.
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>), Error>;
+
+    /// Invoke `f` with the swap chain the overlay is being rendered to, e.g. to
+    /// query monitor or fullscreen information via
+    /// `IDXGISwapChain::GetContainingOutput` or
+    /// `IDXGISwapChain::GetFullscreenState`.
+    ///
+    /// Only available on DirectX 10, 11 and 12; returns `None` without
+    /// invoking `f` on other backends.
+    ///
+    /// The swap chain belongs to the host application. Mutating its state is
+    /// dangerous: `f` must not change the fullscreen state, resize the buffers
+    /// or present.
+    fn with_swap_chain(&self, _f: &mut dyn FnMut(&IDXGISwapChain)) -> Option<()> {
+        None
+    }
+
+    /// Invoke `f` with the DirectX 9 device the overlay is being rendered
+    /// with. This is the DirectX 9 equivalent of
+    /// [`RenderContext::with_swap_chain`].
+    ///
+    /// Only available on DirectX 9; returns `None` without invoking `f` on
+    /// other backends.
+    ///
+    /// The device belongs to the host application. Mutating its state is
+    /// dangerous: `f` must not reset the device or present.
+    fn with_d3d9_device(&self, _f: &mut dyn FnMut(&IDirect3DDevice9)) -> Option<()> {
+        None
+    }
+
+    /// The device the overlay renders with, e.g. to read the application's
+    /// render targets or run compute passes of your own. See
+    /// [`DeviceHandle`] for what the device can and can't be used for.
+    ///
+    /// Only available on DirectX 9, 10, 11 and 12; returns `None` on other
+    /// backends, and while the frame is built on another thread via
+    /// [`HudhookBuilder::with_async_frames`]. On OpenGL, use the current
+    /// context instead.
+    fn device_handle(&self) -> Option<DeviceHandle> {
+        None
+    }
+
+    /// Whether the overlay is currently visible. See
+    /// [`RenderContext::set_visible`].
+    fn is_visible(&self) -> bool {
+        true
+    }
+
+    /// Show or hide the overlay. While hidden, frames are still processed and
+    /// [`ImguiRenderLoop::render`] is still invoked, but nothing gets drawn
+    /// and [`ImguiRenderLoop::message_filter`] is ignored, so that the
+    /// application receives all the input.
+    ///
+    /// This overrides the state set by the key configured via
+    /// [`HudhookBuilder::with_toggle_key`], which keeps toggling from there.
+    fn set_visible(&mut self, _visible: bool) {}
+
+    /// Whether the overlay takes input while visible. See
+    /// [`RenderContext::set_input_enabled`].
+    fn is_input_enabled(&self) -> bool {
+        true
+    }
+
+    /// Let the overlay take input while visible, or not, e.g. to show a
+    /// passive HUD at all times and only grab the input while a menu is open.
+    ///
+    /// While disabled, the overlay is still drawn, but
+    /// [`ImguiRenderLoop::message_filter`] is ignored and the cursor is left
+    /// to the application as with [`RenderContext::set_visible`], and the
+    /// `want_capture_*` and `want_text_input` flags of [`Io`] are cleared
+    /// before [`ImguiRenderLoop::message_filter`] is called. imgui still
+    /// receives the input, so the windows of a passive HUD should be created
+    /// with [`imgui::WindowFlags::NO_INPUTS`].
+    fn set_input_enabled(&mut self, _enabled: bool) {}
+
+    /// Set who is in charge of the mouse cursor while the overlay wants the
+    /// mouse, e.g. to confine it to the window while a modal dialog is open.
+    /// The mode applies from the next window message on, usually as soon as
+    /// the mouse moves. See [`CursorMode`].
+    fn set_cursor_mode(&mut self, _mode: CursorMode) {}
+
+    /// The [`Io`] of the imgui context, if available.
+    fn io(&self) -> Option<&Io> {
+        None
+    }
+
+    /// The [`Io`] of the imgui context, if available, to tweak the input from
+    /// any callback, including [`ImguiRenderLoop::render_with_context`].
+    ///
+    /// imgui reads the input at the start of each frame, and writes the
+    /// `want_*` flags from it. Changes to the input made while a frame is
+    /// being built, such as the mouse position, only apply to the next one,
+    /// and the `want_*` flags set here are overwritten when it starts: set
+    /// them while building a frame so that they apply to the window messages
+    /// received until the next one, as is the case with
+    /// [`ImguiRenderLoop::message_filter`]. [`Io::display_size`] and the fonts
+    /// must not be changed while a frame is being built.
+    fn io_mut(&mut self) -> Option<&mut Io> {
+        None
+    }
+
+    /// The raw imgui context the overlay is drawn with, or null if not
+    /// available, for modules that link imgui on their own, e.g. plugins
+    /// loaded as separate DLLs, to draw into it.
+    ///
+    /// Each module linking imgui has its own copy of its globals, including
+    /// the current context and the allocator, so a plugin has to be handed
+    /// both by the host:
+    ///
+    /// - the context, to make it current in its copy via
+    ///   `igSetCurrentContext` before calling any imgui function, which
+    ///   gives it access to the host's IO and font atlas too, as they belong
+    ///   to the context;
+    /// - the allocator functions, from
+    ///   [`imgui::sys::igGetAllocatorFunctions`] in the host, to pass to
+    ///   `igSetAllocatorFunctions` first, so that what either side allocates
+    ///   in the context can be freed by the other.
+    ///
+    /// The plugin must only draw in between the host's callbacks, e.g. from
+    /// [`ImguiRenderLoop::render`], as the context is only valid and being
+    /// built then, and must not destroy it nor keep it past
+    /// [`Hudhook::eject`].
+    ///
+    /// The context is passed as is across the module boundary, and its
+    /// layout depends on the exact version of dear imgui, its configuration
+    /// (e.g. `IMGUI_USE_WCHAR32`) and its enabled features (e.g. docking),
+    /// none of which is checked. The plugin must be built against the very
+    /// same `imgui-sys` release, with the same hudhook features enabled, as
+    /// the host: check `igGetVersion` on both sides before sharing the
+    /// context, at the very least. Any mismatch is undefined behavior, and
+    /// usually crashes the application.
+    fn raw_imgui_context(&self) -> *mut imgui::sys::ImGuiContext {
+        ptr::null_mut()
+    }
+
+    /// The size of the display in pixels, i.e. [`Io::display_size`], or
+    /// `[0., 0.]` if the [`Io`] isn't available. See
+    /// [`ImguiRenderLoop::on_resize`] to be told when it changes.
+    fn display_size(&self) -> [f32; 2] {
+        self.io().map(|io| io.display_size).unwrap_or_default()
+    }
+
+    /// The part of the display the application's content is shown in, as
+    /// `[min, max]` in pixels, e.g. without the black bars around a
+    /// letterboxed image. It's the whole display, `[[0., 0.],
+    /// display_size]`, unless set via
+    /// [`HudhookBuilder::with_content_rect`].
+    fn content_rect(&self) -> [[f32; 2]; 2] {
+        [[0., 0.], self.display_size()]
+    }
+
+    /// Whether the key of `keybind` went down since the previous frame, while
+    /// its modifiers were held. Auto-repeat doesn't count, and a key pressed
+    /// and released in between two frames does.
+    ///
+    /// The keys are tracked from the window messages, so this works whether or
+    /// not imgui wants the keyboard and the overlay is visible.
+    fn keybind_pressed(&self, _keybind: &Keybind) -> bool {
+        false
+    }
+
+    /// Whether the key of `keybind` is held along with its modifiers. See
+    /// [`RenderContext::keybind_pressed`].
+    fn keybind_held(&self, _keybind: &Keybind) -> bool {
+        false
+    }
+
+    /// The modifiers held, tracked like the keys of
+    /// [`RenderContext::keybind_pressed`], whether or not imgui wants the
+    /// keyboard.
+    fn modifiers(&self) -> Modifiers {
+        Modifiers::default()
+    }
+
+    /// How far the mouse moved since the previous frame, in pixels, whether
+    /// or not imgui wants the mouse, e.g. to drive a camera.
+    ///
+    /// The movement is reported by the mouse itself if the application
+    /// registered for raw input, as games usually do, in which case it isn't
+    /// affected by the cursor being clipped or warped. Otherwise, it's
+    /// measured from the cursor position in the window.
+    fn raw_mouse_delta(&self) -> [f32; 2] {
+        [0., 0.]
+    }
+
+    /// The current title of the application window the overlay is rendered
+    /// on. It is read anew on every call, so it reflects titles that change
+    /// at runtime. Returns an empty string if not available.
+    fn window_title(&self) -> String {
+        String::new()
+    }
+
+    /// The application window the overlay is rendered on, i.e. the one that
+    /// receives the input. Unlike [`hooks::find_process_hwnd`], this is the
+    /// window the hooked swap chain or device presents to, even if the
+    /// process has more than one top level window. Returns a null handle if
+    /// not available.
+    fn target_hwnd(&self) -> HWND {
+        HWND::default()
+    }
+
+    /// The format of the back buffers of the swap chain the overlay is being
+    /// rendered to, e.g. to tell whether the application renders in HDR.
+    ///
+    /// Only available on DirectX 10, 11 and 12; returns `None` on other
+    /// backends. See [`RenderContext::with_swap_chain`].
+    fn swap_chain_format(&self) -> Option<DXGI_FORMAT> {
+        let mut format = None;
+        self.with_swap_chain(&mut |swap_chain| {
+            format = util::try_out_param(|v| unsafe { swap_chain.GetDesc(v) })
+                .map(|desc| desc.BufferDesc.Format)
+                .ok();
+        })?;
+        format
+    }
+
+    /// The color space the swap chain the overlay is being rendered to is
+    /// presented in, guessed from the format of its back buffers and whether
+    /// its display is in HDR mode. Convert colors with
+    /// [`ColorSpace::convert_srgb`] for them to look as they would in SDR.
+    ///
+    /// Only available on DirectX 10, 11 and 12; returns `None` on other
+    /// backends. See [`RenderContext::with_swap_chain`].
+    fn swap_chain_color_space(&self) -> Option<ColorSpace> {
+        let mut color_space = None;
+        self.with_swap_chain(&mut |swap_chain| {
+            color_space = Some(ColorSpace::of_swap_chain(swap_chain));
+        })?;
+        color_space
+    }
+
+    /// The frame rate the presents are limited to, if any. See
+    /// [`RenderContext::set_frame_limit`].
+    fn frame_limit(&self) -> Option<f32> {
+        None
+    }
+
+    /// Limit the frame rate of the application to `fps` frames per second, or
+    /// lift the limit with `None`. See [`HudhookBuilder::with_frame_limit`].
+    fn set_frame_limit(&mut self, _fps: Option<f32>) {}
+
+    /// The number of frames presented with the overlay so far, not counting
+    /// the one being prepared.
+    fn frame_count(&self) -> u64 {
+        0
+    }
+
+    /// The time between the two last presents of the application, i.e. its
+    /// actual frame time, which is also what [`imgui::Io::delta_time`] is set
+    /// to. Zero until two frames have been presented.
+    fn last_present_duration(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Add a TrueType font of `size_px` pixels to the font atlas and return
+    /// its id, to be used with [`imgui::Ui::push_font`].
+    ///
+    /// `glyph_ranges` lists pairs of first and last code points to include,
+    /// terminated by a `0`, e.g. `&[0x20, 0xff, 0x3040, 0x30ff, 0]` for Latin-1
+    /// and Japanese kana. An empty list only includes Basic Latin. If no font
+    /// was added before the atlas was first built, the first one added becomes
+    /// the default font.
+    ///
+    /// The font atlas is rebuilt and its texture uploaded again before the
+    /// next frame, so fonts can be added in [`ImguiRenderLoop::initialize`] as
+    /// well as later on. `data` is copied, and must be a valid TrueType font.
+    ///
+    /// The id can be kept and pushed in any later frame, and any number of
+    /// fonts can be pushed in the same frame. It stays valid when the atlas is
+    /// rebuilt, be it after adding more fonts, via
+    /// [`RenderContext::rebuild_fonts`] or to follow a DPI change, as only the
+    /// font's glyphs are rasterized anew. It's only invalidated by removing
+    /// the fonts from the atlas, e.g. via [`imgui::FontAtlas::clear`], after
+    /// which the fonts have to be added again.
+    fn add_font(
+        &mut self,
+        _data: &[u8],
+        _size_px: f32,
+        _glyph_ranges: &[u32],
+    ) -> Result<FontId, Error> {
+        error!("Fonts can only be added through the render context of a pipeline");
+        Err(Error::from_hresult(HRESULT(-1)))
+    }
+
+    /// Rebuild the font atlas and upload its texture again before the next
+    /// frame, e.g. after changing the fonts or their sizes via
+    /// [`imgui::Context::fonts`] to follow a UI scale set by the user. The
+    /// previous font texture is freed.
+    ///
+    /// The ids returned by [`RenderContext::add_font`] and
+    /// [`imgui::FontAtlas::add_font`] remain valid across the rebuild, unless
+    /// the fonts were cleared from the atlas beforehand.
+    ///
+    /// Errors while rebuilding are returned from the next frame instead.
+    fn rebuild_fonts(&mut self) -> Result<(), Error> {
+        error!("Fonts can only be rebuilt through the render context of a pipeline");
+        Err(Error::from_hresult(HRESULT(-1)))
+    }
 }
 
 /// Allocate a Windows console.
@@ -174,24 +719,30 @@ pub fn alloc_console() -> Result<(), Error> {
 }
 
 /// Enable console colors if the console is allocated.
+///
+/// Colors are cosmetic, so failing to enable them is only logged.
 pub fn enable_console_colors() {
     if CONSOLE_ALLOCATED.load(Ordering::SeqCst) {
-        unsafe {
-            // Get the stdout handle
-            let stdout_handle = GetStdHandle(STD_OUTPUT_HANDLE).unwrap();
+        if let Err(e) = unsafe { enable_virtual_terminal_processing() } {
+            error!("Couldn't enable console colors: {e:?}");
+        }
+    }
+}
 
-            // Call GetConsoleMode to get the current mode of the console
-            let mut current_console_mode = CONSOLE_MODE(0);
-            GetConsoleMode(stdout_handle, &mut current_console_mode).unwrap();
+unsafe fn enable_virtual_terminal_processing() -> Result<(), Error> {
+    // Get the stdout handle
+    let stdout_handle = GetStdHandle(STD_OUTPUT_HANDLE)?;
 
-            // Set the new mode to include ENABLE_VIRTUAL_TERMINAL_PROCESSING for ANSI
-            // escape sequences
-            current_console_mode.0 |= ENABLE_VIRTUAL_TERMINAL_PROCESSING.0;
+    // Call GetConsoleMode to get the current mode of the console
+    let mut current_console_mode = CONSOLE_MODE(0);
+    GetConsoleMode(stdout_handle, &mut current_console_mode)?;
 
-            // Call SetConsoleMode to set the new mode
-            SetConsoleMode(stdout_handle, current_console_mode).unwrap();
-        }
-    }
+    // Set the new mode to include ENABLE_VIRTUAL_TERMINAL_PROCESSING for ANSI
+    // escape sequences
+    current_console_mode.0 |= ENABLE_VIRTUAL_TERMINAL_PROCESSING.0;
+
+    // Call SetConsoleMode to set the new mode
+    SetConsoleMode(stdout_handle, current_console_mode)
 }
 
 /// Free the previously allocated Windows console.
@@ -212,8 +763,14 @@ pub fn free_console() -> Result<(), Error> {
 /// been created before) and invoke
 /// [`windows::Win32::System::LibraryLoader::FreeLibraryAndExitThread`].
 ///
-/// Befor calling [`eject`], make sure to perform any manual cleanup (e.g.
-/// dropping/resetting the contents of static mutable variables).
+/// The hooks are disabled and their render loops dropped first, then the
+/// callbacks set via [`HudhookBuilder::with_unload_callback`] are called,
+/// and only then is the DLL freed. Perform any manual cleanup there (e.g.
+/// flushing files, joining threads, dropping/resetting the contents of static
+/// mutable variables), as nothing of the DLL can run once it's freed.
+///
+/// DLLs built with the [`hudhook!`] macro can also be ejected from the
+/// injector process via [`Process::eject`](crate::inject::Process::eject).
 pub fn eject() {
     thread::spawn(|| unsafe {
         if let Err(e) = free_console() {
@@ -221,6 +778,7 @@ pub fn eject() {
         }
 
         if let Some(mut hudhook) = HUDHOOK.take() {
+            HOOK_STATUS.store(HookStatus::NotApplied as u8, Ordering::SeqCst);
             if let Err(e) = hudhook.unapply() {
                 error!("Couldn't unapply hooks: {e:?}");
             }
@@ -232,6 +790,59 @@ pub fn eject() {
     });
 }
 
+/// The data passed to the DLL by
+/// [`Process::inject_with_data`](crate::inject::Process::inject_with_data), if
+/// any has been received yet.
+///
+/// The data is handed over after the DLL has been loaded, so it's usually
+/// not there yet in `DllMain`, nor while the hooks are built by the
+/// [`hudhook!`] macro: use [`wait_for_init_data`] there.
+pub fn init_data() -> Option<&'static [u8]> {
+    INIT_DATA.get().map(Vec::as_slice)
+}
+
+/// Wait up to `timeout` for the data passed to the DLL by
+/// [`Process::inject_with_data`](crate::inject::Process::inject_with_data).
+///
+/// Returns `None` if no data arrived in time, e.g. because the DLL was
+/// injected with [`Process::inject`](crate::inject::Process::inject) or by
+/// another tool.
+pub fn wait_for_init_data(timeout: Duration) -> Option<&'static [u8]> {
+    let start = Instant::now();
+    loop {
+        if let Some(data) = init_data() {
+            return Some(data);
+        }
+
+        if start.elapsed() >= timeout {
+            return None;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Store the data passed by the injector. Called by the `hudhook_init`
+/// export generated by the [`hudhook!`] macro.
+///
+/// # Safety
+///
+/// `block` must point to a little-endian `u64` length, followed by that many
+/// bytes.
+#[doc(hidden)]
+pub unsafe fn set_init_data(block: *const std::ffi::c_void) {
+    let data = read_init_block(block).to_vec();
+    if INIT_DATA.set(data).is_err() {
+        error!("The DLL already received its init data");
+    }
+}
+
+// Read the data laid out by the injector: its length, then the data.
+pub(crate) unsafe fn read_init_block<'a>(block: *const std::ffi::c_void) -> &'a [u8] {
+    let len = u64::from_le(std::ptr::read_unaligned(block as *const u64)) as usize;
+    std::slice::from_raw_parts((block as *const u8).add(std::mem::size_of::<u64>()), len)
+}
+
 /// Implement your `imgui` rendering logic via this trait.
 pub trait ImguiRenderLoop {
     /// Called once at the first occurrence of the hook. Implement this to
@@ -259,14 +870,215 @@ pub trait ImguiRenderLoop {
     /// Called every frame. Use the provided `ui` object to build your UI.
     fn render(&mut self, ui: &mut Ui);
 
-    /// Called during the window procedure.
-    fn on_wnd_proc(&self, _hwnd: HWND, _umsg: u32, _wparam: WPARAM, _lparam: LPARAM) {}
+    /// Called every frame instead of [`render`], with a render context whose
+    /// [`RenderContext::io_mut`] gives access to the [`Io`] of the frame being
+    /// built, e.g. to set [`Io::want_capture_keyboard`] depending on which
+    /// widget is focused. By default, it calls [`render`].
+    ///
+    /// With [`HudhookBuilder::with_async_frames`], the frame is built off the
+    /// render thread, and the render context only gives access to the
+    /// [`Io`]: loading textures and accessing the native objects fail.
+    ///
+    /// [`render`]: ImguiRenderLoop::render
+    fn render_with_context(&mut self, ui: &mut Ui, _render_context: &mut dyn RenderContext) {
+        self.render(ui);
+    }
+
+    /// Called every frame after the draw data produced by [`render`] has been
+    /// submitted by the backend.
+    ///
+    /// This is guaranteed to run exactly once per rendered frame, after the
+    /// overlay has been drawn and right before the hook calls the original
+    /// presentation function (e.g. the `Present` trampoline). Use it for work
+    /// that has to happen as late as possible in the frame, such as memory
+    /// writes that the host application could otherwise overwrite between
+    /// [`render`] and the presentation.
+    ///
+    /// It is not invoked if rendering the frame failed.
+    ///
+    /// [`render`]: ImguiRenderLoop::render
+    fn after_render(&mut self, _render_context: &mut dyn RenderContext) {}
+
+    /// Called after the DirectX 9 device has been reset, e.g. when the
+    /// application switches to or from fullscreen or changes resolution.
+    ///
+    /// All the textures live in the device's default pool, so they are freed
+    /// before the reset: their ids don't refer to any texture afterwards.
+    /// Implement this to load them again via `render_context`. The font atlas
+    /// is taken care of. Handles returned by
+    /// [`RenderContext::create_render_texture`] must be dropped by the end of
+    /// the frame they're used in, as the device can't be reset while they're
+    /// around.
+    ///
+    /// Not called on other backends, whose textures survive resizes.
+    fn on_device_reset<'a>(
+        &'a mut self,
+        _ctx: &mut Context,
+        _render_context: &'a mut dyn RenderContext,
+    ) {
+    }
+
+    /// Called by the window procedure for each message, on the window's
+    /// thread, before it's handed to imgui and to the application.
+    ///
+    /// Return [`WndProcResult::Handled`] to swallow the message: neither imgui
+    /// nor the application see it, and the window procedure returns the given
+    /// value instead. By default, every message is passed through.
+    ///
+    /// The render loop may be busy on another thread, e.g. rendering a frame,
+    /// in which case the window procedure waits for it for a short while and
+    /// then passes the message through without calling this. The same goes
+    /// for messages sent to the window while the render loop is running on
+    /// the window's thread, e.g. by a call to `SetWindowPos` in
+    /// [`render`](ImguiRenderLoop::render). Keep this quick, as the window
+    /// can't process other messages in the meantime.
+    fn on_wnd_proc(
+        &mut self,
+        _hwnd: HWND,
+        _umsg: u32,
+        _wparam: WPARAM,
+        _lparam: LPARAM,
+    ) -> WndProcResult {
+        WndProcResult::Passthrough
+    }
+
+    /// Called by the window procedure for each message instead of
+    /// [`on_wnd_proc`], with what imgui wanted as of the last frame, e.g. to
+    /// pass the gameplay keys to the application unless a text field is
+    /// focused. By default, it calls [`on_wnd_proc`].
+    ///
+    /// [`on_wnd_proc`]: ImguiRenderLoop::on_wnd_proc
+    fn on_wnd_proc_with_capture(
+        &mut self,
+        hwnd: HWND,
+        umsg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+        _capture: CaptureFlags,
+    ) -> WndProcResult {
+        self.on_wnd_proc(hwnd, umsg, wparam, lparam)
+    }
+
+    /// Returns the minimum rate, in frames per second, at which the overlay
+    /// should be redrawn.
+    ///
+    /// Some applications stop presenting, or present very rarely, when their
+    /// window is minimized or occluded, which freezes time-based UI such as
+    /// animations and countdowns. If this returns a value, a background thread
+    /// invalidates the window whenever no frame has been rendered for longer
+    /// than the corresponding interval, which prompts applications that render
+    /// in response to `WM_PAINT` to present again. Applications that ignore
+    /// repaint requests can't be forced to present.
+    ///
+    /// This is polled every frame. With the default, `None`, the overlay is only
+    /// redrawn when the application presents, and no thread is started.
+    fn min_framerate(&self) -> Option<u32> {
+        None
+    }
 
     /// Returns the types of window message that
     /// you do not want to propagate to the main window
     fn message_filter(&self, _io: &Io) -> MessageFilter {
         MessageFilter::empty()
     }
+
+    /// Called every frame by the DirectX 11 and 12 hooks, after the overlay
+    /// has been drawn and [`ImguiRenderLoop::after_render`] has returned, to
+    /// decide what to do with the application's call to `Present`, or
+    /// `Present1`. `sync_interval` and `flags` are the arguments it was
+    /// called with.
+    ///
+    /// This is also called if rendering the frame failed. By default, the
+    /// original `Present` is called and its result returned. See
+    /// [`PresentAction`].
+    fn on_present(&mut self, _sync_interval: u32, _flags: u32) -> PresentAction {
+        PresentAction::CallOriginal
+    }
+
+    /// Called for each message sent via the [`MessageSender`] returned by
+    /// [`HudhookBuilder::with_channel`], before
+    /// [`ImguiRenderLoop::before_render`]. Use [`Message::downcast`] to get
+    /// the value that was sent.
+    fn on_message(&mut self, _message: Message) {}
+
+    /// Called before [`ImguiRenderLoop::before_render`] when the display
+    /// size changed since the previous frame, e.g. to move windows anchored
+    /// to a corner of the display. `width` and `height` are the new size in
+    /// pixels, as also returned by [`RenderContext::display_size`].
+    ///
+    /// It's called at most once per frame, however many times the window was
+    /// resized in between, and not for the initial size.
+    fn on_resize(&mut self, _width: u32, _height: u32) {}
+}
+
+/// What the hooked `Present` function does once the overlay has been drawn.
+/// Returned by [`ImguiRenderLoop::on_present`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PresentAction {
+    /// Call the original `Present` and return its result.
+    #[default]
+    CallOriginal,
+    /// Don't call the original `Present`, and return `S_OK`. Nothing is
+    /// shown for this frame, e.g. to drop frames when limiting the framerate.
+    Skip,
+    /// Call the original `Present`, but return this value instead of its
+    /// result.
+    Override(HRESULT),
+}
+
+/// What the window procedure does with a message. Returned by
+/// [`ImguiRenderLoop::on_wnd_proc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WndProcResult {
+    /// Hand the message to imgui and to the application as usual.
+    #[default]
+    Passthrough,
+    /// Swallow the message, and return this value from the window procedure.
+    Handled(LRESULT),
+}
+
+/// What imgui wanted as of the last frame, as passed to
+/// [`ImguiRenderLoop::on_wnd_proc_with_capture`].
+///
+/// The window procedure runs in between frames, so these are the
+/// [`Io::want_capture_keyboard`], [`Io::want_capture_mouse`] and
+/// [`Io::want_text_input`] flags of the previous frame: a click that focuses a
+/// text field is only reflected once the next frame has been built. They're
+/// all unset while the overlay is hidden or doesn't take input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureFlags {
+    /// imgui wants the keyboard, e.g. a window is focused.
+    pub want_capture_keyboard: bool,
+    /// imgui wants the mouse, e.g. it's over a window.
+    pub want_capture_mouse: bool,
+    /// A text field is focused.
+    pub want_text_input: bool,
+}
+
+/// Who is in charge of the mouse cursor while the overlay wants the mouse,
+/// i.e. while it's visible and [`imgui::Io::want_capture_mouse`] is set. Set
+/// with [`RenderContext::set_cursor_mode`].
+///
+/// Applications often hide the cursor, or move it back to the center of the
+/// window, to drive a camera. hudhook undoes that while the overlay wants the
+/// mouse, and restores the application's cursor state when it doesn't anymore.
+///
+/// In every mode, while the overlay wants the mouse, the cursor takes the
+/// shape imgui asks for, e.g. via [`imgui::Ui::set_mouse_cursor`], such as
+/// the resize arrows over the borders of the imgui windows. It's hidden if
+/// imgui draws its own, see [`imgui::Io::mouse_draw_cursor`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CursorMode {
+    /// Leave the cursor to the application, but for its shape.
+    #[default]
+    GameControlled,
+    /// Show the cursor over the window, even if the application has hidden
+    /// it.
+    ShowOverlayCursor,
+    /// Show the cursor, and confine it to the client area of the window
+    /// so that it can't wander onto other monitors, e.g. for modal dialogs.
+    ConfineAndShow,
 }
 
 /// Generic trait for platform-specific hooks.
@@ -277,13 +1089,18 @@ pub trait ImguiRenderLoop {
 /// Check out first party implementations for guidance on how to implement the
 /// methods:
 /// - [`ImguiDx9Hooks`](crate::hooks::dx9::ImguiDx9Hooks)
+/// - [`ImguiDx10Hooks`](crate::hooks::dx10::ImguiDx10Hooks)
 /// - [`ImguiDx11Hooks`](crate::hooks::dx11::ImguiDx11Hooks)
 /// - [`ImguiDx12Hooks`](crate::hooks::dx12::ImguiDx12Hooks)
 /// - [`ImguiOpenGl3Hooks`](crate::hooks::opengl3::ImguiOpenGl3Hooks)
+/// - [`ImguiGdiHooks`](crate::hooks::gdi::ImguiGdiHooks)
+/// - [`ImguiOverlayWindow`](crate::hooks::overlay::ImguiOverlayWindow)
 pub trait Hooks {
     /// Construct a boxed instance of the implementor, storing the provided
     /// render loop where appropriate.
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    ///
+    /// Errors are reported by [`Hudhook::apply`].
+    fn from_render_loop<T>(t: T) -> Result<Box<Self>>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static;
@@ -291,6 +1108,18 @@ pub trait Hooks {
     /// Return the list of hooks to be enabled, in order.
     fn hooks(&self) -> &[MhHook];
 
+    /// Whether the application has loaded the graphics library the hooks
+    /// target, which usually happens right before it creates its device. See
+    /// [`HudhookBuilder::with_hook_retry`].
+    ///
+    /// Defaults to `true`.
+    fn is_renderer_loaded() -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
     /// Cleanup global data and disable the hooks.
     ///
     /// # Safety
@@ -299,8 +1128,36 @@ pub trait Hooks {
     unsafe fn unhook(&mut self);
 }
 
+/// Whether the hooks are live, as returned by [`Hudhook::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HookStatus {
+    /// No hooks have been applied yet, or they have been ejected.
+    NotApplied,
+    /// The hooks are enabled and the overlay is drawn.
+    Applied,
+    /// The hooks have been disabled by [`Hudhook::disable`], and can be
+    /// enabled again by [`Hudhook::reapply`].
+    Unapplied,
+}
+
 /// Holds all the activated hooks and manages their lifetime.
-pub struct Hudhook(Vec<Box<dyn Hooks>>);
+pub struct Hudhook {
+    // The hook objects, along with the names of their types.
+    hooks: Vec<(&'static str, Box<dyn Hooks>)>,
+    // Hooks on arbitrary functions, enabled and disabled along with the
+    // render hooks.
+    raw_hooks: Vec<MhHook>,
+    // The first error that occurred while building, reported by `apply`.
+    error: Option<HudhookError>,
+    // How many times, and how often, to check whether the renderer is loaded
+    // before creating hooks.
+    hook_retry: Option<(u32, Duration)>,
+    // Called by `unapply`, in order, once the hooks are gone.
+    unload_callbacks: Vec<Box<dyn FnOnce() + Send>>,
+    // The options set on the builder, published to the pipelines by `apply`.
+    config: PipelineConfig,
+}
 unsafe impl Send for Hudhook {}
 unsafe impl Sync for Hudhook {}
 
@@ -312,54 +1169,173 @@ impl Hudhook {
 
     fn new() -> Self {
         // Initialize minhook.
-        match unsafe { MH_Initialize() } {
-            MH_STATUS::MH_ERROR_ALREADY_INITIALIZED | MH_STATUS::MH_OK => {},
-            status @ MH_STATUS::MH_ERROR_MEMORY_ALLOC => panic!("MH_Initialize: {status:?}"),
-            _ => unreachable!(),
-        }
+        let error = match unsafe { MH_Initialize() } {
+            MH_STATUS::MH_ERROR_ALREADY_INITIALIZED | MH_STATUS::MH_OK => None,
+            status => {
+                error!("MH_Initialize: {status:?}");
+                Some(HudhookError::MinHook(status))
+            },
+        };
 
-        Hudhook(Vec::new())
+        Hudhook {
+            hooks: Vec::new(),
+            raw_hooks: Vec::new(),
+            error,
+            hook_retry: None,
+            unload_callbacks: Vec::new(),
+            config: PipelineConfig::default(),
+        }
     }
 
     /// Return an iterator of all the activated raw hooks.
     fn hooks(&self) -> impl IntoIterator<Item = &MhHook> {
-        self.0.iter().flat_map(|h| h.hooks())
+        self.hooks.iter().flat_map(|(_, h)| h.hooks()).chain(&self.raw_hooks)
     }
 
     /// Apply the hooks.
-    pub fn apply(self) -> Result<(), MH_STATUS> {
+    ///
+    /// The options set on the builder take effect here, before the hooks are
+    /// enabled. Fails with the first error that occurred while building the
+    /// hooks, if any, without enabling any of them. If the hooks can't be
+    /// enabled, minhook is uninitialized, which removes any of them that
+    /// were, and the hooks are cleaned up, so that the DLL can be ejected
+    /// safely.
+    pub fn apply(mut self) -> Result<()> {
+        let result = match self.error.take() {
+            Some(e) => Err(e),
+            None => {
+                *PIPELINE_CONFIG.lock() = mem::take(&mut self.config);
+                unsafe { self.enable() }
+            },
+        };
+
+        if let Err(e) = result {
+            unsafe { self.discard() };
+            return Err(e);
+        }
+
+        unsafe { HUDHOOK.set(self).ok() };
+        HOOK_STATUS.store(HookStatus::Applied as u8, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Return whether the hooks that were applied are currently enabled.
+    pub fn status() -> HookStatus {
+        match HOOK_STATUS.load(Ordering::SeqCst) {
+            s if s == HookStatus::Applied as u8 => HookStatus::Applied,
+            s if s == HookStatus::Unapplied as u8 => HookStatus::Unapplied,
+            _ => HookStatus::NotApplied,
+        }
+    }
+
+    /// Disable the applied hooks, e.g. to toggle the overlay off from a
+    /// settings screen, without ejecting the DLL.
+    ///
+    /// The hooks, their render loops and the state of the overlay are kept
+    /// around, and minhook stays initialized, so that [`Hudhook::reapply`]
+    /// can enable them again. While disabled, window messages go straight to
+    /// the application. Does nothing if the hooks aren't applied.
+    pub fn disable() -> Result<()> {
+        if Self::status() != HookStatus::Applied {
+            return Ok(());
+        }
+
+        let Some(hudhook) = (unsafe { HUDHOOK.get() }) else {
+            return Ok(());
+        };
+
+        unsafe { hudhook.disable_hooks()? };
+        HOOK_STATUS.store(HookStatus::Unapplied as u8, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Enable again the hooks disabled by [`Hudhook::disable`]. Does nothing
+    /// if they aren't disabled.
+    pub fn reapply() -> Result<()> {
+        if Self::status() != HookStatus::Unapplied {
+            return Ok(());
+        }
+
+        let Some(hudhook) = (unsafe { HUDHOOK.get() }) else {
+            return Ok(());
+        };
+
+        unsafe { hudhook.enable()? };
+        HOOK_STATUS.store(HookStatus::Applied as u8, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    unsafe fn enable(&self) -> Result<()> {
         // Queue enabling all the hooks.
         for hook in self.hooks() {
-            unsafe { hook.queue_enable()? };
+            hook.queue_enable()?;
         }
 
         // Apply the queue of enable actions.
-        unsafe { MH_ApplyQueued().ok_context("MH_ApplyQueued")? };
+        diagnostics::hooks_enabling();
+        MH_ApplyQueued().ok_context("MH_ApplyQueued")?;
 
-        unsafe { HUDHOOK.set(self).ok() };
+        diagnostics::hooks_enabled(self.hooks.iter().map(|&(name, _)| name));
 
         Ok(())
     }
 
-    /// Disable and cleanup the hooks.
-    pub fn unapply(&mut self) -> Result<(), MH_STATUS> {
+    // Undo building the hooks after a failure, without bailing out halfway.
+    // Uninitializing minhook disables and removes all of its hooks, enabled
+    // or not, including the ones created before a backend failed to build.
+    unsafe fn discard(&mut self) {
+        MH_Uninitialize().ok_context("MH_Uninitialize").ok();
+
+        for (_, hook) in &mut self.hooks {
+            hook.unhook();
+        }
+    }
+
+    unsafe fn disable_hooks(&self) -> Result<()> {
         // Queue disabling all the hooks.
         for hook in self.hooks() {
-            unsafe { hook.queue_disable()? };
+            hook.queue_disable()?;
         }
 
         // Apply the queue of disable actions.
-        unsafe { MH_ApplyQueued().ok_context("MH_ApplyQueued")? };
+        MH_ApplyQueued().ok_context("MH_ApplyQueued")?;
+
+        Ok(())
+    }
+
+    /// Disable and cleanup the hooks.
+    ///
+    /// Unlike [`Hudhook::disable`], this uninitializes minhook and drops the
+    /// render loops, so the hooks can't be applied again. This is what
+    /// [`eject`] does.
+    ///
+    /// The render loops are dropped and the unload callbacks run even if the
+    /// hooks can't be disabled, and the first error is returned afterwards:
+    /// the module may be freed either way.
+    pub fn unapply(&mut self) -> Result<()> {
+        let disabled = unsafe { self.disable_hooks() };
 
         // Uninitialize minhook.
-        unsafe { MH_Uninitialize().ok_context("MH_Uninitialize")? };
+        let uninitialized = unsafe { MH_Uninitialize().ok_context("MH_Uninitialize") };
 
         // Invoke cleanup for all hooks.
-        for hook in &mut self.0 {
+        for (_, hook) in &mut self.hooks {
             unsafe { hook.unhook() };
         }
 
-        Ok(())
+        // Nothing receives the messages anymore, so stop their senders.
+        PIPELINE_CONFIG.lock().messages = None;
+
+        for callback in self.unload_callbacks.drain(..) {
+            if panic::catch_unwind(AssertUnwindSafe(callback)).is_err() {
+                error!("Unload callback panicked");
+            }
+        }
+
+        disabled.and(uninitialized.map_err(HudhookError::from))
     }
 }
 
@@ -399,17 +1375,612 @@ pub struct HudhookBuilder(Hudhook);
 
 impl HudhookBuilder {
     /// Add a hook object.
+    ///
+    /// If the hooks can't be created, the error is reported by
+    /// [`Hudhook::apply`] and no further hooks are created.
     pub fn with<T: Hooks + 'static>(
         mut self,
         render_loop: impl ImguiRenderLoop + Send + Sync + 'static,
     ) -> Self {
-        self.0 .0.push(T::from_render_loop(render_loop));
+        if self.0.error.is_none() && !self.wait_for_renderer::<T>() {
+            error!("Couldn't create hooks: {}", HudhookError::RendererNotLoaded);
+            self.0.error = Some(HudhookError::RendererNotLoaded);
+        }
+
+        if self.0.error.is_none() {
+            match T::from_render_loop(render_loop) {
+                Ok(hooks) => self.0.hooks.push((any::type_name::<T>(), hooks)),
+                Err(e) => {
+                    error!("Couldn't create hooks: {e}");
+                    self.0.error = Some(e);
+                },
+            }
+        }
+        self
+    }
+
+    /// Draw the UI of `render_loop` in a transparent window laid over the
+    /// application window, instead of in the application's swap chain. This
+    /// is the same as [`HudhookBuilder::with`] the
+    /// [`ImguiOverlayWindow`](hooks::overlay::ImguiOverlayWindow) hooks, which
+    /// hook nothing, and describe the trade-offs.
+    #[cfg(feature = "gdi")]
+    pub fn with_external_overlay_window(
+        self,
+        render_loop: impl ImguiRenderLoop + Send + Sync + 'static,
+    ) -> Self {
+        self.with::<hooks::overlay::ImguiOverlayWindow>(render_loop)
+    }
+
+    // Wait for the application to load the renderer targeted by `T`, if
+    // configured to, and return whether it did.
+    fn wait_for_renderer<T: Hooks>(&self) -> bool {
+        let Some((attempts, delay)) = self.0.hook_retry else {
+            return true;
+        };
+
+        for attempt in 1..=attempts {
+            if T::is_renderer_loaded() {
+                return true;
+            }
+
+            debug!("Renderer not loaded yet, attempt {attempt} of {attempts}");
+            if attempt < attempts {
+                thread::sleep(delay);
+            }
+        }
+
+        false
+    }
+
+    /// Wait for the application to load the graphics library targeted by the
+    /// hooks added afterwards with [`HudhookBuilder::with`], checking up to
+    /// `attempts` times, `delay` apart, before creating them.
+    ///
+    /// This is meant for DLLs injected before the application has created
+    /// its device, e.g. at process start or while a launcher is running, in
+    /// place of sleeping for a fixed time. If the library still isn't loaded
+    /// after the last attempt, [`Hudhook::apply`] fails with
+    /// [`HudhookError::RendererNotLoaded`]. By default, hooks are created
+    /// right away.
+    ///
+    /// As this blocks, build the hooks on a thread of their own, as the
+    /// [`hudhook!`] macro does, rather than in `DllMain`.
+    pub fn with_hook_retry(mut self, attempts: u32, delay: Duration) -> Self {
+        self.0.hook_retry = Some((attempts, delay));
         self
     }
 
     /// Save the DLL instance (for the [`eject`] method).
+    ///
+    /// Only the first instance is kept.
     pub fn with_hmodule(self, module: HINSTANCE) -> Self {
-        unsafe { MODULE.set(module).unwrap() };
+        if unsafe { MODULE.set(module) }.is_err() {
+            error!("The DLL instance was already set");
+        }
+        self
+    }
+
+    /// Choose what happens to the mouse cursor when keyboard or gamepad
+    /// navigation moves the focus to a different widget. By default, the
+    /// cursor doesn't move. See [`NavMouseWarp`].
+    pub fn with_nav_mouse_warp(mut self, nav_mouse_warp: NavMouseWarp) -> Self {
+        self.0.config.nav_mouse_warp = nav_mouse_warp;
+        self
+    }
+
+    /// Choose how the overlay receives the messages of the application
+    /// window. By default, it replaces the window procedure. See
+    /// [`InputMode`] for the tradeoffs of each mode.
+    pub fn with_input_mode(mut self, input_mode: InputMode) -> Self {
+        self.0.config.input_mode = input_mode;
+        self
+    }
+
+    /// Show or hide the overlay whenever `key` is pressed. The overlay starts
+    /// out visible. See [`RenderContext::set_visible`] for what hiding it
+    /// means.
+    pub fn with_toggle_key(mut self, key: VIRTUAL_KEY) -> Self {
+        self.0.config.toggle_key = Some(key);
+        self
+    }
+
+    /// Watch out for frames with more than `max_windows` imgui windows, which
+    /// usually means that windows are being created in a loop with a
+    /// different id on each iteration. A warning is logged whenever a frame
+    /// crosses the limit, and `action` decides whether it still gets
+    /// rendered.
+    pub fn with_window_limit(mut self, max_windows: usize, action: WindowLimitAction) -> Self {
+        self.0.config.window_limit = Some((max_windows, action));
+        self
+    }
+
+    /// Choose which command queue the DirectX 12 overlay renders on. By
+    /// default, it waits for the application's queue to be captured. See
+    /// [`Dx12CommandQueue`].
+    pub fn with_dx12_command_queue(mut self, command_queue: Dx12CommandQueue) -> Self {
+        self.0.config.dx12_command_queue = command_queue;
+        self
+    }
+
+    /// Choose which swap chain the DirectX 10, 11 and 12 overlays are
+    /// rendered on, for applications that present to more than one window,
+    /// e.g. an editor with separate game and debug views.
+    ///
+    /// `selector` receives the description of each swap chain that presents
+    /// until it returns `true`, and the overlay is set up on that swap chain.
+    /// From then on, only presents to the same window are rendered on; the
+    /// others go through to the application untouched.
+    ///
+    /// By default, the largest swap chain presenting to the foreground
+    /// window is picked, after every swap chain has had a chance to present.
+    pub fn with_swap_chain_selector<F>(mut self, selector: F) -> Self
+    where
+        F: Fn(&DXGI_SWAP_CHAIN_DESC) -> bool + Send + Sync + 'static,
+    {
+        self.0.config.swap_chain_selector = Some(Arc::new(selector));
+        self
+    }
+
+    /// Poll an XInput controller each frame and let it navigate the UI, via
+    /// imgui's [`imgui::ConfigFlags::NAV_ENABLE_GAMEPAD`]. The controller is
+    /// only read, so the application keeps receiving its input as well.
+    ///
+    /// The first controller is read unless another one is chosen via
+    /// [`HudhookBuilder::with_gamepad_index`].
+    pub fn with_gamepad_nav(mut self, enabled: bool) -> Self {
+        self.0.config.gamepad_nav = enabled;
+        self
+    }
+
+    /// Choose which XInput controller, `0` to `3`, to read when the gamepad
+    /// navigation is enabled via [`HudhookBuilder::with_gamepad_nav`].
+    pub fn with_gamepad_index(mut self, index: u32) -> Self {
+        self.0.config.gamepad_index = index;
+        self
+    }
+
+    /// Follow the DPI of the application window, which is enabled by default.
+    /// The style and the fonts are scaled by the window's DPI over 96 when the
+    /// overlay starts, and again whenever it changes, e.g. when the window is
+    /// moved to a monitor with a different scale. The fonts are rebuilt at the
+    /// new size, so that text stays crisp.
+    ///
+    /// Disable it to scale the UI manually, e.g. via
+    /// [`imgui::Style::scale_all_sizes`] and [`RenderContext::rebuild_fonts`].
+    pub fn with_dpi_scaling(mut self, enabled: bool) -> Self {
+        self.0.config.dpi_scaling = enabled;
+        self
+    }
+
+    /// Make the overlay click-through, for HUDs that are only ever looked at.
+    /// The application receives all the input, whatever the render loop's
+    /// [`ImguiRenderLoop::message_filter`], imgui never wants the mouse, and
+    /// its windows can't be hovered nor clicked.
+    ///
+    /// Unlike [`RenderContext::set_input_enabled`], this can't be undone
+    /// while the overlay runs. The overlay is drawn in the application's own
+    /// window, so there's no window of its own to make transparent to the
+    /// mouse. Messages handled in [`ImguiRenderLoop::on_wnd_proc`] are still
+    /// swallowed, as the render loop asked for.
+    pub fn with_click_through(mut self, enabled: bool) -> Self {
+        self.0.config.click_through = enabled;
+        self
+    }
+
+    /// Build the frames of the render loop, i.e. call
+    /// [`ImguiRenderLoop::render`], on a separate thread instead of in the
+    /// hooked present function, so that expensive UIs don't slow the
+    /// application down. Disabled by default.
+    ///
+    /// Each present draws the frame built since the previous one, and starts
+    /// building the next one, so that the overlay lags one frame behind: the
+    /// input received before a present shows on screen on the next. Tools
+    /// that need tight input latency should keep building frames
+    /// synchronously.
+    ///
+    /// The other callbacks of the render loop are still invoked in the
+    /// present function, never while a frame is being built. A texture used
+    /// by a frame can only be unloaded after the frame following it has
+    /// started.
+    pub fn with_async_frames(mut self, enabled: bool) -> Self {
+        self.0.config.async_frames = enabled;
+        self
+    }
+
+    /// Catch the panics of the render loop, which is enabled by default. A
+    /// panic in [`ImguiRenderLoop::initialize`],
+    /// [`ImguiRenderLoop::before_render`], [`ImguiRenderLoop::render`],
+    /// [`ImguiRenderLoop::after_render`], [`ImguiRenderLoop::on_wnd_proc`],
+    /// [`ImguiRenderLoop::on_present`], [`ImguiRenderLoop::on_message`],
+    /// [`ImguiRenderLoop::on_resize`], [`ImguiRenderLoop::on_device_reset`],
+    /// [`ImguiRenderLoop::message_filter`], [`ImguiRenderLoop::min_framerate`]
+    /// or the render function of a [layer](Self::with_layer) is logged, and
+    /// the render loop is never called again: the application keeps running
+    /// without the overlay.
+    ///
+    /// Disable it to debug a panic with the usual unwinding, e.g. in a
+    /// debugger. Unwinding out of a hooked function aborts the application.
+    pub fn with_catch_panics(mut self, enabled: bool) -> Self {
+        self.0.config.catch_panics = enabled;
+        self
+    }
+
+    /// Limit the frame rate of the application to `fps` frames per second, or
+    /// leave it alone with `None`, which is the default. The limit can be
+    /// changed at runtime via [`RenderContext::set_frame_limit`].
+    ///
+    /// The present hook waits out what's left of each frame's time before
+    /// presenting, sleeping with a 1ms timer resolution and spinning for the
+    /// last couple of milliseconds. Frames that already take longer, e.g.
+    /// because the present waits for VSync at a refresh rate below the limit,
+    /// aren't delayed.
+    pub fn with_frame_limit(mut self, fps: Option<f32>) -> Self {
+        self.0.config.frame_limit = fps;
+        self
+    }
+
+    /// Create a channel to send values of type `T` to the render loop from
+    /// other threads, e.g. the results of network requests or memory polling,
+    /// instead of sharing them through global state. Each value is delivered
+    /// to [`ImguiRenderLoop::on_message`] on the thread that presents.
+    ///
+    /// The messages are delivered in the order they were sent, all at once
+    /// before each frame, right before [`ImguiRenderLoop::before_render`].
+    /// With several hooks, each message goes to the render loop of whichever
+    /// presents first. While the overlay isn't set up yet, or after its
+    /// render loop has panicked, nothing is delivered.
+    ///
+    /// The channel holds up to `capacity` messages. Once it's full,
+    /// [`MessageSender::send`] waits for the next frame to empty it, and
+    /// [`MessageSender::try_send`] fails right away. After the render loop
+    /// has panicked, the messages are dropped as they come. Once the hooks
+    /// have been unapplied, sending fails.
+    ///
+    /// Only one channel is kept: calling this again replaces the previous
+    /// one, whose senders then fail.
+    pub fn with_channel<T: Send + 'static>(mut self, capacity: usize) -> (Self, MessageSender<T>) {
+        let (tx, rx) = message::channel(capacity);
+        self.0.config.messages = Some(Arc::new(rx));
+        (self, tx)
+    }
+
+    /// Save the imgui settings, e.g. the positions and sizes of the windows,
+    /// to the `.ini` file at `path`, and load them back when the overlay
+    /// starts. By default, or with `None`, nothing is saved.
+    ///
+    /// The file is written by the application process, so `path` must be
+    /// writable from it, e.g. next to the DLL.
+    pub fn with_ini_filename(mut self, path: Option<PathBuf>) -> Self {
+        self.0.config.ini_filename = path;
+        self
+    }
+
+    /// Keep the imgui settings, e.g. the positions and sizes of the windows, in
+    /// memory, so that they survive the overlay being set up again, e.g. after
+    /// the device has been reset or the hooks reapplied, for as long as the
+    /// library is loaded. Disabled by default, in which case the windows are
+    /// laid out anew with each imgui context.
+    ///
+    /// Nothing is written to disk, so the layout is lost once the library is
+    /// ejected. This has no effect if a file is set via
+    /// [`HudhookBuilder::with_ini_filename`], which the settings are kept in
+    /// instead.
+    pub fn with_session_layout_persistence(mut self, enabled: bool) -> Self {
+        self.0.config.session_layout = enabled;
+        self
+    }
+
+    /// Set up the style of the imgui context with `preset`. By default, imgui's
+    /// own dark style is used.
+    ///
+    /// This is the recommended place to theme the UI: the style is applied
+    /// each time the context is created, before
+    /// [`ImguiRenderLoop::initialize`] and before any window is built, so the
+    /// first frame never shows the default style. Sizes are scaled to the
+    /// DPI of the window afterwards, see [`HudhookBuilder::with_dpi_scaling`].
+    ///
+    /// ```no_run
+    /// use hudhook::imgui::{Style, StyleColor};
+    /// use hudhook::*;
+    ///
+    /// fn theme(style: &mut Style) {
+    ///     style.use_dark_colors();
+    ///     style.window_rounding = 4.0;
+    ///     style[StyleColor::TitleBgActive] = [0.4, 0.1, 0.1, 1.0];
+    /// }
+    ///
+    /// let builder = Hudhook::builder().with_style(StylePreset::Custom(theme));
+    /// ```
+    pub fn with_style(mut self, preset: StylePreset) -> Self {
+        self.0.config.style = Some(preset);
+        self
+    }
+
+    /// Configure the imgui context, e.g. its style or config flags, right
+    /// after it has been created and before
+    /// [`ImguiRenderLoop::initialize`]. This runs after the style set via
+    /// [`HudhookBuilder::with_style`] has been applied. `f` is invoked again for each new
+    /// context, i.e. each time the overlay is set up.
+    pub fn with_imgui_settings(mut self, f: impl Fn(&mut Context) + Send + Sync + 'static) -> Self {
+        self.0.config.imgui_settings = Some(Arc::new(f));
+        self
+    }
+
+    /// Render with an imgui context set up beforehand, e.g. with its fonts,
+    /// style, `.ini` file and config flags, instead of creating a new one.
+    ///
+    /// The context is suspended until the overlay is set up, then adopted by
+    /// the first pipeline, i.e. the first hooked renderer to present. If the
+    /// overlay is set up anew later on, e.g. with more than one set of hooks,
+    /// the next pipelines create their own context as usual. Its `.ini`
+    /// filename is copied into the configuration, in place of the one set via
+    /// [`HudhookBuilder::with_ini_filename`] if that was called before.
+    ///
+    /// The rest of the hudhook configuration still applies on top, like for
+    /// a new context, before [`ImguiRenderLoop::initialize`]:
+    /// - the display size, and the renderer name and backend flags of the
+    ///   backend in use, are overwritten;
+    /// - the config flags set via other options, e.g.
+    ///   [`HudhookBuilder::with_nav_mouse_warp`], are added;
+    /// - the style set via [`HudhookBuilder::with_style`], and
+    ///   [`HudhookBuilder::with_imgui_settings`], are applied;
+    /// - the style and fonts are scaled to the DPI of the window, unless
+    ///   disabled via [`HudhookBuilder::with_dpi_scaling`];
+    /// - imgui's platform IME callback is replaced, and the font atlas is
+    ///   built and uploaded by the renderer.
+    ///
+    /// Input is written to its IO each frame, as usual.
+    ///
+    /// imgui only allows one active context: no other context may be active
+    /// when the overlay is set up, or setting up the pipeline fails with an
+    /// error logged, and the context is kept for the next attempt.
+    pub fn with_context(mut self, ctx: Context) -> Self {
+        self.0.config.ini_filename = ctx.ini_filename();
+        renderer::config::supply_context(ctx);
+        self
+    }
+
+    /// Tell where the application's content is shown within the window, for
+    /// applications that letterbox it, e.g. emulators and pixel-art games
+    /// scaling their image by whole factors, or ultrawide games in a 16:9
+    /// window. Before each frame, `f` is given the size of the client area,
+    /// and returns the part of it the content is shown in, in client
+    /// coordinates. It's clamped to the client area.
+    ///
+    /// imgui's display still covers the whole window, so that the imgui
+    /// windows can be dragged over the black bars, but the work area of the
+    /// main viewport, i.e. its `WorkPos` and `WorkSize`, is bounded by the
+    /// content rect, so that the UIs anchored to the work area line up with
+    /// the content. The rect is available as
+    /// [`RenderContext::content_rect`], along with the whole display as
+    /// [`RenderContext::display_size`], to position anything else.
+    ///
+    /// ```no_run
+    /// use hudhook::windows::Win32::Foundation::RECT;
+    /// use hudhook::*;
+    ///
+    /// // 4:3 content, pillarboxed in a wider window.
+    /// let builder = Hudhook::builder().with_content_rect(|width, height| {
+    ///     let content_width = (height * 4 / 3).min(width) as i32;
+    ///     let left = (width as i32 - content_width) / 2;
+    ///     RECT { left, top: 0, right: left + content_width, bottom: height as i32 }
+    /// });
+    /// ```
+    pub fn with_content_rect(
+        mut self,
+        f: impl Fn(u32, u32) -> RECT + Send + Sync + 'static,
+    ) -> Self {
+        self.0.config.content_rect = Some(Arc::new(f));
+        self
+    }
+
+    /// Draw a HUD of its own over a part of the display, e.g. one per player
+    /// viewport of a split-screen game that presents a single swap chain.
+    ///
+    /// Before each frame, `rect` is given the size of the client area, and
+    /// returns the part of it the layer is drawn over, in client coordinates,
+    /// clamped to the client area. After the render loop, `render` is called
+    /// with the clamped rect as `[min, max]`, for each layer in the order they
+    /// were added, with the work area of the main viewport bounded by the
+    /// rect like [`HudhookBuilder::with_content_rect`] does.
+    ///
+    /// All the layers are built in the same imgui frame as the render loop,
+    /// so the positions are in client coordinates, and window names must be
+    /// unique across layers, e.g. by suffixing them with `##` and the layer
+    /// name. If `render` panics, the whole overlay is disabled, like when the
+    /// render loop does.
+    ///
+    /// ```no_run
+    /// use hudhook::imgui::Condition;
+    /// use hudhook::windows::Win32::Foundation::RECT;
+    /// use hudhook::*;
+    ///
+    /// let mut builder = Hudhook::builder();
+    /// for player in 0..2 {
+    ///     builder = builder.with_layer(
+    ///         format!("player {player}"),
+    ///         move |width, height| {
+    ///             let half = width as i32 / 2;
+    ///             let left = half * player;
+    ///             RECT { left, top: 0, right: left + half, bottom: height as i32 }
+    ///         },
+    ///         move |ui, [min, _]| {
+    ///             ui.window(format!("Health##{player}"))
+    ///                 .position([min[0] + 16., min[1] + 16.], Condition::Always)
+    ///                 .build(|| ui.text("100 HP"));
+    ///         },
+    ///     );
+    /// }
+    /// ```
+    pub fn with_layer(
+        mut self,
+        name: impl Into<String>,
+        rect: impl Fn(u32, u32) -> RECT + Send + Sync + 'static,
+        render: impl FnMut(&Ui, [[f32; 2]; 2]) + Send + 'static,
+    ) -> Self {
+        self.0.config.layers.push(Layer {
+            name: name.into(),
+            rect: Arc::new(rect),
+            render: Arc::new(Mutex::new(render)),
+        });
+        self
+    }
+
+    /// Let the imgui windows be docked into each other, via imgui's
+    /// [`imgui::ConfigFlags::DOCKING_ENABLE`], which is set before the first
+    /// frame. Disabled by default.
+    ///
+    /// The docked windows stay within the application window: multiple
+    /// viewports, i.e. imgui windows dragged out into windows of their own,
+    /// aren't supported, as the overlay would have to create those windows
+    /// and a swap chain for each of them on every backend. Setting
+    /// [`imgui::ConfigFlags::VIEWPORTS_ENABLE`] has no effect.
+    #[cfg(feature = "imgui-docking")]
+    pub fn with_docking(mut self, enabled: bool) -> Self {
+        self.0.config.docking = enabled;
+        self
+    }
+
+    /// Report the steps of setting up and drawing the overlay to `f`, e.g. to
+    /// show in a log window of the tool's own whether the hooks were
+    /// installed, whether the application presents, and whether rendering
+    /// fails. See [`DiagnosticEvent`].
+    ///
+    /// `f` is called from the hooks, on whichever thread the application
+    /// presents from, and on every frame, so it should return quickly. If it
+    /// panics, the panic is logged and ignored. Calling this again replaces
+    /// the previous callback.
+    ///
+    /// ```no_run
+    /// use hudhook::*;
+    ///
+    /// let builder = Hudhook::builder().with_diagnostics(|event| match event {
+    ///     DiagnosticEvent::FrameRendered => {},
+    ///     event => eprintln!("hudhook: {event:?}"),
+    /// });
+    /// ```
+    pub fn with_diagnostics(self, f: impl Fn(DiagnosticEvent) + Send + Sync + 'static) -> Self {
+        diagnostics::set_callback(Arc::new(f));
+        self
+    }
+
+    /// Hook the function at `target`, redirecting its calls to `detour`, e.g.
+    /// to patch the application's logic along with drawing the overlay.
+    ///
+    /// The hook is created right away, but only enabled by
+    /// [`Hudhook::apply`], in the same batch as the render hooks, so that
+    /// either all of them or none are live. It's disabled along with them by
+    /// [`Hudhook::disable`] and [`Hudhook::unapply`], and removed when
+    /// minhook is uninitialized.
+    ///
+    /// The returned [`RawHook`] holds the trampoline calling the original
+    /// function, which has to be stored where `detour` can reach it before
+    /// applying the hooks. If the hook can't be created, the error is
+    /// reported by [`Hudhook::apply`], and the handle has no trampoline.
+    ///
+    /// ```no_run
+    /// use std::ffi::c_void;
+    /// use std::mem;
+    /// use std::sync::OnceLock;
+    ///
+    /// use hudhook::mh::RawHook;
+    /// use hudhook::*;
+    ///
+    /// type UpdateFn = unsafe extern "system" fn(f32);
+    ///
+    /// static UPDATE: OnceLock<RawHook> = OnceLock::new();
+    ///
+    /// unsafe extern "system" fn update_impl(dt: f32) {
+    ///     let trampoline = UPDATE.get().and_then(RawHook::trampoline).unwrap();
+    ///     let update: UpdateFn = mem::transmute(trampoline);
+    ///     update(dt * 0.5)
+    /// }
+    ///
+    /// # fn update_addr() -> *mut c_void { std::ptr::null_mut() }
+    /// # struct MyRenderLoop;
+    /// # impl ImguiRenderLoop for MyRenderLoop {
+    /// #     fn render(&mut self, _: &mut imgui::Ui) {}
+    /// # }
+    /// let (builder, update) = unsafe {
+    ///     Hudhook::builder()
+    ///         .with::<hooks::dx12::ImguiDx12Hooks>(MyRenderLoop)
+    ///         .with_raw_hook(update_addr(), update_impl as *mut c_void)
+    /// };
+    /// UPDATE.set(update).ok();
+    /// builder.build().apply().unwrap();
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The same as [`MhHook::new`].
+    pub unsafe fn with_raw_hook(
+        mut self,
+        target: *mut c_void,
+        detour: *mut c_void,
+    ) -> (Self, RawHook) {
+        let mut handle = RawHook { trampoline: None };
+        if self.0.error.is_none() {
+            match MhHook::new(target, detour) {
+                Ok(hook) => {
+                    handle.trampoline = Some(hook.trampoline());
+                    self.0.raw_hooks.push(hook);
+                },
+                Err(status) => {
+                    error!("Couldn't hook {target:p}: {status:?}");
+                    self.0.error = Some(HudhookError::MinHook(status));
+                },
+            }
+        }
+        (self, handle)
+    }
+
+    /// Call `f` when the hooks are unapplied by [`Hudhook::unapply`], e.g. via
+    /// [`eject`], to clean up while the DLL is still loaded: flush files, join
+    /// threads, drop the contents of statics.
+    ///
+    /// By then, the hooks have been disabled and removed, and the render loops
+    /// dropped, so nothing of the overlay runs anymore. When ejecting, the DLL
+    /// is freed right after `f` returns. Each call adds a callback, and they're
+    /// called in the order they were added. If one panics, the panic is logged
+    /// and the next ones are called anyway.
+    pub fn with_unload_callback(mut self, f: impl FnOnce() + Send + 'static) -> Self {
+        self.0.unload_callbacks.push(Box::new(f));
+        self
+    }
+
+    /// Allocate a console, enable its colors, and log the events up to
+    /// `level` to it, with their thread, file and line.
+    ///
+    /// This installs the global `tracing` subscriber, which is shared with
+    /// [`HudhookBuilder::with_file_logging`], so both can be used at once.
+    /// Does nothing if a subscriber was already installed elsewhere, e.g. by
+    /// the DLL itself. Call it first, so that building the hooks is logged
+    /// too.
+    pub fn with_console_logging(self, level: LevelFilter) -> Self {
+        logging::console(level);
+        self
+    }
+
+    /// Log the events up to `level` to the file at `path`, which is
+    /// truncated, with their thread, file and line. The log file is usually
+    /// put next to the DLL:
+    ///
+    /// ```no_run
+    /// # use hudhook::*;
+    /// # use hudhook::tracing::level_filters::LevelFilter;
+    /// let mut path = util::get_dll_path().unwrap();
+    /// path.set_extension("log");
+    ///
+    /// let builder = Hudhook::builder().with_file_logging(path, LevelFilter::DEBUG);
+    /// ```
+    ///
+    /// This installs the global `tracing` subscriber, which is shared with
+    /// [`HudhookBuilder::with_console_logging`], so both can be used at once.
+    /// Does nothing if a subscriber was already installed elsewhere. If the
+    /// file can't be created, the error is logged to the console, if any.
+    pub fn with_file_logging(self, path: impl AsRef<Path>, level: LevelFilter) -> Self {
+        logging::file(path.as_ref(), level);
         self
     }
 
@@ -462,11 +2033,33 @@ macro_rules! hudhook {
                         .build()
                         .apply()
                     {
-                        ::hudhook::tracing::error!("Couldn't apply hooks: {e:?}");
+                        ::hudhook::tracing::error!("Couldn't apply hooks: {e}");
                         ::hudhook::eject();
                     }
                 });
             }
         }
+
+        /// Ejection entry point created by the `hudhook` library.
+        ///
+        /// Meant to be the start routine of a thread spawned by
+        /// `hudhook::inject::Process::eject`.
+        #[no_mangle]
+        pub unsafe extern "system" fn hudhook_eject(_: *mut ::std::ffi::c_void) -> u32 {
+            ::hudhook::tracing::trace!("hudhook_eject()");
+            ::hudhook::eject();
+            0
+        }
+
+        /// Initialization data entry point created by the `hudhook` library.
+        ///
+        /// Meant to be the start routine of a thread spawned by
+        /// `hudhook::inject::Process::inject_with_data`.
+        #[no_mangle]
+        pub unsafe extern "system" fn hudhook_init(block: *mut ::std::ffi::c_void) -> u32 {
+            ::hudhook::tracing::trace!("hudhook_init()");
+            ::hudhook::set_init_data(block);
+            0
+        }
     };
 }
@@ -0,0 +1,106 @@
+//! This module sets up the logging configured via
+//! [`HudhookBuilder::with_console_logging`](crate::HudhookBuilder::with_console_logging)
+//! and
+//! [`HudhookBuilder::with_file_logging`](crate::HudhookBuilder::with_file_logging).
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::{Mutex, RwLock};
+use tracing::level_filters::LevelFilter;
+use tracing::{error, Metadata};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+use crate::{alloc_console, enable_console_colors};
+
+// The levels of the console and of the file layers. Both layers are
+// installed at once, and stay off until configured.
+static CONSOLE_LEVEL: Lazy<RwLock<LevelFilter>> = Lazy::new(|| RwLock::new(LevelFilter::OFF));
+static FILE_LEVEL: Lazy<RwLock<LevelFilter>> = Lazy::new(|| RwLock::new(LevelFilter::OFF));
+
+static LOG_FILE: Lazy<Mutex<Option<File>>> = Lazy::new(|| Mutex::new(None));
+
+// Whether the global subscriber is ours, i.e. whether the logging can be
+// configured.
+static INSTALLED: OnceCell<bool> = OnceCell::new();
+
+pub(crate) fn console(level: LevelFilter) {
+    if !install() {
+        return;
+    }
+
+    if let Err(e) = alloc_console() {
+        error!("Couldn't allocate the console: {e:?}");
+    }
+    enable_console_colors();
+    *CONSOLE_LEVEL.write() = level;
+}
+
+pub(crate) fn file(path: &Path, level: LevelFilter) {
+    if !install() {
+        return;
+    }
+
+    match File::create(path) {
+        Ok(file) => {
+            *LOG_FILE.lock() = Some(file);
+            *FILE_LEVEL.write() = level;
+        },
+        Err(e) => error!("Couldn't create the log file {path:?}: {e:?}"),
+    }
+}
+
+// Install the global subscriber, unless the logging was initialized
+// elsewhere, and return whether it's ours.
+fn install() -> bool {
+    *INSTALLED.get_or_init(|| {
+        if tracing::dispatcher::has_been_set() {
+            return false;
+        }
+
+        let console = fmt::layer()
+            .with_thread_ids(true)
+            .with_thread_names(true)
+            .with_file(true)
+            .with_line_number(true)
+            .with_filter(filter_fn(|meta| enabled(meta, &CONSOLE_LEVEL)));
+
+        let file = fmt::layer()
+            .with_thread_ids(true)
+            .with_thread_names(true)
+            .with_file(true)
+            .with_line_number(true)
+            .with_ansi(false)
+            .with_writer(|| LogFile)
+            .with_filter(filter_fn(|meta| enabled(meta, &FILE_LEVEL)));
+
+        tracing_subscriber::registry().with(console).with(file).try_init().is_ok()
+    })
+}
+
+fn enabled(meta: &Metadata<'_>, level: &RwLock<LevelFilter>) -> bool {
+    meta.level() <= &*level.read()
+}
+
+// Writes to the log file, if any.
+struct LogFile;
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.lock().as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.lock().as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Utilities for finding code and data in the memory of the process.
+//!
+//! Patterns, also known as signatures or AOBs, are strings of space-separated
+//! hexadecimal bytes where `??` matches any byte, e.g.
+//! `"48 8B 05 ?? ?? ?? ?? 48 8B 48"`. The wildcards usually stand for
+//! addresses and offsets that change between versions of the application.
+//!
+//! Only committed, readable pages are ever read, so that scanning doesn't
+//! fault on reserved or guard pages.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use hudhook::memory;
+//!
+//! // Find `mov rax, [rip + ??]` followed by `mov rcx, [rax + 0x48]`.
+//! if let Some(addr) = memory::scan_module("game.exe", "48 8B 05 ?? ?? ?? ?? 48 8B 48") {
+//!     // The displacement is relative to the end of the instruction.
+//!     let displacement = unsafe { addr.add(3).cast::<i32>().read_unaligned() };
+//!     let base = unsafe { addr.offset(7 + displacement as isize) };
+//! }
+//! ```
+
+use std::ffi::c_void;
+use std::{mem, ptr, slice};
+
+use tracing::error;
+use windows::core::HSTRING;
+use windows::Win32::System::Diagnostics::Debug::{
+    IMAGE_FILE_HEADER, IMAGE_SCN_MEM_EXECUTE, IMAGE_SECTION_HEADER,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Memory::{
+    VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
+    PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY,
+};
+use windows::Win32::System::SystemServices::{
+    IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE, IMAGE_NT_SIGNATURE,
+};
+
+/// Find the first match of `pattern` in the executable sections of the loaded
+/// module named `module`, e.g. `"game.exe"` or `"engine.dll"`.
+///
+/// Returns `None` if the module isn't loaded, the pattern is invalid or
+/// matches nowhere.
+pub fn scan_module(module: &str, pattern: &str) -> Option<*mut u8> {
+    let pattern = parse_pattern(pattern)?;
+
+    let module_base = match unsafe { GetModuleHandleW(&HSTRING::from(module)) } {
+        Ok(handle) => handle.0 as *const u8,
+        Err(e) => {
+            error!("Couldn't find module {module}: {e}");
+            return None;
+        },
+    };
+
+    executable_sections(module_base)?.into_iter().find_map(|(start, end)| {
+        let mut found = None;
+        for_each_readable_run(start, end, |run| {
+            found = find(run, &pattern).next();
+            found.is_none()
+        });
+        found
+    })
+}
+
+/// Find all the matches of `pattern` in the readable memory of the process,
+/// e.g. to pick the right one when a pattern isn't specific enough.
+///
+/// This reads all of the address space, heaps and stacks included, so it's
+/// much slower than [`scan_module`]. Memory freed by other threads during the
+/// scan can still fault.
+///
+/// Returns an empty list if the pattern is invalid or matches nowhere.
+pub fn scan_all(pattern: &str) -> Vec<*mut u8> {
+    let Some(pattern) = parse_pattern(pattern) else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    for_each_readable_run(ptr::null(), usize::MAX as *const u8, |run| {
+        found.extend(find(run, &pattern));
+        true
+    });
+    found
+}
+
+// Parse a pattern into a list of bytes, `None` being a wildcard.
+fn parse_pattern(pattern: &str) -> Option<Vec<Option<u8>>> {
+    let parsed = pattern
+        .split_whitespace()
+        .map(|byte| match byte {
+            "?" | "??" => Some(None),
+            byte if byte.len() == 2 => u8::from_str_radix(byte, 16).ok().map(Some),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+
+    match parsed {
+        Some(parsed) if !parsed.is_empty() => Some(parsed),
+        _ => {
+            error!("Invalid pattern: {pattern:?}");
+            None
+        },
+    }
+}
+
+// Iterate over the addresses of the matches of the pattern in the buffer.
+fn find<'a>(haystack: &'a [u8], pattern: &'a [Option<u8>]) -> impl Iterator<Item = *mut u8> + 'a {
+    haystack
+        .windows(pattern.len())
+        .filter(|window| {
+            window
+                .iter()
+                .zip(pattern)
+                .all(|(byte, expected)| expected.is_none() || *expected == Some(*byte))
+        })
+        .map(|window| window.as_ptr() as *mut u8)
+}
+
+// Retrieve the address ranges of the executable sections of a loaded module,
+// as described by its `IMAGE_NT_HEADERS`.
+fn executable_sections(module_base: *const u8) -> Option<Vec<(*const u8, *const u8)>> {
+    unsafe {
+        let dos_header = &*(module_base as *const IMAGE_DOS_HEADER);
+        if dos_header.e_magic != IMAGE_DOS_SIGNATURE {
+            error!("Invalid DOS header at {module_base:p}");
+            return None;
+        }
+
+        // The file header, and thus the location of the section headers, is
+        // the same for 32-bit and 64-bit images.
+        let nt_headers = module_base.offset(dos_header.e_lfanew as isize);
+        if (nt_headers as *const u32).read_unaligned() != IMAGE_NT_SIGNATURE {
+            error!("Invalid NT headers at {nt_headers:p}");
+            return None;
+        }
+
+        let file_header_ptr = nt_headers.add(mem::size_of::<u32>());
+        let file_header = (file_header_ptr as *const IMAGE_FILE_HEADER).read_unaligned();
+        let section_headers = slice::from_raw_parts(
+            file_header_ptr.add(
+                mem::size_of::<IMAGE_FILE_HEADER>() + file_header.SizeOfOptionalHeader as usize,
+            ) as *const IMAGE_SECTION_HEADER,
+            file_header.NumberOfSections as usize,
+        );
+
+        Some(
+            section_headers
+                .iter()
+                .filter(|section| section.Characteristics.0 & IMAGE_SCN_MEM_EXECUTE.0 != 0)
+                .map(|section| {
+                    let start = module_base.add(section.VirtualAddress as usize);
+                    (start, start.add(section.Misc.VirtualSize as usize))
+                })
+                .collect(),
+        )
+    }
+}
+
+// Invoke `f` on each run of contiguous committed, readable memory between
+// `start` and `end`, until it returns `false`. Adjacent readable regions are
+// merged, so that matches spanning them are found.
+fn for_each_readable_run<F: FnMut(&[u8]) -> bool>(start: *const u8, end: *const u8, mut f: F) {
+    const READABLE: u32 = PAGE_READONLY.0
+        | PAGE_READWRITE.0
+        | PAGE_WRITECOPY.0
+        | PAGE_EXECUTE_READ.0
+        | PAGE_EXECUTE_READWRITE.0
+        | PAGE_EXECUTE_WRITECOPY.0;
+
+    let mut addr = start;
+    let mut run_start = None;
+
+    while addr < end {
+        let mut info = MEMORY_BASIC_INFORMATION::default();
+        let written = unsafe {
+            VirtualQuery(
+                Some(addr as *const c_void),
+                &mut info,
+                mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+            )
+        };
+        if written == 0 {
+            break;
+        }
+
+        let region_end = (info.BaseAddress as *const u8).wrapping_add(info.RegionSize).min(end);
+        let readable = info.State == MEM_COMMIT
+            && info.Protect.0 & READABLE != 0
+            && info.Protect.0 & PAGE_GUARD.0 == 0;
+
+        match (readable, run_start) {
+            (true, None) => run_start = Some(addr),
+            (false, Some(run)) => {
+                run_start = None;
+                if !f(unsafe { slice::from_raw_parts(run, addr as usize - run as usize) }) {
+                    return;
+                }
+            },
+            _ => {},
+        }
+
+        // The end of the address space can wrap around.
+        if region_end <= addr {
+            break;
+        }
+        addr = region_end;
+    }
+
+    if let Some(run) = run_start {
+        f(unsafe { slice::from_raw_parts(run, addr as usize - run as usize) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::System::LibraryLoader::GetModuleFileNameW;
+
+    use super::*;
+
+    static NEEDLE: [u8; 12] =
+        [0x4d, 0x9a, 0x31, 0xf7, 0x00, 0x13, 0xc8, 0x5e, 0x7b, 0x02, 0xe4, 0x96];
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(
+            parse_pattern("48 8B ?? 05 ?").unwrap(),
+            [Some(0x48), Some(0x8b), None, Some(0x05), None]
+        );
+        assert!(parse_pattern("").is_none());
+        assert!(parse_pattern("48 8").is_none());
+        assert!(parse_pattern("48 XX").is_none());
+        assert!(parse_pattern("488B").is_none());
+    }
+
+    #[test]
+    fn test_scan_all() {
+        let found = scan_all("4D 9A 31 F7 ?? 13 C8 5E 7B ?? E4 96");
+        assert!(found.contains(&(NEEDLE.as_ptr() as *mut u8)));
+        assert!(scan_all("not a pattern").is_empty());
+    }
+
+    #[test]
+    fn test_scan_module() {
+        let mut buf = [0u16; 260];
+        let len = unsafe { GetModuleFileNameW(None, &mut buf) } as usize;
+        let path = String::from_utf16_lossy(&buf[..len]);
+        let exe_name = path.rsplit('\\').next().unwrap();
+
+        // Look for the code of this very function.
+        let code = test_scan_module as *const u8;
+        let pattern = unsafe { slice::from_raw_parts(code, 16) }
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let found = scan_module(exe_name, &pattern).unwrap();
+        assert_eq!(unsafe { slice::from_raw_parts(found, 16) }, unsafe {
+            slice::from_raw_parts(code, 16)
+        });
+
+        assert!(scan_module("not_loaded.dll", &pattern).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+//! This module contains the channel that delivers messages from other threads
+//! to the render loop. See
+//! [`HudhookBuilder::with_channel`](crate::HudhookBuilder::with_channel).
+
+use std::any::{self, Any};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+use parking_lot::Mutex;
+
+/// A message sent via a [`MessageSender`], as received by
+/// [`ImguiRenderLoop::on_message`](crate::ImguiRenderLoop::on_message).
+///
+/// Render loops aren't generic over the type of their messages, so the type
+/// is recovered via [`Message::downcast`].
+pub struct Message {
+    payload: Box<dyn Any + Send>,
+    type_name: &'static str,
+}
+
+impl Message {
+    fn new<T: Send + 'static>(payload: T) -> Self {
+        Self { payload: Box::new(payload), type_name: any::type_name::<T>() }
+    }
+
+    /// Whether the message holds a `T`.
+    pub fn is<T: 'static>(&self) -> bool {
+        self.payload.is::<T>()
+    }
+
+    /// Take the `T` out of the message, or give the message back if it holds
+    /// something else.
+    pub fn downcast<T: 'static>(self) -> Result<T, Self> {
+        let Self { payload, type_name } = self;
+        payload.downcast().map(|payload| *payload).map_err(|payload| Self { payload, type_name })
+    }
+
+    /// Borrow the `T` in the message, if it holds one.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.payload.downcast_ref()
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Message").field("type", &self.type_name).finish_non_exhaustive()
+    }
+}
+
+/// Sends messages of type `T` to the render loop, from any thread. Obtain one
+/// via [`HudhookBuilder::with_channel`](crate::HudhookBuilder::with_channel),
+/// and clone it to send from multiple threads.
+pub struct MessageSender<T> {
+    tx: SyncSender<Message>,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Send + 'static> MessageSender<T> {
+    /// Send a message, waiting for there to be room in the channel if it's
+    /// full. Fails, giving the message back, once the overlay has been torn
+    /// down.
+    ///
+    /// The channel only empties when the application presents a frame, which
+    /// it may stop doing for a while, e.g. while minimized: prefer
+    /// [`MessageSender::try_send`] on threads that mustn't block.
+    pub fn send(&self, message: T) -> Result<(), T> {
+        self.tx.send(Message::new(message)).map_err(|e| unwrap_message(e.0))
+    }
+
+    /// Send a message if there's room in the channel. Fails, giving the
+    /// message back, if the channel is full or once the overlay has been
+    /// torn down.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.tx.try_send(Message::new(message)).map_err(|e| match e {
+            TrySendError::Full(message) => TrySendError::Full(unwrap_message(message)),
+            TrySendError::Disconnected(message) => {
+                TrySendError::Disconnected(unwrap_message(message))
+            },
+        })
+    }
+}
+
+impl<T> Clone for MessageSender<T> {
+    fn clone(&self) -> Self {
+        Self { tx: self.tx.clone(), _marker: PhantomData }
+    }
+}
+
+impl<T> fmt::Debug for MessageSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageSender").field("type", &any::type_name::<T>()).finish()
+    }
+}
+
+// The messages built by `MessageSender<T>` always hold a `T`.
+fn unwrap_message<T: 'static>(message: Message) -> T {
+    message.downcast().expect("message of the sender's type")
+}
+
+/// The receiving end of the channel, shared by the pipelines.
+pub(crate) struct MessageReceiver {
+    rx: Mutex<Receiver<Message>>,
+    capacity: usize,
+}
+
+impl MessageReceiver {
+    /// Take the messages out of the channel, in the order they were sent.
+    ///
+    /// At most as many messages as the channel holds are taken, so that
+    /// senders that keep up with the render loop can't keep it from
+    /// returning.
+    pub(crate) fn drain(&self, f: impl FnMut(Message)) {
+        self.rx.lock().try_iter().take(self.capacity.max(1)).for_each(f);
+    }
+}
+
+/// Create a channel holding up to `capacity` messages.
+pub(crate) fn channel<T: Send + 'static>(capacity: usize) -> (MessageSender<T>, MessageReceiver) {
+    let (tx, rx) = mpsc::sync_channel(capacity);
+    (MessageSender { tx, _marker: PhantomData }, MessageReceiver { rx: Mutex::new(rx), capacity })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel() {
+        let (tx, rx) = channel::<u32>(2);
+        let other_tx = tx.clone();
+
+        tx.send(1).unwrap();
+        other_tx.try_send(2).unwrap();
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Full(3))));
+
+        let mut received = Vec::new();
+        rx.drain(|message| received.push(message.downcast::<u32>().unwrap()));
+        assert_eq!(received, [1, 2]);
+
+        let message = Message::new(String::from("hello"));
+        assert!(message.is::<String>() && !message.is::<u32>());
+        let message = message.downcast::<u32>().unwrap_err();
+        assert_eq!(message.downcast_ref::<String>().map(String::as_str), Some("hello"));
+
+        drop(rx);
+        assert_eq!(tx.send(4), Err(4));
+        assert!(matches!(tx.try_send(5), Err(TrySendError::Disconnected(5))));
+    }
+}
@@ -1,11 +1,57 @@
 //! Thin FFI wrapper around [`minhook`](https://github.com/TsudaKageyu/minhook).
-#![allow(dead_code, non_snake_case, non_camel_case_types, missing_docs)]
+//!
+//! Besides the rendering functions hooked by hudhook, [`MhHook`] can hook any
+//! function of the application, with the same minhook instance.
+//!
+//! Hooks returned from [`Hooks::hooks`](crate::Hooks::hooks) are enabled by
+//! [`Hudhook::apply`](crate::Hudhook::apply), and disabled along with the
+//! others. Hooks created on their own have to be enabled by queueing them with
+//! [`MhHook::queue_enable`], then applying the queue with
+//! [`MH_ApplyQueued`]. Either way, minhook must have been initialized, which
+//! [`Hudhook::builder`](crate::Hudhook::builder) does, or [`MH_Initialize`]
+//! otherwise.
+//!
+//! ```no_run
+//! use std::ffi::c_void;
+//! use std::mem;
+//! use std::sync::OnceLock;
+//!
+//! use hudhook::mh::{MhHook, MH_ApplyQueued, MH_STATUS};
+//!
+//! type UpdateFn = unsafe extern "C" fn(f32) -> u32;
+//!
+//! static ORIGINAL: OnceLock<UpdateFn> = OnceLock::new();
+//!
+//! unsafe extern "C" fn update_detour(dt: f32) -> u32 {
+//!     // Run the game at half speed.
+//!     let update = *ORIGINAL.get().unwrap();
+//!     update(dt * 0.5)
+//! }
+//!
+//! unsafe fn hook_update(update: *mut c_void) -> Result<MhHook, MH_STATUS> {
+//!     let hook = MhHook::new(update, update_detour as *mut c_void)?;
+//!     // The detour may be called as soon as the hook is enabled.
+//!     ORIGINAL.get_or_init(|| mem::transmute::<*mut c_void, UpdateFn>(hook.trampoline()));
+//!     hook.queue_enable()?;
+//!     MH_ApplyQueued().ok()?;
+//!     Ok(hook)
+//! }
+//! ```
+#![allow(dead_code, non_snake_case, non_camel_case_types)]
 
-use std::ffi::c_void;
+use std::ffi::{c_void, OsString};
+use std::mem;
+use std::os::windows::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
 
-use tracing::error;
+use tracing::{debug, error, warn};
+use windows::Win32::Foundation::MAX_PATH;
+use windows::Win32::System::SystemInformation::GetSystemWindowsDirectoryW;
 
+use crate::util;
+
+/// The result of a minhook call.
 #[allow(non_camel_case_types)]
 #[must_use]
 #[repr(C)]
@@ -44,21 +90,33 @@ pub enum MH_STATUS {
 }
 
 extern "system" {
+    /// Initialize minhook. Fails with
+    /// [`MH_STATUS::MH_ERROR_ALREADY_INITIALIZED`] if it already is.
     pub fn MH_Initialize() -> MH_STATUS;
+    /// Disable and remove all the hooks, and uninitialize minhook.
     pub fn MH_Uninitialize() -> MH_STATUS;
+    /// Create a disabled hook on `pTarget`, and store the trampoline calling
+    /// the original function in `ppOriginal`. Prefer [`MhHook::new`].
     pub fn MH_CreateHook(
         pTarget: *mut c_void,
         pDetour: *mut c_void,
         ppOriginal: *mut *mut c_void,
     ) -> MH_STATUS;
+    /// Enable the hook on `pTarget` right away.
     pub fn MH_EnableHook(pTarget: *mut c_void) -> MH_STATUS;
+    /// Queue enabling the hook on `pTarget`, until [`MH_ApplyQueued`].
     pub fn MH_QueueEnableHook(pTarget: *mut c_void) -> MH_STATUS;
+    /// Disable the hook on `pTarget` right away.
     pub fn MH_DisableHook(pTarget: *mut c_void) -> MH_STATUS;
+    /// Queue disabling the hook on `pTarget`, until [`MH_ApplyQueued`].
     pub fn MH_QueueDisableHook(pTarget: *mut c_void) -> MH_STATUS;
+    /// Enable and disable the queued hooks at once, with the other threads
+    /// of the process suspended.
     pub fn MH_ApplyQueued() -> MH_STATUS;
 }
 
 impl MH_STATUS {
+    /// Turn the status into a `Result`, logging `context` along with errors.
     pub fn ok_context(self, context: &str) -> Result<(), MH_STATUS> {
         if self == MH_STATUS::MH_OK {
             Ok(())
@@ -68,6 +126,7 @@ impl MH_STATUS {
         }
     }
 
+    /// Turn the status into a `Result`.
     pub fn ok(self) -> Result<(), MH_STATUS> {
         if self == MH_STATUS::MH_OK {
             Ok(())
@@ -77,40 +136,208 @@ impl MH_STATUS {
     }
 }
 
+/// Another hook found on a function when hooking it, e.g. the one of the
+/// Steam, Discord or RivaTuner overlays on `Present`.
+///
+/// The hook is installed after the other one: the trampoline jumps to it,
+/// which in turn calls the original function, so that both overlays are drawn.
+#[derive(Debug, Clone)]
+pub struct PriorHook {
+    /// Where the jump patched in the function leads to.
+    pub target: *const c_void,
+    /// The module the jump leads to, or `None` if it leads to code allocated
+    /// at runtime, as is the case with most hooking libraries.
+    pub module: Option<PathBuf>,
+}
+
 /// Structure that holds original address, hook function address, and trampoline
 /// address for a given hook.
+///
+/// The hook is created disabled. Dropping it doesn't remove it: hooks are only
+/// removed when minhook is uninitialized, e.g. by
+/// [`Hudhook::unapply`](crate::Hudhook::unapply).
 pub struct MhHook {
     addr: *mut c_void,
     hook_impl: *mut c_void,
     trampoline: *mut c_void,
+    prior_hook: Option<PriorHook>,
 }
 
 impl MhHook {
+    /// Create a hook on `addr`, redirecting its calls to `hook_impl`. If the
+    /// function is already hooked by some other module, a warning naming it
+    /// is logged, and the hook is chained after it. See
+    /// [`MhHook::prior_hook`].
+    ///
     /// # Safety
     ///
-    /// Most definitely undefined behavior.
+    /// - minhook must be initialized.
+    /// - `addr` must point to the start of a function, which stays loaded as
+    ///   long as it's hooked.
+    /// - `hook_impl` must have exactly the same signature and calling
+    ///   convention as the function, and stay loaded as long as it's hooked.
+    /// - Once enabled, `hook_impl` may be called from any thread, including
+    ///   while it's already running, and must not unwind.
     pub unsafe fn new(addr: *mut c_void, hook_impl: *mut c_void) -> Result<Self, MH_STATUS> {
+        let prior_hook = prior_hook(addr);
+        if let Some(PriorHook { target, module }) = &prior_hook {
+            match module {
+                Some(module) => warn!(
+                    "{addr:p} is already hooked by {}, at {target:p}. Chaining after it.",
+                    module.display()
+                ),
+                None => warn!(
+                    "{addr:p} is already hooked by code outside of any module, at {target:p}. \
+                     Chaining after it."
+                ),
+            }
+        }
+
         let mut trampoline = null_mut();
         MH_CreateHook(addr, hook_impl, &mut trampoline).ok_context("MH_CreateHook")?;
 
-        Ok(Self { addr, hook_impl, trampoline })
+        Ok(Self { addr, hook_impl, trampoline, prior_hook })
     }
 
+    /// The function calling the original one, meant to be transmuted to its
+    /// signature and called from the hook. It's valid until the hook is
+    /// removed.
     pub fn trampoline(&self) -> *mut c_void {
         self.trampoline
     }
 
+    /// The hook that was already on the function when this one was created,
+    /// if any.
+    pub fn prior_hook(&self) -> Option<&PriorHook> {
+        self.prior_hook.as_ref()
+    }
+
+    /// Queue enabling the hook, which takes effect on the next call to
+    /// [`MH_ApplyQueued`]. Not needed for the hooks of [`Hooks`](crate::Hooks)
+    /// implementations, or the ones added with
+    /// [`HudhookBuilder::with_raw_hook`](crate::HudhookBuilder::with_raw_hook),
+    /// which are enabled by [`Hudhook::apply`](crate::Hudhook::apply).
+    ///
     /// # Safety
     ///
-    /// Most definitely undefined behavior.
+    /// The function may be called through the hook as soon as it's enabled,
+    /// so whatever the hook relies on, such as the trampoline, must be set up
+    /// beforehand.
     pub unsafe fn queue_enable(&self) -> Result<(), MH_STATUS> {
         MH_QueueEnableHook(self.addr).ok_context("MH_QueueEnableHook")
     }
 
+    /// Queue disabling the hook, which takes effect on the next call to
+    /// [`MH_ApplyQueued`].
+    ///
     /// # Safety
     ///
-    /// Most definitely undefined behavior.
+    /// The hook may still be running on other threads after it's disabled, so
+    /// whatever it relies on must stay around until it returns.
     pub unsafe fn queue_disable(&self) -> Result<(), MH_STATUS> {
         MH_QueueDisableHook(self.addr).ok_context("MH_QueueDisableHook")
     }
 }
+
+/// A hook added with
+/// [`HudhookBuilder::with_raw_hook`](crate::HudhookBuilder::with_raw_hook),
+/// whose lifetime is managed by [`Hudhook`](crate::Hudhook).
+#[derive(Debug, Clone, Copy)]
+pub struct RawHook {
+    pub(crate) trampoline: Option<*mut c_void>,
+}
+
+// The trampoline is code, which can be called from any thread.
+unsafe impl Send for RawHook {}
+unsafe impl Sync for RawHook {}
+
+impl RawHook {
+    /// The function calling the original one, as with
+    /// [`MhHook::trampoline`], or `None` if the hook couldn't be created.
+    pub fn trampoline(&self) -> Option<*mut c_void> {
+        self.trampoline
+    }
+}
+
+// Look for a jump patched at the start of the function at `addr` by another
+// hook. Jumps to system modules are forwarding stubs, e.g. from `gdi32.dll` to
+// `gdi32full.dll`, rather than hooks.
+unsafe fn prior_hook(addr: *const c_void) -> Option<PriorHook> {
+    let target = jump_target(util::readable_region(addr as *const u8, 12))?;
+    let module = util::module_path_of(target);
+
+    if module.as_deref().is_some_and(is_system_module) {
+        debug!("{addr:p} jumps to {target:p}, in a system module");
+        return None;
+    }
+
+    Some(PriorHook { target, module })
+}
+
+// Decode the jumps hooking libraries usually patch functions with.
+unsafe fn jump_target(code: &[u8]) -> Option<*const c_void> {
+    let base = code.as_ptr();
+    let rel32 = |offset: usize| i32::from_le_bytes(code[offset..offset + 4].try_into().unwrap());
+    let read_ptr = |slot: *const u8| {
+        let size = mem::size_of::<*const c_void>();
+        (util::readable_region(slot, size).len() == size)
+            .then(|| slot.cast::<*const c_void>().read_unaligned())
+    };
+
+    match code {
+        // jmp rel32
+        [0xe9, _, _, _, _, ..] => Some(base.offset(5 + rel32(1) as isize).cast()),
+        // jmp [rip + disp32] on x64, jmp [disp32] on x86
+        [0xff, 0x25, _, _, _, _, ..] => {
+            #[cfg(target_arch = "x86_64")]
+            let slot = base.offset(6 + rel32(2) as isize);
+            #[cfg(target_arch = "x86")]
+            let slot = rel32(2) as usize as *const u8;
+            read_ptr(slot)
+        },
+        // mov rax, imm64; jmp rax
+        #[cfg(target_arch = "x86_64")]
+        [0x48, 0xb8, _, _, _, _, _, _, _, _, 0xff, 0xe0, ..] => {
+            Some(u64::from_le_bytes(code[2..10].try_into().unwrap()) as usize as *const c_void)
+        },
+        // push imm32; ret
+        [0x68, _, _, _, _, 0xc3, ..] => Some(rel32(1) as u32 as usize as *const c_void),
+        _ => None,
+    }
+}
+
+fn is_system_module(module: &Path) -> bool {
+    let mut buf = [0u16; MAX_PATH as usize];
+    let len = unsafe { GetSystemWindowsDirectoryW(Some(&mut buf)) } as usize;
+    if len == 0 || len > buf.len() {
+        return false;
+    }
+
+    let windows_dir = PathBuf::from(OsString::from_wide(&buf[..len]));
+    module
+        .to_string_lossy()
+        .to_lowercase()
+        .starts_with(&windows_dir.to_string_lossy().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jump_target() {
+        unsafe {
+            let code = [0xe9, 0x10, 0x00, 0x00, 0x00, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc];
+            assert_eq!(jump_target(&code), Some(code.as_ptr().add(0x15).cast()));
+
+            let code = [0x68, 0x78, 0x56, 0x34, 0x12, 0xc3, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc];
+            assert_eq!(jump_target(&code), Some(0x12345678 as *const c_void));
+
+            let code = [0x48, 0x89, 0x5c, 0x24, 0x08, 0x57, 0x48, 0x83, 0xec, 0x20, 0xcc, 0xcc];
+            assert_eq!(jump_target(&code), None);
+
+            // Too short to tell.
+            assert_eq!(jump_target(&[0xe9, 0x10]), None);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Proxy DLLs, i.e. DLLs named after a system DLL that the application loads
+//! from its own directory, e.g. `dinput8.dll`, so that they don't need to be
+//! injected.
+//!
+//! A proxy DLL has to export all the functions of the DLL it stands in for,
+//! and forward them to it. [`proxy_dll!`](crate::proxy_dll) generates the
+//! exports for the usual targets, `dinput8`, `dxgi`, `d3d9`, `winmm` and
+//! `version`, or for a custom list of functions.
+//!
+//! The exports jump straight to the functions of the system DLL, so they work
+//! whatever their signature and calling convention. The system DLL is loaded
+//! from the system directory when the proxy DLL is loaded, before `DllMain`
+//! runs.
+//!
+//! # Example
+//!
+//! ```no_run
+//! // lib.rs, built as `dinput8.dll` and copied next to the application.
+//! use hudhook::hooks::dx11::ImguiDx11Hooks;
+//! use hudhook::*;
+//!
+//! pub struct MyRenderLoop;
+//!
+//! impl ImguiRenderLoop for MyRenderLoop {
+//!     fn render(&mut self, ui: &mut imgui::Ui) {
+//!         ui.text("Hello from dinput8.dll!");
+//!     }
+//! }
+//!
+//! hudhook::proxy_dll!("dinput8");
+//! hudhook::hudhook!(ImguiDx11Hooks, MyRenderLoop);
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::{debug, error};
+use windows::core::{HSTRING, PCSTR};
+use windows::Win32::Foundation::MAX_PATH;
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::System::SystemInformation::GetSystemDirectoryW;
+
+/// Generate the exports of a proxy DLL, forwarding them to the system DLL it's
+/// named after. See the [`proxy`](crate::proxy) module.
+///
+/// Invoke it once, at the root of the crate, with either one of the supported
+/// targets, `"dinput8"`, `"dxgi"`, `"d3d9"`, `"winmm"` or `"version"`, or the
+/// file name of the DLL and the names of the functions to forward:
+///
+/// ```no_run
+/// hudhook::proxy_dll!("xinput1_3.dll", [XInputGetState, XInputSetState, XInputGetCapabilities]);
+/// ```
+///
+/// Only x86 and x64 targets are supported.
+#[macro_export]
+macro_rules! proxy_dll {
+    ("dinput8") => {
+        $crate::proxy_dll!("dinput8.dll", [
+            DirectInput8Create,
+            DllCanUnloadNow,
+            DllGetClassObject,
+            DllRegisterServer,
+            DllUnregisterServer,
+            GetdfDIJoystick,
+        ]);
+    };
+    ("dxgi") => {
+        $crate::proxy_dll!("dxgi.dll", [
+            ApplyCompatResolutionQuirking,
+            CompatString,
+            CompatValue,
+            CreateDXGIFactory,
+            CreateDXGIFactory1,
+            CreateDXGIFactory2,
+            DXGID3D10CreateDevice,
+            DXGID3D10CreateLayeredDevice,
+            DXGID3D10GetLayeredDeviceSize,
+            DXGID3D10RegisterLayers,
+            DXGIDeclareAdapterRemovalSupport,
+            DXGIDisableVBlankVirtualization,
+            DXGIDumpJournal,
+            DXGIGetDebugInterface1,
+            DXGIReportAdapterConfiguration,
+            PIXBeginCapture,
+            PIXEndCapture,
+            PIXGetCaptureState,
+            SetAppCompatStringPointer,
+            UpdateHMDEmulationStatus,
+        ]);
+    };
+    ("d3d9") => {
+        $crate::proxy_dll!("d3d9.dll", [
+            D3DPERF_BeginEvent,
+            D3DPERF_EndEvent,
+            D3DPERF_GetStatus,
+            D3DPERF_QueryRepeatFrame,
+            D3DPERF_SetMarker,
+            D3DPERF_SetOptions,
+            D3DPERF_SetRegion,
+            DebugSetLevel,
+            DebugSetMute,
+            Direct3D9EnableMaximizedWindowedModeShim,
+            Direct3DCreate9,
+            Direct3DCreate9Ex,
+            Direct3DCreate9On12,
+            Direct3DCreate9On12Ex,
+            Direct3DShaderValidatorCreate9,
+            PSGPError,
+            PSGPSampleTexture,
+        ]);
+    };
+    ("version") => {
+        $crate::proxy_dll!("version.dll", [
+            GetFileVersionInfoA,
+            GetFileVersionInfoByHandle,
+            GetFileVersionInfoExA,
+            GetFileVersionInfoExW,
+            GetFileVersionInfoSizeA,
+            GetFileVersionInfoSizeExA,
+            GetFileVersionInfoSizeExW,
+            GetFileVersionInfoSizeW,
+            GetFileVersionInfoW,
+            VerFindFileA,
+            VerFindFileW,
+            VerInstallFileA,
+            VerInstallFileW,
+            VerLanguageNameA,
+            VerLanguageNameW,
+            VerQueryValueA,
+            VerQueryValueW,
+        ]);
+    };
+    ("winmm") => {
+        $crate::proxy_dll!("winmm.dll", [
+            CloseDriver, DefDriverProc, DriverCallback, DrvGetModuleHandle,
+            GetDriverModuleHandle, OpenDriver, PlaySound, PlaySoundA, PlaySoundW,
+            SendDriverMessage, WOWAppExit, auxGetDevCapsA, auxGetDevCapsW, auxGetNumDevs,
+            auxGetVolume, auxOutMessage, auxSetVolume, joyConfigChanged, joyGetDevCapsA,
+            joyGetDevCapsW, joyGetNumDevs, joyGetPos, joyGetPosEx, joyGetThreshold,
+            joyReleaseCapture, joySetCapture, joySetThreshold, mciDriverNotify,
+            mciDriverYield, mciExecute, mciFreeCommandResource, mciGetCreatorTask,
+            mciGetDeviceIDA, mciGetDeviceIDFromElementIDA, mciGetDeviceIDFromElementIDW,
+            mciGetDeviceIDW, mciGetDriverData, mciGetErrorStringA, mciGetErrorStringW,
+            mciGetYieldProc, mciLoadCommandResource, mciSendCommandA, mciSendCommandW,
+            mciSendStringA, mciSendStringW, mciSetDriverData, mciSetYieldProc, midiConnect,
+            midiDisconnect, midiInAddBuffer, midiInClose, midiInGetDevCapsA,
+            midiInGetDevCapsW, midiInGetErrorTextA, midiInGetErrorTextW, midiInGetID,
+            midiInGetNumDevs, midiInMessage, midiInOpen, midiInPrepareHeader, midiInReset,
+            midiInStart, midiInStop, midiInUnprepareHeader, midiOutCacheDrumPatches,
+            midiOutCachePatches, midiOutClose, midiOutGetDevCapsA, midiOutGetDevCapsW,
+            midiOutGetErrorTextA, midiOutGetErrorTextW, midiOutGetID, midiOutGetNumDevs,
+            midiOutGetVolume, midiOutLongMsg, midiOutMessage, midiOutOpen,
+            midiOutPrepareHeader, midiOutReset, midiOutSetVolume, midiOutShortMsg,
+            midiOutUnprepareHeader, midiStreamClose, midiStreamOpen, midiStreamOut,
+            midiStreamPause, midiStreamPosition, midiStreamProperty, midiStreamRestart,
+            midiStreamStop, mixerClose, mixerGetControlDetailsA, mixerGetControlDetailsW,
+            mixerGetDevCapsA, mixerGetDevCapsW, mixerGetID, mixerGetLineControlsA,
+            mixerGetLineControlsW, mixerGetLineInfoA, mixerGetLineInfoW, mixerGetNumDevs,
+            mixerMessage, mixerOpen, mixerSetControlDetails, mmDrvInstall, mmGetCurrentTask,
+            mmTaskBlock, mmTaskCreate, mmTaskSignal, mmTaskYield, mmioAdvance, mmioAscend,
+            mmioClose, mmioCreateChunk, mmioDescend, mmioFlush, mmioGetInfo,
+            mmioInstallIOProcA, mmioInstallIOProcW, mmioOpenA, mmioOpenW, mmioRead,
+            mmioRenameA, mmioRenameW, mmioSeek, mmioSendMessage, mmioSetBuffer, mmioSetInfo,
+            mmioStringToFOURCCA, mmioStringToFOURCCW, mmioWrite, mmsystemGetVersion,
+            sndPlaySoundA, sndPlaySoundW, timeBeginPeriod, timeEndPeriod, timeGetDevCaps,
+            timeGetSystemTime, timeGetTime, timeKillEvent, timeSetEvent, waveInAddBuffer,
+            waveInClose, waveInGetDevCapsA, waveInGetDevCapsW, waveInGetErrorTextA,
+            waveInGetErrorTextW, waveInGetID, waveInGetNumDevs, waveInGetPosition,
+            waveInMessage, waveInOpen, waveInPrepareHeader, waveInReset, waveInStart,
+            waveInStop, waveInUnprepareHeader, waveOutBreakLoop, waveOutClose,
+            waveOutGetDevCapsA, waveOutGetDevCapsW, waveOutGetErrorTextA,
+            waveOutGetErrorTextW, waveOutGetID, waveOutGetNumDevs, waveOutGetPitch,
+            waveOutGetPlaybackRate, waveOutGetPosition, waveOutGetVolume, waveOutMessage,
+            waveOutOpen, waveOutPause, waveOutPrepareHeader, waveOutReset, waveOutRestart,
+            waveOutSetPitch, waveOutSetPlaybackRate, waveOutSetVolume,
+            waveOutUnprepareHeader, waveOutWrite,
+        ]);
+    };
+    ($dll:literal, [$($export:ident),* $(,)?]) => {
+        // The addresses of the functions of the system DLL.
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        mod __hudhook_proxy_exports {
+            $(
+                pub static $export: ::std::sync::atomic::AtomicUsize =
+                    ::std::sync::atomic::AtomicUsize::new(0);
+            )*
+        }
+
+        // Each export jumps to the function of the system DLL. The stubs are
+        // named apart from the exports, so that they don't clash with the
+        // import libraries the crate links to, and exported through a linker
+        // directive.
+        $(
+            #[cfg(target_arch = "x86_64")]
+            ::std::arch::global_asm!(
+                ".text",
+                concat!(".globl __hudhook_proxy_", stringify!($export)),
+                concat!("__hudhook_proxy_", stringify!($export), ":"),
+                "jmp qword ptr [rip + {slot}]",
+                ".section .drectve,\"yn\"",
+                concat!(
+                    ".ascii \" -export:",
+                    stringify!($export),
+                    "=__hudhook_proxy_",
+                    stringify!($export),
+                    "\"",
+                ),
+                ".text",
+                slot = sym __hudhook_proxy_exports::$export,
+            );
+
+            // Symbols are looked up with and without the underscore prefix of
+            // the C calling convention on x86.
+            #[cfg(target_arch = "x86")]
+            ::std::arch::global_asm!(
+                ".text",
+                concat!(".globl __hudhook_proxy_", stringify!($export)),
+                concat!(".globl ___hudhook_proxy_", stringify!($export)),
+                concat!("__hudhook_proxy_", stringify!($export), ":"),
+                concat!("___hudhook_proxy_", stringify!($export), ":"),
+                "jmp dword ptr [{slot}]",
+                ".section .drectve,\"yn\"",
+                concat!(
+                    ".ascii \" -export:",
+                    stringify!($export),
+                    "=__hudhook_proxy_",
+                    stringify!($export),
+                    "\"",
+                ),
+                ".text",
+                slot = sym __hudhook_proxy_exports::$export,
+            );
+        )*
+
+        // Load the system DLL along with this one, before `DllMain` runs and
+        // thus before any export can be called.
+        #[used]
+        #[doc(hidden)]
+        #[link_section = ".CRT$XCU"]
+        static __HUDHOOK_PROXY_INIT: unsafe extern "C" fn() = {
+            unsafe extern "C" fn init() {
+                $crate::proxy::load_exports($dll, &[
+                    $((concat!(stringify!($export), "\0"), &__hudhook_proxy_exports::$export)),*
+                ]);
+            }
+            init
+        };
+    };
+}
+
+/// Load the DLL named `dll` from the system directory, and store the addresses
+/// of its `exports`, given as nul-terminated names, for the stubs generated by
+/// [`proxy_dll!`](crate::proxy_dll) to jump to.
+///
+/// # Safety
+///
+/// Loading a DLL runs its initialization code.
+#[doc(hidden)]
+pub unsafe fn load_exports(dll: &str, exports: &[(&str, &AtomicUsize)]) {
+    let mut buf = [0u16; MAX_PATH as usize];
+    let len = GetSystemDirectoryW(Some(&mut buf)) as usize;
+    if len == 0 || len > buf.len() {
+        error!("Couldn't find the system directory");
+        return;
+    }
+
+    let path = format!("{}\\{dll}", String::from_utf16_lossy(&buf[..len]));
+    let module = match LoadLibraryW(&HSTRING::from(path.as_str())) {
+        Ok(module) => module,
+        Err(e) => {
+            error!("Couldn't load {path}: {e:?}");
+            return;
+        },
+    };
+
+    for (name, slot) in exports {
+        debug_assert!(name.ends_with('\0'));
+
+        // Exports missing from this version of the DLL can't be called either.
+        match GetProcAddress(module, PCSTR(name.as_ptr())) {
+            Some(addr) => slot.store(addr as usize, Ordering::SeqCst),
+            None => debug!("{dll} doesn't export {}", name.trim_end_matches('\0')),
+        }
+    }
+}
@@ -0,0 +1,1057 @@
+use std::borrow::Cow;
+use std::ffi::c_void;
+use std::mem::offset_of;
+use std::{mem, ptr};
+
+use imgui::internal::RawWrapper;
+use imgui::{BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
+use once_cell::sync::OnceCell;
+use tracing::error;
+use windows::core::{s, Error, Result, HRESULT};
+use windows::Win32::Foundation::RECT;
+use windows::Win32::Graphics::Direct3D::*;
+use windows::Win32::Graphics::Direct3D10::*;
+use windows::Win32::Graphics::Dxgi::Common::*;
+use windows::Win32::Graphics::Dxgi::IDXGISwapChain;
+
+use crate::renderer::backend::{
+    build_font_atlas, convert_to_rgba8, mip_chain, pack_rgba8, PixelOrder, ShaderBytecode,
+};
+use crate::renderer::texture::TextureRefs;
+use crate::renderer::RenderEngine;
+use crate::{
+    util, DeviceHandle, PixelFormat, RenderContext, RenderTargetHandle, TextureFilter,
+    TextureHandle, TextureOptions,
+};
+
+pub struct D3D10RenderEngine {
+    swap_chain: IDXGISwapChain,
+    device: ID3D10Device,
+
+    shader_program: ShaderProgram,
+    texture_heap: TextureHeap,
+
+    vertex_buffer: Buffer<DrawVert>,
+    index_buffer: Buffer<DrawIdx>,
+    projection_buffer: Buffer<[[f32; 4]; 4]>,
+}
+
+impl D3D10RenderEngine {
+    pub fn new(swap_chain: &IDXGISwapChain, ctx: &mut Context) -> Result<Self> {
+        let swap_chain = swap_chain.clone();
+        let device: ID3D10Device = unsafe { swap_chain.GetDevice() }?;
+
+        let vertex_buffer = Buffer::new(&device, 5000, D3D10_BIND_VERTEX_BUFFER)?;
+        let index_buffer = Buffer::new(&device, 10000, D3D10_BIND_INDEX_BUFFER)?;
+        let projection_buffer = Buffer::new(&device, 1, D3D10_BIND_CONSTANT_BUFFER)?;
+
+        let shader_program = ShaderProgram::new(&device)?;
+        let texture_heap = TextureHeap::new(&device)?;
+
+        ctx.set_ini_filename(None);
+        ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
+        ctx.set_renderer_name(String::from(concat!("hudhook-dx10@", env!("CARGO_PKG_VERSION"))));
+
+        Ok(Self {
+            swap_chain,
+            device,
+            shader_program,
+            texture_heap,
+            vertex_buffer,
+            index_buffer,
+            projection_buffer,
+        })
+    }
+
+    /// Compile the shaders ahead of the first frame, so that creating the
+    /// engine in the present hook doesn't stall on it. Compiling doesn't need
+    /// a device, so this can run on any thread. Only compiling is done ahead
+    /// of time: the shaders, the input layout and the states belong to the
+    /// device, and still get created along with the engine.
+    pub fn prewarm() {
+        // Failures are logged, and compiling is retried with the engine.
+        shader_bytecode().ok();
+    }
+}
+
+impl RenderContext for D3D10RenderEngine {
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.load_texture_with_options(
+            data,
+            width,
+            height,
+            TextureOptions::default().with_format(format),
+        )
+    }
+
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_texture(data, width, height, options) }
+    }
+
+    fn replace_texture_with_format(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<()> {
+        unsafe { self.texture_heap.update_texture(texture_id, data, width, height, format) }
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        self.texture_heap.unload_texture(texture_id)
+    }
+
+    fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        unsafe { self.texture_heap.create_render_texture(width, height) }
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.texture_heap.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        self.texture_heap
+            .get(texture_id)
+            .map(|texture| [texture.width as f32, texture.height as f32])
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        unsafe { self.capture_back_buffer() }
+    }
+
+    fn with_swap_chain(&self, f: &mut dyn FnMut(&IDXGISwapChain)) -> Option<()> {
+        f(&self.swap_chain);
+        Some(())
+    }
+
+    fn device_handle(&self) -> Option<DeviceHandle> {
+        Some(DeviceHandle::D3D10 { device: self.device.clone() })
+    }
+}
+
+impl RenderEngine for D3D10RenderEngine {
+    type RenderTarget = ID3D10Texture2D;
+
+    fn render(
+        &mut self,
+        draw_data: &imgui::DrawData,
+        render_target: Self::RenderTarget,
+    ) -> Result<()> {
+        self.texture_heap.free_released();
+
+        unsafe {
+            let state_backup = StateBackup::backup(&self.device);
+
+            let render_target: ID3D10RenderTargetView = util::try_out_ptr(|v| {
+                self.device.CreateRenderTargetView(&render_target, None, Some(v))
+            })?;
+
+            self.device.OMSetRenderTargets(Some(&[Some(render_target)]), None);
+            self.render_draw_data(draw_data)?;
+            state_backup.restore(&self.device);
+        };
+
+        Ok(())
+    }
+
+    fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
+        let fonts = ctx.fonts();
+        let fonts_texture = build_font_atlas(fonts, D3D10_REQ_TEXTURE2D_U_OR_V_DIMENSION)?;
+        fonts.tex_id =
+            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        Ok(())
+    }
+}
+
+impl D3D10RenderEngine {
+    // Copy the current back buffer to a staging texture and read it back.
+    unsafe fn capture_back_buffer(&self) -> Result<(u32, u32, Vec<u8>)> {
+        let back_buffer: ID3D10Texture2D = self.swap_chain.GetBuffer(0)?;
+        let mut desc = Default::default();
+        back_buffer.GetDesc(&mut desc);
+        let order = PixelOrder::from_dxgi(desc.Format)?;
+        let multisampled = desc.SampleDesc.Count > 1;
+
+        let desc = D3D10_TEXTURE2D_DESC {
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            BindFlags: 0,
+            MiscFlags: 0,
+            ..desc
+        };
+
+        // Multisampled back buffers can't be copied to a staging texture as is.
+        let source = if multisampled {
+            let resolved: ID3D10Texture2D = util::try_out_ptr(|v| {
+                self.device.CreateTexture2D(
+                    &D3D10_TEXTURE2D_DESC { Usage: D3D10_USAGE_DEFAULT, CPUAccessFlags: 0, ..desc },
+                    None,
+                    Some(v),
+                )
+            })?;
+            self.device.ResolveSubresource(&resolved, 0, &back_buffer, 0, desc.Format);
+            resolved
+        } else {
+            back_buffer
+        };
+
+        let staging: ID3D10Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D10_TEXTURE2D_DESC {
+                    Usage: D3D10_USAGE_STAGING,
+                    CPUAccessFlags: D3D10_CPU_ACCESS_READ.0 as u32,
+                    ..desc
+                },
+                None,
+                Some(v),
+            )
+        })?;
+        self.device.CopyResource(&staging, &source);
+
+        let mapped = staging.Map(0, D3D10_MAP_READ, 0)?;
+        let pixels = pack_rgba8(
+            mapped.pData as *const u8,
+            mapped.RowPitch as usize,
+            desc.Width,
+            desc.Height,
+            order,
+        );
+        staging.Unmap(0);
+
+        Ok((desc.Width, desc.Height, pixels))
+    }
+
+    unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.vertex_buffer.clear();
+        self.index_buffer.clear();
+        self.projection_buffer.clear();
+
+        draw_data
+            .draw_lists()
+            .map(|draw_list| {
+                (draw_list.vtx_buffer().iter().copied(), draw_list.idx_buffer().iter().copied())
+            })
+            .for_each(|(vertices, indices)| {
+                self.vertex_buffer.extend(vertices);
+                self.index_buffer.extend(indices);
+            });
+
+        self.projection_buffer.push({
+            let [l, t, r, b] = [
+                draw_data.display_pos[0],
+                draw_data.display_pos[1],
+                draw_data.display_pos[0] + draw_data.display_size[0],
+                draw_data.display_pos[1] + draw_data.display_size[1],
+            ];
+
+            [[2. / (r - l), 0., 0., 0.], [0., 2. / (t - b), 0., 0.], [0., 0., 0.5, 0.], [
+                (r + l) / (l - r),
+                (t + b) / (b - t),
+                0.5,
+                1.0,
+            ]]
+        });
+
+        self.vertex_buffer.upload(&self.device)?;
+        self.index_buffer.upload(&self.device)?;
+        self.projection_buffer.upload(&self.device)?;
+
+        self.setup_render_state(draw_data);
+
+        let mut vtx_offset = 0usize;
+        let mut idx_offset = 0usize;
+
+        for cl in draw_data.draw_lists() {
+            for cmd in cl.commands() {
+                match cmd {
+                    DrawCmd::Elements { count, cmd_params } => {
+                        let [cx, cy, cw, ch] = cmd_params.clip_rect;
+                        let [x, y] = draw_data.display_pos;
+                        let r = RECT {
+                            left: (cx - x) as i32,
+                            top: (cy - y) as i32,
+                            right: (cw - x) as i32,
+                            bottom: (ch - y) as i32,
+                        };
+
+                        let Some(texture) = self.texture_heap.get(cmd_params.texture_id) else {
+                            continue;
+                        };
+
+                        if r.right > r.left && r.bottom > r.top {
+                            let srv = texture.shader_resource_view.clone();
+                            let sampler_state =
+                                self.shader_program.sampler_state(texture.options.filter).clone();
+                            unsafe {
+                                self.device.PSSetShaderResources(0, Some(&[Some(srv)]));
+                                self.device.PSSetSamplers(0, Some(&[Some(sampler_state)]));
+                                self.device.RSSetScissorRects(Some(&[r]));
+                                self.device.DrawIndexed(
+                                    count as _,
+                                    (cmd_params.idx_offset + idx_offset) as _,
+                                    (cmd_params.vtx_offset + vtx_offset) as _,
+                                );
+                            }
+                        }
+                    },
+                    DrawCmd::ResetRenderState => {
+                        self.setup_render_state(draw_data);
+                    },
+                    DrawCmd::RawCallback { callback, raw_cmd } => unsafe {
+                        callback(cl.raw(), raw_cmd)
+                    },
+                }
+            }
+            idx_offset += cl.idx_buffer().len();
+            vtx_offset += cl.vtx_buffer().len();
+        }
+
+        Ok(())
+    }
+
+    unsafe fn setup_render_state(&self, draw_data: &DrawData) {
+        self.device.RSSetViewports(Some(&[D3D10_VIEWPORT {
+            TopLeftX: 0,
+            TopLeftY: 0,
+            Width: draw_data.display_size[0] as u32,
+            Height: draw_data.display_size[1] as u32,
+            MinDepth: 0f32,
+            MaxDepth: 1f32,
+        }]));
+        self.device.IASetInputLayout(&self.shader_program.input_layout);
+        self.device.IASetVertexBuffers(
+            0,
+            1,
+            Some(&Some(self.vertex_buffer.resource.clone())),
+            Some(&(mem::size_of::<DrawVert>() as u32)),
+            Some(&0),
+        );
+        self.device.IASetIndexBuffer(
+            Some(&self.index_buffer.resource),
+            if mem::size_of::<DrawIdx>() == 2 {
+                DXGI_FORMAT_R16_UINT
+            } else {
+                DXGI_FORMAT_R32_UINT
+            },
+            0,
+        );
+        self.device.IASetPrimitiveTopology(D3D10_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+        self.device.VSSetShader(&self.shader_program.vertex_shader);
+        self.device.VSSetConstantBuffers(0, Some(&[Some(self.projection_buffer.resource.clone())]));
+        self.device.PSSetShader(&self.shader_program.pixel_shader);
+        self.device.PSSetSamplers(0, Some(&[Some(self.shader_program.sampler_state.clone())]));
+        self.device.GSSetShader(None);
+        self.device.OMSetBlendState(&self.shader_program.blend_state, &[0.; 4], 0xffffffff);
+        self.device.OMSetDepthStencilState(&self.shader_program.depth_stencil_state, 0);
+        self.device.RSSetState(&self.shader_program.rasterizer_state);
+    }
+}
+
+struct ShaderProgram {
+    vertex_shader: ID3D10VertexShader,
+    pixel_shader: ID3D10PixelShader,
+    input_layout: ID3D10InputLayout,
+    // Linear, set up with the rest of the render state. The others are set
+    // for the textures that use them.
+    sampler_state: ID3D10SamplerState,
+    point_sampler_state: ID3D10SamplerState,
+    anisotropic_sampler_state: ID3D10SamplerState,
+    blend_state: ID3D10BlendState,
+    depth_stencil_state: ID3D10DepthStencilState,
+    rasterizer_state: ID3D10RasterizerState,
+}
+
+const VERTEX_SHADER_SRC: &str = r"
+cbuffer vertex_buffer: register(b0) {
+    float4x4 projection;
+};
+
+struct VS_INPUT {
+  float2 pos: POSITION;
+  float4 col: COLOR0;
+  float2 uv: TEXCOORD0;
+};
+
+struct PS_INPUT {
+  float4 pos: SV_POSITION;
+  float4 col: COLOR0;
+  float2 uv: TEXCOORD0;
+};
+
+PS_INPUT main(VS_INPUT input) {
+  PS_INPUT output;
+  output.pos = mul(projection, float4(input.pos.xy, 0.0f, 1.0f));
+  output.col = input.col;
+  output.uv = input.uv.xy;
+  return output;
+}
+";
+
+const PIXEL_SHADER_SRC: &str = r"
+struct PS_INPUT {
+  float4 pos: SV_POSITION;
+  float4 col: COLOR0;
+  float2 uv: TEXCOORD0;
+};
+
+Texture2D texture0: register(t0);
+SamplerState sampler0: register(s0);
+
+float4 main(PS_INPUT input): SV_Target {
+  float4 col = input.col * texture0.Sample(sampler0, input.uv);
+  return col;
+}
+";
+
+// The bytecode doesn't depend on the device, so it's compiled only once, and
+// possibly ahead of time via `D3D10RenderEngine::prewarm`.
+fn shader_bytecode() -> Result<&'static ShaderBytecode> {
+    static SHADER_BYTECODE: OnceCell<ShaderBytecode> = OnceCell::new();
+
+    SHADER_BYTECODE
+        .get_or_try_init(|| {
+            ShaderBytecode::compile(
+                VERTEX_SHADER_SRC,
+                s!("vs_4_0\0"),
+                PIXEL_SHADER_SRC,
+                s!("ps_4_0\0"),
+            )
+        })
+        .map_err(|e| {
+            error!("{e}");
+            Error::from_hresult(HRESULT(-1))
+        })
+}
+
+impl ShaderProgram {
+    fn new(device: &ID3D10Device) -> Result<Self> {
+        let shader_bytecode = shader_bytecode()?;
+
+        let vertex_shader = util::try_out_ptr(|v| unsafe {
+            device.CreateVertexShader(&shader_bytecode.vertex, Some(v))
+        })?;
+
+        let pixel_shader = util::try_out_ptr(|v| unsafe {
+            device.CreatePixelShader(&shader_bytecode.pixel, Some(v))
+        })?;
+
+        let input_layout = util::try_out_ptr(|v| unsafe {
+            device.CreateInputLayout(
+                &[
+                    D3D10_INPUT_ELEMENT_DESC {
+                        SemanticName: s!("POSITION"),
+                        SemanticIndex: 0,
+                        Format: DXGI_FORMAT_R32G32_FLOAT,
+                        InputSlot: 0,
+                        AlignedByteOffset: offset_of!(DrawVert, pos) as u32,
+                        InputSlotClass: D3D10_INPUT_PER_VERTEX_DATA,
+                        InstanceDataStepRate: 0,
+                    },
+                    D3D10_INPUT_ELEMENT_DESC {
+                        SemanticName: s!("COLOR"),
+                        SemanticIndex: 0,
+                        Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                        InputSlot: 0,
+                        AlignedByteOffset: offset_of!(DrawVert, col) as u32,
+                        InputSlotClass: D3D10_INPUT_PER_VERTEX_DATA,
+                        InstanceDataStepRate: 0,
+                    },
+                    D3D10_INPUT_ELEMENT_DESC {
+                        SemanticName: s!("TEXCOORD"),
+                        SemanticIndex: 0,
+                        Format: DXGI_FORMAT_R32G32_FLOAT,
+                        InputSlot: 0,
+                        AlignedByteOffset: offset_of!(DrawVert, uv) as u32,
+                        InputSlotClass: D3D10_INPUT_PER_VERTEX_DATA,
+                        InstanceDataStepRate: 0,
+                    },
+                ],
+                &shader_bytecode.vertex,
+                Some(v),
+            )
+        })?;
+
+        let create_sampler_state = |filter, max_anisotropy| {
+            util::try_out_ptr(|v| unsafe {
+                device.CreateSamplerState(
+                    &D3D10_SAMPLER_DESC {
+                        Filter: filter,
+                        AddressU: D3D10_TEXTURE_ADDRESS_WRAP,
+                        AddressV: D3D10_TEXTURE_ADDRESS_WRAP,
+                        AddressW: D3D10_TEXTURE_ADDRESS_WRAP,
+                        MipLODBias: 0.,
+                        ComparisonFunc: D3D10_COMPARISON_ALWAYS,
+                        MinLOD: 0.,
+                        MaxLOD: D3D10_FLOAT32_MAX,
+                        BorderColor: [0.; 4],
+                        MaxAnisotropy: max_anisotropy,
+                    },
+                    Some(v),
+                )
+            })
+        };
+        let sampler_state = create_sampler_state(D3D10_FILTER_MIN_MAG_MIP_LINEAR, 0)?;
+        let point_sampler_state = create_sampler_state(D3D10_FILTER_MIN_MAG_MIP_POINT, 0)?;
+        let anisotropic_sampler_state =
+            create_sampler_state(D3D10_FILTER_ANISOTROPIC, D3D10_MAX_MAXANISOTROPY)?;
+
+        // Direct3D 10 shares the blend equation among all the render targets,
+        // and only enables it and sets the write mask per render target.
+        let blend_state = util::try_out_ptr(|v| unsafe {
+            let mut blend_enable: [_; 8] = Default::default();
+            blend_enable[0] = true.into();
+            let mut render_target_write_mask = [0; 8];
+            render_target_write_mask[0] = D3D10_COLOR_WRITE_ENABLE_ALL.0 as u8;
+
+            device.CreateBlendState(
+                &D3D10_BLEND_DESC {
+                    AlphaToCoverageEnable: false.into(),
+                    BlendEnable: blend_enable,
+                    SrcBlend: D3D10_BLEND_SRC_ALPHA,
+                    DestBlend: D3D10_BLEND_INV_SRC_ALPHA,
+                    BlendOp: D3D10_BLEND_OP_ADD,
+                    SrcBlendAlpha: D3D10_BLEND_INV_SRC_ALPHA,
+                    DestBlendAlpha: D3D10_BLEND_ZERO,
+                    BlendOpAlpha: D3D10_BLEND_OP_ADD,
+                    RenderTargetWriteMask: render_target_write_mask,
+                },
+                Some(v),
+            )
+        })?;
+
+        let rasterizer_state = util::try_out_ptr(|v| unsafe {
+            device.CreateRasterizerState(
+                &D3D10_RASTERIZER_DESC {
+                    FillMode: D3D10_FILL_SOLID,
+                    CullMode: D3D10_CULL_NONE,
+                    ScissorEnable: true.into(),
+                    DepthClipEnable: true.into(),
+                    DepthBias: 0,
+                    DepthBiasClamp: 0.,
+                    SlopeScaledDepthBias: 0.,
+                    MultisampleEnable: false.into(),
+                    AntialiasedLineEnable: false.into(),
+                    FrontCounterClockwise: false.into(),
+                },
+                Some(v),
+            )
+        })?;
+
+        let depth_stencil_state = util::try_out_ptr(|v| unsafe {
+            device.CreateDepthStencilState(
+                &D3D10_DEPTH_STENCIL_DESC {
+                    DepthEnable: false.into(),
+                    DepthFunc: D3D10_COMPARISON_ALWAYS,
+                    DepthWriteMask: D3D10_DEPTH_WRITE_MASK_ALL,
+                    StencilEnable: false.into(),
+                    StencilReadMask: 0,
+                    StencilWriteMask: 0,
+                    FrontFace: D3D10_DEPTH_STENCILOP_DESC {
+                        StencilFailOp: D3D10_STENCIL_OP_KEEP,
+                        StencilDepthFailOp: D3D10_STENCIL_OP_KEEP,
+                        StencilPassOp: D3D10_STENCIL_OP_KEEP,
+                        StencilFunc: D3D10_COMPARISON_ALWAYS,
+                    },
+                    BackFace: D3D10_DEPTH_STENCILOP_DESC {
+                        StencilFailOp: D3D10_STENCIL_OP_KEEP,
+                        StencilDepthFailOp: D3D10_STENCIL_OP_KEEP,
+                        StencilPassOp: D3D10_STENCIL_OP_KEEP,
+                        StencilFunc: D3D10_COMPARISON_ALWAYS,
+                    },
+                },
+                Some(v),
+            )
+        })?;
+
+        Ok(ShaderProgram {
+            vertex_shader,
+            pixel_shader,
+            input_layout,
+            sampler_state,
+            point_sampler_state,
+            anisotropic_sampler_state,
+            blend_state,
+            depth_stencil_state,
+            rasterizer_state,
+        })
+    }
+}
+
+struct Buffer<T: Sized> {
+    bind_flag: D3D10_BIND_FLAG,
+    resource: ID3D10Buffer,
+    resource_capacity: usize,
+    data: Vec<T>,
+}
+
+impl<T> Buffer<T> {
+    fn new(
+        device: &ID3D10Device,
+        resource_capacity: usize,
+        bind_flag: D3D10_BIND_FLAG,
+    ) -> Result<Self> {
+        let resource = Self::create_resource(device, resource_capacity, bind_flag)?;
+        let data = Vec::with_capacity(resource_capacity);
+
+        Ok(Self { bind_flag, resource, resource_capacity, data })
+    }
+
+    fn create_resource(
+        device: &ID3D10Device,
+        resource_capacity: usize,
+        bind_flag: D3D10_BIND_FLAG,
+    ) -> Result<ID3D10Buffer> {
+        util::try_out_ptr(|v| unsafe {
+            device.CreateBuffer(
+                &D3D10_BUFFER_DESC {
+                    ByteWidth: (resource_capacity * mem::size_of::<T>()) as u32,
+                    Usage: D3D10_USAGE_DYNAMIC,
+                    BindFlags: bind_flag.0 as u32,
+                    CPUAccessFlags: D3D10_CPU_ACCESS_WRITE.0 as _,
+                    MiscFlags: 0,
+                },
+                None,
+                Some(v),
+            )
+        })
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    fn extend<I: IntoIterator<Item = T>>(&mut self, it: I) {
+        self.data.extend(it)
+    }
+
+    fn push(&mut self, t: T) {
+        self.data.push(t)
+    }
+
+    fn upload(&mut self, device: &ID3D10Device) -> Result<()> {
+        let capacity = self.data.capacity();
+        if capacity > self.resource_capacity {
+            drop(mem::replace(
+                &mut self.resource,
+                Self::create_resource(device, capacity, self.bind_flag)?,
+            ));
+            self.resource_capacity = capacity;
+        }
+
+        unsafe {
+            let mut resource_ptr = ptr::null_mut();
+            self.resource.Map(D3D10_MAP_WRITE_DISCARD, 0, &mut resource_ptr)?;
+            ptr::copy_nonoverlapping(self.data.as_ptr(), resource_ptr as *mut T, self.data.len());
+            self.resource.Unmap();
+        }
+
+        Ok(())
+    }
+}
+
+impl ShaderProgram {
+    fn sampler_state(&self, filter: TextureFilter) -> &ID3D10SamplerState {
+        match filter {
+            TextureFilter::Point => &self.point_sampler_state,
+            TextureFilter::Linear => &self.sampler_state,
+            TextureFilter::Anisotropic => &self.anisotropic_sampler_state,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+struct Texture {
+    resource: ID3D10Texture2D,
+    shader_resource_view: ID3D10ShaderResourceView,
+    id: TextureId,
+    width: u32,
+    height: u32,
+    options: TextureOptions,
+}
+
+struct TextureHeap {
+    device: ID3D10Device,
+    textures: Vec<Option<Texture>>,
+    refs: TextureRefs,
+}
+
+impl TextureHeap {
+    fn new(device: &ID3D10Device) -> Result<Self> {
+        Ok(Self {
+            device: device.clone(),
+            textures: Vec::with_capacity(8),
+            refs: TextureRefs::default(),
+        })
+    }
+
+    fn get(&self, texture_id: TextureId) -> Option<&Texture> {
+        self.textures.get(texture_id.id()).and_then(Option::as_ref)
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.textures[texture_id.id()] = None;
+        }
+
+        Ok(())
+    }
+
+    // Free the textures whose last handle has been dropped since the last frame.
+    fn free_released(&mut self) {
+        for texture_id in self.refs.drain_released() {
+            self.textures[texture_id.id()] = None;
+        }
+    }
+
+    unsafe fn create_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        let (dxgi_format, data) = upload_format(data, options.format);
+        let mips = if options.mipmaps { mip_chain(&data, width, height, 4) } else { Vec::new() };
+        let mip_levels = 1 + mips.len() as u32;
+
+        let subresources: Vec<_> = [(&data[..], width)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width)))
+            .map(|(data, width)| D3D10_SUBRESOURCE_DATA {
+                pSysMem: data.as_ptr() as *const c_void,
+                SysMemPitch: width * 4,
+                SysMemSlicePitch: 0,
+            })
+            .collect();
+
+        let resource: ID3D10Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D10_TEXTURE2D_DESC {
+                    Width: width,
+                    Height: height,
+                    MipLevels: mip_levels,
+                    ArraySize: 1,
+                    Format: dxgi_format,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Usage: D3D10_USAGE_DEFAULT,
+                    BindFlags: D3D10_BIND_SHADER_RESOURCE.0 as u32,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                Some(subresources.as_ptr()),
+                Some(v),
+            )
+        })?;
+
+        let shader_resource_view = util::try_out_ptr(|v| {
+            self.device.CreateShaderResourceView(
+                &resource,
+                Some(&D3D10_SHADER_RESOURCE_VIEW_DESC {
+                    Format: dxgi_format,
+                    ViewDimension: D3D10_SRV_DIMENSION_TEXTURE2D,
+                    Anonymous: D3D10_SHADER_RESOURCE_VIEW_DESC_0 {
+                        Texture2D: D3D10_TEX2D_SRV { MostDetailedMip: 0, MipLevels: mip_levels },
+                    },
+                }),
+                Some(v),
+            )
+        })?;
+
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some(Texture {
+            resource,
+            shader_resource_view,
+            id,
+            width,
+            height,
+            options,
+        }));
+        self.refs.insert(id);
+
+        Ok(id)
+    }
+
+    unsafe fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        let resource: ID3D10Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D10_TEXTURE2D_DESC {
+                    Width: width,
+                    Height: height,
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Usage: D3D10_USAGE_DEFAULT,
+                    BindFlags: (D3D10_BIND_SHADER_RESOURCE.0 | D3D10_BIND_RENDER_TARGET.0) as u32,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                None,
+                Some(v),
+            )
+        })?;
+
+        let shader_resource_view =
+            util::try_out_ptr(|v| self.device.CreateShaderResourceView(&resource, None, Some(v)))?;
+        let render_target_view: ID3D10RenderTargetView =
+            util::try_out_ptr(|v| self.device.CreateRenderTargetView(&resource, None, Some(v)))?;
+        self.device.ClearRenderTargetView(&render_target_view, [0f32; 4].as_ptr());
+
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some(Texture {
+            resource: resource.clone(),
+            shader_resource_view,
+            id,
+            width,
+            height,
+            options: TextureOptions::default(),
+        }));
+        self.refs.insert(id);
+
+        Ok((id, RenderTargetHandle::D3D10 { texture: resource, render_target_view }))
+    }
+
+    unsafe fn update_texture(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<()> {
+        let Some(texture) = self.get(texture_id) else {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+        if texture.width != width || texture.height != height {
+            error!(
+                "image size {width}x{height} do not match expected {}x{}",
+                texture.width, texture.height
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        if texture.options.format != format {
+            error!(
+                "texture {texture_id:?} was loaded as {:?}, not {format:?}",
+                texture.options.format
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let (_, data) = upload_format(data, format);
+        let mips =
+            if texture.options.mipmaps { mip_chain(&data, width, height, 4) } else { Vec::new() };
+
+        for (level, (data, width)) in [(&data[..], width)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width)))
+            .enumerate()
+        {
+            self.device.UpdateSubresource(
+                &texture.resource,
+                level as u32,
+                None,
+                data.as_ptr() as *const c_void,
+                width * 4,
+                0,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// The format of textures loaded from pixels in `format`, and the pixels
+// converted to it. Direct3D 10.0 devices needn't support sampling BGRA, and
+// can't swizzle `R8_UNORM` into gray, so everything is expanded to RGBA8.
+fn upload_format(data: &[u8], format: PixelFormat) -> (DXGI_FORMAT, Cow<'_, [u8]>) {
+    (DXGI_FORMAT_R8G8B8A8_UNORM, convert_to_rgba8(data, format))
+}
+
+const BACKUP_OBJECT_COUNT: usize = 16;
+
+struct StateBackup {
+    scissor_count: u32,
+    scissor_rects: [RECT; BACKUP_OBJECT_COUNT],
+    viewport_count: u32,
+    viewports: [D3D10_VIEWPORT; BACKUP_OBJECT_COUNT],
+
+    rasterizer_state: Option<ID3D10RasterizerState>,
+
+    blend_state: Option<ID3D10BlendState>,
+    blend_factor: [f32; 4],
+    sample_mask: u32,
+
+    depth_stencil_state: Option<ID3D10DepthStencilState>,
+    depth_stencil_ref: u32,
+
+    shader_resources: [Option<ID3D10ShaderResourceView>; 1],
+    sampler: [Option<ID3D10SamplerState>; 1],
+
+    vertex_shader: Option<ID3D10VertexShader>,
+    pixel_shader: Option<ID3D10PixelShader>,
+    geometry_shader: Option<ID3D10GeometryShader>,
+
+    vertex_buffer: Option<ID3D10Buffer>,
+    vertex_buffer_stride: u32,
+    vertex_buffer_offset: u32,
+    index_buffer: Option<ID3D10Buffer>,
+    index_buffer_offset: u32,
+    index_buffer_format: DXGI_FORMAT,
+    constant_buffer: [Option<ID3D10Buffer>; BACKUP_OBJECT_COUNT],
+
+    primitive_topology: D3D_PRIMITIVE_TOPOLOGY,
+    input_layout: Option<ID3D10InputLayout>,
+}
+
+impl StateBackup {
+    unsafe fn backup(device: &ID3D10Device) -> StateBackup {
+        let mut scissor_count = BACKUP_OBJECT_COUNT as u32;
+        let mut scissor_rects: [RECT; BACKUP_OBJECT_COUNT] = Default::default();
+        device.RSGetScissorRects(&mut scissor_count, Some(scissor_rects.as_mut_ptr()));
+
+        let mut viewport_count = BACKUP_OBJECT_COUNT as u32;
+        let mut viewports: [D3D10_VIEWPORT; BACKUP_OBJECT_COUNT] = Default::default();
+        device.RSGetViewports(&mut viewport_count, Some(viewports.as_mut_ptr()));
+
+        let (mut blend_state, mut blend_factor, mut sample_mask) = Default::default();
+        device.OMGetBlendState(
+            Some(&mut blend_state),
+            Some(&mut blend_factor),
+            Some(&mut sample_mask),
+        );
+
+        let (mut depth_stencil_state, mut depth_stencil_ref) = Default::default();
+        device.OMGetDepthStencilState(Some(&mut depth_stencil_state), Some(&mut depth_stencil_ref));
+
+        let mut shader_resources: [Option<ID3D10ShaderResourceView>; 1] = Default::default();
+        device.PSGetShaderResources(0, Some(&mut shader_resources));
+
+        let mut sampler: [Option<ID3D10SamplerState>; 1] = Default::default();
+        device.PSGetSamplers(0, Some(&mut sampler));
+
+        let vertex_shader = device.VSGetShader().ok();
+        let pixel_shader = device.PSGetShader().ok();
+        let geometry_shader = device.GSGetShader().ok();
+
+        let (mut vertex_buffer, mut vertex_buffer_stride, mut vertex_buffer_offset) =
+            Default::default();
+        device.IAGetVertexBuffers(
+            0,
+            1,
+            Some(&mut vertex_buffer),
+            Some(&mut vertex_buffer_stride),
+            Some(&mut vertex_buffer_offset),
+        );
+
+        let (mut index_buffer, mut index_buffer_format, mut index_buffer_offset) =
+            Default::default();
+        device.IAGetIndexBuffer(
+            Some(&mut index_buffer),
+            Some(&mut index_buffer_format),
+            Some(&mut index_buffer_offset),
+        );
+
+        let mut constant_buffer: [Option<ID3D10Buffer>; BACKUP_OBJECT_COUNT] = Default::default();
+        device.VSGetConstantBuffers(0, Some(&mut constant_buffer));
+
+        let rasterizer_state = device.RSGetState().ok();
+        let primitive_topology = device.IAGetPrimitiveTopology();
+        let input_layout = device.IAGetInputLayout().ok();
+
+        Self {
+            scissor_count,
+            scissor_rects,
+            viewport_count,
+            viewports,
+            rasterizer_state,
+            blend_state,
+            blend_factor,
+            sample_mask,
+            depth_stencil_state,
+            depth_stencil_ref,
+            shader_resources,
+            sampler,
+            vertex_shader,
+            pixel_shader,
+            geometry_shader,
+            vertex_buffer,
+            vertex_buffer_stride,
+            vertex_buffer_offset,
+            index_buffer,
+            index_buffer_offset,
+            index_buffer_format,
+            constant_buffer,
+            primitive_topology,
+            input_layout,
+        }
+    }
+
+    unsafe fn restore(self, device: &ID3D10Device) {
+        device.RSSetScissorRects(Some(&self.scissor_rects[..self.scissor_count as usize]));
+        device.RSSetViewports(Some(&self.viewports[..self.viewport_count as usize]));
+
+        device.OMSetBlendState(self.blend_state.as_ref(), &self.blend_factor, self.sample_mask);
+        device.OMSetDepthStencilState(self.depth_stencil_state.as_ref(), self.depth_stencil_ref);
+
+        if self.shader_resources[0].is_some() {
+            device.PSSetShaderResources(0, Some(&self.shader_resources));
+        }
+        if self.sampler[0].is_some() {
+            device.PSSetSamplers(0, Some(&self.sampler));
+        }
+
+        device.PSSetShader(self.pixel_shader.as_ref());
+        device.VSSetShader(self.vertex_shader.as_ref());
+        device.GSSetShader(self.geometry_shader.as_ref());
+
+        device.IASetVertexBuffers(
+            0,
+            1,
+            Some(&self.vertex_buffer),
+            Some(&self.vertex_buffer_stride),
+            Some(&self.vertex_buffer_offset),
+        );
+
+        device.IASetIndexBuffer(
+            self.index_buffer.as_ref(),
+            self.index_buffer_format,
+            self.index_buffer_offset,
+        );
+
+        if self.constant_buffer[0].is_some() {
+            let count = self.constant_buffer.iter().take_while(|x| x.is_some()).count();
+            device.VSSetConstantBuffers(0, Some(&self.constant_buffer[..count]));
+        }
+
+        device.RSSetState(self.rasterizer_state.as_ref());
+        device.IASetPrimitiveTopology(self.primitive_topology);
+        if let Some(il) = self.input_layout {
+            device.IASetInputLayout(&il);
+        }
+    }
+}
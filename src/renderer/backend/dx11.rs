@@ -1,21 +1,31 @@
+use std::borrow::Cow;
 use std::ffi::c_void;
 use std::mem::offset_of;
 use std::{mem, ptr, slice};
 
 use imgui::internal::RawWrapper;
 use imgui::{BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
+use once_cell::sync::OnceCell;
 use tracing::error;
 use windows::core::{s, Error, Result, HRESULT};
 use windows::Win32::Foundation::RECT;
-use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
+use windows::Win32::Graphics::Dxgi::IDXGISwapChain;
 
+use crate::renderer::backend::{
+    build_font_atlas, convert_to_rgba8, mip_chain, pack_rgba8, PixelOrder, ShaderBytecode,
+};
+use crate::renderer::texture::TextureRefs;
 use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::{
+    util, DeviceHandle, PixelFormat, RenderContext, RenderTargetHandle, TextureFilter,
+    TextureHandle, TextureOptions,
+};
 
 pub struct D3D11RenderEngine {
+    swap_chain: IDXGISwapChain,
     device: ID3D11Device,
     device_context: ID3D11DeviceContext,
 
@@ -28,8 +38,9 @@ pub struct D3D11RenderEngine {
 }
 
 impl D3D11RenderEngine {
-    pub fn new(device: &ID3D11Device, ctx: &mut Context) -> Result<Self> {
-        let device = device.clone();
+    pub fn new(swap_chain: &IDXGISwapChain, ctx: &mut Context) -> Result<Self> {
+        let swap_chain = swap_chain.clone();
+        let device: ID3D11Device = unsafe { swap_chain.GetDevice() }?;
         let device_context = unsafe { device.GetImmediateContext() }?;
 
         let vertex_buffer = Buffer::new(&device, 5000, D3D11_BIND_VERTEX_BUFFER)?;
@@ -44,6 +55,7 @@ impl D3D11RenderEngine {
         ctx.set_renderer_name(String::from(concat!("hudhook-dx11@", env!("CARGO_PKG_VERSION"))));
 
         Ok(Self {
+            swap_chain,
             device,
             device_context,
             shader_program,
@@ -53,21 +65,91 @@ impl D3D11RenderEngine {
             projection_buffer,
         })
     }
+
+    /// Compile the shaders ahead of the first frame, so that creating the
+    /// engine in the present hook doesn't stall on it. Compiling doesn't need
+    /// a device, so this can run on any thread. Only compiling is done ahead
+    /// of time: the shaders, the input layout and the states belong to the
+    /// device, and still get created along with the engine.
+    pub fn prewarm() {
+        // Failures are logged, and compiling is retried with the engine.
+        shader_bytecode().ok();
+    }
 }
 
 impl RenderContext for D3D11RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
-        unsafe { self.texture_heap.create_texture(data, width, height) }
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.load_texture_with_options(
+            data,
+            width,
+            height,
+            TextureOptions::default().with_format(format),
+        )
+    }
+
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_texture(data, width, height, options) }
     }
 
-    fn replace_texture(
+    fn replace_texture_with_format(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        unsafe { self.texture_heap.update_texture(texture_id, data, width, height) }
+        unsafe { self.texture_heap.update_texture(texture_id, data, width, height, format) }
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        self.texture_heap.unload_texture(texture_id)
+    }
+
+    fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        unsafe { self.texture_heap.create_render_texture(width, height) }
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.texture_heap.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        self.texture_heap
+            .get(texture_id)
+            .map(|texture| [texture.width as f32, texture.height as f32])
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        unsafe { self.capture_back_buffer() }
+    }
+
+    fn with_swap_chain(&self, f: &mut dyn FnMut(&IDXGISwapChain)) -> Option<()> {
+        f(&self.swap_chain);
+        Some(())
+    }
+
+    fn device_handle(&self) -> Option<DeviceHandle> {
+        Some(DeviceHandle::D3D11 {
+            device: self.device.clone(),
+            device_context: self.device_context.clone(),
+        })
     }
 }
 
@@ -79,6 +161,8 @@ impl RenderEngine for D3D11RenderEngine {
         draw_data: &imgui::DrawData,
         render_target: Self::RenderTarget,
     ) -> Result<()> {
+        self.texture_heap.free_released();
+
         unsafe {
             let state_backup = StateBackup::backup(&self.device_context);
 
@@ -96,7 +180,7 @@ impl RenderEngine for D3D11RenderEngine {
 
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
-        let fonts_texture = fonts.build_rgba32_texture();
+        let fonts_texture = build_font_atlas(fonts, self.max_texture_size())?;
         fonts.tex_id =
             self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
         Ok(())
@@ -104,6 +188,77 @@ impl RenderEngine for D3D11RenderEngine {
 }
 
 impl D3D11RenderEngine {
+    fn max_texture_size(&self) -> u32 {
+        match unsafe { self.device.GetFeatureLevel() } {
+            D3D_FEATURE_LEVEL_9_1 | D3D_FEATURE_LEVEL_9_2 => {
+                D3D_FL9_1_REQ_TEXTURE2D_U_OR_V_DIMENSION
+            },
+            D3D_FEATURE_LEVEL_9_3 => D3D_FL9_3_REQ_TEXTURE2D_U_OR_V_DIMENSION,
+            // D3D10_REQ_TEXTURE2D_U_OR_V_DIMENSION
+            D3D_FEATURE_LEVEL_10_0 | D3D_FEATURE_LEVEL_10_1 => 8192,
+            _ => D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION,
+        }
+    }
+
+    // Copy the current back buffer to a staging texture and read it back.
+    unsafe fn capture_back_buffer(&self) -> Result<(u32, u32, Vec<u8>)> {
+        let back_buffer: ID3D11Texture2D = self.swap_chain.GetBuffer(0)?;
+        let mut desc = Default::default();
+        back_buffer.GetDesc(&mut desc);
+        let order = PixelOrder::from_dxgi(desc.Format)?;
+        let multisampled = desc.SampleDesc.Count > 1;
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            BindFlags: 0,
+            MiscFlags: 0,
+            ..desc
+        };
+
+        // Multisampled back buffers can't be copied to a staging texture as is.
+        let source = if multisampled {
+            let resolved: ID3D11Texture2D = util::try_out_ptr(|v| {
+                self.device.CreateTexture2D(
+                    &D3D11_TEXTURE2D_DESC { Usage: D3D11_USAGE_DEFAULT, CPUAccessFlags: 0, ..desc },
+                    None,
+                    Some(v),
+                )
+            })?;
+            self.device_context.ResolveSubresource(&resolved, 0, &back_buffer, 0, desc.Format);
+            resolved
+        } else {
+            back_buffer
+        };
+
+        let staging: ID3D11Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D11_TEXTURE2D_DESC {
+                    Usage: D3D11_USAGE_STAGING,
+                    CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                    ..desc
+                },
+                None,
+                Some(v),
+            )
+        })?;
+        self.device_context.CopyResource(&staging, &source);
+
+        let mut mapped = Default::default();
+        self.device_context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+        let pixels = pack_rgba8(
+            mapped.pData as *const u8,
+            mapped.RowPitch as usize,
+            desc.Width,
+            desc.Height,
+            order,
+        );
+        self.device_context.Unmap(&staging, 0);
+
+        Ok((desc.Width, desc.Height, pixels))
+    }
+
     unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
         self.vertex_buffer.clear();
         self.index_buffer.clear();
@@ -157,12 +312,17 @@ impl D3D11RenderEngine {
                             bottom: (ch - y) as i32,
                         };
 
+                        let Some(texture) = self.texture_heap.get(cmd_params.texture_id) else {
+                            continue;
+                        };
+
                         if r.right > r.left && r.bottom > r.top {
-                            let srv = self.texture_heap.textures[cmd_params.texture_id.id()]
-                                .shader_resource_view
-                                .clone();
+                            let srv = texture.shader_resource_view.clone();
+                            let sampler_state =
+                                self.shader_program.sampler_state(texture.options.filter).clone();
                             unsafe {
                                 self.device_context.PSSetShaderResources(0, Some(&[Some(srv)]));
+                                self.device_context.PSSetSamplers(0, Some(&[Some(sampler_state)]));
                                 self.device_context.RSSetScissorRects(Some(&[r]));
                                 self.device_context.DrawIndexed(
                                     count as _,
@@ -237,107 +397,91 @@ struct ShaderProgram {
     vertex_shader: ID3D11VertexShader,
     pixel_shader: ID3D11PixelShader,
     input_layout: ID3D11InputLayout,
+    // Linear, set up with the rest of the render state. The others are set
+    // for the textures that use them.
     sampler_state: ID3D11SamplerState,
+    point_sampler_state: ID3D11SamplerState,
+    anisotropic_sampler_state: ID3D11SamplerState,
     blend_state: ID3D11BlendState,
     depth_stencil_state: ID3D11DepthStencilState,
     rasterizer_state: ID3D11RasterizerState,
 }
 
-impl ShaderProgram {
-    fn new(device: &ID3D11Device) -> Result<Self> {
-        const VERTEX_SHADER_SRC: &str = r"
-        cbuffer vertex_buffer: register(b0) {
-            float4x4 projection;
-        };
-
-        struct VS_INPUT {
-          float2 pos: POSITION;
-          float4 col: COLOR0;
-          float2 uv: TEXCOORD0;
-        };
-
-        struct PS_INPUT {
-          float4 pos: SV_POSITION;
-          float4 col: COLOR0;
-          float2 uv: TEXCOORD0;
-        };
+const VERTEX_SHADER_SRC: &str = r"
+    cbuffer vertex_buffer: register(b0) {
+        float4x4 projection;
+    };
+
+    struct VS_INPUT {
+      float2 pos: POSITION;
+      float4 col: COLOR0;
+      float2 uv: TEXCOORD0;
+    };
+
+    struct PS_INPUT {
+      float4 pos: SV_POSITION;
+      float4 col: COLOR0;
+      float2 uv: TEXCOORD0;
+    };
+
+    PS_INPUT main(VS_INPUT input) {
+      PS_INPUT output;
+      output.pos = mul(projection, float4(input.pos.xy, 0.0f, 1.0f));
+      output.col = input.col;
+      output.uv = input.uv.xy;
+      return output;
+    }
+    ";
 
-        PS_INPUT main(VS_INPUT input) {
-          PS_INPUT output;
-          output.pos = mul(projection, float4(input.pos.xy, 0.0f, 1.0f));
-          output.col = input.col;
-          output.uv = input.uv.xy;
-          return output;
-        }
-        ";
+const PIXEL_SHADER_SRC: &str = r"
+    struct PS_INPUT {
+      float4 pos: SV_POSITION;
+      float4 col: COLOR0;
+      float2 uv: TEXCOORD0;
+    };
 
-        const PIXEL_SHADER_SRC: &str = r"
-        struct PS_INPUT {
-          float4 pos: SV_POSITION;
-          float4 col: COLOR0;
-          float2 uv: TEXCOORD0;
-        };
+    Texture2D texture0: register(t0);
+    SamplerState sampler0: register(s0);
 
-        Texture2D texture0: register(t0);
-        SamplerState sampler0: register(s0);
+    float4 main(PS_INPUT input): SV_Target {
+      float4 col = input.col * texture0.Sample(sampler0, input.uv);
+      return col;
+    }
+    ";
 
-        float4 main(PS_INPUT input): SV_Target {
-          float4 col = input.col * texture0.Sample(sampler0, input.uv);
-          return col;
-        }
-        ";
+// The bytecode doesn't depend on the device, so it's compiled only once, and
+// possibly ahead of time via `D3D11RenderEngine::prewarm`.
+fn shader_bytecode() -> Result<&'static ShaderBytecode> {
+    static SHADER_BYTECODE: OnceCell<ShaderBytecode> = OnceCell::new();
 
-        let vs_blob: ID3DBlob = util::try_out_err_blob(|v, err_blob| unsafe {
-            D3DCompile(
-                VERTEX_SHADER_SRC.as_ptr() as _,
-                VERTEX_SHADER_SRC.len(),
-                None,
-                None,
-                None,
-                s!("main\0"),
+    SHADER_BYTECODE
+        .get_or_try_init(|| {
+            ShaderBytecode::compile(
+                VERTEX_SHADER_SRC,
                 s!("vs_4_0\0"),
-                0,
-                0,
-                v,
-                Some(err_blob),
-            )
-        })
-        .map_err(util::print_error_blob("Compiling vertex shader"))
-        .expect("D3DCompile");
-
-        let ps_blob = util::try_out_err_blob(|v, err_blob| unsafe {
-            D3DCompile(
-                PIXEL_SHADER_SRC.as_ptr() as _,
-                PIXEL_SHADER_SRC.len(),
-                None,
-                None,
-                None,
-                s!("main\0"),
+                PIXEL_SHADER_SRC,
                 s!("ps_4_0\0"),
-                0,
-                0,
-                v,
-                Some(err_blob),
             )
         })
-        .map_err(util::print_error_blob("Compiling pixel shader"))
-        .expect("D3DCompile");
+        .map_err(|e| {
+            error!("{e}");
+            Error::from_hresult(HRESULT(-1))
+        })
+}
+
+impl ShaderProgram {
+    fn new(device: &ID3D11Device) -> Result<Self> {
+        let shader_bytecode = shader_bytecode()?;
 
         let vertex_shader = util::try_out_ptr(|v| unsafe {
-            let ptr = vs_blob.GetBufferPointer();
-            let size = vs_blob.GetBufferSize();
-            device.CreateVertexShader(slice::from_raw_parts(ptr as _, size), None, Some(v))
+            device.CreateVertexShader(&shader_bytecode.vertex, None, Some(v))
         })?;
 
         let pixel_shader = util::try_out_ptr(|v| unsafe {
-            let ptr = ps_blob.GetBufferPointer();
-            let size = ps_blob.GetBufferSize();
-            device.CreatePixelShader(slice::from_raw_parts(ptr as _, size), None, Some(v))
+            device.CreatePixelShader(&shader_bytecode.pixel, None, Some(v))
         })?;
 
         let input_layout = util::try_out_ptr(|v| unsafe {
-            let ptr = vs_blob.GetBufferPointer();
-            let size = vs_blob.GetBufferSize();
             device.CreateInputLayout(
                 &[
                     D3D11_INPUT_ELEMENT_DESC {
@@ -368,28 +512,39 @@ impl ShaderProgram {
                         InstanceDataStepRate: 0,
                     },
                 ],
-                slice::from_raw_parts(ptr as _, size),
+                &shader_bytecode.vertex,
                 Some(v),
             )
         })?;
 
-        let sampler_state = util::try_out_ptr(|v| unsafe {
-            device.CreateSamplerState(
-                &D3D11_SAMPLER_DESC {
-                    Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
-                    AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
-                    AddressV: D3D11_TEXTURE_ADDRESS_WRAP,
-                    AddressW: D3D11_TEXTURE_ADDRESS_WRAP,
-                    MipLODBias: 0.,
-                    ComparisonFunc: D3D11_COMPARISON_ALWAYS,
-                    MinLOD: 0.,
-                    MaxLOD: 0.,
-                    BorderColor: [0.; 4],
-                    MaxAnisotropy: 0,
-                },
-                Some(v),
-            )
-        })?;
+        // Feature level 9.1 doesn't go beyond 2x anisotropic filtering.
+        let max_anisotropy = match unsafe { device.GetFeatureLevel() } {
+            D3D_FEATURE_LEVEL_9_1 => 2,
+            _ => D3D11_MAX_MAXANISOTROPY,
+        };
+        let create_sampler_state = |filter, max_anisotropy| {
+            util::try_out_ptr(|v| unsafe {
+                device.CreateSamplerState(
+                    &D3D11_SAMPLER_DESC {
+                        Filter: filter,
+                        AddressU: D3D11_TEXTURE_ADDRESS_WRAP,
+                        AddressV: D3D11_TEXTURE_ADDRESS_WRAP,
+                        AddressW: D3D11_TEXTURE_ADDRESS_WRAP,
+                        MipLODBias: 0.,
+                        ComparisonFunc: D3D11_COMPARISON_ALWAYS,
+                        MinLOD: 0.,
+                        MaxLOD: D3D11_FLOAT32_MAX,
+                        BorderColor: [0.; 4],
+                        MaxAnisotropy: max_anisotropy,
+                    },
+                    Some(v),
+                )
+            })
+        };
+        let sampler_state = create_sampler_state(D3D11_FILTER_MIN_MAG_MIP_LINEAR, 0)?;
+        let point_sampler_state = create_sampler_state(D3D11_FILTER_MIN_MAG_MIP_POINT, 0)?;
+        let anisotropic_sampler_state =
+            create_sampler_state(D3D11_FILTER_ANISOTROPIC, max_anisotropy)?;
         let blend_state = util::try_out_ptr(|v| unsafe {
             device.CreateBlendState(
                 &D3D11_BLEND_DESC {
@@ -468,6 +623,8 @@ impl ShaderProgram {
             pixel_shader,
             input_layout,
             sampler_state,
+            point_sampler_state,
+            anisotropic_sampler_state,
             blend_state,
             depth_stencil_state,
             rasterizer_state,
@@ -475,6 +632,16 @@ impl ShaderProgram {
     }
 }
 
+impl ShaderProgram {
+    fn sampler_state(&self, filter: TextureFilter) -> &ID3D11SamplerState {
+        match filter {
+            TextureFilter::Point => &self.point_sampler_state,
+            TextureFilter::Linear => &self.sampler_state,
+            TextureFilter::Anisotropic => &self.anisotropic_sampler_state,
+        }
+    }
+}
+
 struct Buffer<T: Sized> {
     bind_flag: D3D11_BIND_FLAG,
     resource: ID3D11Buffer,
@@ -570,12 +737,14 @@ struct Texture {
     id: TextureId,
     width: u32,
     height: u32,
+    options: TextureOptions,
 }
 
 struct TextureHeap {
     device: ID3D11Device,
     device_context: ID3D11DeviceContext,
-    textures: Vec<Texture>,
+    textures: Vec<Option<Texture>>,
+    refs: TextureRefs,
 }
 
 impl TextureHeap {
@@ -584,29 +753,65 @@ impl TextureHeap {
             device: device.clone(),
             device_context: device_context.clone(),
             textures: Vec::with_capacity(8),
+            refs: TextureRefs::default(),
         })
     }
 
-    unsafe fn create_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn get(&self, texture_id: TextureId) -> Option<&Texture> {
+        self.textures.get(texture_id.id()).and_then(Option::as_ref)
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.textures[texture_id.id()] = None;
+        }
+
+        Ok(())
+    }
+
+    // Free the textures whose last handle has been dropped since the last frame.
+    fn free_released(&mut self) {
+        for texture_id in self.refs.drain_released() {
+            self.textures[texture_id.id()] = None;
+        }
+    }
+
+    unsafe fn create_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        let (dxgi_format, data) = upload_format(data, options.format);
+        let mips = if options.mipmaps { mip_chain(&data, width, height, 4) } else { Vec::new() };
+        let mip_levels = 1 + mips.len() as u32;
+
+        let subresources: Vec<_> = [(&data[..], width)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width)))
+            .map(|(data, width)| D3D11_SUBRESOURCE_DATA {
+                pSysMem: data.as_ptr() as *const c_void,
+                SysMemPitch: width * 4,
+                SysMemSlicePitch: 0,
+            })
+            .collect();
+
         let resource: ID3D11Texture2D = util::try_out_ptr(|v| {
             self.device.CreateTexture2D(
                 &D3D11_TEXTURE2D_DESC {
                     Width: width,
                     Height: height,
-                    MipLevels: 1,
+                    MipLevels: mip_levels,
                     ArraySize: 1,
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    Format: dxgi_format,
                     SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
                     Usage: D3D11_USAGE_DEFAULT,
                     BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
                     CPUAccessFlags: 0,
                     MiscFlags: 0,
                 },
-                Some(&D3D11_SUBRESOURCE_DATA {
-                    pSysMem: data.as_ptr() as *const c_void,
-                    SysMemPitch: width * 4,
-                    SysMemSlicePitch: 0,
-                }),
+                Some(subresources.as_ptr()),
                 Some(v),
             )
         })?;
@@ -615,10 +820,10 @@ impl TextureHeap {
             self.device.CreateShaderResourceView(
                 &resource,
                 Some(&D3D11_SHADER_RESOURCE_VIEW_DESC {
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    Format: dxgi_format,
                     ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
                     Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
-                        Texture2D: D3D11_TEX2D_SRV { MostDetailedMip: 0, MipLevels: 1 },
+                        Texture2D: D3D11_TEX2D_SRV { MostDetailedMip: 0, MipLevels: mip_levels },
                     },
                 }),
                 Some(v),
@@ -626,19 +831,75 @@ impl TextureHeap {
         })?;
 
         let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource, shader_resource_view, id, width, height });
+        self.textures.push(Some(Texture {
+            resource,
+            shader_resource_view,
+            id,
+            width,
+            height,
+            options,
+        }));
+        self.refs.insert(id);
 
         Ok(id)
     }
 
+    unsafe fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        let resource: ID3D11Texture2D = util::try_out_ptr(|v| {
+            self.device.CreateTexture2D(
+                &D3D11_TEXTURE2D_DESC {
+                    Width: width,
+                    Height: height,
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: (D3D11_BIND_SHADER_RESOURCE.0 | D3D11_BIND_RENDER_TARGET.0) as u32,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                },
+                None,
+                Some(v),
+            )
+        })?;
+
+        let shader_resource_view =
+            util::try_out_ptr(|v| self.device.CreateShaderResourceView(&resource, None, Some(v)))?;
+        let render_target_view: ID3D11RenderTargetView =
+            util::try_out_ptr(|v| self.device.CreateRenderTargetView(&resource, None, Some(v)))?;
+        self.device_context.ClearRenderTargetView(&render_target_view, [0f32; 4].as_ptr());
+
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some(Texture {
+            resource: resource.clone(),
+            shader_resource_view,
+            id,
+            width,
+            height,
+            options: TextureOptions::default(),
+        }));
+        self.refs.insert(id);
+
+        Ok((id, RenderTargetHandle::D3D11 { texture: resource, render_target_view }))
+    }
+
     unsafe fn update_texture(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        let texture = &mut self.textures[texture_id.id()];
+        let Some(texture) = self.get(texture_id) else {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
         if texture.width != width || texture.height != height {
             error!(
                 "image size {width}x{height} do not match expected {}x{}",
@@ -646,20 +907,47 @@ impl TextureHeap {
             );
             return Err(Error::from_hresult(HRESULT(-1)));
         }
+        if texture.options.format != format {
+            error!(
+                "texture {texture_id:?} was loaded as {:?}, not {format:?}",
+                texture.options.format
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
 
-        self.device_context.UpdateSubresource(
-            &texture.resource,
-            0,
-            None,
-            data.as_ptr() as *const c_void,
-            width * 4,
-            0,
-        );
+        let (_, data) = upload_format(data, format);
+        let mips =
+            if texture.options.mipmaps { mip_chain(&data, width, height, 4) } else { Vec::new() };
+
+        for (level, (data, width)) in [(&data[..], width)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width)))
+            .enumerate()
+        {
+            self.device_context.UpdateSubresource(
+                &texture.resource,
+                level as u32,
+                None,
+                data.as_ptr() as *const c_void,
+                width * 4,
+                0,
+            );
+        }
 
         Ok(())
     }
 }
 
+// The format of textures loaded from pixels in `format`, and the pixels
+// converted to it. Sampling `R8_UNORM` yields `(r, 0, 0, 1)`, and Direct3D 11
+// can't swizzle it into gray, so those are expanded.
+fn upload_format(data: &[u8], format: PixelFormat) -> (DXGI_FORMAT, Cow<'_, [u8]>) {
+    match format {
+        PixelFormat::Bgra8 => (DXGI_FORMAT_B8G8R8A8_UNORM, Cow::Borrowed(data)),
+        format => (DXGI_FORMAT_R8G8B8A8_UNORM, convert_to_rgba8(data, format)),
+    }
+}
+
 const BACKUP_OBJECT_COUNT: usize = 16;
 
 struct StateBackup {
@@ -865,3 +1153,74 @@ impl StateBackup {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use windows::Win32::Foundation::HMODULE;
+
+    use super::*;
+
+    fn warp_device() -> Result<ID3D11Device> {
+        let mut device = None;
+        unsafe {
+            D3D11CreateDevice(
+                None,
+                D3D_DRIVER_TYPE_WARP,
+                HMODULE::default(),
+                D3D11_CREATE_DEVICE_FLAG(0),
+                None,
+                D3D11_SDK_VERSION,
+                Some(&mut device),
+                None,
+                None,
+            )?
+        };
+        Ok(device.unwrap())
+    }
+
+    #[test]
+    fn test_prewarm_shader_program() -> Result<()> {
+        let device = warp_device()?;
+
+        D3D11RenderEngine::prewarm();
+        let bytecode = shader_bytecode()?;
+        assert!(!bytecode.vertex.is_empty() && !bytecode.pixel.is_empty());
+        assert!(ptr::eq(bytecode, shader_bytecode()?));
+
+        // The shader program is created from the cached bytecode.
+        ShaderProgram::new(&device)?;
+
+        Ok(())
+    }
+
+    // What creating the shader program costs the first frame, with and
+    // without prewarming. Run with `--ignored --nocapture` to see it.
+    #[test]
+    #[ignore]
+    fn bench_prewarm_shader_program() -> Result<()> {
+        let device = warp_device()?;
+        D3D11RenderEngine::prewarm();
+
+        // Without prewarming, the shaders are compiled first.
+        let start = Instant::now();
+        ShaderBytecode::compile(
+            VERTEX_SHADER_SRC,
+            s!("vs_4_0\0"),
+            PIXEL_SHADER_SRC,
+            s!("ps_4_0\0"),
+        )
+        .unwrap();
+        ShaderProgram::new(&device)?;
+        let cold = start.elapsed();
+
+        let start = Instant::now();
+        ShaderProgram::new(&device)?;
+        let warm = start.elapsed();
+
+        eprintln!("Shader program: {cold:?} without prewarming, {warm:?} with");
+
+        Ok(())
+    }
+}
@@ -1,24 +1,34 @@
 // NOTE: see this for ManuallyDrop instances https://github.com/microsoft/windows-rs/issues/2386
 
 use std::ffi::c_void;
+use std::marker::PhantomData;
 use std::mem::{offset_of, ManuallyDrop};
 use std::{mem, ptr, slice};
 
 use imgui::internal::RawWrapper;
 use imgui::{BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
+use once_cell::sync::OnceCell;
 use tracing::error;
 use windows::core::{s, w, Error, Interface, Result, HRESULT};
 use windows::Win32::Foundation::*;
-use windows::Win32::Graphics::Direct3D::Fxc::*;
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D12::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
+use windows::Win32::Graphics::Dxgi::{IDXGISwapChain, IDXGISwapChain3};
 
+use crate::renderer::backend::{
+    build_font_atlas, mip_chain, pack_rgba8, PixelOrder, ShaderBytecode,
+};
+use crate::renderer::texture::TextureRefs;
 use crate::renderer::RenderEngine;
 use crate::util::{self, Fence};
-use crate::RenderContext;
+use crate::{
+    DeviceHandle, PixelFormat, RenderContext, RenderTargetHandle, TextureFilter, TextureHandle,
+    TextureOptions,
+};
 
 pub struct D3D12RenderEngine {
+    swap_chain: IDXGISwapChain,
     device: ID3D12Device,
 
     command_queue: ID3D12CommandQueue,
@@ -41,7 +51,12 @@ pub struct D3D12RenderEngine {
 }
 
 impl D3D12RenderEngine {
-    pub fn new(command_queue: &ID3D12CommandQueue, ctx: &mut Context) -> Result<Self> {
+    pub fn new(
+        swap_chain: &IDXGISwapChain,
+        command_queue: &ID3D12CommandQueue,
+        ctx: &mut Context,
+    ) -> Result<Self> {
+        let swap_chain = swap_chain.clone();
         let (device, command_queue, command_allocator, command_list) =
             unsafe { create_command_objects(command_queue) }?;
 
@@ -60,6 +75,7 @@ impl D3D12RenderEngine {
         ctx.set_renderer_name(String::from(concat!("hudhook-dx12@", env!("CARGO_PKG_VERSION"))));
 
         Ok(Self {
+            swap_chain,
             device,
             command_queue,
             command_allocator,
@@ -75,25 +91,114 @@ impl D3D12RenderEngine {
             fence,
         })
     }
+
+    /// Compile the shaders and serialize the root signature ahead of the
+    /// first frame, so that creating the engine in the present hook doesn't
+    /// stall on it. Neither needs a device, so this can run on any thread.
+    /// The root signature and the pipeline state objects belong to the
+    /// device, and still get created from these along with the engine.
+    pub fn prewarm() {
+        // Failures are logged, and both are retried with the engine.
+        shader_bytecode().ok();
+        root_signature_blob().ok();
+    }
 }
 
 impl RenderContext for D3D12RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.load_texture_with_options(
+            data,
+            width,
+            height,
+            TextureOptions::default().with_format(format),
+        )
+    }
+
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
         unsafe {
-            let texture_id = self.texture_heap.create_texture(width, height)?;
-            self.texture_heap.upload_texture(texture_id, data, width, height)?;
+            let texture_id = self.texture_heap.create_texture(width, height, options, false)?;
+            self.texture_heap.upload_texture(
+                texture_id,
+                data,
+                width,
+                height,
+                options.format,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+            )?;
             Ok(texture_id)
         }
     }
 
-    fn replace_texture(
+    fn replace_texture_with_format(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }
+        // The texture is updated in place, through the upload buffer of the
+        // previous updates.
+        unsafe {
+            self.texture_heap.upload_texture(
+                texture_id,
+                data,
+                width,
+                height,
+                format,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            )
+        }
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        self.texture_heap.unload_texture(texture_id)
+    }
+
+    fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        unsafe { self.texture_heap.create_render_texture(width, height) }
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.texture_heap.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        self.texture_heap
+            .get(texture_id)
+            .map(|texture| [texture.width as f32, texture.height as f32])
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        unsafe { self.capture_back_buffer() }
+    }
+
+    fn with_swap_chain(&self, f: &mut dyn FnMut(&IDXGISwapChain)) -> Option<()> {
+        f(&self.swap_chain);
+        Some(())
+    }
+
+    fn device_handle(&self) -> Option<DeviceHandle> {
+        Some(DeviceHandle::D3D12 {
+            device: self.device.clone(),
+            command_queue: self.command_queue.clone(),
+        })
     }
 }
 
@@ -101,6 +206,9 @@ impl RenderEngine for D3D12RenderEngine {
     type RenderTarget = ID3D12Resource;
 
     fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()> {
+        // The previous frame has been waited upon, so no texture is in use by the GPU.
+        self.texture_heap.free_released();
+
         unsafe {
             self.device.CreateRenderTargetView(&render_target, None, self.rtv_heap_start);
 
@@ -128,7 +236,7 @@ impl RenderEngine for D3D12RenderEngine {
             self.command_list.ResourceBarrier(&rtv_to_present_barriers);
             self.command_list.Close()?;
             self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
-            self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+            self.fence.signal(&self.command_queue)?;
             self.fence.wait()?;
             self.fence.incr();
 
@@ -141,7 +249,7 @@ impl RenderEngine for D3D12RenderEngine {
 
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
-        let fonts_texture = fonts.build_rgba32_texture();
+        let fonts_texture = build_font_atlas(fonts, self.max_texture_size())?;
         fonts.tex_id =
             self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
         Ok(())
@@ -149,22 +257,126 @@ impl RenderEngine for D3D12RenderEngine {
 }
 
 impl D3D12RenderEngine {
-    unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
-        self.vertex_buffer.clear();
-        self.index_buffer.clear();
+    fn max_texture_size(&self) -> u32 {
+        D3D12_REQ_TEXTURE2D_U_OR_V_DIMENSION
+    }
 
-        draw_data
-            .draw_lists()
-            .map(|draw_list| {
-                (draw_list.vtx_buffer().iter().copied(), draw_list.idx_buffer().iter().copied())
-            })
-            .for_each(|(vertices, indices)| {
-                self.vertex_buffer.extend(vertices);
-                self.index_buffer.extend(indices);
-            });
+    // Copy the current back buffer to a readback buffer and read it back.
+    unsafe fn capture_back_buffer(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        let swap_chain: IDXGISwapChain3 = self.swap_chain.cast()?;
+        let back_buffer: ID3D12Resource =
+            swap_chain.GetBuffer(swap_chain.GetCurrentBackBufferIndex())?;
+        let desc = back_buffer.GetDesc();
+        let order = PixelOrder::from_dxgi(desc.Format)?;
+
+        let (width, height) = (desc.Width as u32, desc.Height);
+        let align = D3D12_TEXTURE_DATA_PITCH_ALIGNMENT;
+        let readback_pitch = (width * 4).div_ceil(align) * align; // 256 bytes aligned
+        let readback_size = height * readback_pitch;
+
+        let readback_buffer: ID3D12Resource = util::try_out_ptr(|v| unsafe {
+            self.device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_READBACK,
+                    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                    CreationNodeMask: Default::default(),
+                    VisibleNodeMask: Default::default(),
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &D3D12_RESOURCE_DESC {
+                    Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                    Alignment: 0,
+                    Width: readback_size as _,
+                    Height: 1,
+                    DepthOrArraySize: 1,
+                    MipLevels: 1,
+                    Format: DXGI_FORMAT_UNKNOWN,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                },
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                None,
+                v,
+            )
+        })?;
+
+        self.command_allocator.Reset()?;
+        self.command_list.Reset(&self.command_allocator, None)?;
+
+        let src_location = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: ManuallyDrop::new(Some(back_buffer.clone())),
+            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
+        };
 
-        self.vertex_buffer.upload(&self.device)?;
-        self.index_buffer.upload(&self.device)?;
+        let dst_location = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: ManuallyDrop::new(Some(readback_buffer.clone())),
+            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
+                    Offset: 0,
+                    Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
+                        Format: desc.Format,
+                        Width: width,
+                        Height: height,
+                        Depth: 1,
+                        RowPitch: readback_pitch,
+                    },
+                },
+            },
+        };
+
+        let present_to_copy_barriers = [util::create_barrier(
+            &back_buffer,
+            D3D12_RESOURCE_STATE_PRESENT,
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+        )];
+
+        let copy_to_present_barriers = [util::create_barrier(
+            &back_buffer,
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+            D3D12_RESOURCE_STATE_PRESENT,
+        )];
+
+        self.command_list.ResourceBarrier(&present_to_copy_barriers);
+        self.command_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, None);
+        self.command_list.ResourceBarrier(&copy_to_present_barriers);
+        self.command_list.Close()?;
+        self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
+        self.fence.signal(&self.command_queue)?;
+        self.fence.wait()?;
+        self.fence.incr();
+
+        present_to_copy_barriers.into_iter().for_each(util::drop_barrier);
+        copy_to_present_barriers.into_iter().for_each(util::drop_barrier);
+
+        // The back buffer must not be leaked, or the swap chain can't be
+        // resized anymore.
+        let _ = ManuallyDrop::into_inner(src_location.pResource);
+        let _ = ManuallyDrop::into_inner(dst_location.pResource);
+
+        let mut readback_ptr = ptr::null_mut();
+        readback_buffer.Map(0, None, Some(&mut readback_ptr))?;
+        let pixels =
+            pack_rgba8(readback_ptr as *const u8, readback_pitch as usize, width, height, order);
+        readback_buffer.Unmap(0, None);
+
+        Ok((width, height, pixels))
+    }
+
+    unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
+        self.vertex_buffer.upload(
+            &self.device,
+            draw_data.total_vtx_count as usize,
+            draw_data.draw_lists().map(|draw_list| draw_list.vtx_buffer()),
+        )?;
+        self.index_buffer.upload(
+            &self.device,
+            draw_data.total_idx_count as usize,
+            draw_data.draw_lists().map(|draw_list| draw_list.idx_buffer()),
+        )?;
 
         self.projection_buffer = {
             let [l, t, r, b] = [
@@ -200,10 +412,18 @@ impl D3D12RenderEngine {
                             bottom: (ch - y) as i32,
                         };
 
+                        let Some(texture) = self.texture_heap.get(cmd_params.texture_id) else {
+                            continue;
+                        };
+
                         if r.right > r.left && r.bottom > r.top {
-                            let tex_handle =
-                                self.texture_heap.textures[cmd_params.texture_id.id()].gpu_desc;
+                            let tex_handle = texture.gpu_desc;
                             self.command_list.SetGraphicsRootDescriptorTable(1, tex_handle);
+                            self.command_list.SetGraphicsRoot32BitConstant(
+                                2,
+                                sampler_index(texture.options.filter),
+                                0,
+                            );
                             self.command_list.RSSetScissorRects(&[r]);
                             self.command_list.DrawIndexedInstanced(
                                 count as _,
@@ -244,14 +464,14 @@ impl D3D12RenderEngine {
             0,
             Some(&[D3D12_VERTEX_BUFFER_VIEW {
                 BufferLocation: self.vertex_buffer.resource.GetGPUVirtualAddress(),
-                SizeInBytes: (self.vertex_buffer.data.len() * mem::size_of::<DrawVert>()) as _,
+                SizeInBytes: (self.vertex_buffer.len * mem::size_of::<DrawVert>()) as _,
                 StrideInBytes: mem::size_of::<DrawVert>() as _,
             }]),
         );
 
         self.command_list.IASetIndexBuffer(Some(&D3D12_INDEX_BUFFER_VIEW {
             BufferLocation: self.index_buffer.resource.GetGPUVirtualAddress(),
-            SizeInBytes: (self.index_buffer.data.len() * mem::size_of::<DrawIdx>()) as _,
+            SizeInBytes: (self.index_buffer.len * mem::size_of::<DrawIdx>()) as _,
             Format: if mem::size_of::<DrawIdx>() == 2 {
                 DXGI_FORMAT_R16_UINT
             } else {
@@ -312,9 +532,92 @@ unsafe fn create_heaps(device: &ID3D12Device) -> Result<(ID3D12DescriptorHeap, T
     Ok((rtv_heap, texture_heap))
 }
 
-unsafe fn create_shader_program(
-    device: &ID3D12Device,
-) -> Result<(ID3D12RootSignature, ID3D12PipelineState)> {
+const VS: &str = r#"
+cbuffer vertexBuffer : register(b0) {
+  float4x4 ProjectionMatrix;
+};
+
+struct VS_INPUT {
+  float2 pos: POSITION;
+  float4 col: COLOR0;
+  float2 uv: TEXCOORD0;
+};
+
+struct PS_INPUT {
+  float4 pos: SV_POSITION;
+  float4 col: COLOR0;
+  float2 uv: TEXCOORD0;
+};
+
+PS_INPUT main(VS_INPUT input) {
+  PS_INPUT output;
+  output.pos = mul( ProjectionMatrix, float4(input.pos.xy, 0.f, 1.f));
+  output.col = input.col;
+  output.uv = input.uv;
+  return output;
+}"#;
+
+const PS: &str = r#"
+struct PS_INPUT {
+  float4 pos: SV_POSITION;
+  float4 col: COLOR0;
+  float2 uv: TEXCOORD0;
+};
+
+cbuffer samplerBuffer: register(b1) {
+  uint sampler_index;
+};
+
+SamplerState sampler0: register(s0);
+SamplerState sampler1: register(s1);
+SamplerState sampler2: register(s2);
+Texture2D texture0: register(t0);
+
+float4 main(PS_INPUT input): SV_Target {
+  float4 tex_col;
+  if (sampler_index == 1) {
+    tex_col = texture0.Sample(sampler1, input.uv);
+  } else if (sampler_index == 2) {
+    tex_col = texture0.Sample(sampler2, input.uv);
+  } else {
+    tex_col = texture0.Sample(sampler0, input.uv);
+  }
+  float4 out_col = input.col * tex_col;
+  return out_col;
+}"#;
+
+// The static sampler, and so the register, the pixel shader samples a
+// texture with.
+fn sampler_index(filter: TextureFilter) -> u32 {
+    match filter {
+        TextureFilter::Linear => 0,
+        TextureFilter::Point => 1,
+        TextureFilter::Anisotropic => 2,
+    }
+}
+
+// The bytecode doesn't depend on the device, so it's compiled only once, and
+// possibly ahead of time via `D3D12RenderEngine::prewarm`.
+fn shader_bytecode() -> Result<&'static ShaderBytecode> {
+    static SHADER_BYTECODE: OnceCell<ShaderBytecode> = OnceCell::new();
+
+    SHADER_BYTECODE
+        .get_or_try_init(|| ShaderBytecode::compile(VS, s!("vs_5_0\0"), PS, s!("ps_5_0\0")))
+        .map_err(|e| {
+            error!("{e}");
+            Error::from_hresult(HRESULT(-1))
+        })
+}
+
+// The serialized root signature doesn't depend on the device either, and is
+// cached the same way as the bytecode.
+fn root_signature_blob() -> Result<&'static [u8]> {
+    static ROOT_SIGNATURE_BLOB: OnceCell<Vec<u8>> = OnceCell::new();
+
+    ROOT_SIGNATURE_BLOB.get_or_try_init(|| unsafe { serialize_root_signature() }).map(Vec::as_slice)
+}
+
+unsafe fn serialize_root_signature() -> Result<Vec<u8>> {
     let parameters = [
         D3D12_ROOT_PARAMETER {
             ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
@@ -343,27 +646,46 @@ unsafe fn create_shader_program(
             },
             ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
         },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                    Num32BitValues: 1,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+    ];
+
+    let static_sampler = |filter, max_anisotropy, shader_register| D3D12_STATIC_SAMPLER_DESC {
+        Filter: filter,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        MipLODBias: 0f32,
+        MaxAnisotropy: max_anisotropy,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_ALWAYS,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
+        MinLOD: 0f32,
+        MaxLOD: D3D12_FLOAT32_MAX,
+        ShaderRegister: shader_register,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+    // In the order of `sampler_index`.
+    let static_samplers = [
+        static_sampler(D3D12_FILTER_MIN_MAG_MIP_LINEAR, 0, 0),
+        static_sampler(D3D12_FILTER_MIN_MAG_MIP_POINT, 0, 1),
+        static_sampler(D3D12_FILTER_ANISOTROPIC, D3D12_MAX_MAXANISOTROPY, 2),
     ];
 
     let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
-        NumParameters: 2,
+        NumParameters: parameters.len() as u32,
         pParameters: parameters.as_ptr(),
-        NumStaticSamplers: 1,
-        pStaticSamplers: &D3D12_STATIC_SAMPLER_DESC {
-            Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
-            AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-            AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-            AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-            MipLODBias: 0f32,
-            MaxAnisotropy: 0,
-            ComparisonFunc: D3D12_COMPARISON_FUNC_ALWAYS,
-            BorderColor: D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
-            MinLOD: 0f32,
-            MaxLOD: 0f32,
-            ShaderRegister: 0,
-            RegisterSpace: 0,
-            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
-        },
+        NumStaticSamplers: static_samplers.len() as u32,
+        pStaticSamplers: static_samplers.as_ptr(),
         Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT
             | D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS
             | D3D12_ROOT_SIGNATURE_FLAG_DENY_DOMAIN_SHADER_ROOT_ACCESS
@@ -378,89 +700,18 @@ unsafe fn create_shader_program(
             Some(err_blob),
         )
     })
-    .map_err(util::print_error_blob("Serializing root signature"))
-    .expect("D3D12SerializeRootSignature");
-
-    let root_signature: ID3D12RootSignature = device.CreateRootSignature(
-        0,
-        slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize()),
-    )?;
-
-    const VS: &str = r#"
-    cbuffer vertexBuffer : register(b0) {
-      float4x4 ProjectionMatrix;
-    };
-
-    struct VS_INPUT {
-      float2 pos: POSITION;
-      float4 col: COLOR0;
-      float2 uv: TEXCOORD0;
-    };
-
-    struct PS_INPUT {
-      float4 pos: SV_POSITION;
-      float4 col: COLOR0;
-      float2 uv: TEXCOORD0;
-    };
-
-    PS_INPUT main(VS_INPUT input) {
-      PS_INPUT output;
-      output.pos = mul( ProjectionMatrix, float4(input.pos.xy, 0.f, 1.f));
-      output.col = input.col;
-      output.uv = input.uv;
-      return output;
-    }"#;
-
-    const PS: &str = r#"
-    struct PS_INPUT {
-      float4 pos: SV_POSITION;
-      float4 col: COLOR0;
-      float2 uv: TEXCOORD0;
-    };
-
-    SamplerState sampler0: register(s0);
-    Texture2D texture0: register(t0);
+    .map_err(util::print_error_blob("Serializing root signature"))?;
 
-    float4 main(PS_INPUT input): SV_Target {
-      float4 out_col = input.col * texture0.Sample(sampler0, input.uv);
-      return out_col;
-    }"#;
+    Ok(slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize()).to_vec())
+}
 
-    let vtx_shader: ID3DBlob = util::try_out_err_blob(|v, err_blob| unsafe {
-        D3DCompile(
-            VS.as_ptr() as _,
-            VS.len(),
-            None,
-            None,
-            None::<&ID3DInclude>,
-            s!("main\0"),
-            s!("vs_5_0\0"),
-            0,
-            0,
-            v,
-            Some(err_blob),
-        )
-    })
-    .map_err(util::print_error_blob("Compiling vertex shader"))
-    .expect("D3DCompile");
+unsafe fn create_shader_program(
+    device: &ID3D12Device,
+) -> Result<(ID3D12RootSignature, ID3D12PipelineState)> {
+    let root_signature: ID3D12RootSignature =
+        device.CreateRootSignature(0, root_signature_blob()?)?;
 
-    let pix_shader = util::try_out_err_blob(|v, err_blob| unsafe {
-        D3DCompile(
-            PS.as_ptr() as _,
-            PS.len(),
-            None,
-            None,
-            None::<&ID3DInclude>,
-            s!("main\0"),
-            s!("ps_5_0\0"),
-            0,
-            0,
-            v,
-            Some(err_blob),
-        )
-    })
-    .map_err(util::print_error_blob("Compiling pixel shader"))
-    .expect("D3DCompile");
+    let shader_bytecode = shader_bytecode()?;
 
     let input_elements = [
         D3D12_INPUT_ELEMENT_DESC {
@@ -512,12 +763,12 @@ unsafe fn create_shader_program(
         ],
         DSVFormat: DXGI_FORMAT_D32_FLOAT,
         VS: D3D12_SHADER_BYTECODE {
-            pShaderBytecode: unsafe { vtx_shader.GetBufferPointer() },
-            BytecodeLength: unsafe { vtx_shader.GetBufferSize() },
+            pShaderBytecode: shader_bytecode.vertex.as_ptr() as _,
+            BytecodeLength: shader_bytecode.vertex.len(),
         },
         PS: D3D12_SHADER_BYTECODE {
-            pShaderBytecode: unsafe { pix_shader.GetBufferPointer() },
-            BytecodeLength: unsafe { pix_shader.GetBufferSize() },
+            pShaderBytecode: shader_bytecode.pixel.as_ptr() as _,
+            BytecodeLength: shader_bytecode.pixel.len(),
         },
         InputLayout: D3D12_INPUT_LAYOUT_DESC {
             pInputElementDescs: input_elements.as_ptr(),
@@ -570,18 +821,21 @@ unsafe fn create_shader_program(
     Ok((root_signature, pipeline_state))
 }
 
+// An upload buffer that the draw lists are copied into as they are, without
+// going through an intermediate buffer on the CPU.
 struct Buffer<T: Sized> {
     resource: ID3D12Resource,
     resource_capacity: usize,
-    data: Vec<T>,
+    // How many elements were uploaded with the last frame.
+    len: usize,
+    _marker: PhantomData<T>,
 }
 
-impl<T> Buffer<T> {
+impl<T: Copy> Buffer<T> {
     fn new(device: &ID3D12Device, resource_capacity: usize) -> Result<Self> {
         let resource = Self::create_resource(device, resource_capacity)?;
-        let data = Vec::with_capacity(resource_capacity);
 
-        Ok(Self { resource, resource_capacity, data })
+        Ok(Self { resource, resource_capacity, len: 0, _marker: PhantomData })
     }
 
     fn create_resource(device: &ID3D12Device, resource_capacity: usize) -> Result<ID3D12Resource> {
@@ -614,17 +868,16 @@ impl<T> Buffer<T> {
         })
     }
 
-    fn clear(&mut self) {
-        self.data.clear();
-    }
-
-    fn extend<I: IntoIterator<Item = T>>(&mut self, it: I) {
-        self.data.extend(it)
-    }
-
-    fn upload(&mut self, device: &ID3D12Device) -> Result<()> {
-        let capacity = self.data.capacity();
-        if capacity > self.resource_capacity {
+    // Copy `len` elements, split in `slices`, one after the other. The
+    // resource grows if they don't fit; the GPU is done with it, as the
+    // previous frame has been waited upon.
+    fn upload<'a, I>(&mut self, device: &ID3D12Device, len: usize, slices: I) -> Result<()>
+    where
+        T: 'a,
+        I: IntoIterator<Item = &'a [T]>,
+    {
+        if len > self.resource_capacity {
+            let capacity = len.next_power_of_two();
             drop(mem::replace(&mut self.resource, Self::create_resource(device, capacity)?));
             self.resource_capacity = capacity;
         }
@@ -632,32 +885,75 @@ impl<T> Buffer<T> {
         unsafe {
             let mut resource_ptr = ptr::null_mut();
             self.resource.Map(0, None, Some(&mut resource_ptr))?;
-            ptr::copy_nonoverlapping(self.data.as_ptr(), resource_ptr as *mut T, self.data.len());
+
+            let mut dst = resource_ptr as *mut T;
+            let mut copied = 0;
+            for slice in slices {
+                // Never write past the resource, should the total be off.
+                let count = slice.len().min(self.resource_capacity - copied);
+                ptr::copy_nonoverlapping(slice.as_ptr(), dst, count);
+                dst = dst.add(count);
+                copied += count;
+            }
+
             self.resource.Unmap(0, None);
+            self.len = copied;
         }
 
         Ok(())
     }
 }
 
+// Sample the red channel of single-channel textures as opaque gray, i.e.
+// `D3D12_ENCODE_SHADER_4_COMPONENT_MAPPING(0, 0, 0, FORCE_VALUE_1)`.
+const GRAYSCALE_SHADER_4_COMPONENT_MAPPING: u32 = {
+    let red = D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_0.0 as u32;
+    let one = D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_1.0 as u32;
+    red | (red << 3) | (red << 6) | (one << 9) | (1 << 12)
+};
+
+// The format of textures loaded from pixels in `format`, and how their shader
+// resource views map its channels. All of them are sampled natively.
+fn texture_format(format: PixelFormat) -> (DXGI_FORMAT, u32) {
+    match format {
+        PixelFormat::Rgba8 => {
+            (DXGI_FORMAT_R8G8B8A8_UNORM, D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING)
+        },
+        PixelFormat::Bgra8 => {
+            (DXGI_FORMAT_B8G8R8A8_UNORM, D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING)
+        },
+        PixelFormat::R8 => (DXGI_FORMAT_R8_UNORM, GRAYSCALE_SHADER_4_COMPONENT_MAPPING),
+    }
+}
+
 #[derive(Debug)]
 #[allow(unused)]
 struct Texture {
     resource: ID3D12Resource,
+    // Index of the texture's descriptor in the heaps.
+    slot: u32,
     gpu_desc: D3D12_GPU_DESCRIPTOR_HANDLE,
     width: u32,
     height: u32,
+    options: TextureOptions,
 }
 
 struct TextureHeap {
     device: ID3D12Device,
     srv_heap: ID3D12DescriptorHeap,
     srv_staging_heap: ID3D12DescriptorHeap,
-    textures: Vec<Texture>,
+    textures: Vec<Option<Texture>>,
+    refs: TextureRefs,
+    // Descriptor slots of the freed textures, reused by the next ones so that
+    // e.g. rebuilding the font atlas doesn't grow the heaps.
+    free_slots: Vec<u32>,
     command_queue: ID3D12CommandQueue,
     command_allocator: ID3D12CommandAllocator,
     command_list: ID3D12GraphicsCommandList,
     fence: Fence,
+    // The buffer the last upload went through, and its size, reused by the
+    // next ones unless they need a larger one.
+    upload_buffer: Option<(ID3D12Resource, u64)>,
 }
 
 impl TextureHeap {
@@ -700,19 +996,52 @@ impl TextureHeap {
             srv_heap,
             srv_staging_heap,
             textures: Vec::new(),
+            refs: TextureRefs::default(),
+            free_slots: Vec::new(),
             command_queue,
             command_allocator,
             command_list,
             fence,
+            upload_buffer: None,
         })
     }
 
+    fn get(&self, texture_id: TextureId) -> Option<&Texture> {
+        self.textures.get(texture_id.id()).and_then(Option::as_ref)
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.free(texture_id);
+        }
+
+        Ok(())
+    }
+
+    // Free the textures whose last handle has been dropped since the last frame.
+    fn free_released(&mut self) {
+        for texture_id in self.refs.drain_released() {
+            self.free(texture_id);
+        }
+    }
+
+    fn free(&mut self, texture_id: TextureId) {
+        if let Some(texture) = self.textures[texture_id.id()].take() {
+            self.free_slots.push(texture.slot);
+        }
+    }
+
+    // Number of descriptor slots in use or freed, i.e. the next new slot.
+    fn slot_count(&self) -> u32 {
+        (self.textures.iter().flatten().count() + self.free_slots.len()) as u32
+    }
+
     unsafe fn resize_heap(&mut self) -> Result<()> {
         let mut desc = self.srv_heap.GetDesc();
         let mut desc_staging = self.srv_staging_heap.GetDesc();
         let old_num_descriptors = desc.NumDescriptors;
 
-        if old_num_descriptors <= self.textures.len() as _ {
+        if self.free_slots.is_empty() && old_num_descriptors <= self.slot_count() {
             desc.NumDescriptors *= 2;
             desc_staging.NumDescriptors = desc.NumDescriptors;
 
@@ -740,9 +1069,9 @@ impl TextureHeap {
                 .device
                 .GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV);
 
-            self.textures.iter_mut().enumerate().for_each(|(texture_index, texture)| {
+            self.textures.iter_mut().flatten().for_each(|texture| {
                 texture.gpu_desc = D3D12_GPU_DESCRIPTOR_HANDLE {
-                    ptr: gpu_heap_start.ptr + (texture_index as u32 * heap_inc_size) as u64,
+                    ptr: gpu_heap_start.ptr + (texture.slot * heap_inc_size) as u64,
                 };
             })
         }
@@ -750,28 +1079,41 @@ impl TextureHeap {
         Ok(())
     }
 
-    unsafe fn create_texture(&mut self, width: u32, height: u32) -> Result<TextureId> {
+    unsafe fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+        render_target: bool,
+    ) -> Result<TextureId> {
         self.resize_heap()?;
 
+        let (dxgi_format, component_mapping) = texture_format(options.format);
+        let mip_levels = if options.mipmaps { 1 + width.max(height).max(1).ilog2() } else { 1 };
+        let (flags, initial_state) = if render_target {
+            (D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET, D3D12_RESOURCE_STATE_RENDER_TARGET)
+        } else {
+            (D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_COPY_DEST)
+        };
+
         let cpu_heap_stg_start = self.srv_staging_heap.GetCPUDescriptorHandleForHeapStart();
         let cpu_heap_start = self.srv_heap.GetCPUDescriptorHandleForHeapStart();
         let gpu_heap_start = self.srv_heap.GetGPUDescriptorHandleForHeapStart();
         let heap_inc_size =
             self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV);
 
-        let texture_index = self.textures.len() as u32;
+        let slot = self.free_slots.pop().unwrap_or_else(|| self.slot_count());
 
         let cpu_desc_stg = D3D12_CPU_DESCRIPTOR_HANDLE {
-            ptr: cpu_heap_stg_start.ptr + (texture_index * heap_inc_size) as usize,
+            ptr: cpu_heap_stg_start.ptr + (slot * heap_inc_size) as usize,
         };
 
         let cpu_desc = D3D12_CPU_DESCRIPTOR_HANDLE {
-            ptr: cpu_heap_start.ptr + (texture_index * heap_inc_size) as usize,
+            ptr: cpu_heap_start.ptr + (slot * heap_inc_size) as usize,
         };
 
-        let gpu_desc = D3D12_GPU_DESCRIPTOR_HANDLE {
-            ptr: gpu_heap_start.ptr + (texture_index * heap_inc_size) as u64,
-        };
+        let gpu_desc =
+            D3D12_GPU_DESCRIPTOR_HANDLE { ptr: gpu_heap_start.ptr + (slot * heap_inc_size) as u64 };
 
         let texture: ID3D12Resource = util::try_out_ptr(|v| unsafe {
             self.device.CreateCommittedResource(
@@ -789,13 +1131,13 @@ impl TextureHeap {
                     Width: width as _,
                     Height: height as _,
                     DepthOrArraySize: 1,
-                    MipLevels: 1,
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    MipLevels: mip_levels as _,
+                    Format: dxgi_format,
                     SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
                     Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
-                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                    Flags: flags,
                 },
-                D3D12_RESOURCE_STATE_COPY_DEST,
+                initial_state,
                 None,
                 v,
             )
@@ -804,13 +1146,13 @@ impl TextureHeap {
         self.device.CreateShaderResourceView(
             &texture,
             Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
-                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                Format: dxgi_format,
                 ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
-                Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                Shader4ComponentMapping: component_mapping,
                 Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
                     Texture2D: D3D12_TEX2D_SRV {
                         MostDetailedMip: 0,
-                        MipLevels: 1,
+                        MipLevels: mip_levels,
                         PlaneSlice: Default::default(),
                         ResourceMinLODClamp: Default::default(),
                     },
@@ -827,31 +1169,66 @@ impl TextureHeap {
         );
 
         let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource: texture.clone(), gpu_desc, width, height });
+        self.textures.push(Some(Texture {
+            resource: texture.clone(),
+            slot,
+            gpu_desc,
+            width,
+            height,
+            options,
+        }));
+        self.refs.insert(id);
 
         Ok(id)
     }
 
-    unsafe fn upload_texture(
+    unsafe fn create_render_texture(
         &mut self,
-        texture_id: TextureId,
-        data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<()> {
-        let texture = &self.textures[texture_id.id()];
-        if texture.width != width || texture.height != height {
-            error!(
-                "image size {width}x{height} do not match expected {}x{}",
-                texture.width, texture.height
-            );
-            return Err(Error::from_hresult(HRESULT(-1)));
-        }
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        let id = self.create_texture(width, height, TextureOptions::default(), true)?;
+        let resource = self.get(id).expect("texture was just created").resource.clone();
+
+        let rtv_heap: ID3D12DescriptorHeap =
+            self.device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+                NumDescriptors: 1,
+                Flags: D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
+                NodeMask: 0,
+            })?;
+        let rtv = rtv_heap.GetCPUDescriptorHandleForHeapStart();
+        self.device.CreateRenderTargetView(&resource, None, rtv);
 
-        let upload_row_size = width * 4;
-        let align = D3D12_TEXTURE_DATA_PITCH_ALIGNMENT;
-        let upload_pitch = upload_row_size.div_ceil(align) * align; // 256 bytes aligned
-        let upload_size = height * upload_pitch;
+        // Clear it, and leave it in the state the overlay samples it in.
+        self.command_allocator.Reset()?;
+        self.command_list.Reset(&self.command_allocator, None)?;
+        self.command_list.ClearRenderTargetView(rtv, [0f32; 4].as_ptr(), None);
+        let barriers = [util::create_barrier(
+            &resource,
+            D3D12_RESOURCE_STATE_RENDER_TARGET,
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        )];
+        self.command_list.ResourceBarrier(&barriers);
+        self.command_list.Close()?;
+        self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
+        self.fence.signal(&self.command_queue)?;
+        self.fence.wait()?;
+        self.fence.incr();
+
+        barriers.into_iter().for_each(util::drop_barrier);
+
+        Ok((id, RenderTargetHandle::D3D12 { resource, rtv_heap, rtv }))
+    }
+
+    // A buffer to upload at least `size` bytes through. Uploads are waited
+    // upon, so the previous one is done with it.
+    unsafe fn upload_buffer(&mut self, size: u64) -> Result<ID3D12Resource> {
+        if let Some((upload_buffer, capacity)) = &self.upload_buffer {
+            if *capacity >= size {
+                return Ok(upload_buffer.clone());
+            }
+        }
 
         let upload_buffer: ID3D12Resource = util::try_out_ptr(|v| unsafe {
             self.device.CreateCommittedResource(
@@ -866,7 +1243,7 @@ impl TextureHeap {
                 &D3D12_RESOURCE_DESC {
                     Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
                     Alignment: 0,
-                    Width: upload_size as _,
+                    Width: size as _,
                     Height: 1,
                     DepthOrArraySize: 1,
                     MipLevels: 1,
@@ -880,16 +1257,74 @@ impl TextureHeap {
                 v,
             )
         })?;
+        self.upload_buffer = Some((upload_buffer.clone(), size));
+
+        Ok(upload_buffer)
+    }
+
+    unsafe fn upload_texture(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        state: D3D12_RESOURCE_STATES,
+    ) -> Result<()> {
+        let Some(texture) = self.get(texture_id) else {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+        if texture.width != width || texture.height != height {
+            error!(
+                "image size {width}x{height} do not match expected {}x{}",
+                texture.width, texture.height
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        if texture.options.format != format {
+            error!(
+                "texture {texture_id:?} was loaded as {:?}, not {format:?}",
+                texture.options.format
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        let (resource, mipmaps) = (resource.clone(), mipmaps);
+
+        let bytes_per_pixel = format.bytes_per_pixel();
+        let mips =
+            if mipmaps { mip_chain(data, width, height, bytes_per_pixel) } else { Vec::new() };
+        let levels: Vec<_> =
+            [data].into_iter().chain(mips.iter().map(|mip| &mip.data[..])).collect();
+
+        // Where each level goes in the upload buffer, with its rows aligned
+        // as the copy requires.
+        let desc = resource.GetDesc();
+        let mut footprints = vec![D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default(); levels.len()];
+        let mut num_rows = vec![0u32; levels.len()];
+        let mut upload_size = 0u64;
+        self.device.GetCopyableFootprints(
+            &desc,
+            0,
+            levels.len() as u32,
+            0,
+            Some(footprints.as_mut_ptr()),
+            Some(num_rows.as_mut_ptr()),
+            None,
+            Some(&mut upload_size),
+        );
+
+        let upload_buffer = self.upload_buffer(upload_size)?;
 
         let mut upload_buffer_ptr = ptr::null_mut();
         upload_buffer.Map(0, None, Some(&mut upload_buffer_ptr))?;
-        if upload_row_size == upload_pitch {
-            ptr::copy_nonoverlapping(data.as_ptr(), upload_buffer_ptr as *mut u8, data.len());
-        } else {
-            for y in 0..height {
-                let src = data.as_ptr().add((y * upload_row_size) as usize);
-                let dst = (upload_buffer_ptr as *mut u8).add((y * upload_pitch) as usize);
-                ptr::copy_nonoverlapping(src, dst, upload_row_size as usize);
+        for ((data, footprint), &num_rows) in levels.iter().zip(&footprints).zip(&num_rows) {
+            let row_size = footprint.Footprint.Width as usize * bytes_per_pixel;
+            let pitch = footprint.Footprint.RowPitch as usize;
+            let base = (upload_buffer_ptr as *mut u8).add(footprint.Offset as usize);
+            for y in 0..num_rows as usize {
+                let src = data.as_ptr().add(y * row_size);
+                ptr::copy_nonoverlapping(src, base.add(y * pitch), row_size);
             }
         }
         upload_buffer.Unmap(0, None);
@@ -897,32 +1332,39 @@ impl TextureHeap {
         self.command_allocator.Reset()?;
         self.command_list.Reset(&self.command_allocator, None)?;
 
-        let dst_location = D3D12_TEXTURE_COPY_LOCATION {
-            pResource: ManuallyDrop::new(Some(texture.resource.clone())),
-            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
-            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
-        };
+        // Textures being replaced are sampled from, and only new ones are
+        // ready to be copied to.
+        if state != D3D12_RESOURCE_STATE_COPY_DEST {
+            let barriers = [util::create_barrier(&resource, state, D3D12_RESOURCE_STATE_COPY_DEST)];
+            self.command_list.ResourceBarrier(&barriers);
+            barriers.into_iter().for_each(util::drop_barrier);
+        }
 
-        let src_location = D3D12_TEXTURE_COPY_LOCATION {
-            pResource: ManuallyDrop::new(Some(upload_buffer.clone())),
-            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
-            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
-                PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
-                    Offset: 0,
-                    Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
-                        Format: DXGI_FORMAT_R8G8B8A8_UNORM,
-                        Width: width,
-                        Height: height,
-                        Depth: 1,
-                        RowPitch: upload_pitch,
-                    },
-                },
-            },
-        };
+        let locations: Vec<_> = footprints
+            .iter()
+            .enumerate()
+            .map(|(level, &footprint)| {
+                let dst_location = D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: ManuallyDrop::new(Some(resource.clone())),
+                    Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: level as u32 },
+                };
 
-        self.command_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, None);
+                let src_location = D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: ManuallyDrop::new(Some(upload_buffer.clone())),
+                    Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { PlacedFootprint: footprint },
+                };
+
+                (dst_location, src_location)
+            })
+            .collect();
+
+        for (dst_location, src_location) in &locations {
+            self.command_list.CopyTextureRegion(dst_location, 0, 0, 0, src_location, None);
+        }
         let barriers = [util::create_barrier(
-            &texture.resource,
+            &resource,
             D3D12_RESOURCE_STATE_COPY_DEST,
             D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
         )];
@@ -930,18 +1372,21 @@ impl TextureHeap {
         self.command_list.ResourceBarrier(&barriers);
         self.command_list.Close()?;
         self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
-        self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+        self.fence.signal(&self.command_queue)?;
         self.fence.wait()?;
         self.fence.incr();
 
         barriers.into_iter().for_each(util::drop_barrier);
 
         // Apparently, leaking the upload buffer into the location is necessary.
+        // It's reused by the next uploads, so this only leaks a reference.
         // Uncommenting the following line consistently leads to a crash, which
         // points to a double-free, but I don't know why: upload_buffer should
         // stay alive with a positive refcount until the end of this block.
         // let _ = ManuallyDrop::into_inner(src_location.pResource);
-        let _ = ManuallyDrop::into_inner(dst_location.pResource);
+        for (dst_location, _) in locations {
+            let _ = ManuallyDrop::into_inner(dst_location.pResource);
+        }
 
         Ok(())
     }
@@ -10,8 +10,13 @@ use windows::Foundation::Numerics::Matrix4x4;
 use windows::Win32::Foundation::RECT;
 use windows::Win32::Graphics::Direct3D9::*;
 
+use crate::renderer::backend::{build_font_atlas, mip_chain, pack_rgba8, PixelOrder};
+use crate::renderer::texture::TextureRefs;
 use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::{
+    util, DeviceHandle, PixelFormat, RenderContext, RenderTargetHandle, TextureFilter,
+    TextureHandle, TextureOptions,
+};
 
 const D3DFVF_CUSTOMVERTEX: u32 = D3DFVF_XYZ | D3DFVF_DIFFUSE | D3DFVF_TEX1;
 const MAT_IDENTITY: Matrix4x4 = Matrix4x4 {
@@ -66,25 +71,93 @@ impl D3D9RenderEngine {
 
         Ok(Self { device, texture_heap, vertex_buffer, index_buffer, projection_buffer })
     }
+
+    /// Release the objects in the default pool, i.e. the vertex and index
+    /// buffers and all the textures, which the device can't be reset with.
+    ///
+    /// The buffers are created again on the next frame. The textures, the
+    /// font atlas included, have to be loaded again by their owners.
+    pub(crate) fn release_device_objects(&mut self) {
+        self.vertex_buffer.release();
+        self.index_buffer.release();
+        self.texture_heap.release_all();
+    }
 }
 
 impl RenderContext for D3D9RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.load_texture_with_options(
+            data,
+            width,
+            height,
+            TextureOptions::default().with_format(format),
+        )
+    }
+
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
         unsafe {
-            let texture_id = self.texture_heap.create_texture(width, height)?;
-            self.texture_heap.upload_texture(texture_id, data, width, height)?;
+            let texture_id = self.texture_heap.create_texture(width, height, options)?;
+            self.texture_heap.upload_texture(texture_id, data, width, height, options.format)?;
             Ok(texture_id)
         }
     }
 
-    fn replace_texture(
+    fn replace_texture_with_format(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }
+        unsafe { self.texture_heap.upload_texture(texture_id, data, width, height, format) }
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        self.texture_heap.unload_texture(texture_id)
+    }
+
+    fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        unsafe { self.texture_heap.create_render_texture(width, height) }
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.texture_heap.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        self.texture_heap
+            .get(texture_id)
+            .map(|texture| [texture.width as f32, texture.height as f32])
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        unsafe { self.capture_back_buffer() }
+    }
+
+    fn with_d3d9_device(&self, f: &mut dyn FnMut(&IDirect3DDevice9)) -> Option<()> {
+        f(&self.device);
+        Some(())
+    }
+
+    fn device_handle(&self) -> Option<DeviceHandle> {
+        Some(DeviceHandle::D3D9 { device: self.device.clone() })
     }
 }
 
@@ -96,6 +169,8 @@ impl RenderEngine for D3D9RenderEngine {
         draw_data: &imgui::DrawData,
         render_target: Self::RenderTarget,
     ) -> Result<()> {
+        self.texture_heap.free_released();
+
         unsafe {
             let state_backup = StateBackup::backup(&self.device)?;
             self.device.SetRenderTarget(0, &render_target)?;
@@ -107,7 +182,7 @@ impl RenderEngine for D3D9RenderEngine {
 
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
-        let fonts_texture = fonts.build_rgba32_texture();
+        let fonts_texture = build_font_atlas(fonts, self.max_texture_size()?)?;
         fonts.tex_id =
             self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
         Ok(())
@@ -115,6 +190,80 @@ impl RenderEngine for D3D9RenderEngine {
 }
 
 impl D3D9RenderEngine {
+    fn max_texture_size(&self) -> Result<u32> {
+        let mut caps = D3DCAPS9::default();
+        unsafe { self.device.GetDeviceCaps(&mut caps) }?;
+        Ok(caps.MaxTextureWidth.min(caps.MaxTextureHeight))
+    }
+
+    // Copy the current back buffer to a system memory surface and read it back.
+    unsafe fn capture_back_buffer(&self) -> Result<(u32, u32, Vec<u8>)> {
+        let back_buffer = self.device.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO)?;
+        let mut desc = D3DSURFACE_DESC::default();
+        back_buffer.GetDesc(&mut desc)?;
+
+        let order = match desc.Format {
+            D3DFMT_A8B8G8R8 => PixelOrder::Rgba,
+            D3DFMT_A8R8G8B8 => PixelOrder::Bgra,
+            D3DFMT_X8R8G8B8 => PixelOrder::Bgrx,
+            format => {
+                error!("Can't capture a screenshot of a back buffer of format {format:?}");
+                return Err(Error::from_hresult(HRESULT(-1)));
+            },
+        };
+
+        // Multisampled back buffers can't be read back as is.
+        let source = if desc.MultiSampleType != D3DMULTISAMPLE_NONE {
+            let resolved = util::try_out_ptr(|v| {
+                self.device.CreateRenderTarget(
+                    desc.Width,
+                    desc.Height,
+                    desc.Format,
+                    D3DMULTISAMPLE_NONE,
+                    0,
+                    false,
+                    v,
+                    ptr::null_mut(),
+                )
+            })?;
+            self.device.StretchRect(
+                &back_buffer,
+                ptr::null(),
+                &resolved,
+                ptr::null(),
+                D3DTEXF_NONE,
+            )?;
+            resolved
+        } else {
+            back_buffer
+        };
+
+        let offscreen = util::try_out_ptr(|v| {
+            self.device.CreateOffscreenPlainSurface(
+                desc.Width,
+                desc.Height,
+                desc.Format,
+                D3DPOOL_SYSTEMMEM,
+                v,
+                ptr::null_mut(),
+            )
+        })?;
+        self.device.GetRenderTargetData(&source, &offscreen)?;
+
+        let mut rect = D3DLOCKED_RECT::default();
+        offscreen.LockRect(&mut rect, ptr::null(), D3DLOCK_READONLY as u32)?;
+        let pixels = pack_rgba8(
+            rect.pBits as *const u8,
+            rect.Pitch as usize,
+            desc.Width,
+            desc.Height,
+            order,
+        );
+        offscreen.UnlockRect()?;
+
+        Ok((desc.Width, desc.Height, pixels))
+    }
+
     unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
         self.vertex_buffer.clear();
         self.index_buffer.clear();
@@ -159,6 +308,11 @@ impl D3D9RenderEngine {
 
         self.setup_render_state(draw_data)?;
 
+        // The viewport covers the display, so the scissor rects must stay within
+        // it: `SetScissorRect` fails on rects that don't, e.g. for clip rects
+        // outside the display when its origin isn't the primary monitor's.
+        let [fb_width, fb_height] = draw_data.display_size.map(|v| v as i32);
+
         let mut vtx_offset = 0usize;
         let mut idx_offset = 0usize;
         let mut last_texture = None;
@@ -170,32 +324,38 @@ impl D3D9RenderEngine {
                         let [cx, cy, cw, ch] = cmd_params.clip_rect;
                         let [x, y] = draw_data.display_pos;
                         let r = RECT {
-                            left: (cx - x) as i32,
-                            top: (cy - y) as i32,
-                            right: (cw - x) as i32,
-                            bottom: (ch - y) as i32,
+                            left: ((cx - x) as i32).clamp(0, fb_width),
+                            top: ((cy - y) as i32).clamp(0, fb_height),
+                            right: ((cw - x) as i32).clamp(0, fb_width),
+                            bottom: ((ch - y) as i32).clamp(0, fb_height),
+                        };
+
+                        if r.right <= r.left || r.bottom <= r.top {
+                            continue;
+                        }
+
+                        let Some(texture) = self.texture_heap.get(cmd_params.texture_id) else {
+                            continue;
                         };
 
                         last_texture = match last_texture {
                             Some(t) if t == cmd_params.texture_id => Some(t),
                             None | Some(_) => {
-                                let texture = self.texture_heap.get(cmd_params.texture_id);
-                                self.device.SetTexture(0, texture)?;
+                                self.device.SetTexture(0, &texture.resource)?;
+                                self.set_sampler_states(texture.options.filter)?;
                                 Some(cmd_params.texture_id)
                             },
                         };
 
-                        if r.right > r.left && r.bottom > r.top {
-                            self.device.SetScissorRect(&r)?;
-                            self.device.DrawIndexedPrimitive(
-                                D3DPT_TRIANGLELIST,
-                                (cmd_params.vtx_offset + vtx_offset) as i32,
-                                0,
-                                cl.vtx_buffer().len() as u32,
-                                (cmd_params.idx_offset + idx_offset) as u32,
-                                count as u32 / 3,
-                            )?;
-                        }
+                        self.device.SetScissorRect(&r)?;
+                        self.device.DrawIndexedPrimitive(
+                            D3DPT_TRIANGLELIST,
+                            (cmd_params.vtx_offset + vtx_offset) as i32,
+                            0,
+                            cl.vtx_buffer().len() as u32,
+                            (cmd_params.idx_offset + idx_offset) as u32,
+                            count as u32 / 3,
+                        )?;
                     },
                     DrawCmd::ResetRenderState => {
                         self.setup_render_state(draw_data)?;
@@ -238,22 +398,42 @@ impl D3D9RenderEngine {
         self.device.SetTextureStageState(0, D3DTSS_ALPHAOP, D3DTOP_MODULATE.0 as u32)?;
         self.device.SetTextureStageState(0, D3DTSS_ALPHAARG1, D3DTA_TEXTURE)?;
         self.device.SetTextureStageState(0, D3DTSS_ALPHAARG2, D3DTA_DIFFUSE)?;
-        self.device.SetSamplerState(0, D3DSAMP_MINFILTER, D3DTEXF_LINEAR.0 as u32)?;
-        self.device.SetSamplerState(0, D3DSAMP_MAGFILTER, D3DTEXF_LINEAR.0 as u32)?;
+        self.set_sampler_states(TextureFilter::Linear)?;
         self.device.SetTransform(D3DTRANSFORMSTATETYPE(256), &MAT_IDENTITY)?;
         self.device.SetTransform(D3DTS_VIEW, &MAT_IDENTITY)?;
         self.device.SetTransform(D3DTS_PROJECTION, &self.projection_buffer)?;
-        self.device.SetStreamSource(
-            0,
-            &self.vertex_buffer.resource,
-            0,
-            mem::size_of::<CustomVertex>() as u32,
-        )?;
-        self.device.SetIndices(&self.index_buffer.resource)?;
+        // The buffers have just been uploaded, and thus created if needed.
+        let (Some(vertex_buffer), Some(index_buffer)) =
+            (&self.vertex_buffer.resource, &self.index_buffer.resource)
+        else {
+            error!("Vertex or index buffer not created");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+        self.device.SetStreamSource(0, vertex_buffer, 0, mem::size_of::<CustomVertex>() as u32)?;
+        self.device.SetIndices(index_buffer)?;
         self.device.SetFVF(D3DFVF_CUSTOMVERTEX)?;
 
         Ok(())
     }
+
+    unsafe fn set_sampler_states(&self, filter: TextureFilter) -> Result<()> {
+        let (min_filter, mag_filter, mip_filter) = match filter {
+            TextureFilter::Point => (D3DTEXF_POINT, D3DTEXF_POINT, D3DTEXF_POINT),
+            TextureFilter::Linear => (D3DTEXF_LINEAR, D3DTEXF_LINEAR, D3DTEXF_LINEAR),
+            TextureFilter::Anisotropic => (D3DTEXF_ANISOTROPIC, D3DTEXF_LINEAR, D3DTEXF_LINEAR),
+        };
+
+        if filter == TextureFilter::Anisotropic {
+            let mut caps: D3DCAPS9 = mem::zeroed();
+            self.device.GetDeviceCaps(&mut caps)?;
+            self.device.SetSamplerState(0, D3DSAMP_MAXANISOTROPY, caps.MaxAnisotropy.max(1))?;
+        }
+        self.device.SetSamplerState(0, D3DSAMP_MINFILTER, min_filter.0 as u32)?;
+        self.device.SetSamplerState(0, D3DSAMP_MAGFILTER, mag_filter.0 as u32)?;
+        self.device.SetSamplerState(0, D3DSAMP_MIPFILTER, mip_filter.0 as u32)?;
+
+        Ok(())
+    }
 }
 
 trait BufferType: Sized {
@@ -262,19 +442,24 @@ trait BufferType: Sized {
 }
 
 struct Buffer<B: BufferType, T> {
-    resource: B,
+    // `None` once released, until the next upload.
+    resource: Option<B>,
     resource_capacity: usize,
     data: Vec<T>,
 }
 
 impl<B: BufferType, T> Buffer<B, T> {
     fn new(device: &IDirect3DDevice9, resource_capacity: usize) -> Result<Self> {
-        let resource = B::create_resource(device, resource_capacity)?;
+        let resource = Some(B::create_resource(device, resource_capacity)?);
         let data = Vec::with_capacity(resource_capacity);
 
         Ok(Self { resource, resource_capacity, data })
     }
 
+    fn release(&mut self) {
+        self.resource = None;
+    }
+
     fn clear(&mut self) {
         self.data.clear();
     }
@@ -285,12 +470,17 @@ impl<B: BufferType, T> Buffer<B, T> {
 
     fn upload(&mut self, device: &IDirect3DDevice9) -> Result<()> {
         let capacity = self.data.capacity();
-        if capacity > self.resource_capacity {
-            drop(mem::replace(&mut self.resource, B::create_resource(device, capacity)?));
-            self.resource_capacity = capacity;
-        }
+        let resource = match &mut self.resource {
+            Some(resource) if capacity <= self.resource_capacity => resource,
+            resource => {
+                // Release the previous buffer first.
+                *resource = None;
+                self.resource_capacity = self.resource_capacity.max(capacity);
+                resource.insert(B::create_resource(device, self.resource_capacity)?)
+            },
+        };
 
-        self.resource.upload(&self.data)?;
+        resource.upload(&self.data)?;
 
         Ok(())
     }
@@ -367,28 +557,57 @@ struct Texture {
     id: TextureId,
     width: u32,
     height: u32,
+    options: TextureOptions,
 }
 
 struct TextureHeap {
     device: IDirect3DDevice9,
-    textures: Vec<Texture>,
+    textures: Vec<Option<Texture>>,
+    refs: TextureRefs,
 }
 
 impl TextureHeap {
     fn new(device: &IDirect3DDevice9) -> Result<Self> {
-        Ok(Self { device: device.clone(), textures: Vec::new() })
+        Ok(Self { device: device.clone(), textures: Vec::new(), refs: TextureRefs::default() })
+    }
+
+    fn get(&self, texture_id: TextureId) -> Option<&Texture> {
+        self.textures.get(texture_id.id()).and_then(Option::as_ref)
     }
 
-    fn get(&self, texture_id: TextureId) -> &IDirect3DTexture9 {
-        &self.textures[texture_id.id()].resource
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.textures[texture_id.id()] = None;
+        }
+
+        Ok(())
     }
 
-    unsafe fn create_texture(&mut self, width: u32, height: u32) -> Result<TextureId> {
+    // Free all the textures. Their ids are kept, and refer to no texture from
+    // then on.
+    fn release_all(&mut self) {
+        self.textures.iter_mut().for_each(|texture| *texture = None);
+    }
+
+    // Free the textures whose last handle has been dropped since the last frame.
+    fn free_released(&mut self) {
+        for texture_id in self.refs.drain_released() {
+            self.textures[texture_id.id()] = None;
+        }
+    }
+
+    unsafe fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        let mip_levels = if options.mipmaps { 1 + width.max(height).max(1).ilog2() } else { 1 };
         let resource = util::try_out_ptr(|v| {
             self.device.CreateTexture(
                 width,
                 height,
-                1,
+                mip_levels,
                 D3DUSAGE_DYNAMIC as u32,
                 D3DFMT_A8R8G8B8,
                 D3DPOOL_DEFAULT,
@@ -398,19 +617,57 @@ impl TextureHeap {
         })?;
 
         let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource, id, width, height });
+        self.textures.push(Some(Texture { resource, id, width, height, options }));
+        self.refs.insert(id);
 
         Ok(id)
     }
 
+    unsafe fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        let resource: IDirect3DTexture9 = util::try_out_ptr(|v| {
+            self.device.CreateTexture(
+                width,
+                height,
+                1,
+                D3DUSAGE_RENDERTARGET as u32,
+                D3DFMT_A8R8G8B8,
+                D3DPOOL_DEFAULT,
+                v,
+                ptr::null_mut(),
+            )
+        })?;
+        let surface = resource.GetSurfaceLevel(0)?;
+        self.device.ColorFill(&surface, ptr::null(), 0)?;
+
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some(Texture {
+            resource: resource.clone(),
+            id,
+            width,
+            height,
+            options: TextureOptions::default(),
+        }));
+        self.refs.insert(id);
+
+        Ok((id, RenderTargetHandle::D3D9 { texture: resource, surface }))
+    }
+
     unsafe fn upload_texture(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        let texture = &self.textures[texture_id.id()];
+        let Some(texture) = self.get(texture_id) else {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
         if texture.width != width || texture.height != height {
             error!(
                 "image size {width}x{height} do not match expected {}x{}",
@@ -418,28 +675,43 @@ impl TextureHeap {
             );
             return Err(Error::from_hresult(HRESULT(-1)));
         }
+        if texture.options.format != format {
+            error!(
+                "texture {texture_id:?} was loaded as {:?}, not {format:?}",
+                texture.options.format
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
 
-        let mut r: D3DLOCKED_RECT = Default::default();
-        texture.resource.LockRect(0, &mut r, ptr::null_mut(), 0)?;
-
-        let bits = r.pBits as *mut u8;
-        let pitch = r.Pitch as usize;
-        let height = height as usize;
-        let width = width as usize;
-
-        // CPU swizzle FTW
-        for y in 0..height {
-            for x in 0..width {
-                let offset_dest = pitch * y + x * 4;
-                let offset_src = width * 4 * y + x * 4;
-                *bits.add(offset_dest) = data[offset_src + 2];
-                *bits.add(offset_dest + 1) = data[offset_src + 1];
-                *bits.add(offset_dest + 2) = data[offset_src];
-                *bits.add(offset_dest + 3) = data[offset_src + 3];
+        // CPU swizzle FTW. `D3DFMT_A8R8G8B8` is laid out as BGRA in memory.
+        let bgra: Vec<u8> = data
+            .chunks_exact(format.bytes_per_pixel())
+            .flat_map(|src| match format {
+                PixelFormat::Rgba8 => [src[2], src[1], src[0], src[3]],
+                PixelFormat::Bgra8 => [src[0], src[1], src[2], src[3]],
+                PixelFormat::R8 => [src[0], src[0], src[0], 0xff],
+            })
+            .collect();
+        let mips =
+            if texture.options.mipmaps { mip_chain(&bgra, width, height, 4) } else { Vec::new() };
+
+        for (level, (data, width, height)) in [(&bgra[..], width, height)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width, mip.height)))
+            .enumerate()
+        {
+            let mut r: D3DLOCKED_RECT = Default::default();
+            texture.resource.LockRect(level as u32, &mut r, ptr::null_mut(), 0)?;
+
+            let bits = r.pBits as *mut u8;
+            let pitch = r.Pitch as usize;
+            let row = width as usize * 4;
+            for y in 0..height as usize {
+                ptr::copy_nonoverlapping(data[row * y..].as_ptr(), bits.add(pitch * y), row);
             }
-        }
 
-        texture.resource.UnlockRect(0)?;
+            texture.resource.UnlockRect(level as u32)?;
+        }
 
         Ok(())
     }
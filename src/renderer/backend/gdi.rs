@@ -0,0 +1,508 @@
+//! A software renderer that draws with GDI, for when no other backend fits.
+//!
+//! Frames are rasterized on the CPU into a bitmap, which is then blended
+//! onto the window. This is slow, and meant for debugging and 2D titles.
+
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+use std::{mem, ptr, slice};
+
+use imgui::internal::RawWrapper;
+use imgui::{Context, DrawCmd, DrawData, DrawVert, TextureId};
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Graphics::Gdi::{
+    AlphaBlend, BitBlt, CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, GetDC,
+    ReleaseDC, SelectObject, AC_SRC_ALPHA, AC_SRC_OVER, BITMAPINFO, BITMAPINFOHEADER, BI_RGB,
+    BLENDFUNCTION, DIB_RGB_COLORS, HBITMAP, HDC, HGDIOBJ, SRCCOPY,
+};
+
+use crate::renderer::backend::{build_font_atlas, convert_to_rgba8, pack_rgba8, PixelOrder};
+use crate::renderer::texture::TextureRefs;
+use crate::renderer::RenderEngine;
+use crate::{util, PixelFormat, RenderContext, TextureHandle, TextureOptions};
+
+// Rasterizing is expensive, so the same frame is blended onto the window
+// until it's this old.
+const RASTERIZE_INTERVAL: Duration = Duration::from_millis(33);
+
+// Keep the atlas small, as it's sampled on the CPU.
+const MAX_TEXTURE_SIZE: u32 = 4096;
+
+pub struct GdiRenderEngine {
+    hwnd: HWND,
+    textures: Vec<Option<Texture>>,
+    refs: TextureRefs,
+    bitmap: Option<Bitmap>,
+    last_rasterized: Option<Instant>,
+}
+
+impl GdiRenderEngine {
+    pub fn new(hwnd: HWND, ctx: &mut Context) -> Result<Self> {
+        ctx.set_ini_filename(None);
+        ctx.set_renderer_name(String::from(concat!("hudhook-gdi@", env!("CARGO_PKG_VERSION"))));
+
+        Ok(Self {
+            hwnd,
+            textures: Vec::new(),
+            refs: TextureRefs::default(),
+            bitmap: None,
+            last_rasterized: None,
+        })
+    }
+}
+
+impl RenderContext for GdiRenderEngine {
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        let texture = Texture::new(data, width, height, format)?;
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some(texture));
+        self.refs.insert(id);
+        Ok(id)
+    }
+
+    // Textures are drawn texel by texel, so there's nothing to filter.
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        self.load_texture_with_format(data, width, height, options.format)
+    }
+
+    fn replace_texture_with_format(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<()> {
+        let Some(Some(texture)) = self.textures.get_mut(texture_id.id()) else {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        if texture.width != width || texture.height != height {
+            error!(
+                "image size {width}x{height} do not match expected {}x{}",
+                texture.width, texture.height
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        if texture.format != format {
+            error!("texture {texture_id:?} was loaded as {:?}, not {format:?}", texture.format);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        *texture = Texture::new(data, width, height, format)?;
+        Ok(())
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.textures[texture_id.id()] = None;
+        }
+
+        Ok(())
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        match self.textures.get(texture_id.id()) {
+            Some(Some(texture)) => Some([texture.width as f32, texture.height as f32]),
+            _ => None,
+        }
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        unsafe { self.capture_window() }
+    }
+}
+
+impl RenderEngine for GdiRenderEngine {
+    type RenderTarget = HDC;
+
+    fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()> {
+        for texture_id in self.refs.drain_released() {
+            self.textures[texture_id.id()] = None;
+        }
+
+        let [width, height] = draw_data.display_size.map(|size| size as u32);
+        if self.bitmap.as_ref().map(|bitmap| (bitmap.width, bitmap.height)) != Some((width, height))
+        {
+            self.bitmap = None;
+            self.bitmap = Some(unsafe { Bitmap::new(width, height) }?);
+            self.last_rasterized = None;
+        }
+
+        let stale = match self.last_rasterized {
+            Some(last) => last.elapsed() >= RASTERIZE_INTERVAL,
+            None => true,
+        };
+        if stale {
+            unsafe { self.rasterize(draw_data) };
+            self.last_rasterized = Some(Instant::now());
+        }
+
+        let Some(bitmap) = &self.bitmap else {
+            return Ok(());
+        };
+
+        let blended = unsafe {
+            AlphaBlend(
+                render_target,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                bitmap.dc,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                BLENDFUNCTION {
+                    BlendOp: AC_SRC_OVER as u8,
+                    BlendFlags: 0,
+                    SourceConstantAlpha: 255,
+                    AlphaFormat: AC_SRC_ALPHA as u8,
+                },
+            )
+        };
+
+        if !blended.as_bool() {
+            error!("Could not blend the overlay onto the window");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        Ok(())
+    }
+
+    fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
+        let fonts = ctx.fonts();
+        let fonts_texture = build_font_atlas(fonts, MAX_TEXTURE_SIZE)?;
+        fonts.tex_id =
+            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        Ok(())
+    }
+}
+
+impl GdiRenderEngine {
+    unsafe fn rasterize(&mut self, draw_data: &DrawData) {
+        let Some(bitmap) = &mut self.bitmap else {
+            return;
+        };
+
+        let pixels = bitmap.pixels();
+        pixels.fill(0);
+
+        let [width, height] = [bitmap.width as i32, bitmap.height as i32];
+        let [x, y] = draw_data.display_pos;
+
+        for cl in draw_data.draw_lists() {
+            let vtx_buffer = cl.vtx_buffer();
+            let idx_buffer = cl.idx_buffer();
+
+            for cmd in cl.commands() {
+                match cmd {
+                    DrawCmd::Elements { count, cmd_params } => {
+                        let Some(Some(texture)) = self.textures.get(cmd_params.texture_id.id())
+                        else {
+                            continue;
+                        };
+
+                        let [cx, cy, cw, ch] = cmd_params.clip_rect;
+                        let clip = [
+                            ((cx - x) as i32).max(0),
+                            ((cy - y) as i32).max(0),
+                            ((cw - x) as i32).min(width),
+                            ((ch - y) as i32).min(height),
+                        ];
+
+                        let indices =
+                            &idx_buffer[cmd_params.idx_offset..cmd_params.idx_offset + count];
+                        for triangle in indices.chunks_exact(3) {
+                            let vertex = |i: usize| &vtx_buffer[cmd_params.vtx_offset + i];
+                            fill_triangle(
+                                pixels,
+                                width,
+                                clip,
+                                [x, y],
+                                [
+                                    vertex(triangle[0] as usize),
+                                    vertex(triangle[1] as usize),
+                                    vertex(triangle[2] as usize),
+                                ],
+                                texture,
+                            );
+                        }
+                    },
+                    DrawCmd::ResetRenderState => {},
+                    DrawCmd::RawCallback { callback, raw_cmd } => callback(cl.raw(), raw_cmd),
+                }
+            }
+        }
+    }
+
+    // Copy the client area of the window, overlay included if it has been
+    // drawn already.
+    unsafe fn capture_window(&self) -> Result<(u32, u32, Vec<u8>)> {
+        let (width, height) = util::win_size(self.hwnd);
+        let bitmap = Bitmap::new(width as u32, height as u32)?;
+
+        let window_dc = GetDC(self.hwnd);
+        let copied = BitBlt(bitmap.dc, 0, 0, width, height, window_dc, 0, 0, SRCCOPY);
+        ReleaseDC(self.hwnd, window_dc);
+        copied?;
+
+        let pixels = pack_rgba8(
+            bitmap.bits as *const u8,
+            width as usize * 4,
+            width as u32,
+            height as u32,
+            PixelOrder::Bgrx,
+        );
+
+        Ok((width as u32, height as u32, pixels))
+    }
+}
+
+struct Texture {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    // Always RGBA8, whatever the format it was loaded from.
+    data: Vec<u8>,
+}
+
+impl Texture {
+    fn new(data: &[u8], width: u32, height: u32, format: PixelFormat) -> Result<Self> {
+        // There would be no texel to sample.
+        if width == 0 || height == 0 {
+            error!("texture size {width}x{height} is empty");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        if width > MAX_TEXTURE_SIZE || height > MAX_TEXTURE_SIZE {
+            error!("texture size {width}x{height} exceeds the maximum {MAX_TEXTURE_SIZE}");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let len = width as usize * height as usize * format.bytes_per_pixel();
+        if data.len() < len {
+            error!("texture data is {} bytes, expected {len}", data.len());
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            format,
+            data: convert_to_rgba8(&data[..len], format).into_owned(),
+        })
+    }
+
+    // Nearest neighbour sampling, clamped to the edges.
+    fn sample(&self, [u, v]: [f32; 2]) -> [f32; 4] {
+        let x = ((u * self.width as f32) as i32).clamp(0, self.width as i32 - 1) as usize;
+        let y = ((v * self.height as f32) as i32).clamp(0, self.height as i32 - 1) as usize;
+        let offset = (y * self.width as usize + x) * 4;
+        let texel = &self.data[offset..offset + 4];
+        [texel[0], texel[1], texel[2], texel[3]].map(|c| c as f32 / 255.)
+    }
+}
+
+// A top-down, 32-bit DIB section selected into its own memory DC. The pixels
+// are premultiplied BGRA, as `AlphaBlend` and `UpdateLayeredWindow` expect.
+pub(crate) struct Bitmap {
+    pub(crate) dc: HDC,
+    bitmap: HBITMAP,
+    old_bitmap: HGDIOBJ,
+    bits: *mut u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Bitmap {
+    pub(crate) unsafe fn new(width: u32, height: u32) -> Result<Self> {
+        let dc = CreateCompatibleDC(None);
+        if dc.is_invalid() {
+            error!("Could not create a memory DC");
+            return Err(Error::from_win32());
+        }
+
+        let info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: mem::size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: width as i32,
+                biHeight: -(height as i32),
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut bits: *mut c_void = ptr::null_mut();
+        let bitmap = match CreateDIBSection(dc, &info, DIB_RGB_COLORS, &mut bits, None, 0) {
+            Ok(bitmap) => bitmap,
+            Err(e) => {
+                DeleteDC(dc);
+                return Err(e);
+            },
+        };
+        let old_bitmap = SelectObject(dc, HGDIOBJ(bitmap.0));
+
+        Ok(Self { dc, bitmap, old_bitmap, bits: bits as *mut u32, width, height })
+    }
+
+    pub(crate) unsafe fn pixels(&mut self) -> &mut [u32] {
+        slice::from_raw_parts_mut(self.bits, self.width as usize * self.height as usize)
+    }
+}
+
+impl Drop for Bitmap {
+    fn drop(&mut self) {
+        unsafe {
+            SelectObject(self.dc, self.old_bitmap);
+            DeleteObject(HGDIOBJ(self.bitmap.0));
+            DeleteDC(self.dc);
+        }
+    }
+}
+
+// Twice the signed area of the triangle `abc`, positive if `c` is on the
+// right of `ab` on screen.
+fn edge(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+// Fill the pixels whose center is within the triangle, sampling the texture
+// modulated by the interpolated vertex colors, and blend them over the
+// premultiplied BGRA pixels. Pixels on an edge shared by two triangles are
+// only filled once, following the usual top-left rule, not to blend the
+// diagonals of quads twice.
+fn fill_triangle(
+    pixels: &mut [u32],
+    width: i32,
+    [clip_left, clip_top, clip_right, clip_bottom]: [i32; 4],
+    [x, y]: [f32; 2],
+    vertices: [&DrawVert; 3],
+    texture: &Texture,
+) {
+    let mut vertices = vertices;
+    let mut p = vertices.map(|v| [v.pos[0] - x, v.pos[1] - y]);
+    let mut area = edge(p[0], p[1], p[2]);
+    if area == 0. {
+        return;
+    }
+    if area < 0. {
+        vertices.swap(1, 2);
+        p.swap(1, 2);
+        area = -area;
+    }
+
+    let top_left = |a: [f32; 2], b: [f32; 2]| b[1] < a[1] || (b[1] == a[1] && b[0] > a[0]);
+    let edges = [(p[1], p[2]), (p[2], p[0]), (p[0], p[1])];
+    let inclusive = edges.map(|(a, b)| top_left(a, b));
+
+    let left = p.iter().map(|p| p[0]).fold(f32::MAX, f32::min).floor().max(clip_left as f32);
+    let top = p.iter().map(|p| p[1]).fold(f32::MAX, f32::min).floor().max(clip_top as f32);
+    let right = p.iter().map(|p| p[0]).fold(f32::MIN, f32::max).ceil().min(clip_right as f32);
+    let bottom = p.iter().map(|p| p[1]).fold(f32::MIN, f32::max).ceil().min(clip_bottom as f32);
+
+    for py in top as i32..bottom as i32 {
+        for px in left as i32..right as i32 {
+            let center = [px as f32 + 0.5, py as f32 + 0.5];
+            let mut weights = [0f32; 3];
+            let mut inside = true;
+            for (i, &(a, b)) in edges.iter().enumerate() {
+                let w = edge(a, b, center);
+                inside &= w > 0. || (w == 0. && inclusive[i]);
+                weights[i] = w / area;
+            }
+            if !inside {
+                continue;
+            }
+
+            let interpolate = |f: &dyn Fn(&DrawVert) -> f32| {
+                weights.iter().zip(vertices).map(|(w, v)| w * f(v)).sum::<f32>()
+            };
+
+            let uv = [interpolate(&|v| v.uv[0]), interpolate(&|v| v.uv[1])];
+            let texel = texture.sample(uv);
+            let [r, g, b, a] =
+                [0, 1, 2, 3].map(|c| interpolate(&|v| v.col[c] as f32) / 255. * texel[c]);
+            if a <= 0. {
+                continue;
+            }
+
+            let pixel = &mut pixels[(py * width + px) as usize];
+            let [db, dg, dr, da] = pixel.to_le_bytes().map(|c| c as f32 / 255.);
+            let blend =
+                |src: f32, dst: f32| ((src + dst * (1. - a)) * 255.).round().min(255.) as u8;
+            *pixel = u32::from_le_bytes([
+                blend(b * a, db),
+                blend(g * a, dg),
+                blend(r * a, dr),
+                blend(a, da),
+            ]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(pos: [f32; 2], col: [u8; 4]) -> DrawVert {
+        DrawVert { pos, uv: [0., 0.], col }
+    }
+
+    #[test]
+    fn test_fill_triangle() {
+        let texture = Texture::new(&[0xff; 4], 1, 1, PixelFormat::Rgba8).unwrap();
+        let mut pixels = vec![0u32; 16];
+
+        // A quad covering the middle 2x2 pixels of a 4x4 bitmap, split along
+        // its diagonal, in half transparent red.
+        let red = [0xff, 0, 0, 0x80];
+        let [a, b, c, d] = [[1., 1.], [3., 1.], [3., 3.], [1., 3.]].map(|pos| vertex(pos, red));
+        fill_triangle(&mut pixels, 4, [0, 0, 4, 4], [0., 0.], [&a, &b, &c], &texture);
+        fill_triangle(&mut pixels, 4, [0, 0, 4, 4], [0., 0.], [&a, &c, &d], &texture);
+
+        // The diagonal isn't blended twice.
+        let expected = u32::from_le_bytes([0, 0, 0x80, 0x80]);
+        for y in 0..4 {
+            for x in 0..4 {
+                let inside = (1..3).contains(&x) && (1..3).contains(&y);
+                assert_eq!(pixels[y * 4 + x], if inside { expected } else { 0 }, "({x}, {y})");
+            }
+        }
+
+        // Clipped pixels are left alone.
+        let mut pixels = vec![0u32; 16];
+        fill_triangle(&mut pixels, 4, [0, 0, 2, 4], [0., 0.], [&a, &b, &c], &texture);
+        assert_eq!(pixels[4 + 2], 0);
+        assert_eq!(pixels[8 + 2], 0);
+    }
+
+    #[test]
+    fn test_empty_texture() {
+        assert!(Texture::new(&[], 0, 0, PixelFormat::Rgba8).is_err());
+        assert!(Texture::new(&[0xff; 4], 1, 0, PixelFormat::Rgba8).is_err());
+    }
+}
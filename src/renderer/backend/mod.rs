@@ -1,8 +1,352 @@
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "gdi", feature = "opengl3"))]
+use std::borrow::Cow;
+
+use imgui::{FontAtlas, FontAtlasTexture};
+use tracing::error;
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use windows::core::{s, PCSTR};
+use windows::core::{Error, Result, HRESULT};
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Direct3D::ID3DBlob;
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "gdi", feature = "opengl3"))]
+use crate::PixelFormat;
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+use crate::{util, HudhookError};
+
+#[cfg(feature = "dx10")]
+pub mod dx10;
 #[cfg(feature = "dx11")]
 pub mod dx11;
 #[cfg(feature = "dx12")]
 pub mod dx12;
 #[cfg(feature = "dx9")]
 pub mod dx9;
+#[cfg(feature = "gdi")]
+pub mod gdi;
+#[cfg(test)]
+pub mod null;
 #[cfg(feature = "opengl3")]
 pub mod opengl3;
+
+// Width above which imgui never grows the font atlas on its own.
+const DEFAULT_ATLAS_MAX_WIDTH: u32 = 4096;
+
+/// Build the font atlas, making sure it fits in a texture of at most
+/// `max_texture_size` pixels per side.
+///
+/// The atlas width is clamped to the maximum size. If the glyphs still don't
+/// fit, an error is returned instead of attempting the upload.
+pub(crate) fn build_font_atlas(
+    fonts: &mut FontAtlas,
+    max_texture_size: u32,
+) -> Result<FontAtlasTexture<'_>> {
+    let desired_width = fonts.tex_desired_width.max(0) as u32;
+    if desired_width > max_texture_size
+        || (desired_width == 0 && max_texture_size < DEFAULT_ATLAS_MAX_WIDTH)
+    {
+        fonts.tex_desired_width = max_texture_size as i32;
+    }
+
+    let fonts_texture = fonts.build_rgba32_texture();
+    if fonts_texture.width > max_texture_size || fonts_texture.height > max_texture_size {
+        error!(
+            "Font atlas is {}x{}, but the renderer only supports textures up to {}x{}. Try reducing \
+             the font sizes or the glyph ranges.",
+            fonts_texture.width,
+            fonts_texture.height,
+            max_texture_size,
+            max_texture_size
+        );
+        return Err(Error::from_hresult(HRESULT(-1)));
+    }
+
+    Ok(fonts_texture)
+}
+
+/// Channel order of the 4-byte pixels read back from a render target.
+#[cfg(any(feature = "dx9", feature = "dx10", feature = "dx11", feature = "dx12", feature = "gdi"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PixelOrder {
+    Rgba,
+    Bgra,
+    /// BGRA with an undefined alpha channel, read back as opaque.
+    Bgrx,
+}
+
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+impl PixelOrder {
+    /// The pixel order of a DXGI back buffer format, or an error if reading
+    /// it back as RGBA8 isn't supported.
+    pub(crate) fn from_dxgi(format: DXGI_FORMAT) -> Result<Self> {
+        match format {
+            DXGI_FORMAT_R8G8B8A8_UNORM | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => Ok(PixelOrder::Rgba),
+            DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => Ok(PixelOrder::Bgra),
+            DXGI_FORMAT_B8G8R8X8_UNORM | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => Ok(PixelOrder::Bgrx),
+            format => {
+                error!("Can't capture a screenshot of a back buffer of format {format:?}");
+                Err(Error::from_hresult(HRESULT(-1)))
+            },
+        }
+    }
+}
+
+/// Copy `height` rows of `width` pixels in `order` into tightly-packed RGBA8.
+/// The rows start `row_pitch` bytes apart, which readback resources usually
+/// pad beyond `width * 4`, e.g. to a multiple of 256 bytes on DirectX 12.
+///
+/// # Safety
+///
+/// `data` must point to at least `height` rows of `row_pitch` bytes.
+#[cfg(any(feature = "dx9", feature = "dx10", feature = "dx11", feature = "dx12", feature = "gdi"))]
+pub(crate) unsafe fn pack_rgba8(
+    data: *const u8,
+    row_pitch: usize,
+    width: u32,
+    height: u32,
+    order: PixelOrder,
+) -> Vec<u8> {
+    let row_len = width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * height as usize);
+
+    for y in 0..height as usize {
+        let row = std::slice::from_raw_parts(data.add(y * row_pitch), row_len);
+        match order {
+            PixelOrder::Rgba => pixels.extend_from_slice(row),
+            PixelOrder::Bgra => pixels
+                .extend(row.chunks_exact(4).flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])),
+            PixelOrder::Bgrx => pixels
+                .extend(row.chunks_exact(4).flat_map(|bgrx| [bgrx[2], bgrx[1], bgrx[0], 0xff])),
+        }
+    }
+
+    pixels
+}
+
+/// Convert tightly-packed pixels in `format` to RGBA8, for the renderers that
+/// can't sample `format` natively. RGBA8 data is borrowed as is.
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "gdi", feature = "opengl3"))]
+pub(crate) fn convert_to_rgba8(data: &[u8], format: PixelFormat) -> Cow<'_, [u8]> {
+    match format {
+        PixelFormat::Rgba8 => Cow::Borrowed(data),
+        PixelFormat::Bgra8 => Cow::Owned(
+            data.chunks_exact(4).flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]]).collect(),
+        ),
+        PixelFormat::R8 => Cow::Owned(data.iter().flat_map(|&r| [r, r, r, 0xff]).collect()),
+    }
+}
+
+/// A level of a mip chain, in the layout of the level it was downscaled from.
+#[cfg(any(
+    feature = "dx9",
+    feature = "dx10",
+    feature = "dx11",
+    feature = "dx12",
+    feature = "opengl3"
+))]
+pub(crate) struct MipLevel {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Downscale `width * height` tightly packed pixels of `bytes_per_pixel`
+/// bytes into the levels of a mip chain below them, halving the size each time
+/// down to 1x1.
+///
+/// 4-byte pixels are taken to have straight alpha last, and their colors are
+/// weighted by it so that transparent pixels don't bleed into their
+/// neighbors.
+#[cfg(any(
+    feature = "dx9",
+    feature = "dx10",
+    feature = "dx11",
+    feature = "dx12",
+    feature = "opengl3"
+))]
+pub(crate) fn mip_chain(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bytes_per_pixel: usize,
+) -> Vec<MipLevel> {
+    let mut levels: Vec<MipLevel> = Vec::new();
+    let (mut w, mut h) = (width.max(1), height.max(1));
+
+    while w > 1 || h > 1 {
+        let src = levels.last().map_or(data, |level| &level.data[..]);
+        let (next_w, next_h) = ((w / 2).max(1), (h / 2).max(1));
+        let texel = |x: u32, y: u32| {
+            let offset = (y.min(h - 1) * w + x.min(w - 1)) as usize * bytes_per_pixel;
+            &src[offset..offset + bytes_per_pixel]
+        };
+
+        let mut next = Vec::with_capacity(next_w as usize * next_h as usize * bytes_per_pixel);
+        for y in 0..next_h {
+            for x in 0..next_w {
+                let texels = [
+                    texel(2 * x, 2 * y),
+                    texel(2 * x + 1, 2 * y),
+                    texel(2 * x, 2 * y + 1),
+                    texel(2 * x + 1, 2 * y + 1),
+                ];
+                let sum = |channel: usize, weighted: bool| -> u32 {
+                    texels
+                        .iter()
+                        .map(|t| t[channel] as u32 * if weighted { t[3] as u32 } else { 1 })
+                        .sum()
+                };
+
+                if bytes_per_pixel == 4 {
+                    let alpha = sum(3, false);
+                    for channel in 0..3 {
+                        next.push(if alpha == 0 {
+                            ((sum(channel, false) + 2) / 4) as u8
+                        } else {
+                            ((sum(channel, true) + alpha / 2) / alpha) as u8
+                        });
+                    }
+                    next.push(((alpha + 2) / 4) as u8);
+                } else {
+                    next.extend((0..bytes_per_pixel).map(|c| ((sum(c, false) + 2) / 4) as u8));
+                }
+            }
+        }
+
+        levels.push(MipLevel { width: next_w, height: next_h, data: next });
+        (w, h) = (next_w, next_h);
+    }
+
+    levels
+}
+
+/// Compiled bytecode of a vertex and pixel shader pair.
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+pub(crate) struct ShaderBytecode {
+    pub(crate) vertex: Vec<u8>,
+    pub(crate) pixel: Vec<u8>,
+}
+
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+impl ShaderBytecode {
+    /// Compile the `main` entry points of the HLSL sources for the given
+    /// shader model targets, e.g. `vs_5_0` and `ps_5_0`.
+    pub(crate) fn compile(
+        vertex_src: &str,
+        vertex_target: PCSTR,
+        pixel_src: &str,
+        pixel_target: PCSTR,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            vertex: compile_shader(vertex_src, vertex_target, "Compiling vertex shader")?,
+            pixel: compile_shader(pixel_src, pixel_target, "Compiling pixel shader")?,
+        })
+    }
+}
+
+#[cfg(any(feature = "dx10", feature = "dx11", feature = "dx12"))]
+fn compile_shader(src: &str, target: PCSTR, msg: &str) -> crate::Result<Vec<u8>> {
+    let blob: ID3DBlob = util::try_out_err_blob(|v, err_blob| unsafe {
+        D3DCompile(
+            src.as_ptr() as _,
+            src.len(),
+            None,
+            None,
+            None,
+            s!("main\0"),
+            target,
+            0,
+            0,
+            v,
+            Some(err_blob),
+        )
+    })
+    .map_err(util::print_error_blob(msg))
+    .map_err(|e| HudhookError::ShaderCompile(format!("{msg}: {e}")))?;
+
+    Ok(unsafe {
+        std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+            .to_vec()
+    })
+}
+
+#[cfg(test)]
+#[cfg(any(
+    feature = "dx9",
+    feature = "dx10",
+    feature = "dx11",
+    feature = "dx12",
+    feature = "gdi",
+    feature = "opengl3"
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(any(feature = "dx10", feature = "dx11", feature = "gdi", feature = "opengl3"))]
+    fn test_convert_to_rgba8() {
+        let rgba = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert!(matches!(convert_to_rgba8(&rgba, PixelFormat::Rgba8), Cow::Borrowed(_)));
+        assert_eq!(*convert_to_rgba8(&rgba, PixelFormat::Bgra8), [3, 2, 1, 4, 7, 6, 5, 8]);
+        assert_eq!(*convert_to_rgba8(&[9, 10], PixelFormat::R8), [9, 9, 9, 255, 10, 10, 10, 255]);
+    }
+
+    #[test]
+    #[cfg(any(
+        feature = "dx9",
+        feature = "dx10",
+        feature = "dx11",
+        feature = "dx12",
+        feature = "opengl3"
+    ))]
+    fn test_mip_chain() {
+        // A 3x2 texture with a transparent texel, whose color must not bleed.
+        #[rustfmt::skip]
+        let rgba = [
+            200, 0, 0, 255,   0, 0, 0, 0,   0, 0, 100, 255,
+            200, 0, 0, 255,   200, 0, 0, 255,   0, 0, 100, 255,
+        ];
+
+        let levels = mip_chain(&rgba, 3, 2, 4);
+        let sizes: Vec<_> = levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, [(1, 1)]);
+        assert_eq!(levels[0].data, [200, 0, 0, 191]);
+
+        let levels = mip_chain(&[0; 64 * 16], 64, 16, 1);
+        let sizes: Vec<_> = levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, [(32, 8), (16, 4), (8, 2), (4, 1), (2, 1), (1, 1)]);
+
+        assert_eq!(mip_chain(&[10, 20, 30, 41], 2, 2, 1)[0].data, [25]);
+        assert!(mip_chain(&[1, 2, 3, 4], 1, 1, 4).is_empty());
+    }
+
+    #[test]
+    #[cfg(any(
+        feature = "dx9",
+        feature = "dx10",
+        feature = "dx11",
+        feature = "dx12",
+        feature = "gdi"
+    ))]
+    fn test_pack_rgba8() {
+        // Two rows of two pixels, padded to 12 bytes.
+        let data: Vec<u8> =
+            [[1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0], [9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0]]
+                .concat();
+
+        let packed = |order| unsafe { pack_rgba8(data.as_ptr(), 12, 2, 2, order) };
+        assert_eq!(packed(PixelOrder::Rgba), (1..=16).collect::<Vec<u8>>());
+        assert_eq!(
+            packed(PixelOrder::Bgra),
+            [3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]
+        );
+        assert_eq!(
+            packed(PixelOrder::Bgrx),
+            [3, 2, 1, 255, 7, 6, 5, 255, 11, 10, 9, 255, 15, 14, 13, 255]
+        );
+    }
+}
@@ -0,0 +1,1938 @@
+//! A render engine that never touches the GPU.
+//!
+//! [`NullRenderEngine`] implements [`RenderEngine`] by recording a summary of
+//! the [`DrawData`] it receives instead of submitting it. It is meant to drive
+//! a full [`Pipeline`](crate::renderer::Pipeline) in unit tests: window
+//! messages, [`ImguiRenderLoop`](crate::ImguiRenderLoop) callbacks and
+//! visibility toggles all go through the same code paths as the real backends,
+//! and the test can then assert on the recorded frames.
+//!
+//! ```ignore
+//! let dummy_hwnd = DummyHwnd::new();
+//! let mut ctx = Context::create();
+//! let engine = NullRenderEngine::new(&mut ctx);
+//! let frames = engine.frames();
+//!
+//! let mut pipeline = Pipeline::new(dummy_hwnd.hwnd(), ctx, engine, render_loop)?;
+//! pipeline.prepare_render()?;
+//! pipeline.render(())?;
+//!
+//! assert!(!frames.lock()[0].draw_cmds.is_empty());
+//! ```
+//!
+//! Only one imgui context can be active at a time, so the tests here set this
+//! up through `with_pipeline`, which takes turns with the other tests.
+
+use std::sync::Arc;
+
+use imgui::internal::RawWrapper;
+use imgui::{Context, DrawCmd, DrawData, TextureId};
+use parking_lot::Mutex;
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+
+use crate::renderer::backend::build_font_atlas;
+use crate::renderer::texture::TextureRefs;
+use crate::renderer::RenderEngine;
+use crate::{PixelFormat, RenderContext, TextureHandle, TextureOptions};
+
+// Same as Direct3D 11 and 12.
+const DEFAULT_MAX_TEXTURE_SIZE: u32 = 16384;
+
+/// A single `DrawCmd::Elements` command, as seen by the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RecordedDrawCmd {
+    pub(crate) texture_id: TextureId,
+    pub(crate) clip_rect: [f32; 4],
+    pub(crate) count: usize,
+}
+
+/// The summary of one rendered frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RecordedFrame {
+    pub(crate) display_pos: [f32; 2],
+    pub(crate) display_size: [f32; 2],
+    pub(crate) draw_cmds: Vec<RecordedDrawCmd>,
+}
+
+pub(crate) struct NullRenderEngine {
+    textures: Vec<Option<(u32, u32)>>,
+    // Options each texture was loaded with, indexed by texture id.
+    options: Vec<TextureOptions>,
+    refs: TextureRefs,
+    frames: Arc<Mutex<Vec<RecordedFrame>>>,
+    max_texture_size: u32,
+}
+
+impl NullRenderEngine {
+    pub(crate) fn new(ctx: &mut Context) -> Self {
+        ctx.set_ini_filename(None);
+        ctx.set_renderer_name(String::from(concat!("hudhook-null@", env!("CARGO_PKG_VERSION"))));
+
+        Self {
+            textures: Vec::new(),
+            options: Vec::new(),
+            refs: TextureRefs::default(),
+            frames: Arc::new(Mutex::new(Vec::new())),
+            max_texture_size: DEFAULT_MAX_TEXTURE_SIZE,
+        }
+    }
+
+    /// Emulate a device that only supports textures up to `size` pixels per
+    /// side.
+    pub(crate) fn set_max_texture_size(&mut self, size: u32) {
+        self.max_texture_size = size;
+    }
+
+    fn max_texture_size(&self) -> u32 {
+        self.max_texture_size
+    }
+
+    /// Shared handle to the recorded frames. It stays valid after the engine
+    /// has been moved into a pipeline.
+    pub(crate) fn frames(&self) -> Arc<Mutex<Vec<RecordedFrame>>> {
+        Arc::clone(&self.frames)
+    }
+
+    /// Size of each texture loaded so far, indexed by texture id. Freed
+    /// textures are `None`.
+    pub(crate) fn textures(&self) -> &[Option<(u32, u32)>] {
+        &self.textures
+    }
+}
+
+// Like the real backends would read them, check that there are enough pixels.
+fn check_texture_data(data: &[u8], width: u32, height: u32, format: PixelFormat) -> Result<()> {
+    let len = width as usize * height as usize * format.bytes_per_pixel();
+    if data.len() < len {
+        error!("texture data is {} bytes, expected {len}", data.len());
+        return Err(Error::from_hresult(HRESULT(-1)));
+    }
+
+    Ok(())
+}
+
+impl RenderContext for NullRenderEngine {
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.load_texture_with_options(
+            data,
+            width,
+            height,
+            TextureOptions::default().with_format(format),
+        )
+    }
+
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        if width > self.max_texture_size || height > self.max_texture_size {
+            error!("texture size {width}x{height} exceeds the maximum {}", self.max_texture_size);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+        check_texture_data(data, width, height, options.format)?;
+
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some((width, height)));
+        self.options.push(options);
+        self.refs.insert(id);
+        Ok(id)
+    }
+
+    fn replace_texture_with_format(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<()> {
+        let Some(&Some((tex_width, tex_height))) = self.textures.get(texture_id.id()) else {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        if tex_width != width || tex_height != height {
+            error!("image size {width}x{height} do not match expected {tex_width}x{tex_height}");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let tex_format = self.options[texture_id.id()].format;
+        if tex_format != format {
+            error!("texture {texture_id:?} was loaded as {tex_format:?}, not {format:?}");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        check_texture_data(data, width, height, format)
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.textures[texture_id.id()] = None;
+        }
+
+        Ok(())
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        match self.textures.get(texture_id.id()) {
+            Some(&Some((width, height))) => Some([width as f32, height as f32]),
+            _ => None,
+        }
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        error!("the null engine has no back buffer to capture");
+        Err(Error::from_hresult(HRESULT(-1)))
+    }
+}
+
+impl RenderEngine for NullRenderEngine {
+    type RenderTarget = ();
+
+    fn render(&mut self, draw_data: &DrawData, _render_target: Self::RenderTarget) -> Result<()> {
+        for texture_id in self.refs.drain_released() {
+            self.textures[texture_id.id()] = None;
+        }
+
+        let mut draw_cmds = Vec::new();
+        for cl in draw_data.draw_lists() {
+            for cmd in cl.commands() {
+                match cmd {
+                    // Like the other backends, skip commands referring to freed textures.
+                    DrawCmd::Elements { count, cmd_params } => {
+                        if self
+                            .textures
+                            .get(cmd_params.texture_id.id())
+                            .is_some_and(Option::is_some)
+                        {
+                            draw_cmds.push(RecordedDrawCmd {
+                                texture_id: cmd_params.texture_id,
+                                clip_rect: cmd_params.clip_rect,
+                                count,
+                            });
+                        }
+                    },
+                    DrawCmd::ResetRenderState => {},
+                    DrawCmd::RawCallback { callback, raw_cmd } => unsafe {
+                        callback(cl.raw(), raw_cmd)
+                    },
+                }
+            }
+        }
+
+        self.frames.lock().push(RecordedFrame {
+            display_pos: draw_data.display_pos,
+            display_size: draw_data.display_size,
+            draw_cmds,
+        });
+
+        Ok(())
+    }
+
+    fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
+        let fonts = ctx.fonts();
+        let fonts_texture = build_font_atlas(fonts, self.max_texture_size())?;
+        fonts.tex_id =
+            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use imgui::{
+        Condition, ConfigFlags, FontConfig, FontId, FontSource, InputTextCallback,
+        InputTextCallbackHandler, Key, MouseButton,
+    };
+    use windows::core::w;
+    use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
+    use windows::Win32::UI::HiDpi::GetDpiForWindow;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{VK_F1, WA_INACTIVE};
+    use windows::Win32::UI::Input::XboxController::{XINPUT_GAMEPAD, XINPUT_GAMEPAD_A};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, GetWindowLongPtrW, PeekMessageW, PostMessageW, SendMessageW,
+        SetWindowTextW, GWLP_WNDPROC, MSG, PM_REMOVE, WM_ACTIVATE, WM_CHAR, WM_DPICHANGED,
+        WM_KEYDOWN, WM_KILLFOCUS, WM_LBUTTONDOWN,
+    };
+
+    use super::*;
+    use crate::hooks::DummyHwnd;
+    use crate::renderer::config::{
+        create_context, supply_context, InputMode, Layer, PipelineConfig,
+    };
+    use crate::renderer::input::{handle_gamepad, imgui_wnd_proc_impl};
+    use crate::renderer::pipeline::SESSION_LAYOUT;
+    use crate::renderer::Pipeline;
+    use crate::{
+        CaptureFlags, ImguiRenderLoop, Message, MessageFilter, NavMouseWarp, PresentAction,
+        StylePreset, WindowLimitAction, WndProcResult,
+    };
+
+    // imgui allows only one active context, so the tests that create one take
+    // turns.
+    static CONTEXT_LOCK: Mutex<()> = Mutex::new(());
+
+    // Run `f` with a new context and a null engine for it.
+    fn with_engine<R>(f: impl FnOnce(&mut Context, &mut NullRenderEngine) -> R) -> R {
+        let _lock = CONTEXT_LOCK.lock();
+        let mut ctx = Context::create();
+        let mut engine = NullRenderEngine::new(&mut ctx);
+        f(&mut ctx, &mut engine)
+    }
+
+    // Run `f` with a null engine pipeline on a dummy window, and the frames it
+    // records, then take the pipeline down.
+    fn with_pipeline<R>(
+        config: PipelineConfig,
+        render_loop: impl ImguiRenderLoop + Send + Sync + 'static,
+        f: impl FnOnce(&mut Pipeline<NullRenderEngine>, &Mutex<Vec<RecordedFrame>>) -> Result<R>,
+    ) -> Result<R> {
+        let dummy_hwnd = DummyHwnd::new();
+        with_pipeline_on(dummy_hwnd.hwnd(), config, render_loop, f)
+    }
+
+    // Same as `with_pipeline`, for tests that look at the window once the
+    // pipeline is gone.
+    fn with_pipeline_on<R>(
+        hwnd: HWND,
+        config: PipelineConfig,
+        render_loop: impl ImguiRenderLoop + Send + Sync + 'static,
+        f: impl FnOnce(&mut Pipeline<NullRenderEngine>, &Mutex<Vec<RecordedFrame>>) -> Result<R>,
+    ) -> Result<R> {
+        let _lock = CONTEXT_LOCK.lock();
+        let mut ctx = Context::create();
+        let engine = NullRenderEngine::new(&mut ctx);
+        let frames = engine.frames();
+
+        let mut pipeline = Pipeline::with_config(hwnd, ctx, engine, Box::new(render_loop), config)
+            .map_err(|(e, _)| e)?;
+        let result = f(&mut pipeline, &frames);
+        pipeline.take();
+
+        result
+    }
+
+    struct TestRenderLoop;
+
+    impl ImguiRenderLoop for TestRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test")
+                .position([8., 8.], Condition::Always)
+                .size([64., 64.], Condition::Always)
+                .build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_null_engine_records_frames() -> Result<()> {
+        with_pipeline(PipelineConfig::default(), TestRenderLoop, |pipeline, frames| {
+            for _ in 0..2 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+
+            let frames = frames.lock();
+            assert_eq!(frames.len(), 2);
+
+            let frame = &frames[1];
+            assert_eq!(frame.display_pos, [0., 0.]);
+            assert!(frame.display_size[0] > 0. && frame.display_size[1] > 0.);
+            assert!(!frame.draw_cmds.is_empty());
+            assert!(frame.draw_cmds.iter().all(|cmd| cmd.texture_id.id() == 0
+                && cmd.count > 0
+                && cmd.clip_rect[2] > cmd.clip_rect[0]));
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct TextureRenderLoop {
+        texture_id: Option<TextureId>,
+        handles: Arc<Mutex<Vec<TextureHandle>>>,
+    }
+
+    impl ImguiRenderLoop for TextureRenderLoop {
+        fn initialize<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            let texture_id = render_context.load_texture(&[0; 16], 2, 2).unwrap();
+            let first = render_context.texture_handle(texture_id).unwrap();
+            let second = first.clone();
+            render_context.unload_texture(texture_id).unwrap();
+
+            self.texture_id = Some(texture_id);
+            self.handles.lock().extend([first, second]);
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            let texture_id = self.texture_id.unwrap();
+            ui.window("Test")
+                .position([8., 8.], Condition::Always)
+                .size([64., 64.], Condition::Always)
+                .build(|| imgui::Image::new(texture_id, [16., 16.]).build(ui));
+        }
+    }
+
+    #[test]
+    fn test_null_engine_texture_refcount() -> Result<()> {
+        let render_loop = TextureRenderLoop::default();
+        let handles = Arc::clone(&render_loop.handles);
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            let texture_id = handles.lock()[0].id();
+            let draws_texture = |frame: &RecordedFrame| {
+                frame.draw_cmds.iter().any(|cmd| cmd.texture_id == texture_id)
+            };
+
+            // Dropping one of the two handles keeps the texture alive.
+            drop(handles.lock().remove(0));
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert!(draws_texture(&frames.lock()[0]));
+
+            // Dropping the last handle frees the texture before the next frame.
+            handles.lock().clear();
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert!(!draws_texture(&frames.lock()[1]));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_null_engine_textures() -> Result<()> {
+        with_engine(|_, engine| {
+            let tex_id = engine.load_texture(&[0; 16], 2, 2)?;
+            assert_eq!(engine.textures(), &[Some((2, 2))]);
+            assert_eq!(engine.texture_size(tex_id), Some([2., 2.]));
+            assert!(engine.replace_texture(tex_id, &[0; 16], 2, 2).is_ok());
+            assert!(engine.replace_texture(tex_id, &[0; 64], 4, 4).is_err());
+            assert!(engine.replace_texture(TextureId::from(1), &[0; 16], 2, 2).is_err());
+
+            // `unload_texture` also releases references held by handles.
+            let handle = engine.texture_handle(tex_id)?;
+            engine.unload_texture(tex_id)?;
+            assert_eq!(engine.textures(), &[Some((2, 2))]);
+            assert!(engine.unload_texture(tex_id).is_ok());
+            assert!(engine.unload_texture(tex_id).is_err());
+            assert_eq!(engine.textures(), &[None]);
+            assert!(engine.texture_handle(tex_id).is_err());
+            assert_eq!(engine.texture_size(tex_id), None);
+            drop(handle);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_null_engine_load_texture_handle() -> Result<()> {
+        with_engine(|_, engine| {
+            let handle = engine.load_texture_handle(&[0; 16], 2, 2)?;
+            let tex_id = handle.id();
+            assert_eq!(engine.textures(), &[Some((2, 2))]);
+
+            // The handles own the only references to the texture.
+            let clone = handle.clone();
+            drop(handle);
+            assert!(engine.refs.drain_released().is_empty());
+            drop(clone);
+            assert_eq!(engine.refs.drain_released(), vec![tex_id]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_null_engine_texture_formats() -> Result<()> {
+        with_engine(|_, engine| {
+            let mask_id = engine.load_texture_with_format(&[0; 4], 2, 2, PixelFormat::R8)?;
+            assert!(engine
+                .replace_texture_with_format(mask_id, &[0; 4], 2, 2, PixelFormat::R8)
+                .is_ok());
+            assert!(engine.replace_texture(mask_id, &[0; 16], 2, 2).is_err());
+
+            let capture_id = engine.load_texture_with_format(&[0; 16], 2, 2, PixelFormat::Bgra8)?;
+            assert!(engine
+                .replace_texture_with_format(capture_id, &[0; 4], 2, 2, PixelFormat::Bgra8)
+                .is_err());
+            assert!(engine.load_texture_with_format(&[0; 3], 2, 2, PixelFormat::R8).is_err());
+            assert_eq!(engine.textures(), &[Some((2, 2)), Some((2, 2))]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_null_engine_oversized_font_atlas() {
+        with_engine(|ctx, engine| {
+            engine.set_max_texture_size(256);
+
+            ctx.fonts().add_font(&[FontSource::DefaultFontData {
+                config: Some(FontConfig { size_pixels: 128., ..Default::default() }),
+            }]);
+
+            assert!(engine.setup_fonts(ctx).is_err());
+            assert!(engine.textures().is_empty());
+        })
+    }
+
+    #[test]
+    fn test_null_engine_font_atlas_width_is_clamped() -> Result<()> {
+        with_engine(|ctx, engine| {
+            engine.set_max_texture_size(1024);
+
+            engine.setup_fonts(ctx)?;
+            assert!(engine.textures().iter().flatten().all(|&(w, h)| w <= 1024 && h <= 1024));
+
+            Ok(())
+        })
+    }
+
+    // Index and rectangle of the widget focused by navigation.
+    type Focus = Option<(usize, [f32; 2], [f32; 2])>;
+
+    struct NavRenderLoop(Arc<Mutex<Focus>>);
+
+    impl ImguiRenderLoop for NavRenderLoop {
+        fn initialize<'a>(
+            &'a mut self,
+            ctx: &mut Context,
+            _render_context: &'a mut dyn RenderContext,
+        ) {
+            ctx.io_mut().config_flags |= ConfigFlags::NAV_ENABLE_KEYBOARD;
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Nav")
+                .position([8., 8.], Condition::Always)
+                .size([128., 128.], Condition::Always)
+                .build(|| {
+                    for (index, label) in ["First", "Second", "Third"].into_iter().enumerate() {
+                        ui.button(label);
+                        if ui.is_item_focused() {
+                            *self.0.lock() = Some((index, ui.item_rect_min(), ui.item_rect_max()));
+                        }
+                    }
+                });
+        }
+    }
+
+    fn nav_frame(pipeline: &mut Pipeline<NullRenderEngine>) -> Result<()> {
+        pipeline.prepare_render()?;
+        pipeline.render(())
+    }
+
+    // Navigate down twice and return the final mouse position alongside the
+    // focused widget.
+    fn navigate(nav_mouse_warp: NavMouseWarp) -> Result<([f32; 2], Focus)> {
+        let focus = Arc::new(Mutex::new(None));
+        let config = PipelineConfig { nav_mouse_warp, ..Default::default() };
+
+        let mouse_pos = with_pipeline(config, NavRenderLoop(Arc::clone(&focus)), |pipeline, _| {
+            nav_frame(pipeline)?;
+            for _ in 0..2 {
+                pipeline.context().io_mut().add_key_event(Key::DownArrow, true);
+                nav_frame(pipeline)?;
+                pipeline.context().io_mut().add_key_event(Key::DownArrow, false);
+                nav_frame(pipeline)?;
+            }
+            nav_frame(pipeline)?;
+
+            Ok(pipeline.context().io().mouse_pos)
+        })?;
+
+        let focus = *focus.lock();
+        Ok((mouse_pos, focus))
+    }
+
+    #[test]
+    fn test_pipeline_nav_mouse_warp() -> Result<()> {
+        // Navigation changes the focus, but leaves the mouse alone.
+        let (mouse_pos, focus) = navigate(NavMouseWarp::Disabled)?;
+        assert!(matches!(focus, Some((index, ..)) if index > 0));
+        assert!(mouse_pos[0] < -1e30 && mouse_pos[1] < -1e30);
+
+        // Navigation changes the focus, and moves imgui's mouse over the
+        // focused widget.
+        let (mouse_pos, focus) = navigate(NavMouseWarp::Overlay)?;
+        let (index, min, max) = focus.expect("no widget focused");
+        assert!(index > 0);
+        assert!((min[0]..=max[0]).contains(&mouse_pos[0]));
+        assert!((min[1]..=max[1]).contains(&mouse_pos[1]));
+
+        Ok(())
+    }
+
+    struct VisibilityRenderLoop(Arc<AtomicBool>);
+
+    impl ImguiRenderLoop for VisibilityRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            if self.0.swap(false, Ordering::SeqCst) {
+                render_context.set_visible(true);
+            }
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_toggle_key() -> Result<()> {
+        let show = Arc::new(AtomicBool::new(false));
+        let config = PipelineConfig { toggle_key: Some(VK_F1), ..Default::default() };
+
+        with_pipeline(config, VisibilityRenderLoop(Arc::clone(&show)), |pipeline, frames| {
+            let hwnd = pipeline.hwnd();
+            let mut frame = || -> Result<usize> {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+                Ok(frames.lock().len())
+            };
+
+            let press_toggle_key = |lparam| unsafe {
+                SendMessageW(hwnd, WM_KEYDOWN, WPARAM(VK_F1.0 as usize), LPARAM(lparam));
+            };
+
+            assert_eq!(frame()?, 1);
+
+            // Hidden frames don't draw anything.
+            press_toggle_key(0);
+            assert_eq!(frame()?, 1);
+
+            // Auto-repeat doesn't toggle back.
+            press_toggle_key(1 << 30);
+            assert_eq!(frame()?, 1);
+
+            // The render loop can override the visibility.
+            show.store(true, Ordering::SeqCst);
+            assert_eq!(frame()?, 2);
+
+            press_toggle_key(0);
+            assert_eq!(frame()?, 2);
+
+            press_toggle_key(0);
+            assert_eq!(frame()?, 3);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_pipeline_input_modes() -> Result<()> {
+        for input_mode in
+            [InputMode::WndProcTrampoline, InputMode::Subclass, InputMode::RawInputOnly]
+        {
+            let dummy_hwnd = DummyHwnd::new();
+            let hwnd = dummy_hwnd.hwnd();
+            let wnd_proc = unsafe { GetWindowLongPtrW(hwnd, GWLP_WNDPROC) };
+
+            let config =
+                PipelineConfig { toggle_key: Some(VK_F1), input_mode, ..Default::default() };
+            let render_loop = VisibilityRenderLoop(Arc::new(AtomicBool::new(false)));
+
+            with_pipeline_on(hwnd, config, render_loop, |pipeline, frames| {
+                if input_mode == InputMode::RawInputOnly {
+                    assert_eq!(unsafe { GetWindowLongPtrW(hwnd, GWLP_WNDPROC) }, wnd_proc);
+                }
+
+                // The toggle key is seen once, whichever way the messages come.
+                unsafe {
+                    PostMessageW(hwnd, WM_KEYDOWN, WPARAM(VK_F1.0 as usize), LPARAM(0))?;
+                    let mut msg = MSG::default();
+                    while PeekMessageW(&mut msg, hwnd, 0, 0, PM_REMOVE).as_bool() {
+                        DispatchMessageW(&msg);
+                    }
+                }
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+                assert!(frames.lock().is_empty(), "{input_mode:?}");
+
+                Ok(())
+            })?;
+
+            // The window is left as it was.
+            assert_eq!(unsafe { GetWindowLongPtrW(hwnd, GWLP_WNDPROC) }, wnd_proc);
+        }
+
+        Ok(())
+    }
+
+    struct InputRenderLoop(Arc<AtomicBool>);
+
+    impl ImguiRenderLoop for InputRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            render_context.set_input_enabled(self.0.load(Ordering::SeqCst));
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+
+        fn message_filter(&self, _io: &imgui::Io) -> MessageFilter {
+            MessageFilter::InputKeyboard
+        }
+    }
+
+    #[test]
+    fn test_pipeline_input_enabled() -> Result<()> {
+        let input_enabled = Arc::new(AtomicBool::new(true));
+        let render_loop = InputRenderLoop(Arc::clone(&input_enabled));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            let hwnd = pipeline.hwnd();
+            let mut frame = || -> Result<()> {
+                pipeline.prepare_render()?;
+                pipeline.render(())
+            };
+
+            // Blocked messages return 1, and those passed to the default window
+            // procedure 0.
+            let press_key =
+                || unsafe { SendMessageW(hwnd, WM_KEYDOWN, WPARAM(VK_F1.0 as usize), LPARAM(0)) };
+
+            frame()?;
+            assert_eq!(press_key(), LRESULT(1));
+
+            // The overlay is still drawn without taking input.
+            input_enabled.store(false, Ordering::SeqCst);
+            frame()?;
+            assert_eq!(press_key(), LRESULT(0));
+            assert_eq!(frames.lock().len(), 2);
+
+            input_enabled.store(true, Ordering::SeqCst);
+            frame()?;
+            assert_eq!(press_key(), LRESULT(1));
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct CaptureRenderLoop(Arc<Mutex<Vec<CaptureFlags>>>);
+
+    impl ImguiRenderLoop for CaptureRenderLoop {
+        fn render(&mut self, _ui: &mut imgui::Ui) {}
+
+        // As if a text field had been focused.
+        fn render_with_context(
+            &mut self,
+            _ui: &mut imgui::Ui,
+            render_context: &mut dyn RenderContext,
+        ) {
+            let io = render_context.io_mut().unwrap();
+            io.want_capture_keyboard = true;
+            io.want_text_input = true;
+        }
+
+        fn on_wnd_proc_with_capture(
+            &mut self,
+            _hwnd: HWND,
+            umsg: u32,
+            _wparam: WPARAM,
+            _lparam: LPARAM,
+            capture: CaptureFlags,
+        ) -> WndProcResult {
+            if umsg == WM_KEYDOWN {
+                self.0.lock().push(capture);
+            }
+            WndProcResult::Passthrough
+        }
+    }
+
+    #[test]
+    fn test_pipeline_wnd_proc_capture() -> Result<()> {
+        let render_loop = CaptureRenderLoop::default();
+        let captures = Arc::clone(&render_loop.0);
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            let hwnd = pipeline.hwnd();
+            let press_key =
+                || unsafe { SendMessageW(hwnd, WM_KEYDOWN, WPARAM(VK_F1.0 as usize), LPARAM(0)) };
+
+            press_key();
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // The flags of a frame are only seen once the next one is prepared.
+            press_key();
+            pipeline.prepare_render()?;
+            press_key();
+
+            Ok(())
+        })?;
+
+        let want_text_input = CaptureFlags {
+            want_capture_keyboard: true,
+            want_capture_mouse: false,
+            want_text_input: true,
+        };
+        assert_eq!(
+            *captures.lock(),
+            [CaptureFlags::default(), CaptureFlags::default(), want_text_input]
+        );
+
+        Ok(())
+    }
+
+    struct WindowSpamRenderLoop;
+
+    impl ImguiRenderLoop for WindowSpamRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            for i in 0..32 {
+                ui.window(format!("Window {i}")).build(|| ui.text("Hello"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipeline_click_through() -> Result<()> {
+        let config = PipelineConfig { click_through: true, ..Default::default() };
+        let render_loop = InputRenderLoop(Arc::new(AtomicBool::new(true)));
+
+        with_pipeline(config, render_loop, |pipeline, _| {
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // The render loop's filter is ignored, and imgui never gets the mouse.
+            let result = unsafe {
+                SendMessageW(pipeline.hwnd(), WM_KEYDOWN, WPARAM(VK_F1.0 as usize), LPARAM(0))
+            };
+            assert_eq!(result, LRESULT(0));
+            let io = pipeline.context().io();
+            assert!(io.config_flags.contains(ConfigFlags::NO_MOUSE));
+            assert!(!io.want_capture_mouse);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_pipeline_session_layout() -> Result<()> {
+        let config = PipelineConfig { session_layout: true, ..Default::default() };
+
+        with_pipeline(config.clone(), TestRenderLoop, |pipeline, _| {
+            pipeline.prepare_render()?;
+            pipeline.render(())
+        })?;
+
+        // The next context starts with the layout of the previous one.
+        let layout = with_pipeline(config, TestRenderLoop, |pipeline, _| {
+            let mut layout = String::new();
+            pipeline.context().save_ini_settings(&mut layout);
+            Ok(layout)
+        })?;
+        assert!(layout.contains("[Window][Test]"), "{layout}");
+
+        *SESSION_LAYOUT.lock() = None;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_supplied_context() -> Result<()> {
+        // This creates contexts, and supplies one globally.
+        let _lock = CONTEXT_LOCK.lock();
+
+        let supplied = Some(PathBuf::from("supplied.ini"));
+        let mut ctx = Context::create();
+        ctx.set_ini_filename(supplied.clone());
+        supply_context(ctx);
+
+        // It can't be activated while another context is, and is kept.
+        let other = Context::create();
+        assert!(create_context().is_err());
+        assert!(create_context().is_err());
+        drop(other);
+
+        let ctx = create_context()?;
+        assert_eq!(ctx.ini_filename(), supplied);
+        drop(ctx);
+
+        // Only the first pipeline adopts it.
+        assert_ne!(create_context()?.ini_filename(), supplied);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipeline_style() -> Result<()> {
+        fn custom(style: &mut imgui::Style) {
+            style.use_light_colors();
+            style.alpha = 0.5;
+        }
+
+        let config =
+            PipelineConfig { style: Some(StylePreset::Custom(custom)), ..Default::default() };
+
+        with_pipeline(config, TestRenderLoop, |pipeline, _| {
+            let style = pipeline.context().style();
+            assert_eq!(style[imgui::StyleColor::Text], [0., 0., 0., 1.]);
+            assert_eq!(style[imgui::StyleColor::WindowBg], [0.94, 0.94, 0.94, 1.]);
+            assert_eq!(style.alpha, 0.5);
+
+            Ok(())
+        })
+    }
+
+    fn render_window_spam(action: WindowLimitAction) -> Result<usize> {
+        let config = PipelineConfig { window_limit: Some((16, action)), ..Default::default() };
+
+        with_pipeline(config, WindowSpamRenderLoop, |pipeline, frames| {
+            for _ in 0..2 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+
+            Ok(frames.lock().len())
+        })
+    }
+
+    #[test]
+    fn test_pipeline_window_limit() -> Result<()> {
+        assert_eq!(render_window_spam(WindowLimitAction::Warn)?, 2);
+        assert_eq!(render_window_spam(WindowLimitAction::Skip)?, 0);
+
+        Ok(())
+    }
+
+    struct WindowTitleRenderLoop(Arc<Mutex<String>>);
+
+    impl ImguiRenderLoop for WindowTitleRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            *self.0.lock() = render_context.window_title();
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_window_title() -> Result<()> {
+        let title = Arc::new(Mutex::new(String::new()));
+        let render_loop = WindowTitleRenderLoop(Arc::clone(&title));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert_eq!(*title.lock(), "HUDHOOK");
+
+            // Titles changed at runtime are picked up on the next frame.
+            unsafe { SetWindowTextW(pipeline.hwnd(), w!("ハドフック 🎮"))? };
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert_eq!(*title.lock(), "ハドフック 🎮");
+
+            Ok(())
+        })
+    }
+
+    struct NoDigits;
+
+    impl InputTextCallbackHandler for NoDigits {
+        fn char_filter(&mut self, c: char) -> Option<char> {
+            (!c.is_ascii_digit()).then_some(c)
+        }
+    }
+
+    struct InputTextRenderLoop {
+        text: Arc<Mutex<String>>,
+        focused: bool,
+    }
+
+    impl ImguiRenderLoop for InputTextRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            let mut text = self.text.lock();
+            ui.window("Test").build(|| {
+                if !self.focused {
+                    ui.set_keyboard_focus_here();
+                    self.focused = true;
+                }
+                ui.input_text("##input", &mut text)
+                    .callback(InputTextCallback::CHAR_FILTER, NoDigits)
+                    .build();
+            });
+        }
+    }
+
+    #[test]
+    fn test_pipeline_input_text_callback() -> Result<()> {
+        let text = Arc::new(Mutex::new(String::new()));
+        let render_loop = InputTextRenderLoop { text: Arc::clone(&text), focused: false };
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            let hwnd = pipeline.hwnd();
+            let mut frame = || -> Result<()> {
+                pipeline.prepare_render()?;
+                pipeline.render(())
+            };
+
+            // Let the input text take the focus.
+            frame()?;
+            frame()?;
+
+            // The emoji comes as a pair of UTF-16 surrogates.
+            for code_unit in "a1b2🎮".encode_utf16() {
+                unsafe { SendMessageW(hwnd, WM_CHAR, WPARAM(code_unit as usize), LPARAM(0)) };
+            }
+            frame()?;
+            frame()?;
+
+            assert_eq!(*text.lock(), "ab🎮");
+
+            Ok(())
+        })
+    }
+
+    const GAMEPAD_KEYS: [Key; 4] = [
+        Key::GamepadFaceDown,
+        Key::GamepadFaceRight,
+        Key::GamepadLStickLeft,
+        Key::GamepadLStickRight,
+    ];
+
+    struct GamepadRenderLoop(Arc<Mutex<Vec<bool>>>);
+
+    impl ImguiRenderLoop for GamepadRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            *self.0.lock() = GAMEPAD_KEYS.iter().map(|&key| ui.is_key_down(key)).collect();
+        }
+    }
+
+    #[test]
+    fn test_pipeline_gamepad() -> Result<()> {
+        let keys_down = Arc::new(Mutex::new(Vec::new()));
+        let render_loop = GamepadRenderLoop(Arc::clone(&keys_down));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            let mut frame = |gamepad: XINPUT_GAMEPAD| -> Result<()> {
+                handle_gamepad(pipeline.context().io_mut(), &gamepad);
+                pipeline.prepare_render()?;
+                pipeline.render(())
+            };
+
+            // A pressed, left stick pushed all the way to the left.
+            frame(XINPUT_GAMEPAD {
+                wButtons: XINPUT_GAMEPAD_A,
+                sThumbLX: i16::MIN,
+                ..Default::default()
+            })?;
+            assert_eq!(*keys_down.lock(), [true, false, true, false]);
+
+            // Stick movements within the deadzone are ignored.
+            frame(XINPUT_GAMEPAD { sThumbLX: 5000, ..Default::default() })?;
+            assert_eq!(*keys_down.lock(), [false, false, false, false]);
+
+            Ok(())
+        })
+    }
+
+    struct FocusRenderLoop(Arc<Mutex<(bool, bool)>>);
+
+    impl ImguiRenderLoop for FocusRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            *self.0.lock() = (ui.is_key_down(Key::A), ui.is_mouse_down(MouseButton::Left));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_focus_loss() -> Result<()> {
+        let held = Arc::new(Mutex::new((false, false)));
+        let render_loop = FocusRenderLoop(Arc::clone(&held));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            let hwnd = pipeline.hwnd();
+            for (msg, wparam) in [(WM_KILLFOCUS, 0), (WM_ACTIVATE, WA_INACTIVE as usize)] {
+                pipeline.context().io_mut().add_key_event(Key::A, true);
+                imgui_wnd_proc_impl(hwnd, WM_LBUTTONDOWN, WPARAM(0), LPARAM(0), pipeline);
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+                assert_eq!(*held.lock(), (true, true));
+
+                // The ups are never received, as the focus is lost.
+                imgui_wnd_proc_impl(hwnd, msg, WPARAM(wparam), LPARAM(0), pipeline);
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+                assert_eq!(*held.lock(), (false, false));
+            }
+
+            Ok(())
+        })
+    }
+
+    struct RawContextRenderLoop(Arc<Mutex<Vec<usize>>>);
+
+    impl ImguiRenderLoop for RawContextRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.0.lock().push(render_context.raw_imgui_context() as usize);
+        }
+
+        fn render(&mut self, _ui: &mut imgui::Ui) {}
+
+        fn render_with_context(
+            &mut self,
+            _ui: &mut imgui::Ui,
+            render_context: &mut dyn RenderContext,
+        ) {
+            self.0.lock().push(render_context.raw_imgui_context() as usize);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_raw_imgui_context() -> Result<()> {
+        let contexts = Arc::new(Mutex::new(Vec::new()));
+        let render_loop = RawContextRenderLoop(Arc::clone(&contexts));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            contexts.lock().clear();
+
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            let raw_ctx = unsafe { imgui::sys::igGetCurrentContext() } as usize;
+            assert_ne!(raw_ctx, 0);
+            assert_eq!(*contexts.lock(), [raw_ctx, raw_ctx]);
+
+            Ok(())
+        })
+    }
+
+    struct FontRenderLoop {
+        frame: usize,
+        font_data: Option<Vec<u8>>,
+        font: Option<FontId>,
+    }
+
+    impl ImguiRenderLoop for FontRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            // Add the font after the first frame has been rendered.
+            self.frame += 1;
+            if self.frame < 2 {
+                return;
+            }
+
+            if let Some(data) = self.font_data.take() {
+                assert!(render_context.add_font(&data, 20., &[0x20, 0xff]).is_err());
+                self.font = Some(render_context.add_font(&data, 20., &[0x20, 0xff, 0]).unwrap());
+            }
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            let _font = self.font.map(|font| ui.push_font(font));
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_add_font() -> Result<()> {
+        let windir = std::env::var("WINDIR").unwrap_or_else(|_| String::from("C:\\Windows"));
+        let font_data = std::fs::read(format!("{windir}\\Fonts\\arial.ttf")).unwrap();
+        let render_loop = FontRenderLoop { frame: 0, font_data: Some(font_data), font: None };
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            for _ in 0..2 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+
+            // The atlas is rebuilt into a new texture, and the old one is freed.
+            let frames = frames.lock();
+            assert!(frames[0].draw_cmds.iter().all(|cmd| cmd.texture_id == TextureId::from(0)));
+            assert!(frames[1].draw_cmds.iter().all(|cmd| cmd.texture_id == TextureId::from(1)));
+            assert!(!frames[1].draw_cmds.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct MultiFontRenderLoop {
+        frame: usize,
+        fonts: Vec<FontId>,
+        used_fonts: Arc<Mutex<Vec<Vec<f32>>>>,
+    }
+
+    impl ImguiRenderLoop for MultiFontRenderLoop {
+        fn initialize<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            let windir = std::env::var("WINDIR").unwrap_or_else(|_| String::from("C:\\Windows"));
+            for (name, size) in [("arial.ttf", 32.), ("consola.ttf", 14.)] {
+                let data = std::fs::read(format!("{windir}\\Fonts\\{name}")).unwrap();
+                self.fonts.push(render_context.add_font(&data, size, &[]).unwrap());
+            }
+        }
+
+        fn before_render<'a>(
+            &'a mut self,
+            ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            // The ids survive rebuilds.
+            self.frame += 1;
+            if self.frame == 2 {
+                render_context.rebuild_fonts().unwrap();
+            }
+            assert!(self.fonts.iter().all(|font| ctx.fonts().fonts().contains(font)));
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            let sizes = self
+                .fonts
+                .iter()
+                .map(|&font| {
+                    let _font = ui.push_font(font);
+                    ui.current_font_size()
+                })
+                .collect();
+            self.used_fonts.lock().push(sizes);
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_multiple_fonts() -> Result<()> {
+        let render_loop = MultiFontRenderLoop::default();
+        let used_fonts = Arc::clone(&render_loop.used_fonts);
+        let config = PipelineConfig { dpi_scaling: false, ..Default::default() };
+
+        with_pipeline(config, render_loop, |pipeline, _| {
+            for _ in 0..3 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+
+            assert_eq!(*used_fonts.lock(), vec![vec![32., 14.]; 3]);
+
+            Ok(())
+        })
+    }
+
+    struct RebuildFontsRenderLoop(usize);
+
+    impl ImguiRenderLoop for RebuildFontsRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.0 += 1;
+            if self.0 == 2 {
+                ctx.fonts().clear();
+                ctx.fonts().add_font(&[FontSource::DefaultFontData {
+                    config: Some(FontConfig { size_pixels: 26., ..FontConfig::default() }),
+                }]);
+                render_context.rebuild_fonts().unwrap();
+            }
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_rebuild_fonts() -> Result<()> {
+        with_pipeline(PipelineConfig::default(), RebuildFontsRenderLoop(0), |pipeline, frames| {
+            for _ in 0..2 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+
+            let frames = frames.lock();
+            assert!(frames[0].draw_cmds.iter().all(|cmd| cmd.texture_id == TextureId::from(0)));
+            assert!(frames[1].draw_cmds.iter().all(|cmd| cmd.texture_id == TextureId::from(1)));
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct DeviceResetRenderLoop {
+        texture: Option<TextureId>,
+        resets: Arc<AtomicUsize>,
+    }
+
+    impl ImguiRenderLoop for DeviceResetRenderLoop {
+        fn initialize<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.texture = Some(render_context.load_texture(&[0xff; 4], 1, 1).unwrap());
+        }
+
+        fn on_device_reset<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.resets.fetch_add(1, Ordering::SeqCst);
+            self.texture = Some(render_context.load_texture(&[0xff; 4], 1, 1).unwrap());
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| {
+                imgui::Image::new(self.texture.unwrap(), [1., 1.]).build(ui);
+            });
+        }
+    }
+
+    #[test]
+    fn test_pipeline_reset_device() -> Result<()> {
+        let render_loop = DeviceResetRenderLoop::default();
+        let resets = Arc::clone(&render_loop.resets);
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // A failed reset leaves the render loop alone, a successful one
+            // doesn't.
+            let result = pipeline.reset_device(|_| {}, || HRESULT(-1));
+            assert_eq!(result, HRESULT(-1));
+            assert_eq!(resets.load(Ordering::SeqCst), 0);
+            let result = pipeline.reset_device(|_| {}, || HRESULT(0));
+            assert_eq!(result, HRESULT(0));
+            assert_eq!(resets.load(Ordering::SeqCst), 1);
+
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // The font atlas and the texture are loaded again.
+            let frames = frames.lock();
+            let texture_ids = |frame: &RecordedFrame| {
+                let mut ids: Vec<_> =
+                    frame.draw_cmds.iter().map(|cmd| cmd.texture_id.id()).collect();
+                ids.sort();
+                ids.dedup();
+                ids
+            };
+            assert_eq!(texture_ids(&frames[0]), [0, 1]);
+            assert_eq!(texture_ids(&frames[1]), [2, 3]);
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct ResizeRenderLoop {
+        resizes: Arc<Mutex<Vec<(u32, u32)>>>,
+        sizes: Arc<Mutex<Vec<[f32; 2]>>>,
+    }
+
+    impl ImguiRenderLoop for ResizeRenderLoop {
+        fn on_resize(&mut self, width: u32, height: u32) {
+            self.resizes.lock().push((width, height));
+        }
+
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.sizes.lock().push(render_context.display_size());
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_on_resize() -> Result<()> {
+        let render_loop = ResizeRenderLoop::default();
+        let resizes = Arc::clone(&render_loop.resizes);
+        let sizes = Arc::clone(&render_loop.sizes);
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            let [width, height] = pipeline.context().io().display_size;
+
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // Only the size the display ends up with between two frames counts.
+            pipeline.resize(640, 480);
+            pipeline.resize(1024, 768);
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            pipeline.resize(1024, 768);
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            assert_eq!(*resizes.lock(), [(1024, 768)]);
+            assert_eq!(*sizes.lock(), [[width, height], [1024., 768.], [1024., 768.]]);
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct ContentRectRenderLoop {
+        rects: Arc<Mutex<Vec<[[f32; 2]; 2]>>>,
+        work_areas: Arc<Mutex<Vec<[[f32; 2]; 2]>>>,
+    }
+
+    impl ImguiRenderLoop for ContentRectRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.rects.lock().push(render_context.content_rect());
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            let viewport = unsafe { &*imgui::sys::igGetMainViewport() };
+            let (pos, size) = (viewport.WorkPos, viewport.WorkSize);
+            self.work_areas.lock().push([[pos.x, pos.y], [pos.x + size.x, pos.y + size.y]]);
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_content_rect() -> Result<()> {
+        let render_loop = ContentRectRenderLoop::default();
+        let rects = Arc::clone(&render_loop.rects);
+        let work_areas = Arc::clone(&render_loop.work_areas);
+
+        let config = PipelineConfig {
+            content_rect: Some(Arc::new(|width, height| RECT {
+                left: 100,
+                top: -10,
+                right: width as i32 + 100,
+                bottom: height as i32 - 100,
+            })),
+            ..Default::default()
+        };
+        with_pipeline(config, render_loop, |pipeline, _| {
+            pipeline.resize(1000, 500);
+            pipeline.prepare_render()?;
+            pipeline.render(())
+        })?;
+
+        // The rect is clamped to the display.
+        let expected = [[100., 0.], [1000., 400.]];
+        assert_eq!(*rects.lock(), [expected]);
+        assert_eq!(*work_areas.lock(), [expected]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipeline_layers() -> Result<()> {
+        let render_loop = ContentRectRenderLoop::default();
+        let work_areas = Arc::clone(&render_loop.work_areas);
+        let layer_areas = Arc::new(Mutex::new(Vec::new()));
+
+        let mut config = PipelineConfig::default();
+        for player in 0..2 {
+            let layer_areas = Arc::clone(&layer_areas);
+            config.layers.push(Layer {
+                name: format!("player {player}"),
+                rect: Arc::new(move |width, height| {
+                    let half = width as i32 / 2;
+                    RECT {
+                        left: half * player,
+                        top: 0,
+                        right: half * (player + 1),
+                        bottom: height as i32,
+                    }
+                }),
+                render: Arc::new(Mutex::new(move |ui: &imgui::Ui, rect: [[f32; 2]; 2]| {
+                    let viewport = unsafe { &*imgui::sys::igGetMainViewport() };
+                    let (pos, size) = (viewport.WorkPos, viewport.WorkSize);
+                    let work_area = [[pos.x, pos.y], [pos.x + size.x, pos.y + size.y]];
+                    layer_areas.lock().push((player, rect, work_area));
+                    ui.window(format!("Layer##{player}")).build(|| ui.text("Hello"));
+                })),
+            });
+        }
+        with_pipeline(config, render_loop, |pipeline, _| {
+            pipeline.resize(1000, 500);
+            pipeline.prepare_render()?;
+            pipeline.render(())
+        })?;
+
+        // The render loop keeps the whole display, and the layers follow in
+        // order, each with its own half.
+        assert_eq!(*work_areas.lock(), [[[0., 0.], [1000., 500.]]]);
+        let left = [[0., 0.], [500., 500.]];
+        let right = [[500., 0.], [1000., 500.]];
+        assert_eq!(*layer_areas.lock(), [(0, left, left), (1, right, right)]);
+
+        Ok(())
+    }
+
+    struct DpiRenderLoop(Arc<Mutex<Vec<f32>>>);
+
+    impl ImguiRenderLoop for DpiRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            self.0.lock().push(ui.current_font_size());
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_dpi_scaling() -> Result<()> {
+        let font_sizes = Arc::new(Mutex::new(Vec::new()));
+        let render_loop = DpiRenderLoop(Arc::clone(&font_sizes));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // Move to a monitor with twice the DPI.
+            let hwnd = pipeline.hwnd();
+            let dpi = unsafe { GetDpiForWindow(hwnd) } * 2;
+            unsafe {
+                SendMessageW(hwnd, WM_DPICHANGED, WPARAM(((dpi << 16) | dpi) as usize), LPARAM(0))
+            };
+
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            // The fonts are rasterized anew at twice the size.
+            let font_sizes = font_sizes.lock();
+            assert_eq!(font_sizes[1], font_sizes[0] * 2.);
+            let frames = frames.lock();
+            assert!(frames[1].draw_cmds.iter().all(|cmd| cmd.texture_id == TextureId::from(1)));
+
+            Ok(())
+        })
+    }
+
+    struct TargetHwndRenderLoop(Arc<Mutex<HWND>>);
+
+    impl ImguiRenderLoop for TargetHwndRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            *self.0.lock() = render_context.target_hwnd();
+            assert_eq!(render_context.swap_chain_format(), None);
+            assert_eq!(render_context.swap_chain_color_space(), None);
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_target_hwnd() -> Result<()> {
+        let hwnd = Arc::new(Mutex::new(HWND::default()));
+        let render_loop = TargetHwndRenderLoop(Arc::clone(&hwnd));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert_eq!(*hwnd.lock(), pipeline.hwnd());
+
+            Ok(())
+        })
+    }
+
+    struct TimingRenderLoop(Arc<Mutex<Vec<(u64, Duration, f32)>>>);
+
+    impl ImguiRenderLoop for TimingRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            ctx: &mut Context,
+            render_context: &'a mut dyn RenderContext,
+        ) {
+            self.0.lock().push((
+                render_context.frame_count(),
+                render_context.last_present_duration(),
+                ctx.io().delta_time,
+            ));
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_frame_timing() -> Result<()> {
+        let timings = Arc::new(Mutex::new(Vec::new()));
+        let render_loop = TimingRenderLoop(Arc::clone(&timings));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, _| {
+            for _ in 0..3 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+                thread::sleep(Duration::from_millis(50));
+            }
+
+            Ok(())
+        })?;
+
+        let timings = timings.lock();
+        assert_eq!(timings.iter().map(|&(count, ..)| count).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(timings[1].1, Duration::ZERO);
+
+        // The delta time follows the actual present interval, not a fixed rate.
+        let (_, last_present_duration, delta_time) = timings[2];
+        assert!(last_present_duration >= Duration::from_millis(50));
+        assert!((delta_time - last_present_duration.as_secs_f32()).abs() < 1e-6);
+
+        Ok(())
+    }
+
+    struct AsyncRenderLoop(Arc<Mutex<Vec<thread::ThreadId>>>);
+
+    impl ImguiRenderLoop for AsyncRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            self.0.lock().push(thread::current().id());
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_async_frames() -> Result<()> {
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let config = PipelineConfig { async_frames: true, ..Default::default() };
+
+        with_pipeline(config, AsyncRenderLoop(Arc::clone(&threads)), |pipeline, frames| {
+            let mut frame = || -> Result<usize> {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+                Ok(frames.lock().len())
+            };
+
+            // Each present draws the frame started by the previous one.
+            assert_eq!(frame()?, 0);
+            assert_eq!(frame()?, 1);
+            assert_eq!(frame()?, 2);
+
+            Ok(())
+        })?;
+
+        let threads = threads.lock();
+        assert_eq!(threads.len(), 3);
+        assert!(threads.iter().all(|&id| id != thread::current().id()));
+
+        Ok(())
+    }
+
+    struct SettingsRenderLoop(Arc<Mutex<Option<(Option<PathBuf>, f32)>>>);
+
+    impl ImguiRenderLoop for SettingsRenderLoop {
+        fn initialize<'a>(
+            &'a mut self,
+            ctx: &mut Context,
+            _render_context: &'a mut dyn RenderContext,
+        ) {
+            *self.0.lock() = Some((ctx.ini_filename(), ctx.style().alpha));
+
+            // Don't leave a settings file behind.
+            ctx.set_ini_filename(None);
+        }
+
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+    }
+
+    #[test]
+    fn test_pipeline_imgui_settings() -> Result<()> {
+        let settings = Arc::new(Mutex::new(None));
+        let render_loop = SettingsRenderLoop(Arc::clone(&settings));
+
+        let config = PipelineConfig {
+            ini_filename: Some(PathBuf::from("hudhook_test.ini")),
+            imgui_settings: Some(Arc::new(|ctx: &mut Context| ctx.style_mut().alpha = 0.5)),
+            ..Default::default()
+        };
+        with_pipeline(config, render_loop, |_, _| Ok(()))?;
+
+        // The settings are applied before the render loop is initialized.
+        assert_eq!(*settings.lock(), Some((Some(PathBuf::from("hudhook_test.ini")), 0.5)));
+
+        Ok(())
+    }
+
+    struct MessageRenderLoop(Arc<Mutex<Vec<String>>>);
+
+    impl ImguiRenderLoop for MessageRenderLoop {
+        fn before_render<'a>(
+            &'a mut self,
+            _ctx: &mut Context,
+            _render_context: &'a mut dyn RenderContext,
+        ) {
+            self.0.lock().push(String::from("before_render"));
+        }
+
+        fn render(&mut self, _ui: &mut imgui::Ui) {}
+
+        fn on_message(&mut self, message: Message) {
+            let message = message.downcast::<u32>().expect("u32 message");
+            self.0.lock().push(message.to_string());
+        }
+    }
+
+    #[test]
+    fn test_pipeline_messages() -> Result<()> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (tx, rx) = crate::message::channel::<u32>(4);
+        let config = PipelineConfig { messages: Some(Arc::new(rx)), ..Default::default() };
+
+        with_pipeline(config, MessageRenderLoop(Arc::clone(&log)), |pipeline, _| {
+            // The messages are delivered in order, before the frame.
+            tx.send(1).unwrap();
+            thread::spawn({
+                let tx = tx.clone();
+                move || tx.send(2).unwrap()
+            })
+            .join()
+            .unwrap();
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            tx.try_send(3).unwrap();
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+
+            assert_eq!(*log.lock(), ["1", "2", "before_render", "3", "before_render"]);
+
+            Ok(())
+        })?;
+
+        // Sending fails once the overlay is gone.
+        assert_eq!(tx.send(4), Err(4));
+
+        Ok(())
+    }
+
+    struct PresentRenderLoop;
+
+    impl ImguiRenderLoop for PresentRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+
+        fn on_present(&mut self, sync_interval: u32, _flags: u32) -> PresentAction {
+            if sync_interval == 0 {
+                PresentAction::Skip
+            } else {
+                PresentAction::CallOriginal
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipeline_on_present() -> Result<()> {
+        with_pipeline(PipelineConfig::default(), PresentRenderLoop, |pipeline, _| {
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert_eq!(pipeline.on_present(0, 0), PresentAction::Skip);
+            assert_eq!(pipeline.on_present(1, 0), PresentAction::CallOriginal);
+
+            Ok(())
+        })
+    }
+
+    struct CallbackRenderLoop(Arc<AtomicUsize>);
+
+    impl ImguiRenderLoop for CallbackRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            let calls = Arc::clone(&self.0);
+            ui.window("Test").build(|| {
+                ui.text("Hello");
+                ui.get_window_draw_list()
+                    .add_callback(move || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .build();
+            });
+        }
+    }
+
+    struct WndProcRenderLoop;
+
+    impl ImguiRenderLoop for WndProcRenderLoop {
+        fn render(&mut self, _ui: &mut imgui::Ui) {}
+
+        fn on_wnd_proc(
+            &mut self,
+            _hwnd: HWND,
+            umsg: u32,
+            wparam: WPARAM,
+            _lparam: LPARAM,
+        ) -> WndProcResult {
+            if umsg == WM_CHAR && wparam.0 == 'a' as usize {
+                WndProcResult::Handled(LRESULT(42))
+            } else {
+                WndProcResult::Passthrough
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipeline_on_wnd_proc() -> Result<()> {
+        with_pipeline(PipelineConfig::default(), WndProcRenderLoop, |pipeline, _| {
+            let hwnd = pipeline.hwnd();
+            let send_char =
+                |c: char| unsafe { SendMessageW(hwnd, WM_CHAR, WPARAM(c as usize), LPARAM(0)) };
+
+            // Swallowed messages return what the render loop says, the others
+            // what the original window procedure does.
+            assert_eq!(send_char('a'), LRESULT(42));
+            assert_eq!(send_char('b'), LRESULT(0));
+
+            pipeline.prepare_render()?;
+            pipeline.render(())
+        })
+    }
+
+    #[test]
+    fn test_pipeline_raw_callback() -> Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let render_loop = CallbackRenderLoop(Arc::clone(&calls));
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            for _ in 0..2 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+
+            // The callback runs once per frame, and the rest of the window is
+            // drawn.
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+            assert!(frames.lock().iter().all(|frame| !frame.draw_cmds.is_empty()));
+
+            Ok(())
+        })
+    }
+
+    #[derive(Default)]
+    struct PanicRenderLoop {
+        renders: usize,
+        after_renders: Arc<AtomicUsize>,
+    }
+
+    impl ImguiRenderLoop for PanicRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            self.renders += 1;
+            ui.window("Test").build(|| {
+                ui.text("Hello");
+                if self.renders == 2 {
+                    panic!("Out of bounds");
+                }
+            });
+        }
+
+        fn after_render(&mut self, _render_context: &mut dyn RenderContext) {
+            self.after_renders.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_catch_panic() -> Result<()> {
+        let render_loop = PanicRenderLoop::default();
+        let after_renders = Arc::clone(&render_loop.after_renders);
+
+        with_pipeline(PipelineConfig::default(), render_loop, |pipeline, frames| {
+            // The panic doesn't escape, and the render loop isn't called again.
+            for _ in 0..3 {
+                pipeline.prepare_render()?;
+                pipeline.render(())?;
+            }
+            assert_eq!(pipeline.on_present(0, 0), PresentAction::CallOriginal);
+
+            assert_eq!(frames.lock().len(), 1);
+            assert_eq!(after_renders.load(Ordering::SeqCst), 1);
+
+            Ok(())
+        })
+    }
+
+    struct FilterPanicRenderLoop;
+
+    impl ImguiRenderLoop for FilterPanicRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.window("Test").build(|| ui.text("Hello"));
+        }
+
+        fn message_filter(&self, _io: &imgui::Io) -> MessageFilter {
+            panic!("Out of bounds");
+        }
+    }
+
+    #[test]
+    fn test_pipeline_catch_message_filter_panic() -> Result<()> {
+        with_pipeline(PipelineConfig::default(), FilterPanicRenderLoop, |pipeline, frames| {
+            // The overlay is disabled before the frame is drawn.
+            pipeline.prepare_render()?;
+            pipeline.render(())?;
+            assert!(frames.lock().is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_load_texture_from_bytes() -> Result<()> {
+        with_engine(|_, engine| {
+            let bytes = include_bytes!("../../../tests/thingken.webp");
+            let expected = image::load_from_memory(bytes).unwrap();
+
+            let (texture_id, width, height) = engine.load_texture_from_bytes(bytes)?;
+            assert_eq!((width, height), (expected.width(), expected.height()));
+            assert_eq!(engine.textures()[texture_id.id()], Some((width, height)));
+
+            assert!(engine.load_texture_from_bytes(b"not an image").is_err());
+
+            Ok(())
+        })
+    }
+}
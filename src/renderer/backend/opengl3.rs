@@ -1,6 +1,7 @@
 // Based on https://github.com/michaelfairley/rust-imgui-opengl-renderer/
 
-use std::ffi::{c_void, CString};
+use std::borrow::Cow;
+use std::ffi::{c_void, CStr, CString};
 use std::mem::{self, offset_of};
 
 use gl::types::*;
@@ -10,11 +11,17 @@ use once_cell::sync::OnceCell;
 use tracing::error;
 use windows::core::{s, Error, Result, HRESULT, PCSTR};
 use windows::Win32::Foundation::{FARPROC, HINSTANCE};
+use windows::Win32::Graphics::Gdi::WindowFromDC;
 use windows::Win32::Graphics::OpenGL::*;
 use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryA};
 
+use crate::renderer::backend::{build_font_atlas, convert_to_rgba8, mip_chain};
+use crate::renderer::texture::TextureRefs;
 use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::{
+    util, PixelFormat, RenderContext, RenderTargetHandle, TextureFilter, TextureHandle,
+    TextureOptions,
+};
 
 mod gl {
     #![allow(
@@ -33,6 +40,11 @@ mod gl {
     include!(concat!(env!("OUT_DIR"), "/gl_bindings.rs"));
 }
 
+// From `GL_ARB_texture_filter_anisotropic`, core since OpenGL 4.6, which the
+// bindings don't go up to.
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
 unsafe fn load_func(function_string: CString) -> *const c_void {
     static OPENGL3_LIB: OnceCell<HINSTANCE> = OnceCell::new();
     let module = OPENGL3_LIB
@@ -56,7 +68,12 @@ pub struct OpenGl3RenderEngine {
     uv_loc: GLuint,
     texture_loc: GLuint,
 
+    // Persistent, so that the vertex attributes are only touched in our own
+    // vertex array object and not the application's.
     vao: GLuint,
+    // `GL_PRIMITIVE_RESTART` is only known from OpenGL 3.1 on, and querying it
+    // before would raise an error that the application may pick up.
+    has_primitive_restart: bool,
 
     vertex_buffer: GLuint,
     index_buffer: GLuint,
@@ -78,7 +95,11 @@ impl OpenGl3RenderEngine {
 
         let vao = util::out_param(|x| unsafe { gl.GenVertexArrays(1, x) });
 
-        let texture_heap = TextureHeap::new();
+        let major = util::out_param(|x| unsafe { gl.GetIntegerv(gl::MAJOR_VERSION, x) });
+        let minor = util::out_param(|x| unsafe { gl.GetIntegerv(gl::MINOR_VERSION, x) });
+        let has_primitive_restart = (major, minor) >= (3, 1);
+
+        let texture_heap = TextureHeap::new(unsafe { max_anisotropy(&gl, (major, minor)) });
 
         ctx.set_ini_filename(None);
         ctx.set_renderer_name(String::from(concat!("hudhook-opengl3@", env!("CARGO_PKG_VERSION"))));
@@ -92,6 +113,7 @@ impl OpenGl3RenderEngine {
             uv_loc,
             texture_loc,
             vao,
+            has_primitive_restart,
             vertex_buffer,
             index_buffer,
             projection_buffer,
@@ -101,18 +123,68 @@ impl OpenGl3RenderEngine {
 }
 
 impl RenderContext for OpenGl3RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
-        unsafe { self.texture_heap.create_texture(&self.gl, data, width, height) }
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.load_texture_with_options(
+            data,
+            width,
+            height,
+            TextureOptions::default().with_format(format),
+        )
     }
 
-    fn replace_texture(
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        unsafe { self.texture_heap.create_texture(&self.gl, data, width, height, options) }
+    }
+
+    fn replace_texture_with_format(
         &mut self,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        unsafe { self.texture_heap.update_texture(&self.gl, texture_id, data, width, height) }
+        unsafe {
+            self.texture_heap.update_texture(&self.gl, texture_id, data, width, height, format)
+        }
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        unsafe { self.texture_heap.unload_texture(&self.gl, texture_id) }
+    }
+
+    fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        unsafe { self.texture_heap.create_render_texture(&self.gl, width, height) }
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.texture_heap.refs.handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        self.texture_heap
+            .get(texture_id)
+            .map(|texture| [texture.width as f32, texture.height as f32])
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        unsafe { self.capture_back_buffer() }
     }
 }
 
@@ -121,7 +193,9 @@ impl RenderEngine for OpenGl3RenderEngine {
 
     fn render(&mut self, draw_data: &DrawData, _render_target: Self::RenderTarget) -> Result<()> {
         unsafe {
-            let state_backup = StateBackup::backup(&self.gl);
+            self.texture_heap.free_released(&self.gl);
+
+            let state_backup = StateBackup::backup(&self.gl, self.has_primitive_restart);
             self.render_draw_data(draw_data)?;
             state_backup.restore(&self.gl);
         }
@@ -130,7 +204,7 @@ impl RenderEngine for OpenGl3RenderEngine {
 
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
-        let fonts_texture = fonts.build_rgba32_texture();
+        let fonts_texture = build_font_atlas(fonts, self.max_texture_size())?;
         fonts.tex_id =
             self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
         Ok(())
@@ -138,6 +212,59 @@ impl RenderEngine for OpenGl3RenderEngine {
 }
 
 impl OpenGl3RenderEngine {
+    fn max_texture_size(&self) -> u32 {
+        let mut max_texture_size = 0;
+        unsafe { self.gl.GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size) };
+        max_texture_size as u32
+    }
+
+    // Read the back buffer of the default framebuffer, i.e. the one of the
+    // window of the current device context.
+    unsafe fn capture_back_buffer(&self) -> Result<(u32, u32, Vec<u8>)> {
+        let gl = &self.gl;
+
+        let hwnd = WindowFromDC(wglGetCurrentDC());
+        let (width, height) = util::win_size(hwnd);
+        if width <= 0 || height <= 0 {
+            error!("Can't capture a screenshot of a {width}x{height} window");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        let last_read_framebuffer =
+            util::out_param(|x| gl.GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, x));
+        let last_read_buffer = util::out_param(|x| gl.GetIntegerv(gl::READ_BUFFER, x));
+        let last_pixel_pack_buffer =
+            util::out_param(|x| gl.GetIntegerv(gl::PIXEL_PACK_BUFFER_BINDING, x));
+        let last_pack_alignment = util::out_param(|x| gl.GetIntegerv(gl::PACK_ALIGNMENT, x));
+
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl.ReadBuffer(gl::BACK);
+        gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+
+        let row_len = width as usize * 4;
+        let mut pixels = vec![0u8; row_len * height as usize];
+        gl.ReadPixels(
+            0,
+            0,
+            width,
+            height,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut c_void,
+        );
+
+        gl.PixelStorei(gl::PACK_ALIGNMENT, last_pack_alignment);
+        gl.BindBuffer(gl::PIXEL_PACK_BUFFER, last_pixel_pack_buffer as _);
+        gl.ReadBuffer(last_read_buffer as _);
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, last_read_framebuffer as _);
+
+        // OpenGL rows go from the bottom up.
+        let pixels = pixels.chunks_exact(row_len).rev().flatten().copied().collect();
+
+        Ok((width as u32, height as u32, pixels))
+    }
+
     unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
         let [clip_offset_x, clip_offset_y] = draw_data.display_pos;
         let [clip_scale_w, clip_scale_h] = draw_data.framebuffer_scale;
@@ -176,6 +303,10 @@ impl OpenGl3RenderEngine {
                             continue;
                         }
 
+                        let Some(texture) = self.texture_heap.get(cmd_params.texture_id) else {
+                            continue;
+                        };
+
                         self.gl.Scissor(
                             clip_min_x as i32,
                             (fb_height - clip_max_y) as i32,
@@ -183,10 +314,7 @@ impl OpenGl3RenderEngine {
                             (clip_max_y - clip_min_y) as i32,
                         );
                         self.gl.ActiveTexture(gl::TEXTURE0);
-                        self.gl.BindTexture(
-                            gl::TEXTURE_2D,
-                            self.texture_heap.get(cmd_params.texture_id).gl_texture,
-                        );
+                        self.gl.BindTexture(gl::TEXTURE_2D, texture.gl_texture);
 
                         self.gl.BufferData(
                             gl::ARRAY_BUFFER,
@@ -235,7 +363,12 @@ impl OpenGl3RenderEngine {
         self.gl.Disable(gl::CULL_FACE);
         self.gl.Disable(gl::DEPTH_TEST);
         self.gl.Disable(gl::STENCIL_TEST);
+        self.gl.Disable(gl::COLOR_LOGIC_OP);
+        if self.has_primitive_restart {
+            self.gl.Disable(gl::PRIMITIVE_RESTART);
+        }
         self.gl.Enable(gl::SCISSOR_TEST);
+        self.gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
         self.gl.PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
 
         self.gl.Viewport(
@@ -349,22 +482,68 @@ unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint,
     (program, projection_loc, position_loc, color_loc, uv_loc, texture_loc)
 }
 
+// The most anisotropic filtering can be turned up to, if it's supported at all.
+// Without it, anisotropic textures get trilinear filtering.
+unsafe fn max_anisotropy(gl: &gl::Gl, version: (GLint, GLint)) -> Option<f32> {
+    let num_extensions = util::out_param(|x| gl.GetIntegerv(gl::NUM_EXTENSIONS, x));
+    let supported = version >= (4, 6)
+        || (0..num_extensions as GLuint).any(|i| {
+            let extension = gl.GetStringi(gl::EXTENSIONS, i);
+            !extension.is_null()
+                && matches!(
+                    CStr::from_ptr(extension as *const _).to_bytes(),
+                    b"GL_ARB_texture_filter_anisotropic" | b"GL_EXT_texture_filter_anisotropic"
+                )
+        });
+
+    supported.then(|| util::out_param(|x| gl.GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, x)))
+}
+
 struct TextureHeap {
-    textures: Vec<Texture>,
+    textures: Vec<Option<Texture>>,
+    refs: TextureRefs,
+    max_anisotropy: Option<f32>,
 }
 struct Texture {
     gl_texture: GLuint,
+    // The framebuffer object of render textures.
+    framebuffer: Option<GLuint>,
     width: u32,
     height: u32,
+    options: TextureOptions,
 }
 
 impl TextureHeap {
-    fn new() -> Self {
-        Self { textures: Vec::new() }
+    fn new(max_anisotropy: Option<f32>) -> Self {
+        Self { textures: Vec::new(), refs: TextureRefs::default(), max_anisotropy }
+    }
+
+    fn get(&self, texture_id: TextureId) -> Option<&Texture> {
+        self.textures.get(texture_id.id()).and_then(Option::as_ref)
+    }
+
+    unsafe fn unload_texture(&mut self, gl: &gl::Gl, texture_id: TextureId) -> Result<()> {
+        if self.refs.release(texture_id)? {
+            self.free_texture(gl, texture_id);
+        }
+
+        Ok(())
+    }
+
+    // Free the textures whose last handle has been dropped since the last frame.
+    unsafe fn free_released(&mut self, gl: &gl::Gl) {
+        for texture_id in self.refs.drain_released() {
+            self.free_texture(gl, texture_id);
+        }
     }
 
-    fn get(&self, texture_id: TextureId) -> &Texture {
-        &self.textures[texture_id.id()]
+    unsafe fn free_texture(&mut self, gl: &gl::Gl, texture_id: TextureId) {
+        if let Some(texture) = self.textures[texture_id.id()].take() {
+            gl.DeleteTextures(1, &texture.gl_texture);
+            if let Some(framebuffer) = texture.framebuffer {
+                gl.DeleteFramebuffers(1, &framebuffer);
+            }
+        }
     }
 
     unsafe fn create_texture(
@@ -373,34 +552,104 @@ impl TextureHeap {
         data: &[u8],
         width: u32,
         height: u32,
+        options: TextureOptions,
     ) -> Result<TextureId> {
+        let (gl_format, data) = upload_format(data, options.format);
+        let mips = if options.mipmaps { mip_chain(&data, width, height, 4) } else { Vec::new() };
         let texture = util::out_param(|x| gl.GenTextures(1, x));
 
-        let mut bound_texture = 0;
-        gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound_texture);
+        let (min_filter, mag_filter) = match (options.filter, options.mipmaps) {
+            (TextureFilter::Point, false) => (gl::NEAREST, gl::NEAREST),
+            (TextureFilter::Point, true) => (gl::NEAREST_MIPMAP_NEAREST, gl::NEAREST),
+            (TextureFilter::Linear | TextureFilter::Anisotropic, false) => (gl::LINEAR, gl::LINEAR),
+            (TextureFilter::Linear | TextureFilter::Anisotropic, true) => {
+                (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR)
+            },
+        };
 
-        gl.ActiveTexture(gl::TEXTURE0);
+        let upload_backup = UploadStateBackup::backup(gl);
         gl.BindTexture(gl::TEXTURE_2D, texture);
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as _);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as _);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, mips.len() as _);
+        if let (TextureFilter::Anisotropic, Some(max_anisotropy)) =
+            (options.filter, self.max_anisotropy)
+        {
+            gl.TexParameterf(gl::TEXTURE_2D, TEXTURE_MAX_ANISOTROPY, max_anisotropy);
+        }
+
+        for (level, (data, width, height)) in [(&data[..], width, height)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width, mip.height)))
+            .enumerate()
+        {
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                level as GLint,
+                gl::RGBA as GLint,
+                width as GLint,
+                height as GLint,
+                0,
+                gl_format,
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const c_void,
+            );
+        }
+        upload_backup.restore(gl);
 
-        gl.TexImage2D(
+        let id = TextureId::from(self.textures.len());
+        self.textures.push(Some(Texture {
+            gl_texture: texture,
+            framebuffer: None,
+            width,
+            height,
+            options,
+        }));
+        self.refs.insert(id);
+
+        Ok(id)
+    }
+
+    unsafe fn create_render_texture(
+        &mut self,
+        gl: &gl::Gl,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        // Uploading zeroes clears the texture without touching the state that
+        // clearing the framebuffer would depend on.
+        let id = self.create_texture(
+            gl,
+            &vec![0; width as usize * height as usize * 4],
+            width,
+            height,
+            TextureOptions::default(),
+        )?;
+        let texture = self.textures[id.id()].as_mut().expect("texture was just created");
+
+        let framebuffer = util::out_param(|x| gl.GenFramebuffers(1, x));
+        let last_framebuffer = util::out_param(|x| gl.GetIntegerv(gl::FRAMEBUFFER_BINDING, x));
+        gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl.FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
             gl::TEXTURE_2D,
+            texture.gl_texture,
             0,
-            gl::RGBA as GLint,
-            width as GLint,
-            height as GLint,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            data.as_ptr() as *const c_void,
         );
-        gl.BindTexture(gl::TEXTURE_2D, bound_texture as _);
+        let status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl.BindFramebuffer(gl::FRAMEBUFFER, last_framebuffer as _);
 
-        let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { gl_texture: texture, width, height });
+        texture.framebuffer = Some(framebuffer);
+        let handle = RenderTargetHandle::OpenGl { texture: texture.gl_texture, framebuffer };
 
-        Ok(id)
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            error!("render texture framebuffer is incomplete: {status:#x}");
+            self.free_texture(gl, id);
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        Ok((id, handle))
     }
 
     unsafe fn update_texture(
@@ -410,8 +659,12 @@ impl TextureHeap {
         data: &[u8],
         width: u32,
         height: u32,
+        format: PixelFormat,
     ) -> Result<()> {
-        let texture_info = self.get(texture);
+        let Some(texture_info) = self.get(texture) else {
+            error!("texture {texture:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
         if texture_info.width != width || texture_info.height != height {
             error!(
                 "image size {width}x{height} do not match expected {}x{}",
@@ -419,31 +672,111 @@ impl TextureHeap {
             );
             return Err(Error::from_hresult(HRESULT(-1)));
         }
+        if texture_info.options.format != format {
+            error!(
+                "texture {texture:?} was loaded as {:?}, not {format:?}",
+                texture_info.options.format
+            );
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
 
-        let mut bound_texture = 0;
-        gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound_texture);
+        let (gl_format, data) = upload_format(data, format);
+        let mips = if texture_info.options.mipmaps {
+            mip_chain(&data, width, height, 4)
+        } else {
+            Vec::new()
+        };
 
-        gl.ActiveTexture(gl::TEXTURE0);
+        let upload_backup = UploadStateBackup::backup(gl);
         gl.BindTexture(gl::TEXTURE_2D, texture_info.gl_texture);
 
-        gl.TexSubImage2D(
-            gl::TEXTURE_2D,
-            0,
-            0,
-            0,
-            width as GLint,
-            height as GLint,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            data.as_ptr() as *const c_void,
-        );
+        for (level, (data, width, height)) in [(&data[..], width, height)]
+            .into_iter()
+            .chain(mips.iter().map(|mip| (&mip.data[..], mip.width, mip.height)))
+            .enumerate()
+        {
+            gl.TexSubImage2D(
+                gl::TEXTURE_2D,
+                level as GLint,
+                0,
+                0,
+                width as GLint,
+                height as GLint,
+                gl_format,
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const c_void,
+            );
+        }
 
-        gl.BindTexture(gl::TEXTURE_2D, bound_texture as _);
+        upload_backup.restore(gl);
 
         Ok(())
     }
 }
 
+// The client format of the pixels uploaded for `format`, and the pixels
+// converted to it. Swizzling single-channel textures into gray needs OpenGL
+// 3.3, so those are expanded to RGBA8 instead.
+fn upload_format(data: &[u8], format: PixelFormat) -> (GLenum, Cow<'_, [u8]>) {
+    match format {
+        PixelFormat::Bgra8 => (gl::BGRA, Cow::Borrowed(data)),
+        format => (gl::RGBA, convert_to_rgba8(data, format)),
+    }
+}
+
+// The state that texture uploads depend on or change. The pixel data is read
+// from client memory, tightly packed, whatever the application left set up.
+struct UploadStateBackup {
+    last_active_texture: i32,
+    last_texture: i32,
+    last_pixel_unpack_buffer: i32,
+    last_unpack_row_length: i32,
+    last_unpack_skip_pixels: i32,
+    last_unpack_skip_rows: i32,
+    last_unpack_alignment: i32,
+}
+
+impl UploadStateBackup {
+    unsafe fn backup(gl: &gl::Gl) -> Self {
+        let last_active_texture = util::out_param(|x| gl.GetIntegerv(gl::ACTIVE_TEXTURE, x));
+        gl.ActiveTexture(gl::TEXTURE0);
+        let last_texture = util::out_param(|x| gl.GetIntegerv(gl::TEXTURE_BINDING_2D, x));
+        let last_pixel_unpack_buffer =
+            util::out_param(|x| gl.GetIntegerv(gl::PIXEL_UNPACK_BUFFER_BINDING, x));
+        let last_unpack_row_length = util::out_param(|x| gl.GetIntegerv(gl::UNPACK_ROW_LENGTH, x));
+        let last_unpack_skip_pixels =
+            util::out_param(|x| gl.GetIntegerv(gl::UNPACK_SKIP_PIXELS, x));
+        let last_unpack_skip_rows = util::out_param(|x| gl.GetIntegerv(gl::UNPACK_SKIP_ROWS, x));
+        let last_unpack_alignment = util::out_param(|x| gl.GetIntegerv(gl::UNPACK_ALIGNMENT, x));
+
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, 0);
+        gl.PixelStorei(gl::UNPACK_SKIP_ROWS, 0);
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+
+        Self {
+            last_active_texture,
+            last_texture,
+            last_pixel_unpack_buffer,
+            last_unpack_row_length,
+            last_unpack_skip_pixels,
+            last_unpack_skip_rows,
+            last_unpack_alignment,
+        }
+    }
+
+    unsafe fn restore(self, gl: &gl::Gl) {
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, self.last_unpack_alignment);
+        gl.PixelStorei(gl::UNPACK_SKIP_ROWS, self.last_unpack_skip_rows);
+        gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, self.last_unpack_skip_pixels);
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, self.last_unpack_row_length);
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, self.last_pixel_unpack_buffer as _);
+        gl.BindTexture(gl::TEXTURE_2D, self.last_texture as _);
+        gl.ActiveTexture(self.last_active_texture as _);
+    }
+}
+
 struct StateBackup {
     last_active_texture: i32,
     last_program: i32,
@@ -464,11 +797,15 @@ struct StateBackup {
     last_enable_blend: bool,
     last_enable_cull_face: bool,
     last_enable_depth_test: bool,
+    last_enable_stencil_test: bool,
     last_enable_scissor_test: bool,
+    last_enable_color_logic_op: bool,
+    last_enable_primitive_restart: Option<bool>,
+    last_color_mask: [u8; 4],
 }
 
 impl StateBackup {
-    unsafe fn backup(gl: &gl::Gl) -> StateBackup {
+    unsafe fn backup(gl: &gl::Gl, has_primitive_restart: bool) -> StateBackup {
         let last_active_texture = util::out_param(|x| gl.GetIntegerv(gl::ACTIVE_TEXTURE, x));
         gl.ActiveTexture(gl::TEXTURE0);
         let last_program = util::out_param(|x| gl.GetIntegerv(gl::CURRENT_PROGRAM, x));
@@ -499,7 +836,14 @@ impl StateBackup {
         let last_enable_blend = gl.IsEnabled(gl::BLEND) == gl::TRUE;
         let last_enable_cull_face = gl.IsEnabled(gl::CULL_FACE) == gl::TRUE;
         let last_enable_depth_test = gl.IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+        let last_enable_stencil_test = gl.IsEnabled(gl::STENCIL_TEST) == gl::TRUE;
         let last_enable_scissor_test = gl.IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+        let last_enable_color_logic_op = gl.IsEnabled(gl::COLOR_LOGIC_OP) == gl::TRUE;
+        let last_enable_primitive_restart =
+            has_primitive_restart.then(|| gl.IsEnabled(gl::PRIMITIVE_RESTART) == gl::TRUE);
+        let last_color_mask = util::out_param(|x: &mut [GLboolean; 4]| {
+            gl.GetBooleanv(gl::COLOR_WRITEMASK, x.as_mut_ptr())
+        });
 
         StateBackup {
             last_active_texture,
@@ -521,7 +865,11 @@ impl StateBackup {
             last_enable_blend,
             last_enable_cull_face,
             last_enable_depth_test,
+            last_enable_stencil_test,
             last_enable_scissor_test,
+            last_enable_color_logic_op,
+            last_enable_primitive_restart,
+            last_color_mask,
         }
     }
 
@@ -546,7 +894,11 @@ impl StateBackup {
             last_enable_blend,
             last_enable_cull_face,
             last_enable_depth_test,
+            last_enable_stencil_test,
             last_enable_scissor_test,
+            last_enable_color_logic_op,
+            last_enable_primitive_restart,
+            last_color_mask,
         } = self;
 
         gl.UseProgram(last_program as _);
@@ -580,11 +932,28 @@ impl StateBackup {
         } else {
             gl.Disable(gl::DEPTH_TEST)
         };
+        if last_enable_stencil_test {
+            gl.Enable(gl::STENCIL_TEST)
+        } else {
+            gl.Disable(gl::STENCIL_TEST)
+        };
         if last_enable_scissor_test {
             gl.Enable(gl::SCISSOR_TEST)
         } else {
             gl.Disable(gl::SCISSOR_TEST)
         };
+        if last_enable_color_logic_op {
+            gl.Enable(gl::COLOR_LOGIC_OP)
+        } else {
+            gl.Disable(gl::COLOR_LOGIC_OP)
+        };
+        match last_enable_primitive_restart {
+            Some(true) => gl.Enable(gl::PRIMITIVE_RESTART),
+            Some(false) => gl.Disable(gl::PRIMITIVE_RESTART),
+            None => {},
+        }
+        let [r, g, b, a] = last_color_mask;
+        gl.ColorMask(r, g, b, a);
         gl.PolygonMode(gl::FRONT_AND_BACK, last_polygon_mode[0] as _);
         gl.Viewport(
             last_viewport[0] as _,
@@ -0,0 +1,136 @@
+//! This module contains the color spaces the overlay can be presented in.
+
+use windows::core::Interface;
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020, DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT,
+};
+use windows::Win32::Graphics::Dxgi::{IDXGIOutput6, IDXGISwapChain};
+
+use crate::util;
+
+// Luminance of SDR white, in nits, that the HDR color spaces are encoded with.
+// Windows shows SDR content at 80 nits by default, the scRGB reference white.
+const SDR_WHITE_NITS: f32 = 80.;
+
+/// The color space the back buffers of a swap chain are presented in. See
+/// [`RenderContext::swap_chain_color_space`](crate::RenderContext::swap_chain_color_space).
+///
+/// imgui's colors are authored in sRGB, and written as they are, so they only
+/// look as intended in [`ColorSpace::Srgb`]. Use
+/// [`ColorSpace::convert_srgb`] to convert them to the others, e.g. for the
+/// colors of the [`imgui::Style`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ColorSpace {
+    /// Standard dynamic range, sRGB encoded colors.
+    #[default]
+    Srgb,
+    /// Linear colors with the Rec. 709 primaries, in 16-bit float back
+    /// buffers, where 1.0 is the SDR white. sRGB colors look washed out in
+    /// it.
+    ScRgb,
+    /// Colors with the Rec. 2020 primaries, encoded with the SMPTE ST 2084
+    /// (PQ) curve, in 10-bit back buffers. sRGB colors look washed out and
+    /// overly bright in it.
+    Hdr10,
+}
+
+impl ColorSpace {
+    /// Guess the color space of the swap chain: DXGI keeps track of the one
+    /// set by the application, but doesn't expose it.
+    ///
+    /// 16-bit float back buffers are always presented as scRGB. 10-bit ones
+    /// are taken as HDR10 while the display they're on is in HDR mode, and as
+    /// sRGB otherwise.
+    pub(crate) fn of_swap_chain(swap_chain: &IDXGISwapChain) -> Self {
+        let Ok(desc) = util::try_out_param(|v| unsafe { swap_chain.GetDesc(v) }) else {
+            return ColorSpace::Srgb;
+        };
+
+        match desc.BufferDesc.Format {
+            DXGI_FORMAT_R16G16B16A16_FLOAT => ColorSpace::ScRgb,
+            DXGI_FORMAT_R10G10B10A2_UNORM => {
+                let output_color_space = unsafe { swap_chain.GetContainingOutput() }
+                    .and_then(|output| output.cast::<IDXGIOutput6>())
+                    .and_then(|output| unsafe { output.GetDesc1() })
+                    .map(|desc| desc.ColorSpace);
+
+                match output_color_space {
+                    Ok(DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020) => ColorSpace::Hdr10,
+                    _ => ColorSpace::Srgb,
+                }
+            },
+            _ => ColorSpace::Srgb,
+        }
+    }
+
+    /// Convert an sRGB color to this color space, so that it looks the same
+    /// as it would in SDR. The alpha is left as it is.
+    ///
+    /// imgui blends in the color space of the back buffer, so translucent
+    /// colors still blend a bit differently than they would in SDR.
+    pub fn convert_srgb(self, [r, g, b, a]: [f32; 4]) -> [f32; 4] {
+        match self {
+            ColorSpace::Srgb => [r, g, b, a],
+            ColorSpace::ScRgb => [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a],
+            ColorSpace::Hdr10 => {
+                let [r, g, b] =
+                    rec709_to_rec2020([srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)]);
+                let scale = SDR_WHITE_NITS / 10000.;
+                [pq_encode(r * scale), pq_encode(g * scale), pq_encode(b * scale), a]
+            },
+        }
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Convert linear colors from the Rec. 709 to the Rec. 2020 primaries, as per
+// ITU-R BT.2087.
+fn rec709_to_rec2020([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        0.6274 * r + 0.3293 * g + 0.0433 * b,
+        0.0691 * r + 0.9195 * g + 0.0114 * b,
+        0.0164 * r + 0.0880 * g + 0.8956 * b,
+    ]
+}
+
+// Encode a linear luminance, relative to 10000 nits, with the PQ curve.
+fn pq_encode(y: f32) -> f32 {
+    const M1: f32 = 2610. / 16384.;
+    const M2: f32 = 2523. / 4096. * 128.;
+    const C1: f32 = 3424. / 4096.;
+    const C2: f32 = 2413. / 4096. * 32.;
+    const C3: f32 = 2392. / 4096. * 32.;
+
+    let y = y.clamp(0., 1.).powf(M1);
+    ((C1 + C2 * y) / (1. + C3 * y)).powf(M2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f32; 4], b: [f32; 4]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_convert_srgb() {
+        let color = [1., 0.5, 0., 0.5];
+        assert_eq!(ColorSpace::Srgb.convert_srgb(color), color);
+        assert_close(ColorSpace::ScRgb.convert_srgb(color), [1., 0.214, 0., 0.5]);
+
+        // 80 nits white.
+        assert_close(ColorSpace::Hdr10.convert_srgb([1., 1., 1., 1.]), [0.486, 0.486, 0.486, 1.]);
+        assert_close(ColorSpace::Hdr10.convert_srgb([0., 0., 0., 0.]), [0., 0., 0., 0.]);
+        assert!((pq_encode(1.) - 1.).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,268 @@
+//! This module contains the pipeline options set via
+//! [`HudhookBuilder`](crate::HudhookBuilder).
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use imgui::{Context, Style, SuspendedContext, Ui};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+use windows::Win32::Foundation::RECT;
+use windows::Win32::Graphics::Dxgi::DXGI_SWAP_CHAIN_DESC;
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+
+use crate::message::MessageReceiver;
+
+/// Controls what happens when keyboard or gamepad navigation moves the mouse
+/// cursor, i.e. imgui's `NavEnableSetMousePos` behavior.
+///
+/// The navigation is only active if it has been enabled on the imgui context,
+/// e.g. via [`imgui::ConfigFlags::NAV_ENABLE_KEYBOARD`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NavMouseWarp {
+    /// Navigation never moves the mouse cursor.
+    #[default]
+    Disabled,
+    /// Navigation moves imgui's own mouse position to the focused widget. The
+    /// OS cursor, and thus the cursor seen by the application, doesn't move.
+    Overlay,
+    /// Navigation moves both imgui's mouse position and the OS cursor to the
+    /// focused widget. The application sees the cursor move, which may
+    /// interfere with games that read it.
+    Os,
+}
+
+/// What to do with a frame that has more imgui windows than the limit set via
+/// [`HudhookBuilder::with_window_limit`](crate::HudhookBuilder::with_window_limit).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WindowLimitAction {
+    /// Log a warning and render the frame anyway.
+    #[default]
+    Warn,
+    /// Log a warning and don't render the frame.
+    Skip,
+}
+
+/// Which command queue the DirectX 12 overlay renders on. Set via
+/// [`HudhookBuilder::with_dx12_command_queue`](crate::HudhookBuilder::with_dx12_command_queue).
+///
+/// The application's queue is captured by hooking
+/// `ID3D12CommandQueue::ExecuteCommandLists`: the first direct queue whose
+/// pointer is found in the presenting swap chain is the one it presents on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dx12CommandQueue {
+    /// Only render on the application's queue. Nothing is drawn until it has
+    /// been captured.
+    #[default]
+    Captured,
+    /// Render on the application's queue if it has been captured by the time
+    /// the overlay is initialized on the first present, and on a new direct
+    /// queue on the swap chain's device otherwise. The application's queue is
+    /// no longer captured afterwards.
+    ///
+    /// The overlay is drawn right away, but without any ordering with the
+    /// application's work on the back buffer, which can cause flickering.
+    CapturedOrOwn,
+}
+
+/// How the overlay receives the messages of the application window. Set via
+/// [`HudhookBuilder::with_input_mode`](crate::HudhookBuilder::with_input_mode).
+///
+/// Games that hook or subclass their own window procedure may not get along
+/// with the default mode, e.g. by replacing the overlay's procedure, or by
+/// calling it again for the messages it forwards, in which case the input
+/// ends up lost, or handled twice. Pick the one that works for the game.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputMode {
+    /// Replace the window procedure via `SetWindowLongPtrW`, and forward the
+    /// messages to the previous one. The overlay sees every message, and can
+    /// block any of them.
+    ///
+    /// The procedure is replaced as is, regardless of what the application
+    /// does with it, so it can be undone by an application that replaces it
+    /// again, or that restores a procedure it saved beforehand.
+    #[default]
+    WndProcTrampoline,
+    /// Subclass the window via `SetWindowSubclass`, which chains with the
+    /// other subclasses of the window instead of replacing its procedure, so
+    /// that an application subclassing its window too keeps receiving each
+    /// message once, and the overlay too. The overlay sees and can block
+    /// every message, as with [`InputMode::WndProcTrampoline`].
+    ///
+    /// A window can only be subclassed from its own thread: if the overlay
+    /// is set up from another one, as is the case with applications that
+    /// present from a render thread, this falls back to
+    /// [`InputMode::WndProcTrampoline`], with a warning.
+    Subclass,
+    /// Leave the window procedure alone, and only see the input messages,
+    /// i.e. those the window's thread takes off its message queue, via a
+    /// `WH_GETMESSAGE` hook set with `SetWindowsHookExW`: the keyboard, the
+    /// mouse, the raw input and the characters typed. The least intrusive
+    /// mode, which can't conflict with what the application does with its
+    /// window procedure.
+    ///
+    /// The messages sent to the window rather than posted are never seen,
+    /// e.g. `WM_SETCURSOR`, `WM_KILLFOCUS` or the IME ones: the cursor
+    /// doesn't take the shape imgui asks for unless imgui draws it, see
+    /// [`imgui::Io::mouse_draw_cursor`], the keys held when the window loses
+    /// the focus stay down until pressed again, and text input through an
+    /// IME isn't supported. The cursor mode can't be undone from the window's
+    /// thread when the overlay is ejected, so the cursor stays shown if it
+    /// was. The messages blocked or handled by the overlay are dropped, and
+    /// the results of [`ImguiRenderLoop::on_wnd_proc`] aren't returned to
+    /// anyone.
+    ///
+    /// [`ImguiRenderLoop::on_wnd_proc`]: crate::ImguiRenderLoop::on_wnd_proc
+    RawInputOnly,
+}
+
+/// The style the imgui context is set up with. Set via
+/// [`HudhookBuilder::with_style`](crate::HudhookBuilder::with_style).
+#[derive(Debug, Clone, Copy)]
+pub enum StylePreset {
+    /// imgui's default dark colors.
+    Dark,
+    /// imgui's light colors.
+    Light,
+    /// imgui's classic colors.
+    Classic,
+    /// A style set up by the function, e.g. colors and rounding of its own,
+    /// starting from imgui's default one.
+    Custom(fn(&mut Style)),
+}
+
+impl StylePreset {
+    pub(crate) fn apply(self, style: &mut Style) {
+        match self {
+            StylePreset::Dark => {
+                style.use_dark_colors();
+            },
+            StylePreset::Light => {
+                style.use_light_colors();
+            },
+            StylePreset::Classic => {
+                style.use_classic_colors();
+            },
+            StylePreset::Custom(f) => f(style),
+        }
+    }
+}
+
+/// A HUD drawn over its own part of the display, added via
+/// [`HudhookBuilder::with_layer`](crate::HudhookBuilder::with_layer).
+#[derive(Clone)]
+pub(crate) struct Layer {
+    pub(crate) name: String,
+    pub(crate) rect: Arc<dyn Fn(u32, u32) -> RECT + Send + Sync>,
+    pub(crate) render: Arc<Mutex<dyn FnMut(&Ui, [[f32; 2]; 2]) + Send>>,
+}
+
+/// Options applied to each pipeline when it's created.
+#[derive(Clone)]
+pub(crate) struct PipelineConfig {
+    pub(crate) nav_mouse_warp: NavMouseWarp,
+    pub(crate) toggle_key: Option<VIRTUAL_KEY>,
+    pub(crate) input_mode: InputMode,
+    pub(crate) window_limit: Option<(usize, WindowLimitAction)>,
+    pub(crate) dx12_command_queue: Dx12CommandQueue,
+    pub(crate) swap_chain_selector:
+        Option<Arc<dyn Fn(&DXGI_SWAP_CHAIN_DESC) -> bool + Send + Sync>>,
+    pub(crate) gamepad_nav: bool,
+    pub(crate) gamepad_index: u32,
+    pub(crate) dpi_scaling: bool,
+    pub(crate) content_rect: Option<Arc<dyn Fn(u32, u32) -> RECT + Send + Sync>>,
+    pub(crate) layers: Vec<Layer>,
+    pub(crate) click_through: bool,
+    pub(crate) async_frames: bool,
+    pub(crate) catch_panics: bool,
+    pub(crate) frame_limit: Option<f32>,
+    pub(crate) messages: Option<Arc<MessageReceiver>>,
+    pub(crate) ini_filename: Option<PathBuf>,
+    pub(crate) session_layout: bool,
+    pub(crate) style: Option<StylePreset>,
+    pub(crate) imgui_settings: Option<Arc<dyn Fn(&mut Context) + Send + Sync>>,
+    #[cfg(feature = "imgui-docking")]
+    pub(crate) docking: bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            nav_mouse_warp: NavMouseWarp::default(),
+            toggle_key: None,
+            input_mode: InputMode::default(),
+            window_limit: None,
+            dx12_command_queue: Dx12CommandQueue::default(),
+            swap_chain_selector: None,
+            gamepad_nav: false,
+            gamepad_index: 0,
+            dpi_scaling: true,
+            content_rect: None,
+            layers: Vec::new(),
+            click_through: false,
+            async_frames: false,
+            catch_panics: true,
+            frame_limit: None,
+            messages: None,
+            ini_filename: None,
+            session_layout: false,
+            style: None,
+            imgui_settings: None,
+            #[cfg(feature = "imgui-docking")]
+            docking: false,
+        }
+    }
+}
+
+impl PipelineConfig {
+    // The settings file, if any, already outlives the pipelines.
+    pub(crate) fn keeps_session_layout(&self) -> bool {
+        self.session_layout && self.ini_filename.is_none()
+    }
+}
+
+pub(crate) static PIPELINE_CONFIG: Lazy<Mutex<PipelineConfig>> =
+    Lazy::new(|| Mutex::new(PipelineConfig::default()));
+
+// The context supplied via `HudhookBuilder::with_context`, suspended until a
+// pipeline adopts it.
+struct SuppliedContext(SuspendedContext);
+unsafe impl Send for SuppliedContext {}
+
+static SUPPLIED_CONTEXT: Lazy<Mutex<Option<SuppliedContext>>> = Lazy::new(|| Mutex::new(None));
+
+pub(crate) fn supply_context(ctx: Context) {
+    *SUPPLIED_CONTEXT.lock() = Some(SuppliedContext(ctx.suspend()));
+}
+
+/// Create the context of a new pipeline. The first pipeline adopts the
+/// context supplied via
+/// [`HudhookBuilder::with_context`](crate::HudhookBuilder::with_context), if
+/// any.
+///
+/// imgui only allows one active context, so this fails if another one is,
+/// e.g. if the context passed to `with_context` wasn't the last one created,
+/// or one was activated afterwards. The supplied context is then kept for the
+/// next pipeline.
+pub(crate) fn create_context() -> Result<Context> {
+    let mut supplied = SUPPLIED_CONTEXT.lock();
+
+    match supplied.take() {
+        Some(SuppliedContext(ctx)) => ctx.activate().map_err(|ctx| {
+            error!(
+                "Could not activate the context supplied via `HudhookBuilder::with_context`: \
+                 another imgui context is active, and must be suspended or dropped first"
+            );
+            *supplied = Some(SuppliedContext(ctx));
+            Error::from_hresult(HRESULT(-1))
+        }),
+        // imgui asserts that there's none when creating one.
+        None if !unsafe { imgui::sys::igGetCurrentContext() }.is_null() => {
+            error!("Could not create the imgui context: another one is active");
+            Err(Error::from_hresult(HRESULT(-1)))
+        },
+        None => Ok(Context::create()),
+    }
+}
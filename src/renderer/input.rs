@@ -3,16 +3,23 @@
 use std::ffi::c_void;
 use std::mem::size_of;
 
-use imgui::{Io, Key, MouseButton};
-use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use imgui::{BackendFlags, Io, Key, MouseButton};
+use windows::Win32::Foundation::{ERROR_SUCCESS, HWND, LPARAM, LRESULT, WPARAM};
 use windows::Win32::UI::Input::KeyboardAndMouse::*;
+use windows::Win32::UI::Input::XboxController::{
+    XInputGetState, XINPUT_GAMEPAD, XINPUT_GAMEPAD_A, XINPUT_GAMEPAD_B, XINPUT_GAMEPAD_BACK,
+    XINPUT_GAMEPAD_BUTTON_FLAGS, XINPUT_GAMEPAD_DPAD_DOWN, XINPUT_GAMEPAD_DPAD_LEFT,
+    XINPUT_GAMEPAD_DPAD_RIGHT, XINPUT_GAMEPAD_DPAD_UP, XINPUT_GAMEPAD_LEFT_SHOULDER,
+    XINPUT_GAMEPAD_LEFT_THUMB, XINPUT_GAMEPAD_RIGHT_SHOULDER, XINPUT_GAMEPAD_RIGHT_THUMB,
+    XINPUT_GAMEPAD_START, XINPUT_GAMEPAD_X, XINPUT_GAMEPAD_Y, XINPUT_STATE,
+};
 use windows::Win32::UI::Input::{
     GetRawInputData, HRAWINPUT, MOUSE_MOVE_ABSOLUTE, RAWINPUT, RAWINPUTHEADER, RAWKEYBOARD,
     RAWMOUSE, RID_DEVICE_INFO_TYPE, RID_INPUT, RIM_TYPEKEYBOARD, RIM_TYPEMOUSE,
 };
 use windows::Win32::UI::WindowsAndMessaging::*;
 
-use super::keys::vk_to_imgui;
+use super::keys::{vk_to_imgui, KeyState};
 use crate::renderer::{Pipeline, RenderEngine};
 
 pub type WndProcType =
@@ -51,7 +58,8 @@ pub fn lowordi(l: u32) -> i16 {
 // Given the RAWINPUT structure, check each possible mouse flag status and
 // update the Io object accordingly. Both the key_down indices associated to the
 // mouse click (VK_...) and the values in mouse_down are updated.
-fn handle_raw_mouse_input(io: &mut Io, raw_mouse: &RAWMOUSE) {
+// Returns the relative movement of the mouse, if it reports one.
+fn handle_raw_mouse_input(io: &mut Io, raw_mouse: &RAWMOUSE) -> Option<[f32; 2]> {
     let button_data = unsafe { raw_mouse.Anonymous.Anonymous };
     let button_flags = button_data.usButtonFlags as u32;
 
@@ -96,8 +104,10 @@ fn handle_raw_mouse_input(io: &mut Io, raw_mouse: &RAWMOUSE) {
 
     if (mouse_flags.0 & MOUSE_MOVE_ABSOLUTE.0) != 0 {
         io.add_mouse_pos_event([last_x, last_y]);
+        None
     } else {
         io.add_mouse_pos_event([io.mouse_pos[0] + last_x, io.mouse_pos[1] + last_y]);
+        Some([last_x, last_y])
     }
 }
 
@@ -153,8 +163,12 @@ fn handle_raw_keyboard_input(io: &mut Io, raw_keyboard: &RAWKEYBOARD) {
     }
 }
 
-// Handle WM_INPUT events.
-fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM) {
+// Handle WM_INPUT events. Returns the relative movement of the mouse, if any.
+fn handle_raw_input(
+    io: &mut Io,
+    WPARAM(wparam): WPARAM,
+    LPARAM(lparam): LPARAM,
+) -> Option<[f32; 2]> {
     let mut raw_data = RAWINPUT { ..Default::default() };
     let mut raw_data_size = size_of::<RAWINPUT>() as u32;
     let raw_data_header_size = size_of::<RAWINPUTHEADER>() as u32;
@@ -172,23 +186,22 @@ fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM)
 
     // If GetRawInputData errors out, return false.
     if r == u32::MAX {
-        return;
+        return None;
     }
 
     // Ignore messages when window is not focused.
     if (wparam as u32 & 0xFFu32) != RIM_INPUT {
-        return;
+        return None;
     }
 
     // Dispatch to the appropriate raw input processing method.
     match RID_DEVICE_INFO_TYPE(raw_data.header.dwType) {
-        RIM_TYPEMOUSE => {
-            handle_raw_mouse_input(io, unsafe { &raw_data.data.mouse });
-        },
+        RIM_TYPEMOUSE => handle_raw_mouse_input(io, unsafe { &raw_data.data.mouse }),
         RIM_TYPEKEYBOARD => {
             handle_raw_keyboard_input(io, unsafe { &raw_data.data.keyboard });
+            None
         },
-        _ => {},
+        _ => None,
     }
 }
 
@@ -266,12 +279,156 @@ fn handle_input(io: &mut Io, state: u32, WPARAM(wparam): WPARAM, LPARAM(lparam):
     // TODO: Workarounds https://github.com/ocornut/imgui/blob/da29b776eed289db16a8527e5f16a0e1fa540251/backends/imgui_impl_win32.cpp#L263
 }
 
+// The key and button ups aren't received once the window has lost the focus, so
+// release everything imgui may still consider held, e.g. after alt-tabbing in
+// the middle of a drag.
+fn release_all(io: &mut Io) {
+    for key in Key::VARIANTS {
+        io.add_key_event(key, false);
+    }
+    for button in MouseButton::VARIANTS {
+        io.add_mouse_button_event(button, false);
+    }
+}
+
+// Track the keys for the keybinds of the render loop, both as the generic
+// virtual key and as its left or right variant. Bit 30 of `lparam` is set on
+// auto-repeat.
+fn track_key(keys: &mut KeyState, state: u32, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM) {
+    let is_key_down = (state == WM_KEYDOWN) || (state == WM_SYSKEYDOWN);
+    let is_repeat = lparam & (1 << 30) != 0;
+    let virtual_key = VIRTUAL_KEY(wparam as _);
+    let scancode = map_vkey(wparam as _, lparam as _);
+
+    keys.on_key(virtual_key, is_key_down, is_repeat);
+    if scancode != virtual_key {
+        keys.on_key(scancode, is_key_down, is_repeat);
+    }
+}
+
+// Handle WM_CHAR events.
+//
+// Characters outside of the basic multilingual plane, such as emojis, are sent
+// as two messages, one per UTF-16 surrogate. Neither is a valid `char` on its
+// own, so the code units are passed to imgui, which pairs them up.
+fn handle_char(io: &mut Io, code_unit: u16) {
+    unsafe { imgui::sys::ImGuiIO_AddInputCharacterUTF16(io as *mut Io as *mut _, code_unit) };
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Gamepad
+////////////////////////////////////////////////////////////////////////////////
+
+// Default values from the XInput headers.
+const LEFT_THUMB_DEADZONE: f32 = 7849.0;
+const RIGHT_THUMB_DEADZONE: f32 = 8689.0;
+const TRIGGER_THRESHOLD: f32 = 30.0;
+
+const GAMEPAD_KEYS: [Key; 24] = [
+    Key::GamepadStart,
+    Key::GamepadBack,
+    Key::GamepadFaceLeft,
+    Key::GamepadFaceRight,
+    Key::GamepadFaceUp,
+    Key::GamepadFaceDown,
+    Key::GamepadDpadLeft,
+    Key::GamepadDpadRight,
+    Key::GamepadDpadUp,
+    Key::GamepadDpadDown,
+    Key::GamepadL1,
+    Key::GamepadR1,
+    Key::GamepadL2,
+    Key::GamepadR2,
+    Key::GamepadL3,
+    Key::GamepadR3,
+    Key::GamepadLStickLeft,
+    Key::GamepadLStickRight,
+    Key::GamepadLStickUp,
+    Key::GamepadLStickDown,
+    Key::GamepadRStickLeft,
+    Key::GamepadRStickRight,
+    Key::GamepadRStickUp,
+    Key::GamepadRStickDown,
+];
+
+// Poll the state of the XInput controller `user_index` and feed it to imgui.
+//
+// The state is only read, so the application keeps seeing the controller as
+// usual. `last_packet` holds the packet number of the last state fed to imgui,
+// so that an unchanged state isn't fed again every frame.
+pub fn update_gamepad(io: &mut Io, user_index: u32, last_packet: &mut Option<u32>) {
+    let mut state = XINPUT_STATE::default();
+    if unsafe { XInputGetState(user_index, &mut state) } != ERROR_SUCCESS.0 {
+        io.backend_flags.remove(BackendFlags::HAS_GAMEPAD);
+
+        // Release everything that was held when the controller disconnected.
+        if last_packet.take().is_some() {
+            for key in GAMEPAD_KEYS {
+                io.add_key_event(key, false);
+            }
+        }
+        return;
+    }
+
+    io.backend_flags.insert(BackendFlags::HAS_GAMEPAD);
+
+    if *last_packet == Some(state.dwPacketNumber) {
+        return;
+    }
+    *last_packet = Some(state.dwPacketNumber);
+
+    handle_gamepad(io, &state.Gamepad);
+}
+
+// Map the buttons, triggers and sticks of an XInput gamepad to imgui's keys.
+pub fn handle_gamepad(io: &mut Io, gamepad: &XINPUT_GAMEPAD) {
+    let button = |io: &mut Io, key: Key, flag: XINPUT_GAMEPAD_BUTTON_FLAGS| {
+        io.add_key_event(key, gamepad.wButtons.0 & flag.0 != 0);
+    };
+
+    button(io, Key::GamepadStart, XINPUT_GAMEPAD_START);
+    button(io, Key::GamepadBack, XINPUT_GAMEPAD_BACK);
+    button(io, Key::GamepadFaceLeft, XINPUT_GAMEPAD_X);
+    button(io, Key::GamepadFaceRight, XINPUT_GAMEPAD_B);
+    button(io, Key::GamepadFaceUp, XINPUT_GAMEPAD_Y);
+    button(io, Key::GamepadFaceDown, XINPUT_GAMEPAD_A);
+    button(io, Key::GamepadDpadLeft, XINPUT_GAMEPAD_DPAD_LEFT);
+    button(io, Key::GamepadDpadRight, XINPUT_GAMEPAD_DPAD_RIGHT);
+    button(io, Key::GamepadDpadUp, XINPUT_GAMEPAD_DPAD_UP);
+    button(io, Key::GamepadDpadDown, XINPUT_GAMEPAD_DPAD_DOWN);
+    button(io, Key::GamepadL1, XINPUT_GAMEPAD_LEFT_SHOULDER);
+    button(io, Key::GamepadR1, XINPUT_GAMEPAD_RIGHT_SHOULDER);
+    button(io, Key::GamepadL3, XINPUT_GAMEPAD_LEFT_THUMB);
+    button(io, Key::GamepadR3, XINPUT_GAMEPAD_RIGHT_THUMB);
+
+    // Map a value in `v0..v1` to `0.0..1.0`, the key being down past 10%.
+    let analog = |io: &mut Io, key: Key, value: f32, v0: f32, v1: f32| {
+        let value = ((value - v0) / (v1 - v0)).clamp(0.0, 1.0);
+        io.add_key_analog_event(key, value > 0.1, value);
+    };
+
+    analog(io, Key::GamepadL2, gamepad.bLeftTrigger as f32, TRIGGER_THRESHOLD, 255.0);
+    analog(io, Key::GamepadR2, gamepad.bRightTrigger as f32, TRIGGER_THRESHOLD, 255.0);
+
+    let (lx, ly) = (gamepad.sThumbLX as f32, gamepad.sThumbLY as f32);
+    analog(io, Key::GamepadLStickLeft, lx, -LEFT_THUMB_DEADZONE, -32768.0);
+    analog(io, Key::GamepadLStickRight, lx, LEFT_THUMB_DEADZONE, 32767.0);
+    analog(io, Key::GamepadLStickUp, ly, LEFT_THUMB_DEADZONE, 32767.0);
+    analog(io, Key::GamepadLStickDown, ly, -LEFT_THUMB_DEADZONE, -32768.0);
+
+    let (rx, ry) = (gamepad.sThumbRX as f32, gamepad.sThumbRY as f32);
+    analog(io, Key::GamepadRStickLeft, rx, -RIGHT_THUMB_DEADZONE, -32768.0);
+    analog(io, Key::GamepadRStickRight, rx, RIGHT_THUMB_DEADZONE, 32767.0);
+    analog(io, Key::GamepadRStickUp, ry, RIGHT_THUMB_DEADZONE, 32767.0);
+    analog(io, Key::GamepadRStickDown, ry, -RIGHT_THUMB_DEADZONE, -32768.0);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Window procedure
 ////////////////////////////////////////////////////////////////////////////////
 
 pub fn imgui_wnd_proc_impl<T: RenderEngine>(
-    hwnd: HWND,
+    _hwnd: HWND,
     umsg: u32,
     WPARAM(wparam): WPARAM,
     LPARAM(lparam): LPARAM,
@@ -280,9 +437,14 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
     let io = pipeline.context().io_mut();
 
     match umsg {
-        WM_INPUT => handle_raw_input(io, WPARAM(wparam), LPARAM(lparam)),
+        WM_INPUT => {
+            if let Some(delta) = handle_raw_input(io, WPARAM(wparam), LPARAM(lparam)) {
+                pipeline.keys().on_raw_mouse_move(delta);
+            }
+        },
         state @ (WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP) if wparam < 256 => {
-            handle_input(io, state, WPARAM(wparam), LPARAM(lparam))
+            handle_input(io, state, WPARAM(wparam), LPARAM(lparam));
+            track_key(pipeline.keys(), state, WPARAM(wparam), LPARAM(lparam));
         },
         WM_LBUTTONDOWN | WM_LBUTTONDBLCLK => {
             io.add_mouse_button_event(MouseButton::Left, true);
@@ -334,13 +496,21 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
             let x = lowordi(lparam as u32) as f32;
             let y = hiwordi(lparam as u32) as f32;
             io.add_mouse_pos_event([x, y]);
+            pipeline.keys().on_mouse_move([x, y]);
         },
-        WM_CHAR => io.add_input_character(char::from_u32(wparam as u32).unwrap()),
+        WM_CHAR => handle_char(io, wparam as u16),
         WM_SIZE => {
             pipeline.resize(loword(lparam as u32) as u32, hiword(lparam as u32) as u32);
         },
+        WM_DPICHANGED => pipeline.set_dpi(hiword(wparam as _) as u32),
+        WM_KILLFOCUS => {
+            release_all(io);
+            pipeline.keys().release_all();
+        },
+        WM_ACTIVATE if loword(wparam as _) == WA_INACTIVE as u16 => {
+            release_all(io);
+            pipeline.keys().release_all();
+        },
         _ => {},
     };
-
-    pipeline.render_loop().on_wnd_proc(hwnd, umsg, WPARAM(wparam), LPARAM(lparam));
 }
@@ -2,11 +2,11 @@ use imgui::Key;
 use once_cell::sync::Lazy;
 use windows::Win32::UI::Input::KeyboardAndMouse::{
     VIRTUAL_KEY, VK_0, VK_1, VK_2, VK_3, VK_4, VK_5, VK_6, VK_7, VK_8, VK_9, VK_A, VK_ADD, VK_B,
-    VK_BACK, VK_C, VK_CAPITAL, VK_D, VK_DECIMAL, VK_DELETE, VK_DIVIDE, VK_DOWN, VK_E, VK_END,
-    VK_ESCAPE, VK_EXECUTE, VK_EXSEL, VK_F, VK_F1, VK_F10, VK_F11, VK_F12, VK_F2, VK_F3, VK_F4,
-    VK_F5, VK_F6, VK_F7, VK_F8, VK_F9, VK_G, VK_GAMEPAD_A, VK_GAMEPAD_B, VK_GAMEPAD_DPAD_DOWN,
-    VK_GAMEPAD_DPAD_LEFT, VK_GAMEPAD_DPAD_RIGHT, VK_GAMEPAD_DPAD_UP, VK_GAMEPAD_LEFT_SHOULDER,
-    VK_GAMEPAD_LEFT_THUMBSTICK_DOWN, VK_GAMEPAD_LEFT_THUMBSTICK_LEFT,
+    VK_BACK, VK_C, VK_CAPITAL, VK_CONTROL, VK_D, VK_DECIMAL, VK_DELETE, VK_DIVIDE, VK_DOWN, VK_E,
+    VK_END, VK_ESCAPE, VK_EXECUTE, VK_EXSEL, VK_F, VK_F1, VK_F10, VK_F11, VK_F12, VK_F2, VK_F3,
+    VK_F4, VK_F5, VK_F6, VK_F7, VK_F8, VK_F9, VK_G, VK_GAMEPAD_A, VK_GAMEPAD_B,
+    VK_GAMEPAD_DPAD_DOWN, VK_GAMEPAD_DPAD_LEFT, VK_GAMEPAD_DPAD_RIGHT, VK_GAMEPAD_DPAD_UP,
+    VK_GAMEPAD_LEFT_SHOULDER, VK_GAMEPAD_LEFT_THUMBSTICK_DOWN, VK_GAMEPAD_LEFT_THUMBSTICK_LEFT,
     VK_GAMEPAD_LEFT_THUMBSTICK_RIGHT, VK_GAMEPAD_LEFT_THUMBSTICK_UP, VK_GAMEPAD_LEFT_TRIGGER,
     VK_GAMEPAD_MENU, VK_GAMEPAD_RIGHT_SHOULDER, VK_GAMEPAD_RIGHT_THUMBSTICK_DOWN,
     VK_GAMEPAD_RIGHT_THUMBSTICK_LEFT, VK_GAMEPAD_RIGHT_THUMBSTICK_RIGHT,
@@ -17,8 +17,8 @@ use windows::Win32::UI::Input::KeyboardAndMouse::{
     VK_NUMPAD7, VK_NUMPAD8, VK_NUMPAD9, VK_O, VK_OEM_1, VK_OEM_2, VK_OEM_3, VK_OEM_4, VK_OEM_5,
     VK_OEM_6, VK_OEM_7, VK_OEM_COMMA, VK_OEM_MINUS, VK_OEM_PERIOD, VK_OEM_PLUS, VK_P, VK_PAUSE,
     VK_PRIOR, VK_Q, VK_R, VK_RBUTTON, VK_RCONTROL, VK_RETURN, VK_RIGHT, VK_RMENU, VK_RSHIFT,
-    VK_RWIN, VK_S, VK_SCROLL, VK_SNAPSHOT, VK_SPACE, VK_SUBTRACT, VK_T, VK_TAB, VK_U, VK_UP, VK_V,
-    VK_W, VK_X, VK_XBUTTON1, VK_XBUTTON2, VK_Y, VK_Z,
+    VK_RWIN, VK_S, VK_SCROLL, VK_SHIFT, VK_SNAPSHOT, VK_SPACE, VK_SUBTRACT, VK_T, VK_TAB, VK_U,
+    VK_UP, VK_V, VK_W, VK_X, VK_XBUTTON1, VK_XBUTTON2, VK_Y, VK_Z,
 };
 
 pub(crate) const KEYS: [(Key, VIRTUAL_KEY); 132] = [
@@ -181,3 +181,250 @@ static VK_TO_IMGUI: Lazy<[Option<Key>; 256]> = Lazy::new(|| {
 pub(crate) fn vk_to_imgui(virtual_key: VIRTUAL_KEY) -> Option<Key> {
     VK_TO_IMGUI[virtual_key.0 as usize]
 }
+
+/// A key to be pressed along with modifiers, e.g. Ctrl+Shift+F1, as checked
+/// with [`RenderContext::keybind_pressed`] and
+/// [`RenderContext::keybind_held`].
+///
+/// The modifiers must be held exactly as described, so that e.g. a keybind on
+/// F1 doesn't trigger along with one on Ctrl+F1. Either the left or the right
+/// key of a modifier can be held.
+///
+/// ```
+/// use hudhook::windows::Win32::UI::Input::KeyboardAndMouse::VK_F1;
+/// use hudhook::Keybind;
+///
+/// let keybind = Keybind::new(VK_F1).with_ctrl().with_shift();
+/// ```
+///
+/// [`RenderContext::keybind_pressed`]: crate::RenderContext::keybind_pressed
+/// [`RenderContext::keybind_held`]: crate::RenderContext::keybind_held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Keybind {
+    /// The key, which may be a modifier itself, e.g. [`VK_LSHIFT`].
+    pub key: VIRTUAL_KEY,
+    /// Whether Ctrl must be held.
+    pub ctrl: bool,
+    /// Whether Shift must be held.
+    pub shift: bool,
+    /// Whether Alt must be held.
+    pub alt: bool,
+}
+
+impl Keybind {
+    /// A keybind on `key` alone, without modifiers.
+    pub const fn new(key: VIRTUAL_KEY) -> Self {
+        Self { key, ctrl: false, shift: false, alt: false }
+    }
+
+    /// Require Ctrl to be held as well.
+    pub const fn with_ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    /// Require Shift to be held as well.
+    pub const fn with_shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    /// Require Alt to be held as well.
+    pub const fn with_alt(mut self) -> Self {
+        self.alt = true;
+        self
+    }
+}
+
+/// The modifiers held, as returned by [`RenderContext::modifiers`]. Either
+/// the left or the right key of a modifier can be held.
+///
+/// [`RenderContext::modifiers`]: crate::RenderContext::modifiers
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    /// Whether Ctrl is held.
+    pub ctrl: bool,
+    /// Whether Shift is held.
+    pub shift: bool,
+    /// Whether Alt is held.
+    pub alt: bool,
+}
+
+// The modifiers, as their generic, left and right keys.
+const MODIFIERS: [[VIRTUAL_KEY; 3]; 3] = [
+    [VK_CONTROL, VK_LCONTROL, VK_RCONTROL],
+    [VK_SHIFT, VK_LSHIFT, VK_RSHIFT],
+    [VK_MENU, VK_LMENU, VK_RMENU],
+];
+
+// The state of the keyboard and the mouse movement, as of the window messages
+// received so far, for the keybinds of the render loop. It's tracked apart
+// from imgui, so that it doesn't depend on whether imgui wants the input.
+pub(crate) struct KeyState {
+    down: [bool; 256],
+    // The keys that went down since the previous frame started.
+    pressed: [bool; 256],
+    // The mouse movement since the previous frame started.
+    mouse_delta: [f32; 2],
+    // The last cursor position, to measure the movement when there's no raw
+    // input.
+    mouse_pos: Option<[f32; 2]>,
+    // Whether the application receives raw mouse input, which the cursor
+    // positions then aren't counted on top of.
+    raw_mouse: bool,
+}
+
+impl KeyState {
+    pub(crate) fn new() -> Self {
+        Self {
+            down: [false; 256],
+            pressed: [false; 256],
+            mouse_delta: [0.; 2],
+            mouse_pos: None,
+            raw_mouse: false,
+        }
+    }
+
+    pub(crate) fn start_frame(&mut self) {
+        self.pressed = [false; 256];
+        self.mouse_delta = [0.; 2];
+    }
+
+    // Auto-repeated key downs don't count as presses.
+    pub(crate) fn on_key(&mut self, key: VIRTUAL_KEY, down: bool, repeat: bool) {
+        let Some(index) = index(key) else {
+            return;
+        };
+
+        if down && !repeat && !self.down[index] {
+            self.pressed[index] = true;
+        }
+        self.down[index] = down;
+    }
+
+    // The key ups aren't received once the window has lost the focus.
+    pub(crate) fn release_all(&mut self) {
+        self.down = [false; 256];
+        self.mouse_pos = None;
+    }
+
+    // The movement of the cursor, in client coordinates. It stops at the
+    // edges of the screen, and includes the application warping it, hence
+    // raw input is preferred once there is some.
+    pub(crate) fn on_mouse_move(&mut self, pos: [f32; 2]) {
+        if let (Some([x, y]), false) = (self.mouse_pos, self.raw_mouse) {
+            self.mouse_delta[0] += pos[0] - x;
+            self.mouse_delta[1] += pos[1] - y;
+        }
+        self.mouse_pos = Some(pos);
+    }
+
+    // The relative movement reported by the mouse itself.
+    pub(crate) fn on_raw_mouse_move(&mut self, [dx, dy]: [f32; 2]) {
+        self.raw_mouse = true;
+        self.mouse_delta[0] += dx;
+        self.mouse_delta[1] += dy;
+    }
+
+    pub(crate) fn mouse_delta(&self) -> [f32; 2] {
+        self.mouse_delta
+    }
+
+    pub(crate) fn modifiers(&self) -> Modifiers {
+        let [ctrl, shift, alt] = MODIFIERS.map(|keys| self.any_down(&keys));
+        Modifiers { ctrl, shift, alt }
+    }
+
+    pub(crate) fn is_held(&self, keybind: &Keybind) -> bool {
+        index(keybind.key).is_some_and(|index| self.down[index]) && self.modifiers_match(keybind)
+    }
+
+    pub(crate) fn is_pressed(&self, keybind: &Keybind) -> bool {
+        index(keybind.key).is_some_and(|index| self.pressed[index]) && self.modifiers_match(keybind)
+    }
+
+    // A modifier used as the key of the keybind is held, and doesn't count.
+    fn modifiers_match(&self, keybind: &Keybind) -> bool {
+        MODIFIERS
+            .iter()
+            .zip([keybind.ctrl, keybind.shift, keybind.alt])
+            .all(|(keys, wanted)| keys.contains(&keybind.key) || self.any_down(keys) == wanted)
+    }
+
+    fn any_down(&self, keys: &[VIRTUAL_KEY]) -> bool {
+        keys.iter().any(|&key| index(key).is_some_and(|index| self.down[index]))
+    }
+}
+
+fn index(key: VIRTUAL_KEY) -> Option<usize> {
+    (key.0 < 256).then_some(key.0 as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keybinds() {
+        let f1 = Keybind::new(VK_F1);
+        let ctrl_shift_f1 = Keybind::new(VK_F1).with_ctrl().with_shift();
+        let shift = Keybind::new(VK_LSHIFT);
+
+        let mut keys = KeyState::new();
+        keys.on_key(VK_LCONTROL, true, false);
+        keys.on_key(VK_RSHIFT, true, false);
+        keys.on_key(VK_F1, true, false);
+        assert!(keys.is_pressed(&ctrl_shift_f1) && keys.is_held(&ctrl_shift_f1));
+        assert!(!keys.is_pressed(&f1) && !keys.is_held(&f1));
+
+        // Held, but not pressed again, including on auto-repeat.
+        keys.start_frame();
+        keys.on_key(VK_F1, true, true);
+        assert!(!keys.is_pressed(&ctrl_shift_f1) && keys.is_held(&ctrl_shift_f1));
+
+        // Pressed and released within the same frame.
+        keys.on_key(VK_LCONTROL, false, false);
+        keys.on_key(VK_RSHIFT, false, false);
+        keys.on_key(VK_F1, false, false);
+        keys.start_frame();
+        keys.on_key(VK_F1, true, false);
+        keys.on_key(VK_F1, false, false);
+        assert!(keys.is_pressed(&f1) && !keys.is_held(&f1));
+
+        // A modifier on its own.
+        keys.on_key(VK_LSHIFT, true, false);
+        assert!(keys.is_pressed(&shift) && keys.is_held(&shift));
+
+        keys.release_all();
+        assert!(!keys.is_held(&shift));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mut keys = KeyState::new();
+        keys.on_key(VK_RCONTROL, true, false);
+        keys.on_key(VK_LMENU, true, false);
+        assert_eq!(keys.modifiers(), Modifiers { ctrl: true, shift: false, alt: true });
+
+        keys.on_key(VK_RCONTROL, false, false);
+        assert_eq!(keys.modifiers(), Modifiers { ctrl: false, shift: false, alt: true });
+    }
+
+    #[test]
+    fn test_mouse_delta() {
+        let mut keys = KeyState::new();
+        keys.on_mouse_move([10., 10.]);
+        keys.on_mouse_move([15., 5.]);
+        keys.on_mouse_move([20., 0.]);
+        assert_eq!(keys.mouse_delta(), [10., -10.]);
+
+        keys.start_frame();
+        assert_eq!(keys.mouse_delta(), [0., 0.]);
+
+        // Once there is raw input, the cursor movement it causes isn't counted
+        // again.
+        keys.on_raw_mouse_move([3., 4.]);
+        keys.on_mouse_move([23., 4.]);
+        assert_eq!(keys.mouse_delta(), [3., 4.]);
+    }
+}
@@ -0,0 +1,140 @@
+//! This module contains the frame rate limiter applied to the presents.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::error;
+use windows::Win32::Media::{timeBeginPeriod, timeEndPeriod, TIMERR_NOERROR};
+
+// How long before the deadline sleeping gives way to spinning. Even with a 1ms
+// timer resolution, the scheduler may wake the thread up a bit late.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Paces the presents to a target frame rate, by waiting out what's left of
+/// each frame's share of time before it's presented.
+///
+/// The deadlines don't accumulate: a frame that takes longer than its share,
+/// e.g. because the present waits for VSync at a lower refresh rate than the
+/// target, isn't made up for by shortening the following ones, so the limiter
+/// never waits when the frame rate is already below the target.
+pub(crate) struct FrameLimiter {
+    fps: Option<f32>,
+    deadline: Option<Instant>,
+    // Whether the timer resolution has been raised, to be lowered back.
+    timer_period: bool,
+}
+
+impl FrameLimiter {
+    pub(crate) fn new(fps: Option<f32>) -> Self {
+        let mut limiter = Self { fps: None, deadline: None, timer_period: false };
+        limiter.set_limit(fps);
+        limiter
+    }
+
+    pub(crate) fn limit(&self) -> Option<f32> {
+        self.fps
+    }
+
+    /// Set the target frame rate, or lift the limit with `None`. Rates that
+    /// aren't positive and finite lift it too.
+    pub(crate) fn set_limit(&mut self, fps: Option<f32>) {
+        self.fps = fps.filter(|fps| fps.is_finite() && *fps > 0.);
+        self.deadline = None;
+
+        // Only raise the system-wide timer resolution while it's needed.
+        match (self.fps.is_some(), self.timer_period) {
+            (true, false) => {
+                if unsafe { timeBeginPeriod(1) } == TIMERR_NOERROR {
+                    self.timer_period = true;
+                } else {
+                    error!("Could not raise the timer resolution");
+                }
+            },
+            (false, true) => {
+                unsafe { timeEndPeriod(1) };
+                self.timer_period = false;
+            },
+            _ => {},
+        }
+    }
+
+    /// Wait until the current frame is due, if the frame rate is limited.
+    pub(crate) fn wait(&mut self) {
+        let Some(deadline) = self.next_deadline(Instant::now()) else {
+            return;
+        };
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            let remaining = deadline - now;
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    // When the frame presented at `now` is due: one interval after the
+    // previous one was, or right away if that's already past.
+    fn next_deadline(&mut self, now: Instant) -> Option<Instant> {
+        let interval = Duration::from_secs_f64(1. / self.fps? as f64);
+        let deadline = match self.deadline {
+            Some(deadline) => (deadline + interval).max(now),
+            None => now,
+        };
+        self.deadline = Some(deadline);
+        Some(deadline)
+    }
+}
+
+impl Drop for FrameLimiter {
+    fn drop(&mut self) {
+        self.set_limit(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_limiter_deadlines() {
+        let mut limiter = FrameLimiter::new(Some(100.));
+        let interval = Duration::from_millis(10);
+        let start = Instant::now();
+
+        // Fast frames are paced one interval apart.
+        assert_eq!(limiter.next_deadline(start), Some(start));
+        assert_eq!(limiter.next_deadline(start + Duration::from_millis(1)), Some(start + interval));
+        assert_eq!(
+            limiter.next_deadline(start + Duration::from_millis(12)),
+            Some(start + 2 * interval)
+        );
+
+        // Slow frames, e.g. VSync'd below the target, aren't waited for, and
+        // don't shorten the following ones.
+        let late = start + Duration::from_millis(50);
+        assert_eq!(limiter.next_deadline(late), Some(late));
+        assert_eq!(
+            limiter.next_deadline(late + Duration::from_millis(16)),
+            Some(late + Duration::from_millis(16))
+        );
+        assert_eq!(
+            limiter.next_deadline(late + Duration::from_millis(17)),
+            Some(late + Duration::from_millis(26))
+        );
+
+        limiter.set_limit(Some(f32::INFINITY));
+        assert_eq!(limiter.limit(), None);
+        assert_eq!(limiter.next_deadline(late), None);
+
+        limiter.set_limit(Some(50.));
+        assert_eq!(limiter.limit(), Some(50.));
+        assert_eq!(limiter.next_deadline(late), Some(late));
+    }
+}
@@ -1,9 +1,13 @@
 //! The [`hudhook`](crate) overlay rendering engine.
 mod backend;
+pub(crate) mod color_space;
+pub(crate) mod config;
 mod input;
-mod keys;
+pub(crate) mod keys;
+mod limiter;
 pub(crate) mod msg_filter;
 mod pipeline;
+pub(crate) mod texture;
 
 use imgui::{Context, DrawData};
 use windows::core::Result;
@@ -16,12 +20,16 @@ pub(crate) trait RenderEngine: RenderContext {
     fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()>;
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()>;
 }
+#[cfg(feature = "dx10")]
+pub(crate) use backend::dx10::D3D10RenderEngine;
 #[cfg(feature = "dx11")]
 pub(crate) use backend::dx11::D3D11RenderEngine;
 #[cfg(feature = "dx12")]
 pub(crate) use backend::dx12::D3D12RenderEngine;
 #[cfg(feature = "dx9")]
 pub(crate) use backend::dx9::D3D9RenderEngine;
+#[cfg(feature = "gdi")]
+pub(crate) use backend::gdi::{Bitmap, GdiRenderEngine};
 #[cfg(feature = "opengl3")]
 pub(crate) use backend::opengl3::OpenGl3RenderEngine;
 pub(crate) use pipeline::Pipeline;
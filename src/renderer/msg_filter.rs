@@ -9,7 +9,29 @@ bitflags! {
     /// Return this on [`ImguiRenderLoop::message_filter`](crate::ImguiRenderLoop::message_filter)
     /// to filter certain types of window message.
     ///
-    /// You can use bitwise-or to combine multiple flags.
+    /// You can use bitwise-or to combine multiple flags. The input flags are
+    /// fine-grained, so that e.g. a text box can capture the keyboard while
+    /// the application keeps receiving the mouse movements for camera
+    /// control; note that applications using raw input for that need
+    /// [`MessageFilter::InputRaw`] to be left out as well.
+    ///
+    /// The input flags match the following client area messages:
+    ///
+    /// | Flag                  | Messages |
+    /// |-----------------------|----------|
+    /// | [`InputKey`]          | `WM_KEYDOWN`, `WM_KEYUP`, `WM_SYSKEYDOWN`, `WM_SYSKEYUP` |
+    /// | [`InputChar`]         | `WM_CHAR`, `WM_DEADCHAR`, `WM_SYSCHAR`, `WM_SYSDEADCHAR`, `WM_UNICHAR` |
+    /// | [`InputMouseButtons`] | `WM_{L,R,M,X}BUTTON{DOWN,UP,DBLCLK}` |
+    /// | [`InputMouseMove`]    | `WM_MOUSEMOVE` |
+    /// | [`InputMouseWheel`]   | `WM_MOUSEWHEEL`, `WM_MOUSEHWHEEL` |
+    /// | [`InputRaw`]          | `WM_INPUT`, `WM_INPUT_DEVICE_CHANGE` |
+    ///
+    /// [`InputKey`]: MessageFilter::InputKey
+    /// [`InputChar`]: MessageFilter::InputChar
+    /// [`InputMouseButtons`]: MessageFilter::InputMouseButtons
+    /// [`InputMouseMove`]: MessageFilter::InputMouseMove
+    /// [`InputMouseWheel`]: MessageFilter::InputMouseWheel
+    /// [`InputRaw`]: MessageFilter::InputRaw
     ///
     /// Example usage:
     /// ```no_run
@@ -24,12 +46,24 @@ bitflags! {
     /// ```
     #[repr(transparent)]
     pub struct MessageFilter: u32 {
-        /// Blocks keyboard input event messages.
-        const InputKeyboard = 1u32 << 0;
-        /// Blocks mouse input event message.
-        const InputMouse = 1u32 << 1;
+        /// Blocks key press and release messages.
+        const InputKey = 1u32 << 0;
+        /// Blocks character messages, i.e. text input.
+        const InputChar = 1u32 << 1;
+        /// Blocks mouse button press, release and double click messages.
+        const InputMouseButtons = 1u32 << 2;
+        /// Blocks mouse movement messages.
+        const InputMouseMove = 1u32 << 3;
+        /// Blocks mouse wheel messages.
+        const InputMouseWheel = 1u32 << 4;
         /// Blocks raw input event messages.
-        const InputRaw = 1u32 << 2;
+        const InputRaw = 1u32 << 5;
+
+        /// Blocks key and character messages.
+        const InputKeyboard = Self::InputKey.bits() | Self::InputChar.bits();
+        /// Blocks mouse button, movement and wheel messages.
+        const InputMouse =
+            Self::InputMouseButtons.bits() | Self::InputMouseMove.bits() | Self::InputMouseWheel.bits();
 
         /// Blocks window gain/lose focus event messages.
         const WindowFocus = 1u32 << 8;
@@ -73,9 +107,20 @@ impl MessageFilter {
         }
 
         match message_id {
-            WM_KEYFIRST..=WM_KEYLAST => self.contains(Self::InputKeyboard),
-            WM_MOUSEFIRST..=WM_MOUSELAST => self.contains(Self::InputMouse),
-            WM_INPUT => self.contains(Self::InputRaw),
+            WM_KEYDOWN | WM_KEYUP | WM_SYSKEYDOWN | WM_SYSKEYUP => self.contains(Self::InputKey),
+            WM_CHAR | WM_DEADCHAR | WM_SYSCHAR | WM_SYSDEADCHAR | WM_UNICHAR => {
+                self.contains(Self::InputChar)
+            },
+
+            WM_LBUTTONDOWN | WM_LBUTTONUP | WM_LBUTTONDBLCLK | WM_RBUTTONDOWN | WM_RBUTTONUP
+            | WM_RBUTTONDBLCLK | WM_MBUTTONDOWN | WM_MBUTTONUP | WM_MBUTTONDBLCLK
+            | WM_XBUTTONDOWN | WM_XBUTTONUP | WM_XBUTTONDBLCLK => {
+                self.contains(Self::InputMouseButtons)
+            },
+            WM_MOUSEMOVE => self.contains(Self::InputMouseMove),
+            WM_MOUSEWHEEL | WM_MOUSEHWHEEL => self.contains(Self::InputMouseWheel),
+
+            WM_INPUT | WM_INPUT_DEVICE_CHANGE => self.contains(Self::InputRaw),
 
             WM_MOUSEACTIVATE | WM_ACTIVATEAPP | WM_ACTIVATE | WM_SETFOCUS | WM_KILLFOCUS
             | WM_ENABLE => self.contains(Self::WindowFocus),
@@ -89,3 +134,39 @@ impl MessageFilter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_categories() {
+        let cases = [
+            (WM_KEYDOWN, MessageFilter::InputKey),
+            (WM_SYSKEYUP, MessageFilter::InputKey),
+            (WM_CHAR, MessageFilter::InputChar),
+            (WM_UNICHAR, MessageFilter::InputChar),
+            (WM_LBUTTONDOWN, MessageFilter::InputMouseButtons),
+            (WM_XBUTTONDBLCLK, MessageFilter::InputMouseButtons),
+            (WM_MOUSEMOVE, MessageFilter::InputMouseMove),
+            (WM_MOUSEHWHEEL, MessageFilter::InputMouseWheel),
+            (WM_INPUT, MessageFilter::InputRaw),
+        ];
+
+        for (msg, category) in &cases {
+            for (_, other) in &cases {
+                let expected = other.bits() == category.bits();
+                assert_eq!(other.is_blocking(*msg), expected, "{msg:#x} with {:#x}", other.bits());
+            }
+        }
+
+        // Capturing the keyboard leaves the mouse to the application.
+        let filter = MessageFilter::InputKeyboard;
+        assert!(filter.is_blocking(WM_KEYDOWN) && filter.is_blocking(WM_CHAR));
+        assert!(!filter.is_blocking(WM_MOUSEMOVE) && !filter.is_blocking(WM_INPUT));
+
+        let filter = MessageFilter::InputMouse;
+        assert!(filter.is_blocking(WM_RBUTTONUP) && filter.is_blocking(WM_MOUSEWHEEL));
+        assert!(filter.is_blocking(WM_MOUSEMOVE) && !filter.is_blocking(WM_KEYUP));
+    }
+}
@@ -1,29 +1,87 @@
+use std::any::Any;
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::mem;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::ffi::c_void;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU8, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
+use std::{mem, slice};
 
-use imgui::Context;
+use imgui::internal::RawCast;
+use imgui::{
+    BackendFlags, ConfigFlags, Context, DrawData, FontAtlas, FontConfig, FontGlyphRanges, FontId,
+    FontSource, Io, TextureId, Ui,
+};
 use once_cell::sync::{Lazy, OnceCell};
-use parking_lot::Mutex;
-use tracing::error;
-use windows::core::{Error, Result, HRESULT};
-use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use tracing::{debug, error, warn};
+use windows::core::{Error, Result, HRESULT, PCWSTR};
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, POINT, RECT, WPARAM};
+use windows::Win32::Globalization::HIMC;
+use windows::Win32::Graphics::Direct3D9::IDirect3DDevice9;
+use windows::Win32::Graphics::Dxgi::IDXGISwapChain;
+use windows::Win32::Graphics::Gdi::{ClientToScreen, InvalidateRect};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::HiDpi::GetDpiForWindow;
+use windows::Win32::UI::Input::Ime::{
+    ImmAssociateContextEx, ImmGetContext, ImmReleaseContext, ImmSetCandidateWindow,
+    ImmSetCompositionWindow, CANDIDATEFORM, CFS_EXCLUDE, CFS_FORCE_POSITION, COMPOSITIONFORM,
+    IACE_DEFAULT,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+use windows::Win32::UI::Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass};
 use windows::Win32::UI::WindowsAndMessaging::{
-    CallWindowProcW, DefWindowProcW, SetWindowLongPtrW, GWLP_WNDPROC,
+    CallNextHookEx, CallWindowProcW, ClipCursor, DefWindowProcW, GetClientRect, GetClipCursor,
+    GetCursor, GetWindowThreadProcessId, InternalGetWindowText, LoadCursorW, SendMessageTimeoutW,
+    SetCursor, SetCursorPos, SetWindowLongPtrW, SetWindowsHookExW, ShowCursor, UnhookWindowsHookEx,
+    GWLP_WNDPROC, HCURSOR, HHOOK, HTCLIENT, IDC_ARROW, IDC_HAND, IDC_IBEAM, IDC_NO, IDC_SIZEALL,
+    IDC_SIZENESW, IDC_SIZENS, IDC_SIZENWSE, IDC_SIZEWE, MSG, PM_REMOVE, SMTO_ABORTIFHUNG,
+    USER_DEFAULT_SCREEN_DPI, WH_GETMESSAGE, WM_IME_CHAR, WM_IME_COMPOSITION,
+    WM_IME_COMPOSITIONFULL, WM_IME_CONTROL, WM_IME_ENDCOMPOSITION, WM_IME_KEYDOWN, WM_IME_KEYUP,
+    WM_IME_NOTIFY, WM_IME_REQUEST, WM_IME_SELECT, WM_IME_SETCONTEXT, WM_IME_STARTCOMPOSITION,
+    WM_KEYDOWN, WM_NULL, WM_SETCURSOR, WM_SYSKEYDOWN,
 };
 
-use crate::renderer::input::{imgui_wnd_proc_impl, WndProcType};
+use crate::diagnostics::{self, DiagnosticEvent};
+use crate::renderer::config::{InputMode, Layer, PipelineConfig, PIPELINE_CONFIG};
+use crate::renderer::input::{imgui_wnd_proc_impl, update_gamepad, WndProcType};
+use crate::renderer::keys::KeyState;
+use crate::renderer::limiter::FrameLimiter;
 use crate::renderer::RenderEngine;
-use crate::{util, ImguiRenderLoop, MessageFilter};
+use crate::{
+    util, CaptureFlags, CursorMode, DeviceHandle, HookStatus, Hudhook, ImguiRenderLoop, Keybind,
+    MessageFilter, Modifiers, NavMouseWarp, PixelFormat, PresentAction, RenderContext,
+    RenderTargetHandle, TextureHandle, TextureOptions, WindowLimitAction, WndProcResult,
+};
 
 type RenderLoop = Box<dyn ImguiRenderLoop + Send + Sync>;
 
+// How long the redraw thread sleeps when no minimum framerate is requested.
+const REDRAW_IDLE_INTERVAL: Duration = Duration::from_millis(250);
+// How long cleanup waits for the window's thread to restore the cursor.
+const CURSOR_RELEASE_TIMEOUT_MS: u32 = 100;
+// How long the window procedure waits for the render loop to be available.
+const WND_PROC_RENDER_LOOP_TIMEOUT: Duration = Duration::from_millis(50);
+// Tells the overlay's subclass of the window apart, along with its procedure.
+const SUBCLASS_ID: usize = 1;
+
+thread_local! {
+    // Whether the render loop is running on this thread, in which case the
+    // window procedure can't wait for it.
+    static IN_RENDER_LOOP: Cell<bool> = const { Cell::new(false) };
+}
+
 static PIPELINE_STATES: Lazy<Mutex<HashMap<isize, Arc<PipelineSharedState>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+// The imgui settings of the last pipeline, when they're kept in memory rather
+// than in a file. See `HudhookBuilder::with_session_layout_persistence`.
+pub(crate) static SESSION_LAYOUT: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
 #[derive(Debug)]
 pub(crate) struct PipelineMessage(
     pub(crate) HWND,
@@ -32,77 +90,335 @@ pub(crate) struct PipelineMessage(
     pub(crate) LPARAM,
 );
 
+// How the messages of the window are received. See `InputMode`.
+#[derive(Clone, Copy)]
+enum WindowHook {
+    // The window procedure was replaced, and the messages are forwarded to
+    // the previous one.
+    WndProc(WndProcType),
+    Subclass,
+    GetMessage(HHOOK),
+}
+
 pub(crate) struct PipelineSharedState {
     pub(crate) message_filter: AtomicU32,
-    pub(crate) wnd_proc: WndProcType,
+    window_hook: WindowHook,
+    // Whether the window is still subclassed. The subclass is removed from
+    // the window's thread if possible, once the cursor is released.
+    subclassed: AtomicBool,
     pub(crate) tx: Sender<PipelineMessage>,
+    min_framerate: AtomicU32,
+    last_frame: Mutex<Instant>,
+    redraw_thread_active: AtomicBool,
+    visible: AtomicBool,
+    // Whether the overlay takes input while visible. See
+    // `RenderContext::set_input_enabled`.
+    input_enabled: AtomicBool,
+    toggle_key: Option<VIRTUAL_KEY>,
+    // The text cursor of the focused text input, if any, in client coordinates.
+    ime_area: Mutex<Option<RECT>>,
+    // Whether the IME was enabled for the text input, the application having
+    // disabled it.
+    ime_associated: AtomicBool,
+    // The `CursorMode` requested by the render loop, and what imgui wanted as
+    // of the last frame.
+    cursor_mode: AtomicU8,
+    want_capture_mouse: AtomicBool,
+    want_capture_keyboard: AtomicBool,
+    want_text_input: AtomicBool,
+    // The `ImGuiMouseCursor` imgui wanted as of the last frame.
+    mouse_cursor: AtomicI32,
+    cursor: Mutex<CursorState>,
+    // Set on cleanup, after which the cursor is never captured again.
+    cursor_released: AtomicBool,
+    // The render loop is called from the window procedure as well as from the
+    // present function, which may run on different threads. It's only taken
+    // out when the pipeline is torn down.
+    render_loop: Mutex<Option<RenderLoop>>,
+    catch_panics: bool,
+    // Set once the render loop has panicked, after which it's never called
+    // again.
+    panicked: AtomicBool,
+}
+
+impl PipelineSharedState {
+    fn takes_input(&self) -> bool {
+        self.visible.load(Ordering::SeqCst) && self.input_enabled.load(Ordering::SeqCst)
+    }
+
+    fn lock_render_loop(&self) -> RenderLoopGuard<'_> {
+        RenderLoopGuard::new(self.render_loop.lock()).expect("Render loop already taken")
+    }
+
+    fn try_lock_render_loop(&self, timeout: Duration) -> Option<RenderLoopGuard<'_>> {
+        self.render_loop.try_lock_for(timeout).and_then(RenderLoopGuard::new)
+    }
+}
+
+// The render loop, locked by the current thread.
+struct RenderLoopGuard<'a>(MappedMutexGuard<'a, dyn ImguiRenderLoop + Send + Sync>);
+
+impl<'a> RenderLoopGuard<'a> {
+    fn new(guard: MutexGuard<'a, Option<RenderLoop>>) -> Option<Self> {
+        let guard = MutexGuard::try_map(guard, |render_loop| render_loop.as_deref_mut()).ok()?;
+        IN_RENDER_LOOP.set(true);
+        Some(Self(guard))
+    }
+}
+
+impl Deref for RenderLoopGuard<'_> {
+    type Target = dyn ImguiRenderLoop + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl DerefMut for RenderLoopGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.0
+    }
+}
+
+impl Drop for RenderLoopGuard<'_> {
+    fn drop(&mut self) {
+        IN_RENDER_LOOP.set(false);
+    }
 }
 
 pub(crate) struct Pipeline<T: RenderEngine> {
     hwnd: HWND,
-    ctx: Context,
+    // Dropped first, as it may be building a frame with the context and the
+    // render loop. The context is boxed so that its address doesn't change if
+    // the pipeline is moved while it does.
+    frame_thread: Option<FrameThread>,
+    ctx: Box<Context>,
     engine: T,
-    render_loop: RenderLoop,
     rx: Receiver<PipelineMessage>,
     shared_state: Arc<PipelineSharedState>,
     queue_buffer: OnceCell<Vec<PipelineMessage>>,
-    start_of_first_frame: OnceCell<Instant>,
+    timing: FrameTiming,
+    redraw_thread: OnceCell<JoinHandle<()>>,
+    config: PipelineConfig,
+    window_limit_exceeded: bool,
+    gamepad_packet: Option<u32>,
+    fonts: PipelineFonts,
+    // The IO of the context, for the render contexts handed out while the
+    // render loop borrows the context.
+    io: *mut Io,
+    keys: KeyState,
+    frame_limiter: FrameLimiter,
+    dpi_scale: f32,
+    // The display size the render loop was last told about.
+    reported_size: [u32; 2],
+    // The part of the display the application's content is shown in.
+    content_rect: [[f32; 2]; 2],
+    // The layers, with the part of the display each is drawn over.
+    layers: Vec<(Layer, [[f32; 2]; 2])>,
+    // Whether the pipeline has been disabled after the render loop panicked.
+    panicked: bool,
 }
 
 impl<T: RenderEngine> Pipeline<T> {
     pub(crate) fn new(
+        hwnd: HWND,
+        ctx: Context,
+        engine: T,
+        render_loop: RenderLoop,
+    ) -> std::result::Result<Self, (Error, RenderLoop)> {
+        let config = PIPELINE_CONFIG.lock().clone();
+        Self::with_config(hwnd, ctx, engine, render_loop, config)
+    }
+
+    // Create the pipeline with the given options, rather than those set via
+    // the builder.
+    pub(crate) fn with_config(
         hwnd: HWND,
         mut ctx: Context,
         mut engine: T,
         mut render_loop: RenderLoop,
+        config: PipelineConfig,
     ) -> std::result::Result<Self, (Error, RenderLoop)> {
         let (width, height) = util::win_size(hwnd);
 
         ctx.io_mut().display_size = [width as f32, height as f32];
 
-        render_loop.initialize(&mut ctx, &mut engine);
+        if config.nav_mouse_warp != NavMouseWarp::Disabled {
+            let io = ctx.io_mut();
+            io.config_flags |= ConfigFlags::NAV_ENABLE_SET_MOUSE_POS;
+            io.backend_flags |= BackendFlags::HAS_SET_MOUSE_POS;
+        }
+
+        if config.gamepad_nav {
+            ctx.io_mut().config_flags |= ConfigFlags::NAV_ENABLE_GAMEPAD;
+        }
 
+        // Windows can't be hovered nor clicked, so that a click on them doesn't
+        // do something the application doesn't see.
+        if config.click_through {
+            ctx.io_mut().config_flags |= ConfigFlags::NO_MOUSE;
+        }
+
+        #[cfg(feature = "imgui-docking")]
+        if config.docking {
+            ctx.io_mut().config_flags |= ConfigFlags::DOCKING_ENABLE;
+        }
+
+        ctx.set_ini_filename(config.ini_filename.clone());
+
+        if config.keeps_session_layout() {
+            if let Some(layout) = SESSION_LAYOUT.lock().as_deref() {
+                ctx.load_ini_settings(layout);
+            }
+        }
+
+        if let Some(style) = config.style {
+            style.apply(ctx.style_mut());
+        }
+
+        if let Some(imgui_settings) = &config.imgui_settings {
+            imgui_settings(&mut ctx);
+        }
+
+        let visible = AtomicBool::new(true);
+        let input_enabled = AtomicBool::new(true);
+        let cursor_mode = AtomicU8::new(CursorMode::GameControlled as u8);
+        let mut fonts = PipelineFonts::new(ctx.fonts());
+        let io: *mut Io = ctx.io_mut();
+        let keys = KeyState::new();
+        let mut frame_limiter = FrameLimiter::new(config.frame_limit);
+
+        let content_rect = full_rect([width as f32, height as f32]);
+
+        let panicked = catch_panic(config.catch_panics, "initialize", || {
+            render_loop.initialize(
+                &mut ctx,
+                // There's no pipeline to borrow the render context from yet.
+                &mut PipelineRenderContext {
+                    hwnd,
+                    engine: &mut engine,
+                    visible: &visible,
+                    input_enabled: &input_enabled,
+                    cursor_mode: &cursor_mode,
+                    fonts: &mut fonts,
+                    io,
+                    keys: &keys,
+                    frame_limiter: &mut frame_limiter,
+                    timing: FrameTiming::default(),
+                    content_rect,
+                },
+            )
+        })
+        .is_none();
+
+        // Scale what the render loop has set up to the window's DPI before the
+        // fonts are first built.
+        let dpi_scale =
+            if config.dpi_scaling { dpi_scale(unsafe { GetDpiForWindow(hwnd) }) } else { 1.0 };
+        if dpi_scale != 1.0 {
+            if ctx.fonts().fonts().is_empty() {
+                ctx.fonts().add_font(&[FontSource::DefaultFontData {
+                    config: Some(FontConfig {
+                        size_pixels: DEFAULT_FONT_SIZE,
+                        ..FontConfig::default()
+                    }),
+                }]);
+            }
+            ctx.style_mut().scale_all_sizes(dpi_scale);
+            scale_fonts(ctx.fonts(), dpi_scale);
+        }
+
+        // Fonts added so far are part of the first build.
         if let Err(e) = engine.setup_fonts(&mut ctx) {
             return Err((e, render_loop));
         }
+        fonts.dirty = false;
 
-        let wnd_proc = unsafe {
-            #[cfg(target_arch = "x86")]
-            type SwlpRet = i32;
-            #[cfg(target_arch = "x86_64")]
-            type SwlpRet = isize;
-
-            mem::transmute::<SwlpRet, WndProcType>(SetWindowLongPtrW(
-                hwnd,
-                GWLP_WNDPROC,
-                pipeline_wnd_proc as usize as _,
-            ))
+        let window_hook = match unsafe { hook_window(hwnd, config.input_mode) } {
+            Ok(window_hook) => window_hook,
+            Err(e) => return Err((e, render_loop)),
         };
 
         let (tx, rx) = mpsc::channel();
         let shared_state = Arc::new(PipelineSharedState {
             message_filter: AtomicU32::new(MessageFilter::empty().bits()),
-            wnd_proc,
+            window_hook,
+            subclassed: AtomicBool::new(matches!(window_hook, WindowHook::Subclass)),
             tx,
+            min_framerate: AtomicU32::new(0),
+            last_frame: Mutex::new(Instant::now()),
+            redraw_thread_active: AtomicBool::new(true),
+            visible,
+            input_enabled,
+            toggle_key: config.toggle_key,
+            ime_area: Mutex::new(None),
+            ime_associated: AtomicBool::new(false),
+            cursor_mode,
+            want_capture_mouse: AtomicBool::new(false),
+            want_capture_keyboard: AtomicBool::new(false),
+            want_text_input: AtomicBool::new(false),
+            mouse_cursor: AtomicI32::new(imgui::sys::ImGuiMouseCursor_Arrow),
+            cursor: Mutex::new(CursorState::default()),
+            cursor_released: AtomicBool::new(false),
+            render_loop: Mutex::new(Some(render_loop)),
+            catch_panics: config.catch_panics,
+            panicked: AtomicBool::new(panicked),
         });
 
+        // imgui tells where its text cursor is at the end of each frame. The
+        // shared state outlives the context.
+        unsafe {
+            (*imgui::sys::igGetMainViewport()).PlatformUserData =
+                Arc::as_ptr(&shared_state) as *mut c_void;
+            (*(ctx.io_mut() as *mut Io as *mut imgui::sys::ImGuiIO)).SetPlatformImeDataFn =
+                Some(set_ime_data);
+        }
+
         PIPELINE_STATES.lock().insert(hwnd.0, Arc::clone(&shared_state));
 
         let queue_buffer = OnceCell::from(Vec::new());
 
+        let frame_thread = config.async_frames.then(|| FrameThread::new(config.catch_panics));
+
         Ok(Self {
             hwnd,
-            ctx,
+            frame_thread,
+            ctx: Box::new(ctx),
             engine,
-            render_loop,
             rx,
             shared_state: Arc::clone(&shared_state),
             queue_buffer,
-            start_of_first_frame: OnceCell::new(),
+            timing: FrameTiming::default(),
+            redraw_thread: OnceCell::new(),
+            window_limit_exceeded: false,
+            gamepad_packet: None,
+            fonts,
+            io,
+            keys,
+            frame_limiter,
+            dpi_scale,
+            reported_size: [width as u32, height as u32],
+            content_rect,
+            layers: config.layers.iter().map(|layer| (layer.clone(), content_rect)).collect(),
+            config,
+            panicked,
         })
     }
 
     pub(crate) fn prepare_render(&mut self) -> Result<()> {
+        self.wait_for_frame();
+
+        if self.panicked {
+            // Keep the messages from piling up.
+            self.rx.try_iter().for_each(drop);
+            if let Some(messages) = &self.config.messages {
+                messages.drain(drop);
+            }
+            return Ok(());
+        }
+
+        self.keys.start_frame();
+
         let mut queue_buffer = self.queue_buffer.take().unwrap();
         queue_buffer.clear();
         queue_buffer.extend(self.rx.try_iter());
@@ -111,28 +427,148 @@ impl<T: RenderEngine> Pipeline<T> {
         });
         self.queue_buffer.set(queue_buffer).expect("OnceCell should be empty");
 
-        let message_filter = self.render_loop.message_filter(self.ctx.io());
+        if self.config.gamepad_nav {
+            update_gamepad(self.ctx.io_mut(), self.config.gamepad_index, &mut self.gamepad_packet);
+        }
+
+        // Without input, the overlay is drawn over an application that keeps
+        // receiving all of it, whatever imgui makes of it.
+        if !self.shared_state.input_enabled.load(Ordering::SeqCst) {
+            let io = self.ctx.io_mut();
+            io.want_capture_mouse = false;
+            io.want_capture_keyboard = false;
+            io.want_text_input = false;
+        }
+
+        let message_filter = catch_panic(self.config.catch_panics, "message_filter", || {
+            self.shared_state.lock_render_loop().message_filter(self.ctx.io())
+        });
+        let Some(mut message_filter) = message_filter else {
+            self.disable();
+            return Ok(());
+        };
+
+        let min_framerate = catch_panic(self.config.catch_panics, "min_framerate", || {
+            self.shared_state.lock_render_loop().min_framerate().unwrap_or(0)
+        });
+        let Some(min_framerate) = min_framerate else {
+            self.disable();
+            return Ok(());
+        };
+
+        // Whatever the render loop and imgui want, a click-through overlay
+        // neither blocks messages nor takes the cursor.
+        if self.config.click_through {
+            message_filter = MessageFilter::empty();
+            self.ctx.io_mut().want_capture_mouse = false;
+        }
 
         self.shared_state.message_filter.store(message_filter.bits(), Ordering::SeqCst);
+        let io = self.ctx.io();
+        self.shared_state.want_capture_mouse.store(io.want_capture_mouse, Ordering::SeqCst);
+        self.shared_state.want_capture_keyboard.store(io.want_capture_keyboard, Ordering::SeqCst);
+        self.shared_state.want_text_input.store(io.want_text_input, Ordering::SeqCst);
+
+        self.shared_state.min_framerate.store(min_framerate, Ordering::SeqCst);
+
+        if min_framerate > 0 && self.redraw_thread.get().is_none() {
+            let hwnd = self.hwnd;
+            let shared_state = Arc::clone(&self.shared_state);
+            let redraw_thread = thread::spawn(move || redraw_loop(hwnd, shared_state));
+            self.redraw_thread.set(redraw_thread).expect("OnceCell should be empty");
+        }
 
         let io = self.ctx.io_mut();
 
         io.nav_active = true;
         io.nav_visible = true;
 
-        self.render_loop.before_render(&mut self.ctx, &mut self.engine);
+        if let Some(messages) = &self.config.messages {
+            let on_message = catch_panic(self.config.catch_panics, "on_message", || {
+                let mut render_loop = self.shared_state.lock_render_loop();
+                messages.drain(|message| render_loop.on_message(message));
+            });
+            if on_message.is_none() {
+                self.disable();
+                return Ok(());
+            }
+        }
+
+        // The size changes with each message received while the window is being
+        // resized, but the render loop only needs to know where it ended up.
+        let [width, height] = self.ctx.io().display_size.map(|v| v as u32);
+        if [width, height] != self.reported_size {
+            self.reported_size = [width, height];
+            let on_resize = catch_panic(self.config.catch_panics, "on_resize", || {
+                self.shared_state.lock_render_loop().on_resize(width, height)
+            });
+            if on_resize.is_none() {
+                self.disable();
+                return Ok(());
+            }
+        }
+
+        self.content_rect = match &self.config.content_rect {
+            Some(content_rect) => clamp_rect(content_rect(width, height), [width, height]),
+            None => full_rect(self.ctx.io().display_size),
+        };
+        for (layer, rect) in &mut self.layers {
+            *rect = clamp_rect((layer.rect)(width, height), [width, height]);
+        }
+
+        let shared_state = Arc::clone(&self.shared_state);
+        let before_render = catch_panic(self.config.catch_panics, "before_render", || {
+            let (ctx, mut render_context) = self.split_render_context();
+            shared_state.lock_render_loop().before_render(ctx, &mut render_context)
+        });
+        if before_render.is_none() {
+            self.disable();
+            return Ok(());
+        }
+
+        // Fonts added since the last frame are only available once the atlas
+        // has been rebuilt, and imgui can't start a frame with a stale one.
+        // The same goes for fonts changed before requesting a rebuild.
+        if self.fonts.dirty {
+            self.rebuild_fonts()?;
+        }
 
         Ok(())
     }
 
+    fn rebuild_fonts(&mut self) -> Result<()> {
+        let old_tex_id = self.ctx.fonts().tex_id;
+
+        // The atlas isn't built again as long as its texture data is around.
+        self.ctx.fonts().clear_tex_data();
+        self.engine.setup_fonts(&mut self.ctx)?;
+        self.fonts.dirty = false;
+
+        // A frame built ahead of time still refers to the previous texture.
+        if let Some(frame_thread) = &mut self.frame_thread {
+            frame_thread.draw_data = None;
+        }
+
+        // The previous texture is only freed once the GPU is done with it.
+        self.engine.unload_texture(old_tex_id)
+    }
+
     pub(crate) fn render(&mut self, render_target: T::RenderTarget) -> Result<()> {
-        let delta_time = Instant::now()
-            .checked_duration_since(*self.start_of_first_frame.get_or_init(Instant::now))
-            .unwrap_or(Duration::ZERO)
-            .checked_sub(Duration::from_secs_f64(self.ctx.time()))
-            .unwrap_or(Duration::ZERO);
+        self.wait_for_frame();
+
+        if self.panicked {
+            return Ok(());
+        }
 
-        self.ctx.io_mut().update_delta_time(delta_time);
+        let now = Instant::now();
+        *self.shared_state.last_frame.lock() = now;
+
+        // The first frame keeps imgui's default delta time, as there is no
+        // previous present to measure from.
+        if let Some(last_present) = self.timing.last_present.replace(now) {
+            self.timing.last_present_duration = now.duration_since(last_present);
+            self.ctx.io_mut().update_delta_time(self.timing.last_present_duration);
+        }
 
         let [w, h] = self.ctx.io().display_size;
         let [fsw, fsh] = self.ctx.io().display_framebuffer_scale;
@@ -142,37 +578,993 @@ impl<T: RenderEngine> Pipeline<T> {
             return Err(Error::from_hresult(HRESULT(-1)));
         }
 
-        let ui = self.ctx.frame();
-        self.render_loop.render(ui);
-        let draw_data = self.ctx.render();
+        // Navigation requested a mouse warp during the previous frame. imgui has
+        // already moved its own mouse position, so only the OS cursor is left.
+        if self.config.nav_mouse_warp == NavMouseWarp::Os && self.ctx.io().want_set_mouse_pos {
+            set_cursor_pos(self.hwnd, self.ctx.io().mouse_pos);
+        }
+
+        let draw_data = match &mut self.frame_thread {
+            // The frame was built since the previous present, if there was one.
+            Some(frame_thread) => {
+                frame_thread.draw_data.take().map(|draw_data| unsafe { &*draw_data })
+            },
+            None => {
+                let catch_panics = self.config.catch_panics;
+                let work_area = self.config.content_rect.as_ref().map(|_| self.content_rect);
+                let shared_state = Arc::clone(&self.shared_state);
+                // Taken out while the render context borrows the pipeline.
+                let layers = mem::take(&mut self.layers);
+
+                let (ctx, mut render_context) = self.split_render_context();
+                let ui = ctx.frame();
+                if let Some(work_area) = work_area {
+                    set_work_area(work_area);
+                }
+                let render = catch_panic(catch_panics, "render", || {
+                    shared_state.lock_render_loop().render_with_context(ui, &mut render_context)
+                })
+                .and_then(|()| render_layers(catch_panics, ui, &layers));
 
-        self.engine.render(draw_data, render_target)?;
+                self.layers = layers;
+                if render.is_none() {
+                    self.disable();
+                    return Ok(());
+                }
+                Some(self.ctx.render())
+            },
+        };
+
+        // imgui draws its own cursor instead of the system one if asked to.
+        let mouse_cursor = if self.ctx.io().mouse_draw_cursor {
+            imgui::sys::ImGuiMouseCursor_None
+        } else {
+            unsafe { imgui::sys::igGetMouseCursor() }
+        };
+        self.shared_state.mouse_cursor.store(mouse_cursor, Ordering::SeqCst);
+
+        if let Some(draw_data) = draw_data {
+            let mut skip = !self.shared_state.visible.load(Ordering::SeqCst);
+
+            if let Some((max_windows, action)) = self.config.window_limit {
+                let windows = draw_data.draw_lists_count();
+                let exceeded = windows > max_windows;
+
+                // Only warn once each time the limit is crossed, not on every frame.
+                if exceeded && !self.window_limit_exceeded {
+                    warn!(
+                        "Frame has {windows} windows, more than the limit of {max_windows}. Are \
+                         windows being created in a loop?"
+                    );
+                }
+
+                self.window_limit_exceeded = exceeded;
+                skip |= exceeded && action == WindowLimitAction::Skip;
+            }
+
+            if !skip {
+                self.engine.render(draw_data, render_target)?;
+            }
+        }
+
+        self.timing.frame_count += 1;
+        diagnostics::emit(DiagnosticEvent::FrameRendered);
+
+        let shared_state = Arc::clone(&self.shared_state);
+        let after_render = catch_panic(self.config.catch_panics, "after_render", || {
+            shared_state.lock_render_loop().after_render(&mut self.render_context())
+        });
+        if after_render.is_none() {
+            self.disable();
+            return Ok(());
+        }
+
+        // imgui asks for the settings to be saved a few seconds after they've
+        // changed.
+        if self.config.keeps_session_layout() && self.ctx.io().want_save_ini_settings {
+            self.save_session_layout();
+        }
+
+        // Starting a frame invalidates the draw data of the previous one, so
+        // only start building the next frame once this one has been drawn.
+        if let Some(frame_thread) = &mut self.frame_thread {
+            let work_area = self.config.content_rect.as_ref().map(|_| self.content_rect);
+            frame_thread.start(
+                &mut self.ctx,
+                &self.shared_state,
+                &self.keys,
+                self.content_rect,
+                work_area,
+                &self.layers,
+            )?;
+        }
+
+        // Right before the hook presents.
+        self.frame_limiter.wait();
 
         Ok(())
     }
 
+    // Wait for the frame being built on the frame thread, if any, to be done,
+    // before touching the context or the render loop.
+    fn wait_for_frame(&mut self) {
+        if let Some(frame_thread) = &mut self.frame_thread {
+            frame_thread.wait();
+            if frame_thread.panicked && !self.panicked {
+                self.disable();
+            }
+        }
+
+        // The window procedure may have seen the render loop panic.
+        if self.shared_state.panicked.load(Ordering::SeqCst) && !self.panicked {
+            self.disable();
+        }
+    }
+
+    // Stop calling the render loop after it has panicked, and hand the input
+    // it may have been blocking back to the application.
+    fn disable(&mut self) {
+        self.panicked = true;
+        self.shared_state.panicked.store(true, Ordering::SeqCst);
+        self.shared_state.message_filter.store(MessageFilter::empty().bits(), Ordering::SeqCst);
+        self.shared_state.min_framerate.store(0, Ordering::SeqCst);
+        self.shared_state.cursor_mode.store(CursorMode::GameControlled as u8, Ordering::SeqCst);
+    }
+
+    fn save_session_layout(&mut self) {
+        let mut layout = String::new();
+        self.ctx.save_ini_settings(&mut layout);
+        self.ctx.io_mut().want_save_ini_settings = false;
+        *SESSION_LAYOUT.lock() = Some(layout);
+    }
+
+    pub(crate) fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    // The render context handed to the render loop.
+    fn render_context(&mut self) -> PipelineRenderContext<'_, T> {
+        self.split_render_context().1
+    }
+
+    // The render context, along with the imgui context, which it doesn't
+    // borrow, for the callbacks that take both.
+    fn split_render_context(&mut self) -> (&mut Context, PipelineRenderContext<'_, T>) {
+        (
+            &mut *self.ctx,
+            PipelineRenderContext {
+                hwnd: self.hwnd,
+                engine: &mut self.engine,
+                visible: &self.shared_state.visible,
+                input_enabled: &self.shared_state.input_enabled,
+                cursor_mode: &self.shared_state.cursor_mode,
+                fonts: &mut self.fonts,
+                io: self.io,
+                keys: &self.keys,
+                frame_limiter: &mut self.frame_limiter,
+                timing: self.timing,
+                content_rect: self.content_rect,
+            },
+        )
+    }
+
     pub(crate) fn context(&mut self) -> &mut Context {
+        self.wait_for_frame();
         &mut self.ctx
     }
 
-    pub(crate) fn render_loop(&mut self) -> &mut RenderLoop {
-        &mut self.render_loop
+    pub(crate) fn keys(&mut self) -> &mut KeyState {
+        self.wait_for_frame();
+        &mut self.keys
     }
 
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        self.wait_for_frame();
         self.ctx.io_mut().display_size = [width as f32, height as f32];
     }
 
+    // Scale the style and the fonts from the current DPI to `dpi`. imgui keeps
+    // working in pixels, so the display size and the mouse position are left
+    // alone.
+    pub(crate) fn set_dpi(&mut self, dpi: u32) {
+        self.wait_for_frame();
+
+        let dpi_scale = dpi_scale(dpi);
+        if !self.config.dpi_scaling || dpi_scale == self.dpi_scale {
+            return;
+        }
+
+        let factor = dpi_scale / self.dpi_scale;
+        self.ctx.style_mut().scale_all_sizes(factor);
+        scale_fonts(self.ctx.fonts(), factor);
+        self.fonts.dirty = true;
+        self.dpi_scale = dpi_scale;
+    }
+
+    pub(crate) fn on_present(&mut self, sync_interval: u32, flags: u32) -> PresentAction {
+        self.wait_for_frame();
+        if self.panicked {
+            return PresentAction::CallOriginal;
+        }
+
+        let on_present = catch_panic(self.config.catch_panics, "on_present", || {
+            self.shared_state.lock_render_loop().on_present(sync_interval, flags)
+        });
+        on_present.unwrap_or_else(|| {
+            self.disable();
+            PresentAction::CallOriginal
+        })
+    }
+
+    // Reset the device via `reset`, after letting `release` free the engine's
+    // objects that would keep it from being reset. The fonts are uploaded
+    // again on the next frame, and the render loop is told once the reset
+    // succeeded, to load its own textures again.
+    pub(crate) fn reset_device(
+        &mut self,
+        release: impl FnOnce(&mut T),
+        reset: impl FnOnce() -> HRESULT,
+    ) -> HRESULT {
+        self.wait_for_frame();
+
+        // A frame built ahead of time refers to the released textures.
+        if let Some(frame_thread) = &mut self.frame_thread {
+            frame_thread.draw_data = None;
+        }
+
+        release(&mut self.engine);
+        self.fonts.dirty = true;
+
+        let result = reset();
+        if result.is_err() || self.panicked {
+            return result;
+        }
+
+        let shared_state = Arc::clone(&self.shared_state);
+        let on_device_reset = catch_panic(self.config.catch_panics, "on_device_reset", || {
+            let (ctx, mut render_context) = self.split_render_context();
+            shared_state.lock_render_loop().on_device_reset(ctx, &mut render_context)
+        });
+        if on_device_reset.is_none() {
+            self.disable();
+        }
+
+        result
+    }
+
     pub(crate) fn cleanup(&mut self) {
+        self.frame_thread.take();
+
+        // The next pipeline picks up where this one left off.
+        if self.config.keeps_session_layout() {
+            self.save_session_layout();
+        }
+
+        // Hand the cursor back to the application the way it was. Its display
+        // count can only be restored from the window's thread, so a message is
+        // sent through the window procedure before it's restored. The window's
+        // thread may be waiting on this one, hence the timeout, and the clip
+        // is released from here regardless.
+        self.shared_state.cursor_released.store(true, Ordering::SeqCst);
         unsafe {
-            SetWindowLongPtrW(self.hwnd, GWLP_WNDPROC, self.shared_state.wnd_proc as usize as _)
-        };
+            SendMessageTimeoutW(
+                self.hwnd,
+                WM_NULL,
+                WPARAM(0),
+                LPARAM(0),
+                SMTO_ABORTIFHUNG,
+                CURSOR_RELEASE_TIMEOUT_MS,
+                None,
+            );
+            restore_clip(&mut self.shared_state.cursor.lock());
+        }
+
+        unsafe { unhook_window(self.hwnd, &self.shared_state) };
+
+        // The thread must not outlive the pipeline, as the library may be ejected right after.
+        self.shared_state.redraw_thread_active.store(false, Ordering::SeqCst);
+        if let Some(redraw_thread) = self.redraw_thread.take() {
+            redraw_thread.thread().unpark();
+            if redraw_thread.join().is_err() {
+                error!("Redraw thread panicked");
+            }
+        }
     }
 
     pub(crate) fn take(mut self) -> RenderLoop {
         self.cleanup();
-        self.render_loop
+        self.shared_state.render_loop.lock().take().expect("Render loop already taken")
+    }
+}
+
+// Builds the frames of the render loop off the present function. The pipeline
+// hands the context and the render loop over when starting a frame, and
+// doesn't touch them again until it has waited for the frame to be done.
+struct FrameThread {
+    jobs: Option<Sender<FrameJob>>,
+    results: Receiver<FrameResult>,
+    in_flight: bool,
+    // Whether the render loop panicked while building the last frame.
+    panicked: bool,
+    // The last frame built and not drawn yet. It's valid until the next
+    // frame is started.
+    draw_data: Option<*const DrawData>,
+    handle: Option<JoinHandle<()>>,
+}
+
+// The content rect, the work area to set, if any, and the layers.
+struct FrameJob(
+    *mut Context,
+    Arc<PipelineSharedState>,
+    *const KeyState,
+    [[f32; 2]; 2],
+    Option<[[f32; 2]; 2]>,
+    *const [(Layer, [[f32; 2]; 2])],
+);
+// No draw data if the render loop panicked.
+struct FrameResult(Option<*const DrawData>);
+
+// SAFETY: the pointees are only ever accessed by one thread at a time.
+unsafe impl Send for FrameJob {}
+unsafe impl Send for FrameResult {}
+
+impl FrameThread {
+    fn new(catch_panics: bool) -> Self {
+        let (jobs, rx) = mpsc::channel::<FrameJob>();
+        let (tx, results) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            for FrameJob(ctx, shared_state, keys, content_rect, work_area, layers) in rx {
+                let ctx = unsafe { &mut *ctx };
+                let mut render_context =
+                    FrameRenderContext { io: ctx.io_mut(), keys, content_rect };
+                let ui = ctx.frame();
+                if let Some(work_area) = work_area {
+                    set_work_area(work_area);
+                }
+                let draw_data = catch_panic(catch_panics, "render", || {
+                    shared_state.lock_render_loop().render_with_context(ui, &mut render_context)
+                })
+                .and_then(|()| render_layers(catch_panics, ui, unsafe { &*layers }))
+                .map(|()| ctx.render() as *const DrawData);
+
+                if tx.send(FrameResult(draw_data)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            in_flight: false,
+            panicked: false,
+            draw_data: None,
+            handle: Some(handle),
+        }
     }
+
+    fn start(
+        &mut self,
+        ctx: &mut Context,
+        shared_state: &Arc<PipelineSharedState>,
+        keys: &KeyState,
+        content_rect: [[f32; 2]; 2],
+        work_area: Option<[[f32; 2]; 2]>,
+        layers: &[(Layer, [[f32; 2]; 2])],
+    ) -> Result<()> {
+        let job = FrameJob(ctx, Arc::clone(shared_state), keys, content_rect, work_area, layers);
+        match self.jobs.as_ref().map(|jobs| jobs.send(job)) {
+            Some(Ok(())) => {
+                self.in_flight = true;
+                Ok(())
+            },
+            _ => {
+                error!("Frame thread is not running");
+                Err(Error::from_hresult(HRESULT(-1)))
+            },
+        }
+    }
+
+    fn wait(&mut self) {
+        if mem::take(&mut self.in_flight) {
+            match self.results.recv() {
+                Ok(FrameResult(Some(draw_data))) => self.draw_data = Some(draw_data),
+                Ok(FrameResult(None)) => self.panicked = true,
+                Err(_) => error!("Frame thread panicked"),
+            }
+        }
+    }
+}
+
+impl Drop for FrameThread {
+    fn drop(&mut self) {
+        // Closing the channel stops the thread once the frame being built, if
+        // any, is done, which must happen before the context is dropped.
+        self.jobs.take();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Frame thread panicked");
+            }
+        }
+    }
+}
+
+// The render context handed to the render loop while it builds a frame on the
+// frame thread, where only the context and the keys can be accessed.
+struct FrameRenderContext {
+    io: *mut Io,
+    keys: *const KeyState,
+    content_rect: [[f32; 2]; 2],
+}
+
+impl FrameRenderContext {
+    fn unavailable<T>(&self) -> Result<T> {
+        error!("Only the IO is available while building frames asynchronously");
+        Err(Error::from_hresult(HRESULT(-1)))
+    }
+}
+
+impl RenderContext for FrameRenderContext {
+    fn load_texture_with_format(
+        &mut self,
+        _data: &[u8],
+        _width: u32,
+        _height: u32,
+        _format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.unavailable()
+    }
+
+    fn replace_texture_with_format(
+        &mut self,
+        _texture_id: TextureId,
+        _data: &[u8],
+        _width: u32,
+        _height: u32,
+        _format: PixelFormat,
+    ) -> Result<()> {
+        self.unavailable()
+    }
+
+    fn unload_texture(&mut self, _texture_id: TextureId) -> Result<()> {
+        self.unavailable()
+    }
+
+    fn create_render_texture(
+        &mut self,
+        _width: u32,
+        _height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        self.unavailable()
+    }
+
+    fn texture_handle(&mut self, _texture_id: TextureId) -> Result<TextureHandle> {
+        self.unavailable()
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        self.unavailable()
+    }
+
+    fn io(&self) -> Option<&Io> {
+        Some(unsafe { &*self.io })
+    }
+
+    fn io_mut(&mut self) -> Option<&mut Io> {
+        Some(unsafe { &mut *self.io })
+    }
+
+    fn raw_imgui_context(&self) -> *mut imgui::sys::ImGuiContext {
+        unsafe { imgui::sys::igGetCurrentContext() }
+    }
+
+    fn keybind_pressed(&self, keybind: &Keybind) -> bool {
+        unsafe { &*self.keys }.is_pressed(keybind)
+    }
+
+    fn keybind_held(&self, keybind: &Keybind) -> bool {
+        unsafe { &*self.keys }.is_held(keybind)
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        unsafe { &*self.keys }.modifiers()
+    }
+
+    fn content_rect(&self) -> [[f32; 2]; 2] {
+        self.content_rect
+    }
+
+    fn raw_mouse_delta(&self) -> [f32; 2] {
+        unsafe { &*self.keys }.mouse_delta()
+    }
+}
+
+// Invoke the `callback` of the render loop, catching and logging its panic, if
+// it panics and `enabled` is set. Unwinding out of a hooked function would
+// abort the application.
+fn catch_panic<R>(enabled: bool, callback: &str, f: impl FnOnce() -> R) -> Option<R> {
+    if !enabled {
+        return Some(f());
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            error!(
+                "Render loop panicked in {callback}: {}. It won't be called again.",
+                panic_message(&*payload)
+            );
+            None
+        },
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+// Size of imgui's default font at 96 DPI.
+const DEFAULT_FONT_SIZE: f32 = 13.;
+
+// The whole display, as `[min, max]`.
+fn full_rect(display_size: [f32; 2]) -> [[f32; 2]; 2] {
+    [[0., 0.], display_size]
+}
+
+// The part of `rect` within the display, as `[min, max]`.
+fn clamp_rect(rect: RECT, [width, height]: [u32; 2]) -> [[f32; 2]; 2] {
+    let clamp = |v: i32, max: u32| v.clamp(0, max as i32) as f32;
+    let min = [clamp(rect.left, width), clamp(rect.top, height)];
+    let max = [clamp(rect.right, width).max(min[0]), clamp(rect.bottom, height).max(min[1])];
+    [min, max]
+}
+
+// Bound the area of the main viewport that windows are laid out in, i.e.
+// imgui's `WorkPos` and `WorkSize`, by the content rect. imgui resets it when
+// starting each frame.
+fn set_work_area([min, max]: [[f32; 2]; 2]) {
+    unsafe {
+        let viewport = &mut *imgui::sys::igGetMainViewport();
+        viewport.WorkPos = imgui::sys::ImVec2 { x: min[0], y: min[1] };
+        viewport.WorkSize = imgui::sys::ImVec2 { x: max[0] - min[0], y: max[1] - min[1] };
+    }
+}
+
+// Build the layers after the render loop, in order, each with the work area
+// bounded by its rect.
+fn render_layers(catch_panics: bool, ui: &Ui, layers: &[(Layer, [[f32; 2]; 2])]) -> Option<()> {
+    for (layer, rect) in layers {
+        set_work_area(*rect);
+        catch_panic(catch_panics, &layer.name, || (layer.render.lock())(ui, *rect))?;
+    }
+
+    Some(())
+}
+
+// The scale of a window of the given DPI, relative to the default of 96.
+// `GetDpiForWindow` returns 0 for invalid windows.
+fn dpi_scale(dpi: u32) -> f32 {
+    match dpi {
+        0 => 1.,
+        dpi => dpi as f32 / USER_DEFAULT_SCREEN_DPI as f32,
+    }
+}
+
+// Scale the sizes of all the fonts in the atlas. They are rasterized anew at
+// the new sizes on the next build, instead of being stretched.
+fn scale_fonts(fonts: &mut FontAtlas, factor: f32) {
+    let config_data = unsafe { &mut fonts.raw_mut().ConfigData };
+    if config_data.Size > 0 {
+        let configs =
+            unsafe { slice::from_raw_parts_mut(config_data.Data, config_data.Size as usize) };
+        for config in configs {
+            config.SizePixels *= factor;
+        }
+    }
+    fonts.clear_tex_data();
+}
+
+// The fonts added through the render context.
+struct PipelineFonts {
+    // The atlas is owned by the imgui context, which outlives the pipeline's
+    // render contexts but is borrowed by the render loop alongside them.
+    atlas: *mut FontAtlas,
+    // imgui keeps pointing to the glyph ranges to rebuild the atlas, so they
+    // must live as long as the context.
+    glyph_ranges: Vec<Box<[u32]>>,
+    // Whether the atlas must be built again before the next frame, because
+    // fonts were added or a rebuild was requested.
+    dirty: bool,
+}
+
+impl PipelineFonts {
+    fn new(atlas: &mut FontAtlas) -> Self {
+        Self { atlas, glyph_ranges: Vec::new(), dirty: false }
+    }
+
+    fn add(&mut self, data: &[u8], size_px: f32, glyph_ranges: &[u32]) -> Result<FontId> {
+        let glyph_ranges = match glyph_ranges {
+            [] => FontGlyphRanges::default(),
+            [.., 0] if glyph_ranges.len() % 2 == 1 => {
+                let owned: Box<[u32]> = glyph_ranges.into();
+                // SAFETY: the boxed slice doesn't move and is never dropped
+                // before the pipeline, and thus the context.
+                let ranges = unsafe { &*(&*owned as *const [u32]) };
+                self.glyph_ranges.push(owned);
+                FontGlyphRanges::from_slice(ranges)
+            },
+            _ => {
+                error!("Glyph ranges must be pairs of code points followed by a 0");
+                return Err(Error::from_hresult(HRESULT(-1)));
+            },
+        };
+
+        // imgui copies the font data.
+        let atlas = unsafe { &mut *self.atlas };
+        let font_id = atlas.add_font(&[FontSource::TtfData {
+            data,
+            size_pixels: size_px,
+            config: Some(FontConfig { glyph_ranges, ..FontConfig::default() }),
+        }]);
+        self.dirty = true;
+
+        Ok(font_id)
+    }
+}
+
+// How many frames were presented, and how far apart.
+#[derive(Clone, Copy, Default)]
+struct FrameTiming {
+    frame_count: u64,
+    last_present: Option<Instant>,
+    last_present_duration: Duration,
+}
+
+// The render context handed to the render loop: the engine's texture
+// management and native objects, plus the window, visibility, fonts, IO and
+// frame timing of the pipeline.
+struct PipelineRenderContext<'a, T: RenderEngine> {
+    hwnd: HWND,
+    engine: &'a mut T,
+    visible: &'a AtomicBool,
+    input_enabled: &'a AtomicBool,
+    cursor_mode: &'a AtomicU8,
+    fonts: &'a mut PipelineFonts,
+    io: *mut Io,
+    keys: &'a KeyState,
+    frame_limiter: &'a mut FrameLimiter,
+    timing: FrameTiming,
+    content_rect: [[f32; 2]; 2],
+}
+
+impl<T: RenderEngine> RenderContext for PipelineRenderContext<'_, T> {
+    fn load_texture_with_format(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<TextureId> {
+        self.engine.load_texture_with_format(data, width, height, format)
+    }
+
+    fn load_texture_with_options(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        options: TextureOptions,
+    ) -> Result<TextureId> {
+        self.engine.load_texture_with_options(data, width, height, options)
+    }
+
+    fn replace_texture_with_format(
+        &mut self,
+        texture_id: TextureId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Result<()> {
+        self.engine.replace_texture_with_format(texture_id, data, width, height, format)
+    }
+
+    fn unload_texture(&mut self, texture_id: TextureId) -> Result<()> {
+        self.engine.unload_texture(texture_id)
+    }
+
+    fn create_render_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(TextureId, RenderTargetHandle)> {
+        self.engine.create_render_texture(width, height)
+    }
+
+    fn texture_handle(&mut self, texture_id: TextureId) -> Result<TextureHandle> {
+        self.engine.texture_handle(texture_id)
+    }
+
+    fn texture_size(&self, texture_id: TextureId) -> Option<[f32; 2]> {
+        self.engine.texture_size(texture_id)
+    }
+
+    fn capture_screenshot(&mut self) -> Result<(u32, u32, Vec<u8>)> {
+        self.engine.capture_screenshot()
+    }
+
+    fn with_swap_chain(&self, f: &mut dyn FnMut(&IDXGISwapChain)) -> Option<()> {
+        self.engine.with_swap_chain(f)
+    }
+
+    fn device_handle(&self) -> Option<DeviceHandle> {
+        self.engine.device_handle()
+    }
+
+    fn with_d3d9_device(&self, f: &mut dyn FnMut(&IDirect3DDevice9)) -> Option<()> {
+        self.engine.with_d3d9_device(f)
+    }
+
+    fn is_visible(&self) -> bool {
+        self.visible.load(Ordering::SeqCst)
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        self.visible.store(visible, Ordering::SeqCst);
+    }
+
+    fn is_input_enabled(&self) -> bool {
+        self.input_enabled.load(Ordering::SeqCst)
+    }
+
+    fn set_input_enabled(&mut self, enabled: bool) {
+        self.input_enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    fn set_cursor_mode(&mut self, mode: CursorMode) {
+        self.cursor_mode.store(mode as u8, Ordering::SeqCst);
+    }
+
+    fn io(&self) -> Option<&Io> {
+        Some(unsafe { &*self.io })
+    }
+
+    fn io_mut(&mut self) -> Option<&mut Io> {
+        Some(unsafe { &mut *self.io })
+    }
+
+    fn raw_imgui_context(&self) -> *mut imgui::sys::ImGuiContext {
+        unsafe { imgui::sys::igGetCurrentContext() }
+    }
+
+    fn keybind_pressed(&self, keybind: &Keybind) -> bool {
+        self.keys.is_pressed(keybind)
+    }
+
+    fn keybind_held(&self, keybind: &Keybind) -> bool {
+        self.keys.is_held(keybind)
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        self.keys.modifiers()
+    }
+
+    fn content_rect(&self) -> [[f32; 2]; 2] {
+        self.content_rect
+    }
+
+    fn raw_mouse_delta(&self) -> [f32; 2] {
+        self.keys.mouse_delta()
+    }
+
+    fn window_title(&self) -> String {
+        window_title(self.hwnd)
+    }
+
+    fn target_hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    fn frame_limit(&self) -> Option<f32> {
+        self.frame_limiter.limit()
+    }
+
+    fn set_frame_limit(&mut self, fps: Option<f32>) {
+        self.frame_limiter.set_limit(fps);
+    }
+
+    fn frame_count(&self) -> u64 {
+        self.timing.frame_count
+    }
+
+    fn last_present_duration(&self) -> Duration {
+        self.timing.last_present_duration
+    }
+
+    fn add_font(&mut self, data: &[u8], size_px: f32, glyph_ranges: &[u32]) -> Result<FontId> {
+        self.fonts.add(data, size_px, glyph_ranges)
+    }
+
+    fn rebuild_fonts(&mut self) -> Result<()> {
+        self.fonts.dirty = true;
+        Ok(())
+    }
+}
+
+// Read the title of the window. `GetWindowTextW` would send `WM_GETTEXT` to
+// the window, which blocks if its thread is waiting on the render thread, so
+// the title is read directly instead.
+fn window_title(hwnd: HWND) -> String {
+    let mut buf = vec![0u16; 256];
+
+    loop {
+        let len = unsafe { InternalGetWindowText(hwnd, &mut buf) }.max(0) as usize;
+
+        // The title was truncated if it filled the buffer up to the terminator.
+        if len + 1 < buf.len() {
+            return String::from_utf16_lossy(&buf[..len]);
+        }
+
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+// Move the OS cursor to a position in the client area of the window.
+fn set_cursor_pos(hwnd: HWND, [x, y]: [f32; 2]) {
+    let mut point = POINT { x: x as i32, y: y as i32 };
+
+    unsafe {
+        if !ClientToScreen(hwnd, &mut point).as_bool() {
+            error!("Could not convert cursor position to screen coordinates");
+            return;
+        }
+
+        if let Err(e) = SetCursorPos(point.x, point.y) {
+            error!("Could not set cursor position: {e:?}");
+        }
+    }
+}
+
+// Invalidate the window whenever the host hasn't presented a frame for longer
+// than the minimum framerate allows, so that hosts that render in response to
+// `WM_PAINT` present again.
+fn redraw_loop(hwnd: HWND, shared_state: Arc<PipelineSharedState>) {
+    while shared_state.redraw_thread_active.load(Ordering::SeqCst) {
+        let min_framerate = shared_state.min_framerate.load(Ordering::SeqCst);
+        if min_framerate == 0 {
+            thread::park_timeout(REDRAW_IDLE_INTERVAL);
+            continue;
+        }
+
+        let interval = Duration::from_secs_f64(1.0 / min_framerate as f64);
+        let elapsed = shared_state.last_frame.lock().elapsed();
+
+        if elapsed >= interval {
+            unsafe { InvalidateRect(hwnd, None, false) };
+            thread::park_timeout(interval);
+        } else {
+            thread::park_timeout(interval - elapsed);
+        }
+    }
+}
+
+// Let the render loop handle the message first. It can't be waited for from the
+// thread it's running on, and is skipped if it's busy for too long.
+fn render_loop_wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    shared_state: &PipelineSharedState,
+) -> WndProcResult {
+    if IN_RENDER_LOOP.get() || shared_state.panicked.load(Ordering::SeqCst) {
+        return WndProcResult::Passthrough;
+    }
+
+    let Some(mut render_loop) = shared_state.try_lock_render_loop(WND_PROC_RENDER_LOOP_TIMEOUT)
+    else {
+        debug!("Render loop unavailable, passing message {msg:#x} through");
+        return WndProcResult::Passthrough;
+    };
+
+    let capture = CaptureFlags {
+        want_capture_keyboard: shared_state.want_capture_keyboard.load(Ordering::SeqCst),
+        want_capture_mouse: shared_state.want_capture_mouse.load(Ordering::SeqCst),
+        want_text_input: shared_state.want_text_input.load(Ordering::SeqCst),
+    };
+
+    catch_panic(shared_state.catch_panics, "on_wnd_proc", || {
+        render_loop.on_wnd_proc_with_capture(hwnd, msg, wparam, lparam, capture)
+    })
+    .unwrap_or_else(|| {
+        shared_state.panicked.store(true, Ordering::SeqCst);
+        WndProcResult::Passthrough
+    })
+}
+
+// Install the hook the window's messages are received through in `mode`.
+unsafe fn hook_window(hwnd: HWND, mode: InputMode) -> Result<WindowHook> {
+    let window_thread = GetWindowThreadProcessId(hwnd, None);
+
+    match mode {
+        InputMode::WndProcTrampoline => {},
+        InputMode::Subclass if window_thread != GetCurrentThreadId() => {
+            warn!("Can't subclass the window from another thread, replacing its procedure instead");
+        },
+        InputMode::Subclass => {
+            if SetWindowSubclass(hwnd, Some(pipeline_subclass_proc), SUBCLASS_ID, 0).as_bool() {
+                return Ok(WindowHook::Subclass);
+            }
+            warn!("Could not subclass the window, replacing its procedure instead");
+        },
+        InputMode::RawInputOnly => {
+            let hook = SetWindowsHookExW(
+                WH_GETMESSAGE,
+                Some(pipeline_get_msg_proc),
+                HINSTANCE::default(),
+                window_thread,
+            )
+            .map_err(|e| {
+                error!("Could not hook the messages of the window's thread: {e:?}");
+                e
+            })?;
+            return Ok(WindowHook::GetMessage(hook));
+        },
+    }
+
+    #[cfg(target_arch = "x86")]
+    type SwlpRet = i32;
+    #[cfg(target_arch = "x86_64")]
+    type SwlpRet = isize;
+
+    Ok(WindowHook::WndProc(mem::transmute::<SwlpRet, WndProcType>(SetWindowLongPtrW(
+        hwnd,
+        GWLP_WNDPROC,
+        pipeline_wnd_proc as usize as _,
+    ))))
+}
+
+// Remove the hook installed by `hook_window`. A subclass can only be removed
+// from the window's thread, which has been given the chance to do so by now.
+unsafe fn unhook_window(hwnd: HWND, shared_state: &PipelineSharedState) {
+    match shared_state.window_hook {
+        WindowHook::WndProc(wnd_proc) => {
+            SetWindowLongPtrW(hwnd, GWLP_WNDPROC, wnd_proc as usize as _);
+        },
+        WindowHook::Subclass => {
+            if shared_state.subclassed.swap(false, Ordering::SeqCst)
+                && !RemoveWindowSubclass(hwnd, Some(pipeline_subclass_proc), SUBCLASS_ID).as_bool()
+            {
+                error!("Could not remove the window subclass");
+            }
+        },
+        WindowHook::GetMessage(hook) => {
+            if let Err(e) = UnhookWindowsHookEx(hook) {
+                error!("Could not unhook the messages of the window's thread: {e:?}");
+            }
+        },
+    }
+}
+
+fn pipeline_shared_state(hwnd: HWND) -> Option<Arc<PipelineSharedState>> {
+    let Some(shared_state_guard) = PIPELINE_STATES.try_lock() else {
+        error!("Could not lock shared state in window procedure");
+        return None;
+    };
+
+    let Some(shared_state) = shared_state_guard.get(&hwnd.0) else {
+        error!("Could not get shared state for handle {hwnd:?}");
+        return None;
+    };
+
+    Some(Arc::clone(shared_state))
 }
 
 unsafe extern "system" fn pipeline_wnd_proc(
@@ -181,32 +1573,369 @@ unsafe extern "system" fn pipeline_wnd_proc(
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
-    let shared_state = {
-        let Some(shared_state_guard) = PIPELINE_STATES.try_lock() else {
-            error!("Could not lock shared state in window procedure");
-            return DefWindowProcW(hwnd, msg, wparam, lparam);
-        };
+    let Some(shared_state) = pipeline_shared_state(hwnd) else {
+        return DefWindowProcW(hwnd, msg, wparam, lparam);
+    };
 
-        let Some(shared_state) = shared_state_guard.get(&hwnd.0) else {
-            error!("Could not get shared state for handle {hwnd:?}");
-            return DefWindowProcW(hwnd, msg, wparam, lparam);
-        };
+    if let Some(result) = handle_message(hwnd, msg, wparam, lparam, &shared_state) {
+        return result;
+    }
 
-        Arc::clone(shared_state)
+    match shared_state.window_hook {
+        WindowHook::WndProc(wnd_proc) => CallWindowProcW(Some(wnd_proc), hwnd, msg, wparam, lparam),
+        // The window procedure is only replaced in that mode.
+        WindowHook::Subclass | WindowHook::GetMessage(_) => {
+            DefWindowProcW(hwnd, msg, wparam, lparam)
+        },
+    }
+}
+
+unsafe extern "system" fn pipeline_subclass_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _id: usize,
+    _data: usize,
+) -> LRESULT {
+    let Some(shared_state) = pipeline_shared_state(hwnd) else {
+        return DefSubclassProc(hwnd, msg, wparam, lparam);
     };
 
+    let result = handle_message(hwnd, msg, wparam, lparam, &shared_state)
+        .unwrap_or_else(|| DefSubclassProc(hwnd, msg, wparam, lparam));
+
+    // The pipeline is being cleaned up, and has sent a message to get here.
+    if shared_state.cursor_released.load(Ordering::SeqCst)
+        && shared_state.subclassed.swap(false, Ordering::SeqCst)
+        && !RemoveWindowSubclass(hwnd, Some(pipeline_subclass_proc), SUBCLASS_ID).as_bool()
+    {
+        error!("Could not remove the window subclass");
+    }
+
+    result
+}
+
+unsafe extern "system" fn pipeline_get_msg_proc(
+    code: i32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    // Only the messages taken off the queue are handled, as they may also be
+    // peeked at beforehand. The thread may have other windows than the
+    // pipeline's.
+    if code >= 0 && wparam.0 as u32 == PM_REMOVE.0 {
+        let msg = &mut *(lparam.0 as *mut MSG);
+        let shared_state =
+            PIPELINE_STATES.try_lock().and_then(|states| states.get(&msg.hwnd.0).cloned());
+
+        if let Some(shared_state) = shared_state {
+            // There's no one to return a result to: the message can only be
+            // dropped.
+            if handle_message(msg.hwnd, msg.message, msg.wParam, msg.lParam, &shared_state)
+                .is_some()
+            {
+                msg.message = WM_NULL;
+            }
+        }
+    }
+
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+// Handle a message of the window, and return its result if it must not reach
+// the application.
+unsafe fn handle_message(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    shared_state: &PipelineSharedState,
+) -> Option<LRESULT> {
+    // The hooks are disabled and nothing drains the messages, so the overlay
+    // shouldn't see nor block them until they are reapplied.
+    if Hudhook::status() == HookStatus::Unapplied {
+        handle_cursor(hwnd, msg, lparam, shared_state);
+        return None;
+    }
+
+    if let WndProcResult::Handled(result) =
+        render_loop_wnd_proc(hwnd, msg, wparam, lparam, shared_state)
+    {
+        return Some(result);
+    }
+
     if let Err(e) = shared_state.tx.send(PipelineMessage(hwnd, msg, wparam, lparam)) {
         error!("Could not send window message through pipeline: {e:?}");
     }
 
+    // Bit 30 of `lparam` is set when the key was already down, i.e. on
+    // auto-repeat, which must not toggle the overlay again.
+    if let Some(toggle_key) = shared_state.toggle_key {
+        if matches!(msg, WM_KEYDOWN | WM_SYSKEYDOWN)
+            && wparam.0 == toggle_key.0 as usize
+            && lparam.0 & (1 << 30) == 0
+        {
+            shared_state.visible.fetch_xor(true, Ordering::SeqCst);
+        }
+    }
+
     // CONCURRENCY: as the message interpretation now happens out of band, this
     // expresses the intent as of *before* the current message was received.
-    let message_filter =
-        MessageFilter::from_bits_retain(shared_state.message_filter.load(Ordering::SeqCst));
+    let message_filter = if shared_state.takes_input() {
+        MessageFilter::from_bits_retain(shared_state.message_filter.load(Ordering::SeqCst))
+    } else {
+        MessageFilter::empty()
+    };
+
+    if handle_cursor(hwnd, msg, lparam, shared_state) {
+        Some(LRESULT(1))
+    } else if handle_ime(hwnd, msg, shared_state) {
+        Some(DefWindowProcW(hwnd, msg, wparam, lparam))
+    } else if message_filter.is_blocking(msg) {
+        Some(LRESULT(1))
+    } else {
+        None
+    }
+}
+
+// Keep track of the text cursor of the focused text input, as reported by
+// imgui at the end of a frame.
+unsafe extern "C" fn set_ime_data(
+    viewport: *mut imgui::sys::ImGuiViewport,
+    data: *mut imgui::sys::ImGuiPlatformImeData,
+) {
+    let shared_state = &*((*viewport).PlatformUserData as *const PipelineSharedState);
+    let imgui::sys::ImGuiPlatformImeData { WantVisible, InputPos, InputLineHeight } = *data;
+
+    *shared_state.ime_area.lock() = WantVisible.then(|| RECT {
+        left: InputPos.x as i32,
+        top: InputPos.y as i32,
+        right: InputPos.x as i32 + 1,
+        bottom: (InputPos.y + InputLineHeight) as i32,
+    });
+}
+
+// Let the IME work with the focused text input, if any: enable it if the
+// application has disabled it, and move its windows next to the text cursor.
+// This has to happen on the window's thread, as the IME functions don't work
+// across threads.
+//
+// Returns whether the message should go straight to the default window
+// procedure, which turns compositions into `WM_CHAR` messages, rather than
+// the application's, which may swallow it.
+unsafe fn handle_ime(hwnd: HWND, msg: u32, shared_state: &PipelineSharedState) -> bool {
+    let area = *shared_state.ime_area.lock();
+    let Some(area) = area.filter(|_| shared_state.takes_input()) else {
+        // Hand the IME back to the application the way it was.
+        if shared_state.ime_associated.swap(false, Ordering::SeqCst) {
+            ImmAssociateContextEx(hwnd, HIMC::default(), 0);
+        }
+        return false;
+    };
+
+    let mut himc = ImmGetContext(hwnd);
+    if himc.is_invalid() {
+        ImmAssociateContextEx(hwnd, HIMC::default(), IACE_DEFAULT);
+        shared_state.ime_associated.store(true, Ordering::SeqCst);
+        himc = ImmGetContext(hwnd);
+    }
+
+    if !himc.is_invalid() {
+        if matches!(msg, WM_IME_STARTCOMPOSITION | WM_IME_COMPOSITION) {
+            let pos = POINT { x: area.left, y: area.top };
+            ImmSetCompositionWindow(
+                himc,
+                &COMPOSITIONFORM {
+                    dwStyle: CFS_FORCE_POSITION,
+                    ptCurrentPos: pos,
+                    rcArea: RECT::default(),
+                },
+            );
+            // Keep the candidate list off the line being typed.
+            ImmSetCandidateWindow(
+                himc,
+                &CANDIDATEFORM {
+                    dwIndex: 0,
+                    dwStyle: CFS_EXCLUDE,
+                    ptCurrentPos: pos,
+                    rcArea: area,
+                },
+            );
+        }
+        ImmReleaseContext(hwnd, himc);
+    }
+
+    matches!(
+        msg,
+        WM_IME_SETCONTEXT
+            | WM_IME_NOTIFY
+            | WM_IME_CONTROL
+            | WM_IME_COMPOSITIONFULL
+            | WM_IME_SELECT
+            | WM_IME_CHAR
+            | WM_IME_REQUEST
+            | WM_IME_KEYDOWN
+            | WM_IME_KEYUP
+            | WM_IME_STARTCOMPOSITION
+            | WM_IME_COMPOSITION
+            | WM_IME_ENDCOMPOSITION
+    )
+}
+
+// What has been changed about the OS cursor while the overlay wanted the mouse.
+#[derive(Default)]
+struct CursorState {
+    // How many times the display count of the cursor was incremented to show
+    // it, and the cursor the application had set before.
+    shown: Option<u32>,
+    previous_cursor: HCURSOR,
+    // Whether the cursor is confined to the window, and the area it was
+    // confined to before, if any.
+    confined: bool,
+    previous_clip: Option<RECT>,
+}
+
+fn cursor_mode(value: u8) -> CursorMode {
+    match value {
+        v if v == CursorMode::ShowOverlayCursor as u8 => CursorMode::ShowOverlayCursor,
+        v if v == CursorMode::ConfineAndShow as u8 => CursorMode::ConfineAndShow,
+        _ => CursorMode::GameControlled,
+    }
+}
+
+// Show the cursor, and confine it to the window, while the overlay wants the
+// mouse as requested through `RenderContext::set_cursor_mode`, and restore the
+// cursor state of the application once it doesn't. This has to happen on the
+// window's thread, as the display count of the cursor is per thread.
+//
+// Whatever the mode, the shape of the cursor is the one imgui wants while it
+// wants the mouse. Returns whether the message is a `WM_SETCURSOR` that was
+// handled, and must not reach the application, which would set its own cursor.
+unsafe fn handle_cursor(
+    hwnd: HWND,
+    msg: u32,
+    lparam: LPARAM,
+    shared_state: &PipelineSharedState,
+) -> bool {
+    let mode = cursor_mode(shared_state.cursor_mode.load(Ordering::SeqCst));
+    let wants_mouse = shared_state.takes_input()
+        && shared_state.want_capture_mouse.load(Ordering::SeqCst)
+        && !shared_state.cursor_released.load(Ordering::SeqCst)
+        && Hudhook::status() != HookStatus::Unapplied;
+    let captured = mode != CursorMode::GameControlled && wants_mouse;
+
+    let mut cursor = shared_state.cursor.lock();
+
+    // Applications hide the cursor by decrementing its display count, which
+    // must not be negative for the cursor to be shown.
+    match (captured, cursor.shown) {
+        (true, None) => {
+            let mut shown = 1;
+            while ShowCursor(true) < 0 {
+                shown += 1;
+            }
+            cursor.shown = Some(shown);
+            cursor.previous_cursor = GetCursor();
+        },
+        (false, Some(shown)) => {
+            for _ in 0..shown {
+                ShowCursor(false);
+            }
+            cursor.shown = None;
+            SetCursor(mem::take(&mut cursor.previous_cursor));
+        },
+        _ => {},
+    }
+
+    if captured && mode == CursorMode::ConfineAndShow {
+        if !cursor.confined {
+            cursor.previous_clip = util::try_out_param(|rect| GetClipCursor(rect)).ok();
+            cursor.confined = true;
+        }
 
-    if message_filter.is_blocking(msg) {
-        LRESULT(1)
+        // Follow the window as it's moved or resized.
+        if let Some(rect) = client_screen_rect(hwnd) {
+            if let Err(e) = ClipCursor(Some(&rect as *const RECT)) {
+                error!("Could not confine cursor: {e:?}");
+            }
+        }
     } else {
-        CallWindowProcW(Some(shared_state.wnd_proc), hwnd, msg, wparam, lparam)
+        restore_clip(&mut cursor);
+    }
+
+    if !wants_mouse || msg != WM_SETCURSOR || (lparam.0 & 0xffff) as u32 != HTCLIENT {
+        return false;
+    }
+
+    let Some(shape) = system_cursor(shared_state.mouse_cursor.load(Ordering::SeqCst)) else {
+        SetCursor(HCURSOR::default());
+        return true;
+    };
+
+    match LoadCursorW(None, shape) {
+        Ok(handle) => {
+            SetCursor(handle);
+            true
+        },
+        Err(e) => {
+            error!("Could not load cursor: {e:?}");
+            false
+        },
     }
 }
+
+// The system cursor for an `ImGuiMouseCursor`, or `None` if there's to be no
+// cursor, as in imgui's own Win32 backend.
+fn system_cursor(mouse_cursor: imgui::sys::ImGuiMouseCursor) -> Option<PCWSTR> {
+    use imgui::sys::{
+        ImGuiMouseCursor_Hand, ImGuiMouseCursor_None, ImGuiMouseCursor_NotAllowed,
+        ImGuiMouseCursor_ResizeAll, ImGuiMouseCursor_ResizeEW, ImGuiMouseCursor_ResizeNESW,
+        ImGuiMouseCursor_ResizeNS, ImGuiMouseCursor_ResizeNWSE, ImGuiMouseCursor_TextInput,
+    };
+
+    #[allow(non_upper_case_globals)]
+    match mouse_cursor {
+        ImGuiMouseCursor_None => None,
+        ImGuiMouseCursor_TextInput => Some(IDC_IBEAM),
+        ImGuiMouseCursor_ResizeAll => Some(IDC_SIZEALL),
+        ImGuiMouseCursor_ResizeNS => Some(IDC_SIZENS),
+        ImGuiMouseCursor_ResizeEW => Some(IDC_SIZEWE),
+        ImGuiMouseCursor_ResizeNESW => Some(IDC_SIZENESW),
+        ImGuiMouseCursor_ResizeNWSE => Some(IDC_SIZENWSE),
+        ImGuiMouseCursor_Hand => Some(IDC_HAND),
+        ImGuiMouseCursor_NotAllowed => Some(IDC_NO),
+        _ => Some(IDC_ARROW),
+    }
+}
+
+// Release the cursor from the window, back to the area it was confined to
+// before, if any.
+unsafe fn restore_clip(cursor: &mut CursorState) {
+    if mem::take(&mut cursor.confined) {
+        let previous_clip = cursor.previous_clip.take();
+        if let Err(e) = ClipCursor(previous_clip.as_ref().map(|rect| rect as *const RECT)) {
+            error!("Could not release cursor: {e:?}");
+        }
+    }
+}
+
+// The client area of the window, in screen coordinates.
+fn client_screen_rect(hwnd: HWND) -> Option<RECT> {
+    let mut rect = RECT::default();
+    unsafe { GetClientRect(hwnd, &mut rect) }.ok()?;
+
+    let mut top_left = POINT { x: rect.left, y: rect.top };
+    let mut bottom_right = POINT { x: rect.right, y: rect.bottom };
+    let converted = unsafe {
+        ClientToScreen(hwnd, &mut top_left).as_bool()
+            && ClientToScreen(hwnd, &mut bottom_right).as_bool()
+    };
+
+    converted.then(|| RECT {
+        left: top_left.x,
+        top: top_left.y,
+        right: bottom_right.x,
+        bottom: bottom_right.y,
+    })
+}
@@ -0,0 +1,319 @@
+//! This module contains the reference counting logic for textures.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use imgui::TextureId;
+use parking_lot::Mutex;
+use tracing::error;
+use windows::core::{Error, Result, HRESULT};
+use windows::Win32::Graphics::Direct3D10::{ID3D10Device, ID3D10RenderTargetView, ID3D10Texture2D};
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView, ID3D11Texture2D,
+};
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12CommandQueue, ID3D12DescriptorHeap, ID3D12Device, ID3D12Resource,
+    D3D12_CPU_DESCRIPTOR_HANDLE,
+};
+use windows::Win32::Graphics::Direct3D9::{IDirect3DDevice9, IDirect3DSurface9, IDirect3DTexture9};
+
+#[derive(Default)]
+struct TextureRefsInner {
+    counts: Vec<usize>,
+    released: Vec<TextureId>,
+}
+
+impl TextureRefsInner {
+    fn release(&mut self, texture_id: TextureId) -> bool {
+        match self.counts.get_mut(texture_id.id()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                *count == 0
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Reference counts of the textures loaded by a render engine.
+///
+/// Every texture starts with the single reference owned by the caller of
+/// [`RenderContext::load_texture`](crate::RenderContext::load_texture), and
+/// each [`TextureHandle`] holds one more. The backend frees the GPU resource
+/// once the count drops to zero.
+#[derive(Default)]
+pub(crate) struct TextureRefs(Arc<Mutex<TextureRefsInner>>);
+
+impl TextureRefs {
+    /// Register a newly loaded texture with a single reference.
+    pub(crate) fn insert(&self, texture_id: TextureId) {
+        let mut inner = self.0.lock();
+        let index = texture_id.id();
+        if inner.counts.len() <= index {
+            inner.counts.resize(index + 1, 0);
+        }
+        inner.counts[index] = 1;
+    }
+
+    /// Acquire a new reference to the texture, owned by the returned handle.
+    pub(crate) fn handle(&self, texture_id: TextureId) -> Result<TextureHandle> {
+        let mut inner = self.0.lock();
+        match inner.counts.get_mut(texture_id.id()) {
+            Some(count) if *count > 0 => {
+                *count += 1;
+                Ok(TextureHandle { texture_id, refs: Arc::clone(&self.0) })
+            },
+            _ => {
+                error!("texture {texture_id:?} does not exist");
+                Err(Error::from_hresult(HRESULT(-1)))
+            },
+        }
+    }
+
+    /// Release a reference to the texture. Returns `true` if it was the last
+    /// one and the texture has to be freed.
+    pub(crate) fn release(&self, texture_id: TextureId) -> Result<bool> {
+        let mut inner = self.0.lock();
+        if inner.counts.get(texture_id.id()).copied().unwrap_or(0) == 0 {
+            error!("texture {texture_id:?} does not exist");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        }
+
+        Ok(inner.release(texture_id))
+    }
+
+    /// Take the textures whose last reference was held by a dropped
+    /// [`TextureHandle`], so that they can be freed on the render thread.
+    pub(crate) fn drain_released(&self) -> Vec<TextureId> {
+        let mut inner = self.0.lock();
+        if inner.released.is_empty() {
+            Vec::new()
+        } else {
+            std::mem::take(&mut inner.released)
+        }
+    }
+}
+
+/// Layout of the pixels of texture data, in tightly packed rows from top to
+/// bottom.
+///
+/// The renderers sample the formats their graphics API supports natively, and
+/// convert the others while uploading.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Four 8-bit channels, red first.
+    #[default]
+    Rgba8,
+    /// Four 8-bit channels, blue first, as in most screen captures.
+    Bgra8,
+    /// A single 8-bit channel, sampled as opaque gray, e.g. for masks.
+    R8,
+}
+
+impl PixelFormat {
+    /// The size of a pixel, in bytes.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => 4,
+            PixelFormat::R8 => 1,
+        }
+    }
+}
+
+/// How the color channels of texture data relate to its alpha channel.
+///
+/// The renderers blend straight alpha, like Dear ImGui does. Premultiplied
+/// data, as produced by some image editors and video decoders, shows up with
+/// dark fringes around translucent areas unless it's converted first, e.g. by
+/// [`RenderContext::load_texture_with_alpha_mode`](crate::RenderContext::load_texture_with_alpha_mode).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// The color channels are independent from alpha.
+    #[default]
+    Straight,
+    /// The color channels have already been multiplied by alpha.
+    Premultiplied,
+}
+
+/// How a texture is sampled when it's drawn at a size other than its own.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFilter {
+    /// The nearest texel, for pixel art.
+    Point,
+    /// A blend of the nearest texels, as for the font atlas.
+    #[default]
+    Linear,
+    /// Like [`TextureFilter::Linear`], with less blur where the texture is
+    /// drawn at an angle or squashed. Only differs from it for mipmapped
+    /// textures.
+    Anisotropic,
+}
+
+/// How a texture is loaded by
+/// [`RenderContext::load_texture_with_options`](crate::RenderContext::load_texture_with_options).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TextureOptions {
+    /// The layout of the pixels.
+    pub format: PixelFormat,
+    /// How the texture is sampled.
+    pub filter: TextureFilter,
+    /// Whether to generate a full chain of mipmaps, i.e. successively halved
+    /// copies of the texture, which keep it from shimmering when it's drawn
+    /// much smaller than its size, e.g. for icons and minimaps. They're
+    /// generated again whenever the texture is replaced.
+    pub mipmaps: bool,
+}
+
+impl TextureOptions {
+    /// Set the layout of the pixels.
+    pub fn with_format(mut self, format: PixelFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set how the texture is sampled.
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Generate mipmaps for the texture.
+    pub fn with_mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+}
+
+/// The native objects to draw to a texture created by
+/// [`RenderContext::create_render_texture`](crate::RenderContext::create_render_texture),
+/// for the backend the overlay is rendered with.
+///
+/// The texture is 8-bit RGBA, in the format the backend loads RGBA textures
+/// with, and has a single mip level. Its contents are only meant to be drawn
+/// to, not replaced via
+/// [`RenderContext::replace_texture`](crate::RenderContext::replace_texture).
+/// The handle keeps the native objects alive, but drawing to them after the
+/// texture is unloaded has no visible effect.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum RenderTargetHandle {
+    /// A `D3DUSAGE_RENDERTARGET` texture, and its surface to pass to
+    /// `IDirect3DDevice9::SetRenderTarget`.
+    D3D9 { texture: IDirect3DTexture9, surface: IDirect3DSurface9 },
+    /// A texture, and its render target view.
+    D3D10 { texture: ID3D10Texture2D, render_target_view: ID3D10RenderTargetView },
+    /// A texture, and its render target view.
+    D3D11 { texture: ID3D11Texture2D, render_target_view: ID3D11RenderTargetView },
+    /// A texture created with `D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET`, and
+    /// its render target view in a heap of its own.
+    ///
+    /// Between frames, the resource is in the
+    /// `D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE` state. A command list that
+    /// draws to it must transition it to `D3D12_RESOURCE_STATE_RENDER_TARGET`
+    /// and back.
+    D3D12 {
+        resource: ID3D12Resource,
+        rtv_heap: ID3D12DescriptorHeap,
+        rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+    },
+    /// The names of a texture, and of a framebuffer object it's attached to
+    /// as `GL_COLOR_ATTACHMENT0`.
+    OpenGl { texture: u32, framebuffer: u32 },
+}
+
+/// The device the overlay renders with, as returned by
+/// [`RenderContext::device_handle`](crate::RenderContext::device_handle), for
+/// the backend in use.
+///
+/// These are the application's own objects, not copies: holding the handle
+/// keeps them alive, but they're only safe to use on the render thread while
+/// the render loop is being called, i.e. from
+/// [`ImguiRenderLoop::before_render`](crate::ImguiRenderLoop::before_render)
+/// or [`ImguiRenderLoop::after_render`](crate::ImguiRenderLoop::after_render).
+/// Drop the handle before returning: the application may release or reset
+/// the device at any point outside of those, e.g. when resizing or switching
+/// to fullscreen, and keeping it alive past that can break the application.
+///
+/// All the state set on the device or context must be restored before
+/// returning, as the application's frame is still being rendered, and the
+/// overlay relies on its own state being set up from scratch.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum DeviceHandle {
+    /// The application's device. It must not be reset.
+    D3D9 { device: IDirect3DDevice9 },
+    /// The device of the application's swap chain.
+    D3D10 { device: ID3D10Device },
+    /// The device of the application's swap chain, and its immediate context.
+    ///
+    /// The immediate context isn't thread-safe: only use it on the render
+    /// thread, and record work for other threads on deferred contexts
+    /// created from the device instead.
+    D3D11 { device: ID3D11Device, device_context: ID3D11DeviceContext },
+    /// The device of the application's swap chain, and the command queue the
+    /// overlay executes its command lists on, which is the application's
+    /// unless configured otherwise via
+    /// [`HudhookBuilder::with_dx12_command_queue`](crate::HudhookBuilder::with_dx12_command_queue).
+    ///
+    /// Synchronize any resource shared with the application's other queues
+    /// with fences, and wait for the work executed on the queue to complete
+    /// before the resources it uses are released.
+    D3D12 { device: ID3D12Device, command_queue: ID3D12CommandQueue },
+}
+
+/// A reference to a texture that is released when dropped.
+///
+/// Obtain one via [`RenderContext::texture_handle`](crate::RenderContext::texture_handle).
+/// The texture stays alive as long as any handle to it exists, or until the
+/// reference returned by [`RenderContext::load_texture`](crate::RenderContext::load_texture)
+/// is released with [`RenderContext::unload_texture`](crate::RenderContext::unload_texture),
+/// whichever comes last. Cloning a handle acquires a new reference.
+///
+/// Dropping a handle is safe on any thread: the GPU resource is only freed
+/// later, on the render thread.
+pub struct TextureHandle {
+    texture_id: TextureId,
+    refs: Arc<Mutex<TextureRefsInner>>,
+}
+
+impl TextureHandle {
+    /// The id of the texture, to be used in [`imgui::Image`] and similar
+    /// widgets.
+    pub fn id(&self) -> TextureId {
+        self.texture_id
+    }
+}
+
+impl Deref for TextureHandle {
+    type Target = TextureId;
+
+    fn deref(&self) -> &Self::Target {
+        &self.texture_id
+    }
+}
+
+impl Clone for TextureHandle {
+    fn clone(&self) -> Self {
+        if let Some(count) = self.refs.lock().counts.get_mut(self.texture_id.id()) {
+            *count += 1;
+        }
+
+        Self { texture_id: self.texture_id, refs: Arc::clone(&self.refs) }
+    }
+}
+
+impl Drop for TextureHandle {
+    fn drop(&mut self) {
+        let mut inner = self.refs.lock();
+        if inner.release(self.texture_id) {
+            inner.released.push(self.texture_id);
+        }
+    }
+}
+
+impl fmt::Debug for TextureHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TextureHandle").field(&self.texture_id).finish()
+    }
+}
@@ -9,12 +9,12 @@ use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use tracing::{debug, error};
-use windows::core::s;
-use windows::Win32::Foundation::{HANDLE, HMODULE, HWND, MAX_PATH, RECT};
+use windows::core::{s, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HMODULE, HWND, MAX_PATH, RECT};
 use windows::Win32::Graphics::Direct3D::ID3DBlob;
 use windows::Win32::Graphics::Direct3D12::{
-    D3D12GetDebugInterface, ID3D12Debug, ID3D12Device, ID3D12Fence, ID3D12Resource,
-    D3D12_FENCE_FLAG_NONE, D3D12_RESOURCE_BARRIER, D3D12_RESOURCE_BARRIER_0,
+    D3D12GetDebugInterface, ID3D12CommandQueue, ID3D12Debug, ID3D12Device, ID3D12Fence,
+    ID3D12Resource, D3D12_FENCE_FLAG_NONE, D3D12_RESOURCE_BARRIER, D3D12_RESOURCE_BARRIER_0,
     D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES, D3D12_RESOURCE_BARRIER_FLAG_NONE,
     D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_STATES,
     D3D12_RESOURCE_TRANSITION_BARRIER,
@@ -23,8 +23,8 @@ use windows::Win32::Graphics::Dxgi::{
     DXGIGetDebugInterface1, IDXGIInfoQueue, DXGI_DEBUG_ALL, DXGI_INFO_QUEUE_MESSAGE,
 };
 use windows::Win32::System::LibraryLoader::{
-    GetModuleFileNameW, GetModuleHandleExA, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
-    GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+    GetModuleFileNameW, GetModuleHandleExA, GetModuleHandleExW,
+    GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
 };
 use windows::Win32::System::Memory::{
     VirtualQuery, MEMORY_BASIC_INFORMATION, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
@@ -39,7 +39,7 @@ use windows::Win32::UI::WindowsAndMessaging::GetClientRect;
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// let swap_chain_desc = try_out_param(|sd| unsafe { self.swap_chain.GetDesc1(sd) })?;
 /// ```
 pub fn try_out_param<T, F, E, O>(mut f: F) -> Result<T, E>
@@ -59,7 +59,7 @@ where
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// let dev: ID3D12Device =
 ///     try_out_ptr(|v| unsafe { D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_11_0, v) })
 ///         .expect("D3D12CreateDevice failed");
@@ -80,7 +80,7 @@ where
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// let blob: ID3DBlob = util::try_out_err_blob(|v, err_blob| {
 ///     D3D12SerializeRootSignature(
 ///         &root_signature_desc,
@@ -107,7 +107,7 @@ where
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// let vertex_buffer = out_param(|x| unsafe { gl.GenBuffers(1, x) });
 /// ```
 pub fn out_param<T: Default, F>(f: F) -> T
@@ -202,6 +202,25 @@ pub fn get_dll_path() -> Option<PathBuf> {
     Some(OsString::from_wide(&sz_filename[..len]).into())
 }
 
+/// Returns the path of the loaded module that contains `addr`, or `None` if
+/// it isn't part of any, e.g. because it was allocated at runtime.
+pub fn module_path_of(addr: *const c_void) -> Option<PathBuf> {
+    let mut hmodule = HMODULE(0);
+    unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT | GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+            PCWSTR(addr as *const u16),
+            &mut hmodule,
+        )
+    }
+    .ok()?;
+
+    let mut sz_filename = [0u16; MAX_PATH as usize];
+    let len = unsafe { GetModuleFileNameW(hmodule, &mut sz_filename) } as usize;
+
+    Some(OsString::from_wide(&sz_filename[..len]).into())
+}
+
 /// Creates a [`D3D12_RESOURCE_BARRIER`].
 ///
 /// Use this function and the associated [`drop_barrier`] for correctly managing
@@ -209,6 +228,27 @@ pub fn get_dll_path() -> Option<PathBuf> {
 ///
 /// RAII was not used due to the complicated signature of
 /// [`windows::Win32::Graphics::Direct3D12::ID3D12GraphicsCommandList::ResourceBarrier`].
+///
+/// # Example
+///
+/// ```no_run
+/// use hudhook::util::{create_barrier, drop_barrier};
+/// use windows::Win32::Graphics::Direct3D12::{
+///     ID3D12GraphicsCommandList, ID3D12Resource, D3D12_RESOURCE_STATE_PRESENT,
+///     D3D12_RESOURCE_STATE_RENDER_TARGET,
+/// };
+///
+/// unsafe fn draw(command_list: &ID3D12GraphicsCommandList, back_buffer: &ID3D12Resource) {
+///     let barriers = [create_barrier(
+///         back_buffer,
+///         D3D12_RESOURCE_STATE_PRESENT,
+///         D3D12_RESOURCE_STATE_RENDER_TARGET,
+///     )];
+///     command_list.ResourceBarrier(&barriers);
+///     // Record the draw calls, execute the command list and wait for it...
+///     barriers.into_iter().for_each(drop_barrier);
+/// }
+/// ```
 pub fn create_barrier(
     resource: &ID3D12Resource,
     before: D3D12_RESOURCE_STATES,
@@ -240,7 +280,37 @@ pub fn drop_barrier(barrier: D3D12_RESOURCE_BARRIER) {
     let _ = ManuallyDrop::into_inner(transition.pResource);
 }
 
-/// Wrapper around [`windows::Win32::Graphics::Direct3D12::ID3D12Fence`].
+/// Wrapper around [`windows::Win32::Graphics::Direct3D12::ID3D12Fence`], for
+/// waiting on the GPU to be done with the work submitted to a command queue.
+///
+/// The fence holds the value that the next [`Fence::signal`] sets once the
+/// command queue gets to it. [`Fence::wait`] blocks until it has, and
+/// [`Fence::incr`] moves on to the next value.
+///
+/// # Example
+///
+/// ```no_run
+/// use hudhook::util::Fence;
+/// use windows::core::Result;
+/// use windows::Win32::Graphics::Direct3D12::{
+///     ID3D12CommandList, ID3D12CommandQueue, ID3D12Device,
+/// };
+///
+/// unsafe fn submit(
+///     device: &ID3D12Device,
+///     command_queue: &ID3D12CommandQueue,
+///     command_list: &ID3D12CommandList,
+/// ) -> Result<()> {
+///     let fence = Fence::new(device)?;
+///
+///     command_queue.ExecuteCommandLists(&[Some(command_list.clone())]);
+///     fence.signal(command_queue)?;
+///     fence.wait()?;
+///     fence.incr();
+///
+///     Ok(())
+/// }
+/// ```
 pub struct Fence {
     fence: ID3D12Fence,
     value: AtomicU64,
@@ -272,7 +342,14 @@ impl Fence {
         self.value.fetch_add(1, Ordering::SeqCst);
     }
 
-    /// Wait for completion of the fence.
+    /// Have `command_queue` set the fence to its current value once it's done
+    /// with the work submitted so far.
+    pub fn signal(&self, command_queue: &ID3D12CommandQueue) -> windows::core::Result<()> {
+        unsafe { command_queue.Signal(&self.fence, self.value()) }
+    }
+
+    /// Wait for the fence to reach its current value, i.e. for the work
+    /// submitted before the last [`Fence::signal`] to be done.
     pub fn wait(&self) -> windows::core::Result<()> {
         let value = self.value();
         unsafe {
@@ -286,6 +363,43 @@ impl Fence {
     }
 }
 
+impl Drop for Fence {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.event);
+        }
+    }
+}
+
+/// Convert tightly packed RGBA8 pixels from premultiplied to straight alpha,
+/// in place, as expected by [`crate::RenderContext::load_texture`].
+///
+/// Fully transparent pixels become transparent black, as their color can't be
+/// recovered. Trailing bytes that don't make up a whole pixel are left as is.
+pub fn unpremultiply_alpha(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            *channel = match alpha {
+                0 => 0,
+                255 => *channel,
+                _ => ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8,
+            };
+        }
+    }
+}
+
+/// Convert tightly packed RGBA8 pixels from straight to premultiplied alpha,
+/// in place. This is the inverse of [`unpremultiply_alpha`], up to rounding.
+pub fn premultiply_alpha(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
 /// Returns a slice of **up to** `limit` elements of type `T` starting at `ptr`.
 ///
 /// If the memory protection of some pages in this region prevents reading from
@@ -391,4 +505,18 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_premultiplied_alpha() {
+        let straight = [255, 128, 0, 128, 10, 20, 30, 255, 40, 50, 60, 0, 200, 100, 50, 1];
+
+        let mut pixels = straight;
+        premultiply_alpha(&mut pixels);
+        assert_eq!(pixels, [128, 64, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0, 1, 0, 0, 1]);
+
+        unpremultiply_alpha(&mut pixels);
+        assert_eq!(pixels[..12], [255, 128, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0]);
+        // Precision is lost at very low opacity.
+        assert_eq!(pixels[12..], [255, 0, 0, 1]);
+    }
 }
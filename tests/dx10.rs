@@ -0,0 +1,25 @@
+mod harness;
+mod hook;
+
+use std::thread;
+use std::time::Duration;
+
+use harness::dx10::Dx10Harness;
+use hook::HookExample;
+use hudhook::hooks::dx10::ImguiDx10Hooks;
+use hudhook::*;
+
+#[test]
+fn test_imgui_dx10() {
+    hook::setup_tracing();
+
+    let dx10_harness = Dx10Harness::new("DX10 hook example");
+    thread::sleep(Duration::from_millis(500));
+
+    if let Err(e) = Hudhook::builder().with::<ImguiDx10Hooks>(HookExample::new()).build().apply() {
+        eprintln!("Couldn't apply hooks: {e:?}");
+    }
+
+    thread::sleep(Duration::from_millis(25000));
+    drop(dx10_harness);
+}
@@ -1,3 +1,4 @@
+pub mod dx10;
 pub mod dx11;
 pub mod dx12;
 pub mod dx9;
@@ -40,6 +40,44 @@ fn test_inject_by_name() {
     child.wait().expect("Couldn't wait on child process");
 }
 
+#[test]
+#[ignore]
+fn test_eject_by_name() {
+    let mut child = Command::new("notepad.exe").spawn().expect("Couldn't start notepad");
+    std::thread::sleep(Duration::from_millis(500));
+    println!("Should show a message box that says \"Hello\".");
+
+    let process = Process::by_name("notepad.exe").unwrap();
+    process.inject(examples_path().join("dummy_hook.dll")).unwrap();
+
+    std::thread::sleep(Duration::from_millis(1000));
+    process.eject("dummy_hook.dll").unwrap();
+    assert!(process.eject("dummy_hook.dll").is_err());
+
+    child.kill().expect("Couldn't kill notepad");
+    child.wait().expect("Couldn't wait on child process");
+}
+
+#[test]
+#[ignore]
+fn test_wait_for_by_name() {
+    let waiter = std::thread::spawn(|| {
+        Process::wait_for_by_name("notepad.exe", Duration::from_secs(10))
+            .unwrap()
+            .inject(examples_path().join("dummy_hook.dll"))
+            .unwrap();
+    });
+
+    std::thread::sleep(Duration::from_millis(500));
+    let mut child = Command::new("notepad.exe").spawn().expect("Couldn't start notepad");
+    println!("Should show a message box that says \"Hello\".");
+    waiter.join().expect("Couldn't wait for notepad");
+
+    std::thread::sleep(Duration::from_millis(1000));
+    child.kill().expect("Couldn't kill notepad");
+    child.wait().expect("Couldn't wait on child process");
+}
+
 fn examples_path() -> PathBuf {
     project_root().join("target").join("debug").join("examples")
 }
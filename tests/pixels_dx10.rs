@@ -0,0 +1,12 @@
+mod harness;
+mod probe;
+
+use harness::dx10::Dx10Harness;
+use hudhook::hooks::dx10::ImguiDx10Hooks;
+
+#[test]
+fn test_pixels_dx10() {
+    let dx10_harness = Dx10Harness::new("DX10 pixel test");
+    probe::check_pixels::<ImguiDx10Hooks>();
+    drop(dx10_harness);
+}
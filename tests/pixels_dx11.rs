@@ -0,0 +1,12 @@
+mod harness;
+mod probe;
+
+use harness::dx11::Dx11Harness;
+use hudhook::hooks::dx11::ImguiDx11Hooks;
+
+#[test]
+fn test_pixels_dx11() {
+    let dx11_harness = Dx11Harness::new("DX11 pixel test");
+    probe::check_pixels::<ImguiDx11Hooks>();
+    drop(dx11_harness);
+}
@@ -0,0 +1,12 @@
+mod harness;
+mod probe;
+
+use harness::dx12::Dx12Harness;
+use hudhook::hooks::dx12::ImguiDx12Hooks;
+
+#[test]
+fn test_pixels_dx12() {
+    let dx12_harness = Dx12Harness::new();
+    probe::check_pixels::<ImguiDx12Hooks>();
+    drop(dx12_harness);
+}
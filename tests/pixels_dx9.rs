@@ -0,0 +1,12 @@
+mod harness;
+mod probe;
+
+use harness::dx9::Dx9Harness;
+use hudhook::hooks::dx9::ImguiDx9Hooks;
+
+#[test]
+fn test_pixels_dx9() {
+    let dx9_harness = Dx9Harness::new("DX9 pixel test");
+    probe::check_pixels::<ImguiDx9Hooks>();
+    drop(dx9_harness);
+}
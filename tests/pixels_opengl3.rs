@@ -0,0 +1,12 @@
+mod harness;
+mod probe;
+
+use harness::opengl3::Opengl3Harness;
+use hudhook::hooks::opengl3::ImguiOpenGl3Hooks;
+
+#[test]
+fn test_pixels_opengl3() {
+    let opengl3_harness = Opengl3Harness::new("OpenGL3 pixel test");
+    probe::check_pixels::<ImguiOpenGl3Hooks>();
+    drop(opengl3_harness);
+}
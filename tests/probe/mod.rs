@@ -0,0 +1,156 @@
+//! A render loop that draws known shapes over the harness' frame, and reads
+//! back the back buffer before and after drawing them to check the pixels the
+//! render engine produced.
+
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+use std::time::Duration;
+
+use hudhook::hooks::Hooks;
+use hudhook::{Hudhook, ImguiRenderLoop, RenderContext};
+use imgui::{Context, ImColor32, Ui};
+
+// An opaque rectangle, checked for its exact color.
+const OPAQUE_MIN: [f32; 2] = [100., 100.];
+const OPAQUE_MAX: [f32; 2] = [200., 200.];
+const OPAQUE_COLOR: [u8; 4] = [0xe0, 0x20, 0x60, 0xff];
+
+// A translucent rectangle, checked for straight alpha blending over the frame.
+const TRANSLUCENT_MIN: [f32; 2] = [300., 100.];
+const TRANSLUCENT_MAX: [f32; 2] = [400., 200.];
+const TRANSLUCENT_COLOR: [u8; 4] = [0x20, 0x40, 0xff, 0x80];
+
+// A rectangle straddling its clip rect, checked for the scissor rect.
+const CLIPPED_MIN: [f32; 2] = [500., 100.];
+const CLIPPED_MAX: [f32; 2] = [700., 200.];
+const CLIP_MAX: [f32; 2] = [600., 200.];
+
+// How many frames to let the overlay settle before reading back.
+const WARMUP_FRAMES: u32 = 10;
+
+// Channel components can be off by one or two after blending.
+const TOLERANCE: i32 = 2;
+
+type Screenshot = (u32, u32, Vec<u8>);
+
+struct PixelProbe {
+    frame: u32,
+    before: Option<Screenshot>,
+    tx: Option<SyncSender<Result<(Screenshot, Screenshot), String>>>,
+}
+
+impl ImguiRenderLoop for PixelProbe {
+    fn before_render<'a>(
+        &'a mut self,
+        _ctx: &mut Context,
+        render_context: &'a mut dyn RenderContext,
+    ) {
+        self.frame += 1;
+        if self.frame == WARMUP_FRAMES {
+            match render_context.capture_screenshot() {
+                Ok(screenshot) => self.before = Some(screenshot),
+                Err(e) => self.send(Err(format!("Couldn't read back the frame: {e:?}"))),
+            }
+        }
+    }
+
+    fn render(&mut self, ui: &mut Ui) {
+        let draw_list = ui.get_background_draw_list();
+        draw_list.add_rect(OPAQUE_MIN, OPAQUE_MAX, color(OPAQUE_COLOR)).filled(true).build();
+        draw_list
+            .add_rect(TRANSLUCENT_MIN, TRANSLUCENT_MAX, color(TRANSLUCENT_COLOR))
+            .filled(true)
+            .build();
+        draw_list.with_clip_rect(CLIPPED_MIN, CLIP_MAX, || {
+            draw_list.add_rect(CLIPPED_MIN, CLIPPED_MAX, color(OPAQUE_COLOR)).filled(true).build();
+        });
+    }
+
+    fn after_render(&mut self, render_context: &mut dyn RenderContext) {
+        let Some(before) = self.before.take() else {
+            return;
+        };
+
+        match render_context.capture_screenshot() {
+            Ok(after) => self.send(Ok((before, after))),
+            Err(e) => self.send(Err(format!("Couldn't read back the overlay: {e:?}"))),
+        }
+    }
+}
+
+impl PixelProbe {
+    fn send(&mut self, result: Result<(Screenshot, Screenshot), String>) {
+        if let Some(tx) = self.tx.take() {
+            tx.send(result).ok();
+        }
+    }
+}
+
+fn color([r, g, b, a]: [u8; 4]) -> ImColor32 {
+    ImColor32::from_rgba(r, g, b, a)
+}
+
+fn pixel((width, height, pixels): &Screenshot, [x, y]: [f32; 2]) -> [u8; 4] {
+    let (x, y) = (x as usize, y as usize);
+    assert!(x < *width as usize && y < *height as usize, "({x}, {y}) is out of the frame");
+    let offset = (y * *width as usize + x) * 4;
+    pixels[offset..offset + 4].try_into().unwrap()
+}
+
+fn center(min: [f32; 2], max: [f32; 2]) -> [f32; 2] {
+    [(min[0] + max[0]) / 2., (min[1] + max[1]) / 2.]
+}
+
+// Blend `src` over `dst` the way imgui expects: straight alpha, i.e.
+// `src * a + dst * (1 - a)`.
+fn blend(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let a = src[3] as f32 / 255.;
+    let channel = |s: u8, d: u8| (s as f32 * a + d as f32 * (1. - a)).round() as u8;
+    [channel(src[0], dst[0]), channel(src[1], dst[1]), channel(src[2], dst[2]), dst[3]]
+}
+
+fn assert_rgb(what: &str, actual: [u8; 4], expected: [u8; 4]) {
+    let close = actual[..3]
+        .iter()
+        .zip(&expected[..3])
+        .all(|(&a, &e)| (a as i32 - e as i32).abs() <= TOLERANCE);
+    assert!(close, "{what}: expected {expected:?}, got {actual:?}");
+}
+
+/// Apply the hooks with the probe, wait for it to read back a frame, and
+/// check the pixels. The harness presenting to the hooked API must be running.
+pub fn check_pixels<T: Hooks + 'static>() {
+    // Let the harness create its device and present a few frames.
+    thread::sleep(Duration::from_millis(500));
+
+    let (tx, rx) = mpsc::sync_channel(1);
+    let probe = PixelProbe { frame: 0, before: None, tx: Some(tx) };
+    Hudhook::builder().with::<T>(probe).build().apply().expect("Couldn't apply hooks");
+
+    let (before, after) = rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("The overlay didn't render in time")
+        .unwrap();
+    assert_eq!((before.0, before.1), (after.0, after.1));
+
+    assert_rgb("opaque rectangle", pixel(&after, center(OPAQUE_MIN, OPAQUE_MAX)), OPAQUE_COLOR);
+
+    let translucent = center(TRANSLUCENT_MIN, TRANSLUCENT_MAX);
+    assert_rgb(
+        "translucent rectangle",
+        pixel(&after, translucent),
+        blend(TRANSLUCENT_COLOR, pixel(&before, translucent)),
+    );
+
+    let inside_clip = center(CLIPPED_MIN, CLIP_MAX);
+    let outside_clip = center(CLIP_MAX, CLIPPED_MAX);
+    assert_rgb("clipped rectangle, inside", pixel(&after, inside_clip), OPAQUE_COLOR);
+    assert_rgb(
+        "clipped rectangle, outside",
+        pixel(&after, outside_clip),
+        pixel(&before, outside_clip),
+    );
+
+    let untouched = [50., 400.];
+    assert_rgb("untouched frame", pixel(&after, untouched), pixel(&before, untouched));
+}